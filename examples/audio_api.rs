@@ -0,0 +1,655 @@
+// REST/JSON control of the WM8960 over Ethernet
+//
+// GET  /audio/status
+// GET  /audio/volume        {"headphone":80,"speaker":100}, percent
+// PUT  /audio/volume        either or both members
+// GET  /audio/mute          {"muted":false}
+// PUT  /audio/mute
+// GET  /audio/play/{clip}
+// PUT  /audio/play/{clip}   {"playing":true,"loop":false}, both optional
+//
+// curl -X PUT -H 'Content-Type: application/json' \
+//     -d '{"headphone":60}' http://<address>/audio/volume
+//
+// PB13, the I2S clock in the wm8960 example, is RMII TXD1 on this board so
+// the clock is on PB10 here. The codec's GPIO1 (ADCLRC) reports the jack
+// state and goes to D8 (PF12).
+
+#![no_main]
+#![no_std]
+
+extern crate stm32f4xx_hal as hal;
+
+#[allow(unused_imports)]
+use panic_semihosting;
+
+use crate::hal::{
+    i2c,
+    i2c::I2c,
+    i2s,
+    i2s::{I2s, I2sStandard},
+    prelude::*,
+    serial::config::Config,
+    serial::Serial,
+    stm32,
+    stm32::interrupt,
+};
+use core::cell::RefCell;
+use core::fmt::{self, Write};
+use cortex_m::asm;
+use cortex_m::interrupt::Mutex;
+use cortex_m::peripheral::syst::SystClkSource;
+use cortex_m_rt::{entry, exception, ExceptionFrame};
+use embedded_hal::digital::v2::InputPin;
+use net::dhcp::{self, DhcpClient, DhcpDevice, Event};
+use net::http::{Method, Request, Response, Route, Server, Slot, Status};
+use net::json::{self, ObjectWriter, Quoted, Value};
+use smoltcp::iface::{EthernetInterfaceBuilder, NeighborCache, Routes};
+use smoltcp::socket::{RawPacketMetadata, RawSocketBuffer, SocketSet, TcpSocketBuffer};
+use smoltcp::time::Instant;
+use smoltcp::wire::{EthernetAddress, IpCidr};
+use stm32_eth::{Eth, RingEntry};
+use wm8960::control::{Controls, Level, Output};
+use wm8960::player::Player;
+use wm8960::wave_header::EmbeddedWave;
+use wm8960::Wm8960;
+use wm8960_macros::include_wav;
+
+const SRC_MAC: [u8; 6] = [0x00, 0x00, 0xDE, 0xAD, 0xBE, 0xEF];
+
+const JSON: &str = "application/json";
+
+static TIME: Mutex<RefCell<u64>> = Mutex::new(RefCell::new(0));
+static ETH_PENDING: Mutex<RefCell<bool>> = Mutex::new(RefCell::new(false));
+
+// Mono, ~0.5 MiB at the codec's rate, stereo wouldn't fit in bank 1
+include_wav!(RING, "assets/ring.wav", convert, channels = 1);
+
+struct Clip {
+    name: &'static str,
+    wave: &'static EmbeddedWave,
+}
+
+static CLIPS: &[Clip] = &[Clip {
+    name: "ring",
+    wave: &RING,
+}];
+
+/// The codec didn't take a setting or a clip can't be played
+#[derive(Debug)]
+struct AudioError;
+
+/// What the handlers need, implemented by `Audio` for the board's types
+trait AudioControl {
+    fn controls(&self) -> Controls;
+    fn set_level(&mut self, output: Output, level: Level) -> Result<(), AudioError>;
+    fn set_muted(&mut self, muted: bool) -> Result<(), AudioError>;
+    /// Headphones plugged in
+    fn jack_inserted(&self) -> bool;
+    /// Play `CLIPS[clip]` from the start, repeating its loop, if any, until
+    /// stopped when `hold_loop`
+    fn play(&mut self, clip: usize, hold_loop: bool) -> Result<(), AudioError>;
+    fn stop(&mut self);
+    /// Index into `CLIPS` and frame of the clip playing
+    fn playing(&self) -> Option<(usize, usize)>;
+}
+
+/// State of the handlers, the trait object keeps the board's types out of
+/// the route table
+type Api = dyn AudioControl;
+
+struct Audio<I2C, I2S, JACK> {
+    codec: Wm8960<I2C, I2S>,
+    jack: JACK,
+    playing: Option<(usize, Player<'static>)>,
+}
+
+impl<I2C, I2S, JACK> Audio<I2C, I2S, JACK>
+where
+    I2C: embedded_hal::blocking::i2c::Write<Error = i2c::Error>,
+    I2S: i2s::Write<u16, Error = i2s::Error>,
+    JACK: InputPin,
+{
+    /// Play the next buffer of the clip, blocks until it's written
+    fn play_buffer(&mut self, buf: &mut [i16]) -> Result<(), wm8960::Error> {
+        if let Some((_, player)) = self.playing.as_mut() {
+            if self.codec.play_source(player, buf)? == 0 {
+                self.playing = None;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<I2C, I2S, JACK> AudioControl for Audio<I2C, I2S, JACK>
+where
+    I2C: embedded_hal::blocking::i2c::Write<Error = i2c::Error>,
+    I2S: i2s::Write<u16, Error = i2s::Error>,
+    JACK: InputPin,
+{
+    fn controls(&self) -> Controls {
+        *self.codec.controls()
+    }
+
+    fn set_level(&mut self, output: Output, level: Level) -> Result<(), AudioError> {
+        self.codec.set_level(output, level).map_err(|_| AudioError)
+    }
+
+    fn set_muted(&mut self, muted: bool) -> Result<(), AudioError> {
+        self.codec.set_muted(muted).map_err(|_| AudioError)
+    }
+
+    fn jack_inserted(&self) -> bool {
+        self.jack.is_high().unwrap_or(false)
+    }
+
+    fn play(&mut self, clip: usize, hold_loop: bool) -> Result<(), AudioError> {
+        let wave = CLIPS[clip].wave.parsed();
+        let mut player = Player::from_parsed(&wave).map_err(|_| AudioError)?;
+        if !hold_loop {
+            player.release();
+        }
+        self.playing = Some((clip, player));
+        Ok(())
+    }
+
+    fn stop(&mut self) {
+        self.playing = None;
+    }
+
+    fn playing(&self) -> Option<(usize, usize)> {
+        self.playing
+            .as_ref()
+            .map(|(clip, player)| (*clip, player.position()))
+    }
+}
+
+static ROUTES: &[Route<Api>] = &[
+    Route {
+        method: Method::GET,
+        path: "/audio/status",
+        handler: get_status,
+    },
+    Route {
+        method: Method::GET,
+        path: "/audio/volume",
+        handler: get_volume,
+    },
+    Route {
+        method: Method::PUT,
+        path: "/audio/volume",
+        handler: put_volume,
+    },
+    Route {
+        method: Method::GET,
+        path: "/audio/mute",
+        handler: get_mute,
+    },
+    Route {
+        method: Method::PUT,
+        path: "/audio/mute",
+        handler: put_mute,
+    },
+    Route {
+        method: Method::GET,
+        path: "/audio/play/{clip}",
+        handler: get_clip,
+    },
+    Route {
+        method: Method::PUT,
+        path: "/audio/play/{clip}",
+        handler: put_clip,
+    },
+];
+
+fn get_status(audio: &mut Api, _request: &Request, response: &mut Response) {
+    let controls = audio.controls();
+    let jack = if audio.jack_inserted() {
+        "inserted"
+    } else {
+        "removed"
+    };
+    let playing = audio.playing().map(|(clip, _)| CLIPS[clip].name);
+    response.start(Status::Ok, JSON);
+    let mut o = json::object(response);
+    o.object("volume", |o| volume_members(o, controls))
+        .member("muted", &controls.muted)
+        .member("jack", jack)
+        .member("playing", &playing)
+        .array("clips", CLIPS.iter().map(|c| c.name));
+    let _ = o.finish();
+}
+
+fn get_volume(audio: &mut Api, _request: &Request, response: &mut Response) {
+    let controls = audio.controls();
+    response.start(Status::Ok, JSON);
+    let mut o = json::object(response);
+    volume_members(&mut o, controls);
+    let _ = o.finish();
+}
+
+fn put_volume(audio: &mut Api, request: &Request, response: &mut Response) {
+    let body = match object_body(request, response) {
+        Some(body) => body,
+        None => return,
+    };
+    let mut headphone = None;
+    let mut speaker = None;
+    for (name, value) in body.members() {
+        let level = match name {
+            "headphone" => &mut headphone,
+            "speaker" => &mut speaker,
+            _ => return unknown_member(response, name),
+        };
+        *level = match value.as_u64().filter(|p| *p <= 100) {
+            Some(percent) => Level::new(percent as u8),
+            None => {
+                return send_error(
+                    response,
+                    Status::UnprocessableEntity,
+                    format_args!("{} must be an integer from 0 to 100", name),
+                )
+            }
+        };
+    }
+    if headphone.is_none() && speaker.is_none() {
+        return send_error(
+            response,
+            Status::UnprocessableEntity,
+            "expected headphone or speaker",
+        );
+    }
+
+    let levels = [(Output::Headphone, headphone), (Output::Speaker, speaker)];
+    for (output, level) in levels.iter() {
+        if let Some(level) = level {
+            if audio.set_level(*output, *level).is_err() {
+                return send_error(response, Status::InternalServerError, "codec error");
+            }
+        }
+    }
+    get_volume(audio, request, response);
+}
+
+fn get_mute(audio: &mut Api, _request: &Request, response: &mut Response) {
+    let muted = audio.controls().muted;
+    response.start(Status::Ok, JSON);
+    let _ = json::object(response).member("muted", &muted).finish();
+}
+
+fn put_mute(audio: &mut Api, request: &Request, response: &mut Response) {
+    let body = match object_body(request, response) {
+        Some(body) => body,
+        None => return,
+    };
+    let mut muted = None;
+    for (name, value) in body.members() {
+        match name {
+            "muted" => muted = value.as_bool(),
+            _ => return unknown_member(response, name),
+        }
+    }
+    let muted = match muted {
+        Some(muted) => muted,
+        None => {
+            return send_error(
+                response,
+                Status::UnprocessableEntity,
+                "muted must be true or false",
+            )
+        }
+    };
+    if audio.set_muted(muted).is_err() {
+        return send_error(response, Status::InternalServerError, "codec error");
+    }
+    get_mute(audio, request, response);
+}
+
+fn get_clip(audio: &mut Api, request: &Request, response: &mut Response) {
+    let clip = match find_clip(request, response) {
+        Some(clip) => clip,
+        None => return,
+    };
+    let wave = CLIPS[clip].wave.parsed();
+    let sample_rate = wave.fmt().sample_rate;
+    let has_loop = match wave.sampler() {
+        Ok(Some(sampler)) => sampler.loops().next().is_some(),
+        _ => false,
+    };
+    let position = audio
+        .playing()
+        .filter(|(playing, _)| *playing == clip)
+        .map(|(_, frame)| frames_to_ms(frame, sample_rate));
+
+    response.start(Status::Ok, JSON);
+    let _ = json::object(response)
+        .member("clip", CLIPS[clip].name)
+        .member("playing", &position.is_some())
+        .member("position_ms", &position)
+        .member("duration_ms", &frames_to_ms(wave.num_frames(), sample_rate))
+        .member("sample_rate", &sample_rate)
+        .member("loop", &has_loop)
+        .finish();
+}
+
+fn put_clip(audio: &mut Api, request: &Request, response: &mut Response) {
+    let clip = match find_clip(request, response) {
+        Some(clip) => clip,
+        None => return,
+    };
+    // An empty body plays the clip once
+    let body = if request.body.is_empty() {
+        Value::Null
+    } else {
+        match object_body(request, response) {
+            Some(body) => body,
+            None => return,
+        }
+    };
+    let mut playing = true;
+    let mut hold_loop = false;
+    for (name, value) in body.members() {
+        let flag = match name {
+            "playing" => &mut playing,
+            "loop" => &mut hold_loop,
+            _ => return unknown_member(response, name),
+        };
+        *flag = match value.as_bool() {
+            Some(b) => b,
+            None => {
+                return send_error(
+                    response,
+                    Status::UnprocessableEntity,
+                    format_args!("{} must be true or false", name),
+                )
+            }
+        };
+    }
+
+    if playing {
+        if audio.play(clip, hold_loop).is_err() {
+            return send_error(
+                response,
+                Status::InternalServerError,
+                "clip can't be played",
+            );
+        }
+    } else if audio.playing().map(|(c, _)| c) == Some(clip) {
+        audio.stop();
+    }
+    get_clip(audio, request, response);
+}
+
+fn volume_members<W: fmt::Write>(o: &mut ObjectWriter<'_, W>, controls: Controls) {
+    o.member("headphone", &controls.headphone.percent())
+        .member("speaker", &controls.speaker.percent());
+}
+
+fn frames_to_ms(frames: usize, sample_rate: u32) -> u64 {
+    frames as u64 * 1000 / u64::from(sample_rate.max(1))
+}
+
+/// Index into `CLIPS` of the `{clip}` path parameter, responds with 404 if
+/// there's no such clip
+fn find_clip(request: &Request, response: &mut Response) -> Option<usize> {
+    let name = request.param("clip").unwrap_or("");
+    let clip = CLIPS.iter().position(|c| c.name == name);
+    if clip.is_none() {
+        send_error(response, Status::NotFound, "unknown clip");
+    }
+    clip
+}
+
+/// Request body as a JSON object, responds with the error otherwise
+fn object_body<'a>(request: &Request<'a>, response: &mut Response) -> Option<Value<'a>> {
+    match request.header("Content-Type") {
+        Some(t) if !t.starts_with(JSON) => {
+            send_error(
+                response,
+                Status::UnsupportedMediaType,
+                "expected application/json",
+            );
+            return None;
+        }
+        _ => (),
+    }
+    match json::parse(request.body) {
+        Ok(body @ Value::Object(_)) => Some(body),
+        Ok(_) => {
+            send_error(response, Status::UnprocessableEntity, "expected an object");
+            None
+        }
+        Err(_) => {
+            send_error(response, Status::BadRequest, "invalid JSON");
+            None
+        }
+    }
+}
+
+fn unknown_member(response: &mut Response, name: &str) {
+    send_error(
+        response,
+        Status::UnprocessableEntity,
+        format_args!("unknown member {}", name),
+    )
+}
+
+fn send_error<M: fmt::Display>(response: &mut Response, status: Status, message: M) {
+    response.start(status, JSON);
+    let _ = json::object(response)
+        .member("error", &Quoted(message))
+        .finish();
+}
+
+#[entry]
+fn main() -> ! {
+    let dp = stm32::Peripherals::take().expect("Failed to take stm32::Peripherals");
+    let mut cp =
+        cortex_m::peripheral::Peripherals::take().expect("Failed to take cortex_m::Peripherals");
+
+    stm32_eth::setup(&dp.RCC, &dp.SYSCFG);
+
+    let rcc = dp.RCC.constrain();
+    let clocks = rcc.cfgr.sysclk(180.mhz()).freeze();
+
+    setup_systick(&mut cp.SYST, clocks.sysclk().0);
+
+    let gpioa = dp.GPIOA.split();
+    let gpiob = dp.GPIOB.split();
+    let gpioc = dp.GPIOC.split();
+    let gpiod = dp.GPIOD.split();
+    let gpiof = dp.GPIOF.split();
+    let gpiog = dp.GPIOG.split();
+
+    let serial = Serial::usart3(
+        dp.USART3,
+        (
+            gpiod.pd8.into_alternate_af7(),
+            gpiod.pd9.into_alternate_af7(),
+        ),
+        Config {
+            baudrate: 115_200.bps(),
+            ..Default::default()
+        },
+        clocks,
+    )
+    .unwrap();
+    let (mut stdout, _rx) = serial.split();
+
+    writeln!(stdout, "Init Wm8960").unwrap();
+
+    let scl = gpiob.pb8.into_alternate_af4().set_open_drain();
+    let sda = gpiob.pb9.into_alternate_af4().set_open_drain();
+    let i2c = I2c::i2c1(dp.I2C1, (scl, sda), 100.khz(), clocks);
+
+    let i2s_ck = gpiob.pb10.into_alternate_af5();
+    let i2s_ws = gpiob.pb12.into_alternate_af5();
+    let i2s_sd = gpiob.pb15.into_alternate_af5();
+    let i2s_mck = gpioc.pc6.into_alternate_af5();
+    let i2s = I2s::i2s2(dp.SPI2, (i2s_sd, i2s_ck, i2s_ws, i2s_mck), clocks)
+        .into_master_output::<u16>(I2sStandard::Philips);
+
+    let mut codec = Wm8960::new(i2c, i2s).unwrap();
+    codec.enable_jack_detect_output().unwrap();
+    let mut audio = Audio {
+        codec,
+        jack: gpiof.pf12.into_pull_down_input(),
+        playing: None,
+    };
+
+    writeln!(stdout, "Enabling ethernet...").unwrap();
+
+    stm32_eth::setup_pins(
+        gpioa.pa1, gpioa.pa2, gpioa.pa7, gpiob.pb13, gpioc.pc1, gpioc.pc4, gpioc.pc5, gpiog.pg11,
+        gpiog.pg13,
+    );
+
+    let mut rx_ring: [RingEntry<_>; 16] = Default::default();
+    let mut tx_ring: [RingEntry<_>; 8] = Default::default();
+    let mut eth = Eth::new(
+        dp.ETHERNET_MAC,
+        dp.ETHERNET_DMA,
+        SRC_MAC,
+        &mut rx_ring[..],
+        &mut tx_ring[..],
+    );
+    eth.enable_interrupt(&mut cp.NVIC);
+
+    let ethernet_addr = EthernetAddress(SRC_MAC);
+    let fallback = dhcp::link_local(ethernet_addr);
+    let mut ip_addrs = [IpCidr::Ipv4(fallback)];
+    let mut neighbor_storage = [None; 16];
+    let neighbor_cache = NeighborCache::new(&mut neighbor_storage[..]);
+    let mut routes_storage = [None; 1];
+    let routes = Routes::new(&mut routes_storage[..]);
+    let mut iface = EthernetInterfaceBuilder::new(DhcpDevice::new(&mut eth))
+        .ethernet_addr(ethernet_addr)
+        .ip_addrs(&mut ip_addrs[..])
+        .neighbor_cache(neighbor_cache)
+        .routes(routes)
+        .finalize();
+
+    let mut dhcp_rx_metadata = [RawPacketMetadata::EMPTY; 4];
+    let mut dhcp_tx_metadata = [RawPacketMetadata::EMPTY; 1];
+    // The set holds TCP sockets, which makes it require 'static packet buffers
+    let dhcp_rx_buffer = cortex_m::singleton!(: [u8; 4 * 576] = [0; 4 * 576]).unwrap();
+    let dhcp_tx_buffer =
+        cortex_m::singleton!(: [u8; dhcp::PACKET_LEN] = [0; dhcp::PACKET_LEN]).unwrap();
+    let mut http_rx_buffers = [[0; 1024]; 2];
+    let mut http_tx_buffers = [[0; 1024]; 2];
+    let mut sockets_storage = [None, None, None];
+    let mut sockets = SocketSet::new(&mut sockets_storage[..]);
+
+    let time: u64 = cortex_m::interrupt::free(|cs| *TIME.borrow(cs).borrow());
+    let mut dhcp = DhcpClient::new(
+        &mut sockets,
+        RawSocketBuffer::new(&mut dhcp_rx_metadata[..], &mut dhcp_rx_buffer[..]),
+        RawSocketBuffer::new(&mut dhcp_tx_metadata[..], &mut dhcp_tx_buffer[..]),
+        ethernet_addr,
+        fallback,
+        Instant::from_millis(time as i64),
+    );
+
+    let [rx0, rx1] = &mut http_rx_buffers;
+    let [tx0, tx1] = &mut http_tx_buffers;
+    let mut slots = [
+        Slot::new(
+            &mut sockets,
+            TcpSocketBuffer::new(&mut rx0[..]),
+            TcpSocketBuffer::new(&mut tx0[..]),
+        ),
+        Slot::new(
+            &mut sockets,
+            TcpSocketBuffer::new(&mut rx1[..]),
+            TcpSocketBuffer::new(&mut tx1[..]),
+        ),
+    ];
+    let mut server = Server::new(80, ROUTES, &mut slots[..]);
+
+    writeln!(stdout, "Ready, listening at {} until DHCP binds", fallback).unwrap();
+
+    let mut buf = [0_i16; 1024];
+
+    loop {
+        let time: u64 = cortex_m::interrupt::free(|cs| *TIME.borrow(cs).borrow());
+        cortex_m::interrupt::free(|cs| {
+            let mut eth_pending = ETH_PENDING.borrow(cs).borrow_mut();
+            *eth_pending = false;
+        });
+        let now = Instant::from_millis(time as i64);
+
+        match dhcp.poll(&mut iface, &mut sockets, now) {
+            Some(Event::Bound(lease)) | Some(Event::Renewed(lease)) => {
+                writeln!(stdout, "DHCP bound {}", lease.address).unwrap()
+            }
+            Some(Event::Expired(fallback)) => {
+                writeln!(stdout, "DHCP lease expired, using {}", fallback).unwrap()
+            }
+            None => (),
+        }
+
+        let processed = match iface.poll(&mut sockets, now) {
+            Ok(processed) => processed,
+            Err(e) => {
+                // Ignore malformed packets
+                writeln!(stdout, "Error: {:?}", e).unwrap();
+                true
+            }
+        };
+
+        server.poll(&mut sockets, &mut audio);
+
+        if audio.playing.is_some() {
+            // The network is polled between buffers, ~10 ms apart
+            audio.play_buffer(&mut buf).unwrap();
+        } else if !processed {
+            // Sleep if no ethernet work is pending
+            cortex_m::interrupt::free(|cs| {
+                let eth_pending = ETH_PENDING.borrow(cs).borrow_mut();
+                if !*eth_pending {
+                    asm::wfi();
+                    // Awaken by interrupt
+                }
+            });
+        }
+    }
+}
+
+/// 1 ms ticks
+fn setup_systick(syst: &mut stm32::SYST, sysclk: u32) {
+    syst.set_clock_source(SystClkSource::Core);
+    syst.set_reload(sysclk / 1000 - 1);
+    syst.clear_current();
+    syst.enable_counter();
+    syst.enable_interrupt();
+}
+
+#[exception]
+fn SysTick() {
+    cortex_m::interrupt::free(|cs| {
+        let mut time = TIME.borrow(cs).borrow_mut();
+        *time += 1;
+    })
+}
+
+#[interrupt]
+fn ETH() {
+    cortex_m::interrupt::free(|cs| {
+        let mut eth_pending = ETH_PENDING.borrow(cs).borrow_mut();
+        *eth_pending = true;
+    });
+
+    // Clear interrupt flags
+    let p = unsafe { stm32::Peripherals::steal() };
+    stm32_eth::eth_interrupt_handler(&p.ETHERNET_DMA);
+}
+
+#[exception]
+fn HardFault(ef: &ExceptionFrame) -> ! {
+    panic!("HardFault at {:#?}", ef);
+}
+
+#[exception]
+fn DefaultHandler(irqn: i16) {
+    panic!("Unhandled exception (IRQn = {})", irqn);
+}
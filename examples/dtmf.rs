@@ -0,0 +1,135 @@
+// DTMF from the WM8960's input
+//
+// Captures LINPUT1/RINPUT1 through I2S2ext, see i2s_ext, decodes keypad
+// digits with wm8960::dtmf and meters the capture levels. ADCDAT goes to
+// PC2, the I2S clocks are wired as in the wm8960 example.
+
+#![no_main]
+#![no_std]
+
+extern crate stm32f4xx_hal as hal;
+
+#[allow(unused_imports)]
+use panic_semihosting;
+
+use crate::hal::{
+    i2c::I2c,
+    i2s::{I2s, I2sStandard},
+    prelude::*,
+    serial::config::Config,
+    serial::Serial,
+    stm32,
+};
+use core::fmt::Write;
+use cortex_m_rt::ExceptionFrame;
+use cortex_m_rt::{entry, exception};
+use wm8960::audio::{AudioSource, Processed, NUM_CHANNELS, SAMPLE_RATE};
+use wm8960::dtmf::{DtmfDetector, DtmfEvent};
+use wm8960::meter::LevelMeter;
+use wm8960::Wm8960;
+
+mod i2s_ext;
+use i2s_ext::I2sExt;
+
+/// Frames captured per loop, 10 ms
+const PERIOD_FRAMES: usize = SAMPLE_RATE as usize / 100;
+
+/// Levels are printed this often
+const REPORT_PERIODS: u32 = 500;
+
+#[entry]
+fn main() -> ! {
+    let dp = stm32::Peripherals::take().expect("Failed to take stm32::Peripherals");
+    let _cp =
+        cortex_m::peripheral::Peripherals::take().expect("Failed to take cortex_m::Peripherals");
+
+    let rcc = dp.RCC.constrain();
+    let clocks = rcc.cfgr.sysclk(180.mhz()).freeze();
+
+    let gpiob = dp.GPIOB.split();
+    let gpioc = dp.GPIOC.split();
+    let gpiod = dp.GPIOD.split();
+
+    let scl = gpiob.pb8.into_alternate_af4().set_open_drain();
+    let sda = gpiob.pb9.into_alternate_af4().set_open_drain();
+
+    let i2s_ck = gpiob.pb13.into_alternate_af5();
+    let i2s_ws = gpiob.pb12.into_alternate_af5();
+    let i2s_sd = gpiob.pb15.into_alternate_af5();
+    let i2s_mck = gpioc.pc6.into_alternate_af5();
+    let i2s_ext_sd = gpioc.pc2.into_alternate_af6();
+
+    let serial_tx = gpiod.pd8.into_alternate_af7();
+    let serial_rx = gpiod.pd9.into_alternate_af7();
+
+    let serial = Serial::usart3(
+        dp.USART3,
+        (serial_tx, serial_rx),
+        Config {
+            baudrate: 115_200.bps(),
+            ..Default::default()
+        },
+        clocks,
+    )
+    .unwrap();
+    let (mut stdout, _rx) = serial.split();
+
+    writeln!(stdout, "Init Wm8960").unwrap();
+
+    let i2c = I2c::i2c1(dp.I2C1, (scl, sda), 100.khz(), clocks);
+    let i2s = I2s::i2s2(dp.SPI2, (i2s_sd, i2s_ck, i2s_ws, i2s_mck), clocks)
+        .into_master_output::<u16>(I2sStandard::Philips);
+    let mut wm8960 = Wm8960::new(i2c, i2s).unwrap();
+    wm8960.enable_capture().unwrap();
+
+    let capture = I2sExt::new(dp.I2S2EXT, i2s_ext_sd);
+    let mut capture = Processed::new(capture, LevelMeter::new(SAMPLE_RATE));
+    let mut detector = DtmfDetector::new(SAMPLE_RATE);
+
+    writeln!(stdout, "Listening at {} Hz", SAMPLE_RATE).unwrap();
+
+    let mut buf = [0_i16; PERIOD_FRAMES * NUM_CHANNELS];
+    let mut periods = 0;
+
+    loop {
+        capture.fill(&mut buf);
+        detector.process_frames(&buf, |event| match event {
+            DtmfEvent::Pressed(digit) => writeln!(stdout, "{}", digit.as_char()).unwrap(),
+            DtmfEvent::Released(_) => (),
+        });
+
+        periods += 1;
+        if periods == REPORT_PERIODS {
+            periods = 0;
+            let meter = &mut capture.processor;
+            for (ch, level) in meter.channels().iter().enumerate() {
+                writeln!(
+                    stdout,
+                    "  ch{} peak {:.1} dBFS, rms {:.1} dBFS",
+                    ch,
+                    level.peak_dbfs(),
+                    level.rms_dbfs()
+                )
+                .unwrap();
+            }
+            let clips = meter.take_clips();
+            if clips != 0 {
+                writeln!(stdout, "  clipped {} samples", clips).unwrap();
+            }
+            let overruns = capture.source.overruns();
+            if overruns != 0 {
+                writeln!(stdout, "  {} overruns", overruns).unwrap();
+            }
+        }
+    }
+}
+
+#[exception]
+fn HardFault(ef: &ExceptionFrame) -> ! {
+    panic!("HardFault at {:#?}", ef);
+}
+
+#[exception]
+fn DefaultHandler(irqn: i16) {
+    panic!("Unhandled exception (IRQn = {})", irqn);
+}
@@ -0,0 +1,141 @@
+// net::link::Mac for the STM32F429's Ethernet MAC and the board's LAN8742A
+//
+// Shared by the Ethernet examples. Works on the registers, stm32_eth::Eth
+// can't restart its DMA and the IP examples lend it to the smoltcp
+// interface for good. Only reads the PHY, which Eth::status() would too,
+// so don't use both.
+
+use crate::hal::stm32::{self, ETHERNET_DMA, ETHERNET_MAC, ETHERNET_MMC};
+use net::link::{Errors, Link, Mac, Process};
+
+/// SMI address of the PHY
+const PHY_ADDRESS: u32 = 0;
+
+/// PHY basic status register
+const PHY_BSR: u32 = 1;
+const BSR_LINK_STATUS: u32 = 1 << 2;
+
+/// PHY special control/status register, what autonegotiation settled on
+const PHY_SCSR: u32 = 31;
+const SCSR_AUTONEGOTIATION_DONE: u32 = 1 << 12;
+const SCSR_100M: u32 = 1 << 3;
+const SCSR_FULL_DUPLEX: u32 = 1 << 4;
+
+/// MACMIIAR
+const MIIAR_PA_SHIFT: u32 = 11;
+const MIIAR_MR_SHIFT: u32 = 6;
+const MIIAR_CR_MASK: u32 = 0b111 << 2;
+const MIIAR_MB: u32 = 1;
+
+/// DMASR process states
+const DMASR_RPS_SHIFT: u32 = 17;
+const DMASR_TPS_SHIFT: u32 = 20;
+const RPS_SUSPENDED: u32 = 0b100;
+const TPS_SUSPENDED: u32 = 0b110;
+
+/// DMAOMR start/stop receive and transmit
+const DMAOMR_SR: u32 = 1 << 1;
+const DMAOMR_ST: u32 = 1 << 13;
+
+/// DMAMFBOCR missed frame and FIFO overflow counts, cleared by reading
+const MFBOCR_MFC_MASK: u32 = 0xFFFF;
+const MFBOCR_MFA_SHIFT: u32 = 17;
+const MFBOCR_MFA_MASK: u32 = 0x7FF;
+
+/// MMCCR counters reset on read
+const MMCCR_CROR: u32 = 1 << 2;
+
+pub struct EthMac {
+    mac: ETHERNET_MAC,
+    dma: ETHERNET_DMA,
+    mmc: ETHERNET_MMC,
+}
+
+impl EthMac {
+    /// After stm32_eth::Eth::new()
+    pub fn new(mmc: ETHERNET_MMC) -> Self {
+        mmc.mmccr
+            .modify(|r, w| unsafe { w.bits(r.bits() | MMCCR_CROR) });
+        // Eth keeps the MAC and DMA, this only reads the PHY and poll
+        // demands or restarts the DMA
+        let p = unsafe { stm32::Peripherals::steal() };
+        EthMac {
+            mac: p.ETHERNET_MAC,
+            dma: p.ETHERNET_DMA,
+            mmc,
+        }
+    }
+
+    fn read_phy(&mut self, register: u32) -> u32 {
+        // Keep the clock range Eth set
+        let clock_range = self.mac.macmiiar.read().bits() & MIIAR_CR_MASK;
+        self.mac.macmiiar.write(|w| unsafe {
+            w.bits(
+                PHY_ADDRESS << MIIAR_PA_SHIFT | register << MIIAR_MR_SHIFT | clock_range | MIIAR_MB,
+            )
+        });
+        while self.mac.macmiiar.read().bits() & MIIAR_MB != 0 {}
+        self.mac.macmiidr.read().bits() & 0xFFFF
+    }
+
+    fn process_state(&self, shift: u32) -> u32 {
+        self.dma.dmasr.read().bits() >> shift & 0b111
+    }
+}
+
+impl Mac for EthMac {
+    fn link(&mut self) -> Option<Link> {
+        // The link status latches low, a drop shows for one read
+        if self.read_phy(PHY_BSR) & BSR_LINK_STATUS == 0 {
+            return None;
+        }
+        let scsr = self.read_phy(PHY_SCSR);
+        if scsr & SCSR_AUTONEGOTIATION_DONE == 0 {
+            return None;
+        }
+        Some(Link {
+            speed: if scsr & SCSR_100M != 0 { 100 } else { 10 },
+            full_duplex: scsr & SCSR_FULL_DUPLEX != 0,
+        })
+    }
+
+    fn rx_process(&mut self) -> Process {
+        match self.process_state(DMASR_RPS_SHIFT) {
+            0 => Process::Stopped,
+            RPS_SUSPENDED => Process::Suspended,
+            _ => Process::Running,
+        }
+    }
+
+    fn tx_process(&mut self) -> Process {
+        match self.process_state(DMASR_TPS_SHIFT) {
+            0 => Process::Stopped,
+            TPS_SUSPENDED => Process::Suspended,
+            _ => Process::Running,
+        }
+    }
+
+    fn restart_rx(&mut self) {
+        self.dma
+            .dmaomr
+            .modify(|r, w| unsafe { w.bits(r.bits() | DMAOMR_SR) });
+        self.dma.dmarpdr.write(|w| unsafe { w.bits(1) });
+    }
+
+    fn restart_tx(&mut self) {
+        self.dma
+            .dmaomr
+            .modify(|r, w| unsafe { w.bits(r.bits() | DMAOMR_ST) });
+        self.dma.dmatpdr.write(|w| unsafe { w.bits(1) });
+    }
+
+    fn errors(&mut self) -> Errors {
+        let mfbocr = self.dma.dmamfbocr.read().bits();
+        Errors {
+            rx_crc: self.mmc.mmcrfcecr.read().bits(),
+            rx_alignment: self.mmc.mmcrfaecr.read().bits(),
+            rx_missed: mfbocr & MFBOCR_MFC_MASK,
+            rx_overflow: mfbocr >> MFBOCR_MFA_SHIFT & MFBOCR_MFA_MASK,
+        }
+    }
+}
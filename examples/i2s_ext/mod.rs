@@ -0,0 +1,113 @@
+// Capture from the WM8960 through I2S2ext, the full-duplex extension of I2S2
+//
+// Shared by the capture examples. The HAL's I2s only transmits, I2S2ext
+// shares its clocks and receives the codec's ADCDAT on PC2 as a slave, in
+// the frame format the HAL set up. PB14, the other I2S2ext_SD pin, is the
+// red LED on this board. Works on the registers and feeds I2S2's transmit
+// buffer word for word with the receive side, so don't play through the
+// Wm8960 while a transfer is running.
+
+use crate::hal::gpio::{gpioc::PC2, Alternate, AF6};
+use crate::hal::stm32::{self, I2S2EXT, SPI2};
+use wm8960::audio::AudioSource;
+
+/// SR
+const SR_RXNE: u32 = 1;
+const SR_TXE: u32 = 1 << 1;
+/// The word received is the right channel's
+const SR_CHSIDE: u32 = 1 << 2;
+const SR_OVR: u32 = 1 << 6;
+
+/// I2SCFGR channel and data length, clock polarity, standard and PCM sync
+const I2SCFGR_FORMAT_MASK: u32 = 0b1011_1111;
+const I2SCFGR_SLAVE_RX: u32 = 0b01 << 8;
+const I2SCFGR_I2SE: u32 = 1 << 10;
+const I2SCFGR_I2SMOD: u32 = 1 << 11;
+
+pub struct I2sExt {
+    spi: SPI2,
+    ext: I2S2EXT,
+    overruns: u32,
+}
+
+impl I2sExt {
+    /// After the Wm8960 is set up with the I2s and `enable_capture`
+    pub fn new(ext: I2S2EXT, _sd: PC2<Alternate<AF6>>) -> Self {
+        // The Wm8960 keeps I2S2, this only reads its format and writes its
+        // data register
+        let p = unsafe { stm32::Peripherals::steal() };
+        let spi = p.SPI2;
+        let format = spi.i2scfgr.read().bits() & I2SCFGR_FORMAT_MASK;
+        ext.i2scfgr
+            .write(|w| unsafe { w.bits(I2SCFGR_I2SMOD | I2SCFGR_SLAVE_RX | format) });
+        ext.i2scfgr
+            .modify(|r, w| unsafe { w.bits(r.bits() | I2SCFGR_I2SE) });
+        I2sExt {
+            spi,
+            ext,
+            overruns: 0,
+        }
+    }
+
+    /// Receive overruns, the frame in progress is dropped on each
+    pub fn overruns(&self) -> u32 {
+        self.overruns
+    }
+
+    /// Capture interleaved stereo frames into `rx` while playing `tx`,
+    /// silence once `tx` runs out. Blocks until `rx` is full.
+    pub fn transfer(&mut self, tx: &[i16], rx: &mut [i16]) {
+        // The clocks only run while the master is enabled
+        if self.spi.i2scfgr.read().bits() & I2SCFGR_I2SE == 0 {
+            self.spi
+                .i2scfgr
+                .modify(|r, w| unsafe { w.bits(r.bits() | I2SCFGR_I2SE) });
+        }
+
+        let mut tx = tx.iter();
+        let mut i = 0;
+        while i < rx.len() {
+            if self.spi.sr.read().bits() & SR_TXE != 0 {
+                let word = tx.next().cloned().unwrap_or(0) as u16;
+                self.spi.dr.write(|w| unsafe { w.bits(u32::from(word)) });
+            }
+
+            let sr = self.ext.sr.read().bits();
+            if sr & (SR_RXNE | SR_OVR) == 0 {
+                continue;
+            }
+            let word = self.ext.dr.read().bits() as u16 as i16;
+            if sr & SR_OVR != 0 {
+                // Cleared by reading DR, then SR
+                self.ext.sr.read();
+                self.overruns = self.overruns.wrapping_add(1);
+                i -= i % 2;
+                continue;
+            }
+
+            // Frames start on the left channel
+            let right = sr & SR_CHSIDE != 0;
+            if right != (i % 2 == 1) {
+                i -= i % 2;
+                if right {
+                    continue;
+                }
+            }
+            rx[i] = word;
+            i += 1;
+        }
+    }
+
+    /// Capture interleaved stereo frames, playing silence
+    pub fn read_samples(&mut self, rx: &mut [i16]) {
+        self.transfer(&[], rx);
+    }
+}
+
+/// The capture never runs out
+impl AudioSource for I2sExt {
+    fn fill(&mut self, buf: &mut [i16]) -> usize {
+        self.read_samples(buf);
+        buf.len()
+    }
+}
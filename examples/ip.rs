@@ -15,17 +15,128 @@ use core::fmt::Write;
 use cortex_m::asm;
 use cortex_m::interrupt::Mutex;
 use cortex_m_rt::{entry, exception, ExceptionFrame};
-use smoltcp::iface::{EthernetInterfaceBuilder, NeighborCache};
-use smoltcp::socket::{SocketSet, TcpSocket, TcpSocketBuffer};
+use eth_mac::EthMac;
+use net::dhcp::{self, DhcpClient, DhcpDevice, Event, Lease};
+use net::http::{Method, Request, Response, Route, Server, Slot, Status};
+use net::link::{self, Link, LinkManager};
+use smoltcp::iface::{EthernetInterfaceBuilder, NeighborCache, Routes};
+use smoltcp::socket::{RawPacketMetadata, RawSocketBuffer, SocketSet, TcpSocketBuffer};
 use smoltcp::time::Instant;
-use smoltcp::wire::{EthernetAddress, IpAddress, IpCidr, Ipv4Address};
+use smoltcp::wire::{EthernetAddress, IpCidr, Ipv4Cidr};
 use stm32_eth::{Eth, RingEntry};
 
+mod eth_mac;
+
 const SRC_MAC: [u8; 6] = [0x00, 0x00, 0xDE, 0xAD, 0xBE, 0xEF];
 
 static TIME: Mutex<RefCell<u64>> = Mutex::new(RefCell::new(0));
 static ETH_PENDING: Mutex<RefCell<bool>> = Mutex::new(RefCell::new(false));
 
+/// What the HTTP handlers report
+struct Board {
+    time: u64,
+    address: Ipv4Cidr,
+    lease: Option<Lease>,
+    link: Option<Link>,
+    link_counters: link::Counters,
+    requests: u32,
+}
+
+static ROUTES: &[Route<Board>] = &[
+    Route {
+        method: Method::GET,
+        path: "/",
+        handler: status_page,
+    },
+    Route {
+        method: Method::GET,
+        path: "/status",
+        handler: status_json,
+    },
+];
+
+fn status_page(board: &mut Board, _request: &Request, response: &mut Response) {
+    board.requests += 1;
+    response.start(Status::Ok, "text/html");
+    let _ = write!(
+        response,
+        "<html><head><title>nucleo-f429zi</title></head><body>\
+         <h1>nucleo-f429zi</h1><table>\
+         <tr><td>Uptime</td><td>{} s</td></tr>\
+         <tr><td>Address</td><td>{}</td></tr>",
+        board.time / 1000,
+        board.address
+    );
+    if let Some(lease) = board.lease {
+        if let Some(router) = lease.router {
+            let _ = write!(response, "<tr><td>Router</td><td>{}</td></tr>", router);
+        }
+        let _ = write!(
+            response,
+            "<tr><td>DHCP server</td><td>{}</td></tr>",
+            lease.server
+        );
+    }
+    match board.link {
+        Some(link) => {
+            let _ = write!(
+                response,
+                "<tr><td>Link</td><td>{} Mbps {}</td></tr>",
+                link.speed,
+                if link.full_duplex { "FD" } else { "HD" }
+            );
+        }
+        None => {
+            let _ = write!(response, "<tr><td>Link</td><td>down</td></tr>");
+        }
+    }
+    let counters = &board.link_counters;
+    let _ = write!(
+        response,
+        "<tr><td>Link downs</td><td>{}</td></tr>\
+         <tr><td>RX CRC errors</td><td>{}</td></tr>\
+         <tr><td>RX missed</td><td>{}</td></tr>\
+         <tr><td>RX restarts</td><td>{}</td></tr>",
+        counters.downs, counters.errors.rx_crc, counters.errors.rx_missed, counters.rx_restarts
+    );
+    let _ = writeln!(
+        response,
+        "<tr><td>Requests</td><td>{}</td></tr></table></body></html>",
+        board.requests
+    );
+}
+
+fn status_json(board: &mut Board, _request: &Request, response: &mut Response) {
+    board.requests += 1;
+    response.start(Status::Ok, "application/json");
+    let _ = write!(
+        response,
+        "{{\"uptime_ms\":{},\"address\":\"{}\",\"dhcp\":{},",
+        board.time,
+        board.address,
+        board.lease.is_some()
+    );
+    let _ = match board.link {
+        Some(link) => write!(
+            response,
+            "\"link\":{{\"speed\":{},\"full_duplex\":{}}},",
+            link.speed, link.full_duplex
+        ),
+        None => write!(response, "\"link\":null,"),
+    };
+    let counters = &board.link_counters;
+    let _ = write!(
+        response,
+        "\"link_downs\":{},\"rx_crc_errors\":{},\"rx_missed\":{},\"rx_restarts\":{},\
+         \"requests\":{}}}",
+        counters.downs,
+        counters.errors.rx_crc,
+        counters.errors.rx_missed,
+        counters.rx_restarts,
+        board.requests
+    );
+}
+
 #[entry]
 fn main() -> ! {
     let dp = stm32::Peripherals::take().expect("Failed to take stm32::Peripherals");
@@ -84,58 +195,152 @@ fn main() -> ! {
         &mut tx_ring[..],
     );
     eth.enable_interrupt(&mut cp.NVIC);
+    let mut mac = EthMac::new(dp.ETHERNET_MMC);
 
-    let local_addr = Ipv4Address::new(192, 168, 1, 39);
-    //let local_addr = Ipv4Address::new(10, 0, 0, 1);
-    let ip_addr = IpCidr::new(IpAddress::from(local_addr), 24);
-    let mut ip_addrs = [ip_addr];
-    let mut neighbor_storage = [None; 16];
-    let neighbor_cache = NeighborCache::new(&mut neighbor_storage[..]);
     let ethernet_addr = EthernetAddress(SRC_MAC);
-    let mut iface = EthernetInterfaceBuilder::new(&mut eth)
-        .ethernet_addr(ethernet_addr)
-        .ip_addrs(&mut ip_addrs[..])
-        .neighbor_cache(neighbor_cache)
-        .finalize();
-
-    let mut server_rx_buffer = [0; 2048];
-    let mut server_tx_buffer = [0; 2048];
-    let server_socket = TcpSocket::new(
-        TcpSocketBuffer::new(&mut server_rx_buffer[..]),
-        TcpSocketBuffer::new(&mut server_tx_buffer[..]),
-    );
-    let mut sockets_storage = [None, None];
+    // Used until DHCP binds a lease, or a static address:
+    // Ipv4Cidr::new(Ipv4Address::new(192, 168, 1, 39), 24)
+    let fallback = dhcp::link_local(ethernet_addr);
+    let mut ip_addrs = [IpCidr::Ipv4(fallback)];
+    let mut routes_storage = [None; 1];
+
+    let mut dhcp_rx_metadata = [RawPacketMetadata::EMPTY; 4];
+    let mut dhcp_tx_metadata = [RawPacketMetadata::EMPTY; 1];
+    // The set holds TCP sockets, which makes it require 'static packet buffers
+    let dhcp_rx_buffer = cortex_m::singleton!(: [u8; 4 * 576] = [0; 4 * 576]).unwrap();
+    let dhcp_tx_buffer =
+        cortex_m::singleton!(: [u8; dhcp::PACKET_LEN] = [0; dhcp::PACKET_LEN]).unwrap();
+    let mut http_rx_buffers = [[0; 1024]; 3];
+    let mut http_tx_buffers = [[0; 2048]; 3];
+    let mut sockets_storage = [None, None, None, None];
     let mut sockets = SocketSet::new(&mut sockets_storage[..]);
-    let server_handle = sockets.add(server_socket);
 
-    writeln!(stdout, "Ready, listening at {}", ip_addr).unwrap();
+    let time: u64 = cortex_m::interrupt::free(|cs| *TIME.borrow(cs).borrow());
+    let mut dhcp = DhcpClient::new(
+        &mut sockets,
+        RawSocketBuffer::new(&mut dhcp_rx_metadata[..], &mut dhcp_rx_buffer[..]),
+        RawSocketBuffer::new(&mut dhcp_tx_metadata[..], &mut dhcp_tx_buffer[..]),
+        ethernet_addr,
+        fallback,
+        Instant::from_millis(time as i64),
+    );
+
+    // One slot per concurrent connection
+    let [rx0, rx1, rx2] = &mut http_rx_buffers;
+    let [tx0, tx1, tx2] = &mut http_tx_buffers;
+    let mut slots = [
+        Slot::new(
+            &mut sockets,
+            TcpSocketBuffer::new(&mut rx0[..]),
+            TcpSocketBuffer::new(&mut tx0[..]),
+        ),
+        Slot::new(
+            &mut sockets,
+            TcpSocketBuffer::new(&mut rx1[..]),
+            TcpSocketBuffer::new(&mut tx1[..]),
+        ),
+        Slot::new(
+            &mut sockets,
+            TcpSocketBuffer::new(&mut rx2[..]),
+            TcpSocketBuffer::new(&mut tx2[..]),
+        ),
+    ];
+    let mut server = Server::new(80, ROUTES, &mut slots[..]);
+    let mut board = Board {
+        time,
+        address: fallback,
+        lease: None,
+        link: None,
+        link_counters: link::Counters::default(),
+        requests: 0,
+    };
+    let mut link_manager =
+        LinkManager::new(link::Config::default(), Instant::from_millis(time as i64));
+
+    writeln!(stdout, "Ready, listening at {} until DHCP binds", fallback).unwrap();
 
     loop {
-        let time: u64 = cortex_m::interrupt::free(|cs| *TIME.borrow(cs).borrow());
-        cortex_m::interrupt::free(|cs| {
-            let mut eth_pending = ETH_PENDING.borrow(cs).borrow_mut();
-            *eth_pending = false;
-        });
-        match iface.poll(&mut sockets, Instant::from_millis(time as i64)) {
-            Ok(true) => {
-                let mut socket = sockets.get::<TcpSocket>(server_handle);
-                if !socket.is_open() {
-                    socket
-                        .listen(80)
-                        .or_else(|e| writeln!(stdout, "TCP listen error: {:?}", e))
-                        .unwrap();
+        // Rebuilt whenever the link comes up
+        let mut neighbor_storage = [None; 16];
+        let neighbor_cache = NeighborCache::new(&mut neighbor_storage[..]);
+        let routes = Routes::new(&mut routes_storage[..]);
+        let mut iface = EthernetInterfaceBuilder::new(DhcpDevice::new(&mut eth))
+            .ethernet_addr(ethernet_addr)
+            .ip_addrs(&mut ip_addrs[..])
+            .neighbor_cache(neighbor_cache)
+            .routes(routes)
+            .finalize();
+
+        loop {
+            let time: u64 = cortex_m::interrupt::free(|cs| *TIME.borrow(cs).borrow());
+            cortex_m::interrupt::free(|cs| {
+                let mut eth_pending = ETH_PENDING.borrow(cs).borrow_mut();
+                *eth_pending = false;
+            });
+            let now = Instant::from_millis(time as i64);
+
+            match link_manager.poll(&mut mac, now) {
+                Some(link::Event::Up(link)) => {
+                    writeln!(
+                        stdout,
+                        "Ethernet: link up at {} Mbps/{}",
+                        link.speed,
+                        if link.full_duplex { "FD" } else { "HD" }
+                    )
+                    .unwrap();
+                    // For an empty neighbor cache, the hosts on the other
+                    // end of the cable may not be the ones from before
+                    break;
+                }
+                Some(link::Event::Down) => writeln!(stdout, "Ethernet: link down").unwrap(),
+                Some(link::Event::RxRestarted) => {
+                    writeln!(stdout, "Ethernet: RX restarted").unwrap()
+                }
+                Some(link::Event::TxRestarted) => {
+                    writeln!(stdout, "Ethernet: TX restarted").unwrap()
                 }
+                None => (),
+            }
+            board.link = link_manager.link();
+            board.link_counters = *link_manager.counters();
 
-                if socket.can_send() {
-                    write!(socket, "hello\n")
-                        .map(|_| {
-                            socket.close();
-                        })
-                        .or_else(|e| writeln!(stdout, "TCP send error: {:?}", e))
-                        .unwrap();
+            let event = dhcp.poll(&mut iface, &mut sockets, now);
+            match event {
+                Some(Event::Bound(lease)) => {
+                    writeln!(stdout, "DHCP bound {}", lease.address).unwrap();
+                    if let Some(router) = lease.router {
+                        writeln!(stdout, "  router {}", router).unwrap();
+                    }
+                    for dns in lease.dns_servers.iter().filter_map(|d| *d) {
+                        writeln!(stdout, "  dns {}", dns).unwrap();
+                    }
+                }
+                Some(Event::Renewed(lease)) => {
+                    writeln!(stdout, "DHCP renewed {}", lease.address).unwrap()
+                }
+                Some(Event::Expired(fallback)) => {
+                    writeln!(stdout, "DHCP lease expired, using {}", fallback).unwrap()
                 }
+                None => (),
             }
-            Ok(false) => {
+            if event.is_some() {
+                board.address = dhcp.address();
+                board.lease = dhcp.lease().cloned();
+            }
+
+            let processed = match iface.poll(&mut sockets, now) {
+                Ok(processed) => processed,
+                Err(e) => {
+                    // Ignore malformed packets
+                    writeln!(stdout, "Error: {:?}", e).unwrap();
+                    true
+                }
+            };
+
+            board.time = time;
+            server.poll(&mut sockets, &mut board);
+
+            if !processed {
                 // Sleep if no ethernet work is pending
                 cortex_m::interrupt::free(|cs| {
                     let eth_pending = ETH_PENDING.borrow(cs).borrow_mut();
@@ -145,11 +350,6 @@ fn main() -> ! {
                     }
                 });
             }
-            Err(e) =>
-            // Ignore malformed packets
-            {
-                writeln!(stdout, "Error: {:?}", e).unwrap()
-            }
         }
     }
 }
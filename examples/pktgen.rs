@@ -9,20 +9,82 @@ extern crate stm32f4xx_hal as hal;
 #[allow(unused_imports)]
 use panic_semihosting;
 
-use crate::hal::{prelude::*, serial::config::Config, serial::Serial, stm32, stm32::interrupt};
+use crate::hal::prelude::*;
+use crate::hal::serial::{config::Config, Event, Rx, Serial};
+use crate::hal::stm32::{self, interrupt, Interrupt, NVIC, USART3};
 use core::cell::RefCell;
 use core::fmt::Write;
 use cortex_m::asm;
 use cortex_m::interrupt::Mutex;
+use cortex_m::peripheral::syst::SystClkSource;
+use cortex_m::peripheral::SCB;
 use cortex_m_rt::{entry, exception, ExceptionFrame};
+use eth_mac::EthMac;
+use net::link::{self, LinkManager};
+use net::pktgen::{self, Analyzer, Command, Generator, Kind, Rate, Size};
+use smoltcp::time::Instant;
+use smoltcp::wire::EthernetAddress;
 use stm32_eth::{Eth, RingEntry};
 
+mod eth_mac;
+
 const SRC_MAC: [u8; 6] = [0x00, 0x00, 0xDE, 0xAD, 0xBE, 0xEF];
 const DST_MAC: [u8; 6] = [0x00, 0x00, 0xBE, 0xEF, 0xDE, 0xAD];
-const ETH_TYPE: [u8; 2] = [0x80, 0x00];
 
-static TIME: Mutex<RefCell<usize>> = Mutex::new(RefCell::new(0));
+/// What to send, e.g. UDP datagrams instead:
+///
+/// kind: Kind::Udp {
+///     src_addr: Ipv4Address([192, 168, 1, 39]),
+///     src_port: 9,
+///     dst_addr: Ipv4Address([192, 168, 1, 40]),
+///     dst_port: 9,
+/// },
+const CONFIG: pktgen::Config = pktgen::Config {
+    src: EthernetAddress(SRC_MAC),
+    dst: EthernetAddress(DST_MAC),
+    kind: Kind::Raw {
+        ethertype: pktgen::ETHERTYPE,
+    },
+    vlan: None,
+    size: Size::Fixed(1500),
+    rate: Rate::Mbps(50),
+    burst: 1,
+};
+
+/// Send the frames of the peer's stream back for it to measure the round
+/// trip
+const REFLECT: bool = true;
+
+/// Seconds between printing the counters unless set on the console
+const STATS_INTERVAL: u64 = 30;
+
+/// Longest console command
+const MAX_LINE_LEN: usize = 64;
+
+/// Milliseconds
+static TIME: Mutex<RefCell<u64>> = Mutex::new(RefCell::new(0));
 static ETH_PENDING: Mutex<RefCell<bool>> = Mutex::new(RefCell::new(false));
+static SERIAL_RX: Mutex<RefCell<Option<Rx<USART3>>>> = Mutex::new(RefCell::new(None));
+static SERIAL_INPUT: Mutex<RefCell<Input>> = Mutex::new(RefCell::new(Input {
+    bytes: [0; MAX_LINE_LEN],
+    len: 0,
+}));
+
+/// Bytes received on the console since the main loop last took them
+struct Input {
+    bytes: [u8; MAX_LINE_LEN],
+    len: usize,
+}
+
+/// Traffic since the counters were last printed
+#[derive(Default)]
+struct Rates {
+    since: u64,
+    rx_bytes: usize,
+    rx_pkts: usize,
+    tx_bytes: usize,
+    tx_pkts: usize,
+}
 
 #[entry]
 fn main() -> ! {
@@ -30,19 +92,20 @@ fn main() -> ! {
     let mut cp =
         cortex_m::peripheral::Peripherals::take().expect("Failed to take cortex_m::Peripherals");
 
-    setup_systick(&mut cp.SYST);
     stm32_eth::setup(&dp.RCC, &dp.SYSCFG);
 
     // Set up the system clock. We want to run at 48MHz for this one.
     let rcc = dp.RCC.constrain();
     let clocks = rcc.cfgr.sysclk(48.mhz()).freeze();
 
+    setup_systick(&mut cp.SYST, clocks.sysclk().0);
+
     // Setup USART3
     let gpiod = dp.GPIOD.split();
     let pin_tx = gpiod.pd8.into_alternate_af7();
     let pin_rx = gpiod.pd9.into_alternate_af7();
 
-    let serial = Serial::usart3(
+    let mut serial = Serial::usart3(
         dp.USART3,
         (pin_tx, pin_rx),
         Config {
@@ -53,19 +116,16 @@ fn main() -> ! {
     )
     .unwrap();
 
-    let (mut stdout, _rx) = serial.split();
+    serial.listen(Event::Rxne);
+    let (mut stdout, rx) = serial.split();
+    cortex_m::interrupt::free(|cs| SERIAL_RX.borrow(cs).replace(Some(rx)));
+    NVIC::unpend(Interrupt::USART3);
+    unsafe {
+        NVIC::unmask(Interrupt::USART3);
+    }
 
     writeln!(stdout, "Initializing").unwrap();
 
-    if !stm32::SYST::is_precise() {
-        writeln!(
-            stdout,
-            "Warning: SYSTICK with source {:?} is not precise",
-            cp.SYST.get_clock_source()
-        )
-        .unwrap();
-    }
-
     let gpioa = dp.GPIOA.split();
     let gpiob = dp.GPIOB.split();
     let gpioc = dp.GPIOC.split();
@@ -85,65 +145,105 @@ fn main() -> ! {
         &mut tx_ring[..],
     );
     eth.enable_interrupt(&mut cp.NVIC);
+    let mut mac = EthMac::new(dp.ETHERNET_MMC);
+    let mut link_manager = LinkManager::new(link::Config::default(), Instant::from_millis(0));
+
+    let mut generator = Generator::new(CONFIG).expect("Bad pktgen config");
+    let mut analyzer = Analyzer::new();
+    let mut frame = [0; 1536];
+    let mut running = true;
 
     // Main loop
-    let mut last_stats_time = 0usize;
-    let mut rx_bytes = 0usize;
-    let mut rx_pkts = 0usize;
-    let mut tx_bytes = 0usize;
-    let mut tx_pkts = 0usize;
-    let mut last_status = None;
+    let mut stats_interval = STATS_INTERVAL;
+    let mut rates = Rates::default();
+    let mut line = [0; MAX_LINE_LEN];
+    let mut line_len = 0;
 
-    writeln!(stdout, "Starting").unwrap();
+    writeln!(stdout, "Starting\n{}", pktgen::USAGE).unwrap();
 
     loop {
-        let time: usize = cortex_m::interrupt::free(|cs| *TIME.borrow(cs).borrow());
+        let time = cortex_m::interrupt::free(|cs| *TIME.borrow(cs).borrow());
 
-        // Print stats every 30 seconds
-        if time >= last_stats_time + 30 {
-            let t = time - last_stats_time;
-            writeln!(
-                stdout,
-                "T={}\tRx:\t{} KB/s\t{} pps\tTx:\t{} KB/s\t{} pps",
-                time,
-                rx_bytes / 1024 / t,
-                rx_pkts / t,
-                tx_bytes / 1024 / t,
-                tx_pkts / t
-            )
-            .unwrap();
-
-            // Reset
-            rx_bytes = 0;
-            rx_pkts = 0;
-            tx_bytes = 0;
-            tx_pkts = 0;
-            last_stats_time = time;
+        // Print stats every interval
+        if stats_interval != 0 && time >= rates.since + stats_interval * 1000 {
+            print_stats(&mut stdout, time, &mut rates, &analyzer, &link_manager);
         }
 
-        // Link change detection
-        let status = eth.status();
-        if last_status
-            .map(|last_status| last_status != status)
-            .unwrap_or(true)
-        {
-            if !status.link_detected() {
-                writeln!(stdout, "Ethernet: no link detected").unwrap();
-            } else {
-                writeln!(
-                    stdout,
-                    "Ethernet: link detected with {} Mbps/{}",
-                    status.speed(),
-                    match status.is_full_duplex() {
-                        Some(true) => "FD",
-                        Some(false) => "HD",
-                        None => "?",
+        // Console input, echoed back
+        let mut input = [0; MAX_LINE_LEN];
+        let num_input = cortex_m::interrupt::free(|cs| {
+            let mut pending = SERIAL_INPUT.borrow(cs).borrow_mut();
+            let len = pending.len;
+            input[..len].copy_from_slice(&pending.bytes[..len]);
+            pending.len = 0;
+            len
+        });
+        for &byte in &input[..num_input] {
+            match byte {
+                b'\r' | b'\n' => {
+                    writeln!(stdout).unwrap();
+                    let command = core::str::from_utf8(&line[..line_len])
+                        .ok()
+                        .and_then(Command::parse);
+                    let empty = line_len == 0;
+                    line_len = 0;
+                    let mut config = *generator.config();
+                    match command {
+                        Some(Command::Start) => running = true,
+                        Some(Command::Stop) => running = false,
+                        Some(Command::Stats) => {
+                            print_stats(&mut stdout, time, &mut rates, &analyzer, &link_manager)
+                        }
+                        Some(Command::Reset) => {
+                            analyzer.reset();
+                            rates = Rates {
+                                since: time,
+                                ..Rates::default()
+                            };
+                        }
+                        Some(Command::Src(address)) => config.src = address,
+                        Some(Command::Dst(address)) => config.dst = address,
+                        Some(Command::Size(size)) => config.size = size,
+                        Some(Command::Rate(rate)) => config.rate = rate,
+                        Some(Command::Burst(burst)) => config.burst = burst,
+                        Some(Command::Interval(seconds)) => stats_interval = u64::from(seconds),
+                        None if empty => (),
+                        None => write!(stdout, "{}", pktgen::USAGE).unwrap(),
+                    }
+                    if config != *generator.config() {
+                        match generator.set_config(config) {
+                            Ok(()) => writeln!(stdout, "{:?}", config).unwrap(),
+                            Err(e) => writeln!(stdout, "Error: {:?}", e).unwrap(),
+                        }
                     }
-                )
-                .unwrap();
+                }
+                // Backspace or delete
+                0x08 | 0x7F if line_len > 0 => {
+                    line_len -= 1;
+                    write!(stdout, "\x08 \x08").unwrap();
+                }
+                b' '..=b'~' if line_len < line.len() => {
+                    line[line_len] = byte;
+                    line_len += 1;
+                    write!(stdout, "{}", byte as char).unwrap();
+                }
+                _ => (),
             }
+        }
 
-            last_status = Some(status);
+        // Link changes and DMA stalls
+        match link_manager.poll(&mut mac, Instant::from_millis(time as i64)) {
+            Some(link::Event::Up(link)) => writeln!(
+                stdout,
+                "Ethernet: link up at {} Mbps/{}",
+                link.speed,
+                if link.full_duplex { "FD" } else { "HD" }
+            )
+            .unwrap(),
+            Some(link::Event::Down) => writeln!(stdout, "Ethernet: link down").unwrap(),
+            Some(link::Event::RxRestarted) => writeln!(stdout, "RX restarted").unwrap(),
+            Some(link::Event::TxRestarted) => writeln!(stdout, "TX restarted").unwrap(),
+            None => (),
         }
 
         cortex_m::interrupt::free(|cs| {
@@ -155,10 +255,20 @@ fn main() -> ! {
         {
             let mut recvd = 0usize;
             while let Ok(pkt) = eth.recv_next() {
-                rx_bytes += pkt.len();
-                rx_pkts += 1;
+                rates.rx_bytes += pkt.len();
+                rates.rx_pkts += 1;
+                let len = pkt.len().min(frame.len());
+                frame[..len].copy_from_slice(&pkt[..len]);
                 pkt.free();
 
+                let forward = analyzer
+                    .receive(&frame[..len], now_us())
+                    .map_or(false, |header| !header.reflected);
+                if REFLECT && forward && pktgen::reflect(&mut frame[..len]) {
+                    // Dropped if the ring is full, the peer counts it lost
+                    let _ = eth.send(len, |buf| buf.copy_from_slice(&frame[..len]));
+                }
+
                 recvd += 1;
                 if recvd > 16 {
                     // Break arbitrarily to process tx eventually
@@ -166,25 +276,20 @@ fn main() -> ! {
                 }
             }
         }
-        if !eth.rx_is_running() {
-            writeln!(stdout, "RX stopped").unwrap();
-        }
 
-        // Fill tx queue
-        const SIZE: usize = 1500;
-        if status.link_detected() {
-            let r = eth.send(SIZE, |buf| {
-                buf[0..6].copy_from_slice(&DST_MAC);
-                buf[6..12].copy_from_slice(&SRC_MAC);
-                buf[12..14].copy_from_slice(&ETH_TYPE);
-            });
-
-            match r {
-                Ok(()) => {
-                    tx_bytes += SIZE;
-                    tx_pkts += 1;
+        // Fill tx queue with what's due
+        if running && link_manager.is_up() {
+            while let Some(len) = generator.poll(now_us()) {
+                if eth
+                    .send(len, |buf| {
+                        generator.write(buf, now_us());
+                    })
+                    .is_err()
+                {
+                    break;
                 }
-                _ => (),
+                rates.tx_bytes += len;
+                rates.tx_pkts += 1;
             }
         }
 
@@ -197,18 +302,62 @@ fn main() -> ! {
     }
 }
 
-fn setup_systick(syst: &mut stm32::SYST) {
-    syst.set_reload(100 * stm32::SYST::get_ticks_per_10ms());
+/// Rates since the counters were last printed at `time`, in milliseconds,
+/// the analyzer's counters and the link's
+fn print_stats<W: Write>(
+    w: &mut W,
+    time: u64,
+    rates: &mut Rates,
+    analyzer: &Analyzer,
+    link_manager: &LinkManager,
+) {
+    let ms = (time - rates.since).max(1) as usize;
+    writeln!(
+        w,
+        "T={}\tRx:\t{} KB/s\t{} pps\tTx:\t{} KB/s\t{} pps",
+        time / 1000,
+        rates.rx_bytes * 1000 / 1024 / ms,
+        rates.rx_pkts * 1000 / ms,
+        rates.tx_bytes * 1000 / 1024 / ms,
+        rates.tx_pkts * 1000 / ms
+    )
+    .unwrap();
+    writeln!(w, "Received {:?}", analyzer.received.counters).unwrap();
+    writeln!(w, "  one-way {}", analyzer.received.latency).unwrap();
+    writeln!(w, "Returned {:?}", analyzer.returned.counters).unwrap();
+    writeln!(w, "  round trip {}", analyzer.returned.latency).unwrap();
+    writeln!(w, "Link {:?}", link_manager.link()).unwrap();
+    writeln!(w, "  {:?}", link_manager.counters()).unwrap();
+
+    *rates = Rates {
+        since: time,
+        ..Rates::default()
+    };
+}
+
+/// 1 ms ticks
+fn setup_systick(syst: &mut stm32::SYST, sysclk: u32) {
+    syst.set_clock_source(SystClkSource::Core);
+    syst.set_reload(sysclk / 1000 - 1);
     syst.clear_current();
     syst.enable_counter();
     syst.enable_interrupt();
+}
 
-    //if !stm32::SYST::is_precise() {
-    //    panic!(
-    //        "Warning: SYSTICK with source {:?} is not precise",
-    //        syst.get_clock_source()
-    //    )
-    //}
+/// Microseconds since boot, from the SysTick count down within the
+/// millisecond
+fn now_us() -> u64 {
+    cortex_m::interrupt::free(|cs| {
+        let mut ms = *TIME.borrow(cs).borrow();
+        let ticks = stm32::SYST::get_reload() + 1;
+        let mut current = stm32::SYST::get_current();
+        // Wrapped since the interrupts were masked
+        if SCB::is_pendst_pending() {
+            ms += 1;
+            current = stm32::SYST::get_current();
+        }
+        ms * 1000 + u64::from(ticks - 1 - current) * 1000 / u64::from(ticks)
+    })
 }
 
 #[exception]
@@ -231,6 +380,30 @@ fn ETH() {
     stm32_eth::eth_interrupt_handler(&p.ETHERNET_DMA);
 }
 
+#[interrupt]
+fn USART3() {
+    cortex_m::interrupt::free(|cs| {
+        if let Some(rx) = SERIAL_RX.borrow(cs).borrow_mut().as_mut() {
+            match rx.read() {
+                Ok(byte) => {
+                    let mut input = SERIAL_INPUT.borrow(cs).borrow_mut();
+                    if input.len < input.bytes.len() {
+                        let len = input.len;
+                        input.bytes[len] = byte;
+                        input.len += 1;
+                    }
+                }
+                Err(_) => {
+                    // Overrun or framing errors, reading the data register
+                    // after the status register the HAL read clears them
+                    let p = unsafe { stm32::Peripherals::steal() };
+                    let _ = p.USART3.dr.read();
+                }
+            }
+        }
+    })
+}
+
 #[exception]
 fn HardFault(ef: &ExceptionFrame) -> ! {
     panic!("HardFault at {:#?}", ef);
@@ -0,0 +1,271 @@
+// RTP audio from the network to the WM8960
+//
+// Listens on UDP port 5004 for PCMU, PCMA or L16 (static payload types, or
+// 96 as L16 at 48 kHz stereo) and plays the stream through the codec. The
+// blocking I2S writes pace the loop at the codec's clock, the jitter buffer
+// resamples to its 48.828 kHz and takes up the difference to the sender's.
+//
+// ffmpeg -re -i some.wav -ac 1 -ar 8000 -c:a pcm_mulaw -f rtp rtp://<address>:5004
+//
+// PB13, the I2S clock in the wm8960 example, is RMII TXD1 on this board so
+// the clock is on PB10 here.
+
+#![no_main]
+#![no_std]
+
+extern crate stm32f4xx_hal as hal;
+
+#[allow(unused_imports)]
+use panic_semihosting;
+
+use crate::hal::{
+    i2c::I2c,
+    i2s::{I2s, I2sStandard},
+    prelude::*,
+    serial::config::Config,
+    serial::Serial,
+    stm32,
+};
+use core::cell::RefCell;
+use core::fmt::Write;
+use cortex_m::interrupt::Mutex;
+use cortex_m::peripheral::syst::SystClkSource;
+use cortex_m_rt::{entry, exception, ExceptionFrame};
+use net::dhcp::{self, DhcpClient, DhcpDevice, Event};
+use net::jitter::{self, JitterBuffer};
+use net::rtp::{self, Encoding, Format, Receiver};
+use smoltcp::iface::{EthernetInterfaceBuilder, NeighborCache, Routes};
+use smoltcp::socket::{RawPacketMetadata, RawSocketBuffer, SocketSet};
+use smoltcp::socket::{UdpPacketMetadata, UdpSocketBuffer};
+use smoltcp::time::Instant;
+use smoltcp::wire::{EthernetAddress, IpCidr};
+use stm32_eth::{Eth, RingEntry};
+use wm8960::audio::{NUM_CHANNELS, SAMPLE_RATE};
+use wm8960::Wm8960;
+
+const SRC_MAC: [u8; 6] = [0x00, 0x00, 0xDE, 0xAD, 0xBE, 0xEF];
+
+/// Samples per jitter buffer slot, 20 ms of L16 at 48 kHz mono
+const SLOT_LEN: usize = 960;
+const NUM_SLOTS: usize = 16;
+
+/// Samples played per loop, 10 ms
+const PERIOD_LEN: usize = SAMPLE_RATE as usize / 100 * NUM_CHANNELS;
+
+/// Stats are printed this often
+const REPORT_MS: u64 = 5000;
+
+static TIME: Mutex<RefCell<u64>> = Mutex::new(RefCell::new(0));
+
+#[entry]
+fn main() -> ! {
+    let dp = stm32::Peripherals::take().expect("Failed to take stm32::Peripherals");
+    let mut cp =
+        cortex_m::peripheral::Peripherals::take().expect("Failed to take cortex_m::Peripherals");
+
+    stm32_eth::setup(&dp.RCC, &dp.SYSCFG);
+
+    let rcc = dp.RCC.constrain();
+    let clocks = rcc.cfgr.sysclk(180.mhz()).freeze();
+
+    setup_systick(&mut cp.SYST, clocks.sysclk().0);
+
+    let gpioa = dp.GPIOA.split();
+    let gpiob = dp.GPIOB.split();
+    let gpioc = dp.GPIOC.split();
+    let gpiod = dp.GPIOD.split();
+    let gpiog = dp.GPIOG.split();
+
+    let serial = Serial::usart3(
+        dp.USART3,
+        (
+            gpiod.pd8.into_alternate_af7(),
+            gpiod.pd9.into_alternate_af7(),
+        ),
+        Config {
+            baudrate: 115_200.bps(),
+            ..Default::default()
+        },
+        clocks,
+    )
+    .unwrap();
+    let (mut stdout, _rx) = serial.split();
+
+    writeln!(stdout, "Init Wm8960").unwrap();
+
+    let scl = gpiob.pb8.into_alternate_af4().set_open_drain();
+    let sda = gpiob.pb9.into_alternate_af4().set_open_drain();
+    let i2c = I2c::i2c1(dp.I2C1, (scl, sda), 100.khz(), clocks);
+
+    let i2s_ck = gpiob.pb10.into_alternate_af5();
+    let i2s_ws = gpiob.pb12.into_alternate_af5();
+    let i2s_sd = gpiob.pb15.into_alternate_af5();
+    let i2s_mck = gpioc.pc6.into_alternate_af5();
+    let i2s = I2s::i2s2(dp.SPI2, (i2s_sd, i2s_ck, i2s_ws, i2s_mck), clocks)
+        .into_master_output::<u16>(I2sStandard::Philips);
+
+    let mut codec = Wm8960::new(i2c, i2s).unwrap();
+
+    writeln!(stdout, "Enabling ethernet...").unwrap();
+
+    stm32_eth::setup_pins(
+        gpioa.pa1, gpioa.pa2, gpioa.pa7, gpiob.pb13, gpioc.pc1, gpioc.pc4, gpioc.pc5, gpiog.pg11,
+        gpiog.pg13,
+    );
+
+    // Polled once per period, packets queue here meanwhile
+    let mut rx_ring: [RingEntry<_>; 16] = Default::default();
+    let mut tx_ring: [RingEntry<_>; 4] = Default::default();
+    let mut eth = Eth::new(
+        dp.ETHERNET_MAC,
+        dp.ETHERNET_DMA,
+        SRC_MAC,
+        &mut rx_ring[..],
+        &mut tx_ring[..],
+    );
+
+    let ethernet_addr = EthernetAddress(SRC_MAC);
+    let fallback = dhcp::link_local(ethernet_addr);
+    let mut ip_addrs = [IpCidr::Ipv4(fallback)];
+    let mut neighbor_storage = [None; 16];
+    let neighbor_cache = NeighborCache::new(&mut neighbor_storage[..]);
+    let mut routes_storage = [None; 1];
+    let routes = Routes::new(&mut routes_storage[..]);
+    let mut iface = EthernetInterfaceBuilder::new(DhcpDevice::new(&mut eth))
+        .ethernet_addr(ethernet_addr)
+        .ip_addrs(&mut ip_addrs[..])
+        .neighbor_cache(neighbor_cache)
+        .routes(routes)
+        .finalize();
+
+    let mut dhcp_rx_metadata = [RawPacketMetadata::EMPTY; 4];
+    let mut dhcp_tx_metadata = [RawPacketMetadata::EMPTY; 1];
+    let mut dhcp_rx_buffer = [0; 4 * 576];
+    let mut dhcp_tx_buffer = [0; dhcp::PACKET_LEN];
+    let mut rtp_rx_metadata = [UdpPacketMetadata::EMPTY; 16];
+    let mut rtp_rx_buffer = [0; 16 * 1024];
+    let mut sockets_storage = [None, None];
+    let mut sockets = SocketSet::new(&mut sockets_storage[..]);
+
+    let time: u64 = cortex_m::interrupt::free(|cs| *TIME.borrow(cs).borrow());
+    let mut dhcp = DhcpClient::new(
+        &mut sockets,
+        RawSocketBuffer::new(&mut dhcp_rx_metadata[..], &mut dhcp_rx_buffer[..]),
+        RawSocketBuffer::new(&mut dhcp_tx_metadata[..], &mut dhcp_tx_buffer[..]),
+        ethernet_addr,
+        fallback,
+        Instant::from_millis(time as i64),
+    );
+
+    let mut jitter_storage = [0; NUM_SLOTS * SLOT_LEN];
+    let jitter = JitterBuffer::new(
+        &mut jitter_storage[..],
+        jitter::Config {
+            depth_ms: 60,
+            // The I2S frame rate, not a nominal 48 kHz, the drift
+            // compensation only covers the clocks' tolerances
+            output_rate: SAMPLE_RATE,
+            slot_len: SLOT_LEN,
+        },
+    );
+    let mut receiver = Receiver::new(
+        &mut sockets,
+        UdpSocketBuffer::new(&mut rtp_rx_metadata[..], &mut rtp_rx_buffer[..]),
+        UdpSocketBuffer::new(&mut [][..], &mut [][..]),
+        rtp::DEFAULT_PORT,
+        jitter,
+    );
+    receiver.set_payload_type(
+        rtp::DYNAMIC_PAYLOAD_TYPE,
+        Format {
+            encoding: Encoding::L16,
+            clock_rate: 48_000,
+            channels: 2,
+        },
+    );
+
+    writeln!(
+        stdout,
+        "Ready, RTP on port {} at {} until DHCP binds",
+        rtp::DEFAULT_PORT,
+        fallback
+    )
+    .unwrap();
+
+    let mut buf = [0_i16; PERIOD_LEN];
+    let mut next_report = time + REPORT_MS;
+
+    loop {
+        let time: u64 = cortex_m::interrupt::free(|cs| *TIME.borrow(cs).borrow());
+        let now = Instant::from_millis(time as i64);
+
+        match dhcp.poll(&mut iface, &mut sockets, now) {
+            Some(Event::Bound(lease)) | Some(Event::Renewed(lease)) => {
+                writeln!(stdout, "DHCP bound {}", lease.address).unwrap()
+            }
+            Some(Event::Expired(fallback)) => {
+                writeln!(stdout, "DHCP lease expired, using {}", fallback).unwrap()
+            }
+            None => (),
+        }
+
+        if let Err(e) = iface.poll(&mut sockets, now) {
+            // Ignore malformed packets
+            writeln!(stdout, "Error: {:?}", e).unwrap();
+        }
+
+        receiver.poll(&mut sockets, now);
+
+        // Silence while buffering, blocks until it's written
+        let len = receiver.fill(&mut buf);
+        codec.play_samples(&buf[..len]).unwrap();
+
+        if time >= next_report {
+            next_report = time + REPORT_MS;
+            let jb = receiver.jitter_buffer();
+            if let Some(format) = jb.format() {
+                let stats = receiver.stats();
+                writeln!(
+                    stdout,
+                    "{:?} {} Hz, playing {}, received {} lost {} late {} depth {} ms drift {} ppm",
+                    format.encoding,
+                    format.clock_rate,
+                    jb.is_playing(),
+                    stats.received,
+                    stats.lost,
+                    stats.late,
+                    stats.depth_ms,
+                    stats.drift_ppm
+                )
+                .unwrap();
+            }
+        }
+    }
+}
+
+/// 1 ms ticks
+fn setup_systick(syst: &mut stm32::SYST, sysclk: u32) {
+    syst.set_clock_source(SystClkSource::Core);
+    syst.set_reload(sysclk / 1000 - 1);
+    syst.clear_current();
+    syst.enable_counter();
+    syst.enable_interrupt();
+}
+
+#[exception]
+fn SysTick() {
+    cortex_m::interrupt::free(|cs| {
+        let mut time = TIME.borrow(cs).borrow_mut();
+        *time += 1;
+    })
+}
+
+#[exception]
+fn HardFault(ef: &ExceptionFrame) -> ! {
+    panic!("HardFault at {:#?}", ef);
+}
+
+#[exception]
+fn DefaultHandler(irqn: i16) {
+    panic!("Unhandled exception (IRQn = {})", irqn);
+}
@@ -0,0 +1,276 @@
+// RTP audio from the WM8960's input to the network
+//
+// Captures LINPUT1/RINPUT1 through I2S2ext, see i2s_ext, low-passes it for
+// PCMU and sends 20 ms packets to UDP port 5004 at DESTINATION, with sender
+// reports to 5005. The blocking capture reads pace the loop at the codec's
+// 48.828 kHz, the sender decimates from that to 8 kHz.
+//
+// ffplay -protocol_whitelist file,udp,rtp pcmu.sdp
+//
+// with pcmu.sdp as in net/examples/rtp_send_tap.rs, the board's address in
+// the c= line.
+//
+// The I2S clocks are wired as in the rtp_audio example, ADCDAT as in the
+// dtmf example.
+
+#![no_main]
+#![no_std]
+
+extern crate stm32f4xx_hal as hal;
+
+#[allow(unused_imports)]
+use panic_semihosting;
+
+use crate::hal::{
+    i2c::I2c,
+    i2s::{I2s, I2sStandard},
+    prelude::*,
+    serial::config::Config,
+    serial::Serial,
+    stm32,
+};
+use core::cell::RefCell;
+use core::fmt::Write;
+use cortex_m::interrupt::Mutex;
+use cortex_m::peripheral::syst::SystClkSource;
+use cortex_m_rt::{entry, exception, ExceptionFrame};
+use net::dhcp::{self, DhcpClient, DhcpDevice, Event};
+use net::rtp::{self, Format, Sender, SenderConfig};
+use smoltcp::iface::{EthernetInterfaceBuilder, NeighborCache, Routes};
+use smoltcp::socket::{RawPacketMetadata, RawSocketBuffer, SocketSet};
+use smoltcp::socket::{UdpPacketMetadata, UdpSocketBuffer};
+use smoltcp::time::Instant;
+use smoltcp::wire::{EthernetAddress, IpCidr, IpEndpoint, Ipv4Address};
+use stm32_eth::{Eth, RingEntry};
+use wm8960::audio::{AudioSource, Processed, NUM_CHANNELS, SAMPLE_RATE};
+use wm8960::biquad::{BiquadCascade, Coefficients, FilterType};
+use wm8960::Wm8960;
+
+mod i2s_ext;
+use i2s_ext::I2sExt;
+
+const SRC_MAC: [u8; 6] = [0x00, 0x00, 0xDE, 0xAD, 0xBE, 0xEF];
+
+/// Where the stream goes
+const DESTINATION: Ipv4Address = Ipv4Address([192, 168, 1, 100]);
+
+const CNAME: &str = "wm8960@nucleo-f429zi";
+
+/// Samples captured per loop, 10 ms
+const PERIOD_LEN: usize = SAMPLE_RATE as usize / 100 * NUM_CHANNELS;
+
+/// Stats are printed this often
+const REPORT_MS: u64 = 5000;
+
+static TIME: Mutex<RefCell<u64>> = Mutex::new(RefCell::new(0));
+
+#[entry]
+fn main() -> ! {
+    let dp = stm32::Peripherals::take().expect("Failed to take stm32::Peripherals");
+    let mut cp =
+        cortex_m::peripheral::Peripherals::take().expect("Failed to take cortex_m::Peripherals");
+
+    stm32_eth::setup(&dp.RCC, &dp.SYSCFG);
+
+    let rcc = dp.RCC.constrain();
+    let clocks = rcc.cfgr.sysclk(180.mhz()).freeze();
+
+    setup_systick(&mut cp.SYST, clocks.sysclk().0);
+
+    let gpioa = dp.GPIOA.split();
+    let gpiob = dp.GPIOB.split();
+    let gpioc = dp.GPIOC.split();
+    let gpiod = dp.GPIOD.split();
+    let gpiog = dp.GPIOG.split();
+
+    let serial = Serial::usart3(
+        dp.USART3,
+        (
+            gpiod.pd8.into_alternate_af7(),
+            gpiod.pd9.into_alternate_af7(),
+        ),
+        Config {
+            baudrate: 115_200.bps(),
+            ..Default::default()
+        },
+        clocks,
+    )
+    .unwrap();
+    let (mut stdout, _rx) = serial.split();
+
+    writeln!(stdout, "Init Wm8960").unwrap();
+
+    let scl = gpiob.pb8.into_alternate_af4().set_open_drain();
+    let sda = gpiob.pb9.into_alternate_af4().set_open_drain();
+    let i2c = I2c::i2c1(dp.I2C1, (scl, sda), 100.khz(), clocks);
+
+    let i2s_ck = gpiob.pb10.into_alternate_af5();
+    let i2s_ws = gpiob.pb12.into_alternate_af5();
+    let i2s_sd = gpiob.pb15.into_alternate_af5();
+    let i2s_mck = gpioc.pc6.into_alternate_af5();
+    let i2s = I2s::i2s2(dp.SPI2, (i2s_sd, i2s_ck, i2s_ws, i2s_mck), clocks)
+        .into_master_output::<u16>(I2sStandard::Philips);
+
+    let mut codec = Wm8960::new(i2c, i2s).unwrap();
+    codec.enable_capture().unwrap();
+
+    // Keep what the decimation would fold back out of PCMU's band
+    let format = Format::PCMU;
+    let cutoff = format.clock_rate as f32 * 0.425;
+    let mut lowpass = BiquadCascade::new();
+    for q in &[0.54, 1.31] {
+        let coeffs = Coefficients::design(FilterType::LowPass, cutoff, *q, 0.0, SAMPLE_RATE)
+            .expect("Bad low-pass");
+        lowpass.push(coeffs).unwrap();
+    }
+    let capture = I2sExt::new(dp.I2S2EXT, gpioc.pc2.into_alternate_af6());
+    let mut capture = Processed::new(capture, lowpass);
+
+    writeln!(stdout, "Enabling ethernet...").unwrap();
+
+    stm32_eth::setup_pins(
+        gpioa.pa1, gpioa.pa2, gpioa.pa7, gpiob.pb13, gpioc.pc1, gpioc.pc4, gpioc.pc5, gpiog.pg11,
+        gpiog.pg13,
+    );
+
+    let mut rx_ring: [RingEntry<_>; 8] = Default::default();
+    let mut tx_ring: [RingEntry<_>; 8] = Default::default();
+    let mut eth = Eth::new(
+        dp.ETHERNET_MAC,
+        dp.ETHERNET_DMA,
+        SRC_MAC,
+        &mut rx_ring[..],
+        &mut tx_ring[..],
+    );
+
+    let ethernet_addr = EthernetAddress(SRC_MAC);
+    let fallback = dhcp::link_local(ethernet_addr);
+    let mut ip_addrs = [IpCidr::Ipv4(fallback)];
+    let mut neighbor_storage = [None; 16];
+    let neighbor_cache = NeighborCache::new(&mut neighbor_storage[..]);
+    let mut routes_storage = [None; 1];
+    let routes = Routes::new(&mut routes_storage[..]);
+    let mut iface = EthernetInterfaceBuilder::new(DhcpDevice::new(&mut eth))
+        .ethernet_addr(ethernet_addr)
+        .ip_addrs(&mut ip_addrs[..])
+        .neighbor_cache(neighbor_cache)
+        .routes(routes)
+        .finalize();
+
+    let mut dhcp_rx_metadata = [RawPacketMetadata::EMPTY; 4];
+    let mut dhcp_tx_metadata = [RawPacketMetadata::EMPTY; 1];
+    let mut dhcp_rx_buffer = [0; 4 * 576];
+    let mut dhcp_tx_buffer = [0; dhcp::PACKET_LEN];
+    // A packet is sent every other period, the rest is slack for ARP
+    let mut rtp_tx_metadata = [UdpPacketMetadata::EMPTY; 4];
+    let mut rtp_tx_buffer = [0; 4 * 256];
+    let mut rtcp_tx_metadata = [UdpPacketMetadata::EMPTY; 1];
+    let mut rtcp_tx_buffer = [0; 128];
+    let mut sockets_storage = [None, None, None];
+    let mut sockets = SocketSet::new(&mut sockets_storage[..]);
+
+    let time: u64 = cortex_m::interrupt::free(|cs| *TIME.borrow(cs).borrow());
+    let mut dhcp = DhcpClient::new(
+        &mut sockets,
+        RawSocketBuffer::new(&mut dhcp_rx_metadata[..], &mut dhcp_rx_buffer[..]),
+        RawSocketBuffer::new(&mut dhcp_tx_metadata[..], &mut dhcp_tx_buffer[..]),
+        ethernet_addr,
+        fallback,
+        Instant::from_millis(time as i64),
+    );
+
+    let destination = IpEndpoint::new(DESTINATION.into(), rtp::DEFAULT_PORT);
+    // The SSRC should be random, the MAC and the boot time stand in
+    let ssrc = u32::from_be_bytes([SRC_MAC[2], SRC_MAC[3], SRC_MAC[4], SRC_MAC[5]]) ^ time as u32;
+    let mut sender = Sender::new(
+        &mut sockets,
+        UdpSocketBuffer::new(&mut [][..], &mut [][..]),
+        UdpSocketBuffer::new(&mut rtp_tx_metadata[..], &mut rtp_tx_buffer[..]),
+        rtp::DEFAULT_PORT,
+        destination,
+        SenderConfig::new(format, SAMPLE_RATE, ssrc),
+    )
+    .expect("Unsupported sender configuration");
+    sender.enable_rtcp(
+        &mut sockets,
+        UdpSocketBuffer::new(&mut [][..], &mut [][..]),
+        UdpSocketBuffer::new(&mut rtcp_tx_metadata[..], &mut rtcp_tx_buffer[..]),
+        CNAME,
+    );
+
+    writeln!(
+        stdout,
+        "Sending {:?} to {} from {} until DHCP binds",
+        format.encoding, destination, fallback
+    )
+    .unwrap();
+
+    let mut buf = [0_i16; PERIOD_LEN];
+    let mut next_report = time + REPORT_MS;
+
+    loop {
+        let time: u64 = cortex_m::interrupt::free(|cs| *TIME.borrow(cs).borrow());
+        let now = Instant::from_millis(time as i64);
+
+        match dhcp.poll(&mut iface, &mut sockets, now) {
+            Some(Event::Bound(lease)) | Some(Event::Renewed(lease)) => {
+                writeln!(stdout, "DHCP bound {}", lease.address).unwrap()
+            }
+            Some(Event::Expired(fallback)) => {
+                writeln!(stdout, "DHCP lease expired, using {}", fallback).unwrap()
+            }
+            None => (),
+        }
+
+        // Blocks for the period
+        capture.fill(&mut buf);
+        sender.send(&mut sockets, &buf);
+        sender.poll(&mut sockets, now);
+
+        if let Err(e) = iface.poll(&mut sockets, now) {
+            // Ignore malformed packets
+            writeln!(stdout, "Error: {:?}", e).unwrap();
+        }
+
+        if time >= next_report {
+            next_report = time + REPORT_MS;
+            let stats = sender.stats();
+            writeln!(
+                stdout,
+                "sent {} dropped {} reports {}, {} capture overruns",
+                stats.packets,
+                stats.dropped,
+                stats.reports,
+                capture.source.overruns()
+            )
+            .unwrap();
+        }
+    }
+}
+
+/// 1 ms ticks
+fn setup_systick(syst: &mut stm32::SYST, sysclk: u32) {
+    syst.set_clock_source(SystClkSource::Core);
+    syst.set_reload(sysclk / 1000 - 1);
+    syst.clear_current();
+    syst.enable_counter();
+    syst.enable_interrupt();
+}
+
+#[exception]
+fn SysTick() {
+    cortex_m::interrupt::free(|cs| {
+        let mut time = TIME.borrow(cs).borrow_mut();
+        *time += 1;
+    })
+}
+
+#[exception]
+fn HardFault(ef: &ExceptionFrame) -> ! {
+    panic!("HardFault at {:#?}", ef);
+}
+
+#[exception]
+fn DefaultHandler(irqn: i16) {
+    panic!("Unhandled exception (IRQn = {})", irqn);
+}
@@ -0,0 +1,361 @@
+// SIP phone on the WM8960
+//
+// Registers USER@DOMAIN with the registrar at PROXY once DHCP binds. The
+// user button calls CALLEE, answers a ringing call or hangs up. Calls play
+// the received stream through the codec and send its capture, both through
+// I2S2ext, see i2s_ext. The blocking transfers pace the loop at the codec's
+// 48.828 kHz, the jitter buffer resamples to it and the sender decimates
+// from it to the negotiated PCMU or PCMA.
+//
+// The I2S clocks are wired as in the rtp_audio example, ADCDAT as in the
+// dtmf example.
+
+#![no_main]
+#![no_std]
+
+extern crate stm32f4xx_hal as hal;
+
+#[allow(unused_imports)]
+use panic_semihosting;
+
+use crate::hal::{
+    i2c::I2c,
+    i2s::{I2s, I2sStandard},
+    prelude::*,
+    serial::config::Config,
+    serial::Serial,
+    stm32,
+};
+use core::cell::RefCell;
+use core::fmt::Write;
+use cortex_m::interrupt::Mutex;
+use cortex_m::peripheral::syst::SystClkSource;
+use cortex_m_rt::{entry, exception, ExceptionFrame};
+use net::dhcp::{self, DhcpClient, DhcpDevice};
+use net::jitter::{self, JitterBuffer};
+use net::rtp::{self, Format, Ptime, Receiver, Sender, SenderConfig};
+use net::sip::{self, UserAgent};
+use smoltcp::iface::{EthernetInterfaceBuilder, NeighborCache, Routes};
+use smoltcp::socket::{RawPacketMetadata, RawSocketBuffer, SocketSet};
+use smoltcp::socket::{UdpPacketMetadata, UdpSocketBuffer};
+use smoltcp::time::Instant;
+use smoltcp::wire::{EthernetAddress, IpCidr, IpEndpoint, Ipv4Address};
+use stm32_eth::{Eth, RingEntry};
+use wm8960::audio::{AudioProcessor, NUM_CHANNELS, SAMPLE_RATE};
+use wm8960::biquad::{BiquadCascade, Coefficients, FilterType};
+use wm8960::Wm8960;
+
+mod i2s_ext;
+use i2s_ext::I2sExt;
+
+const SRC_MAC: [u8; 6] = [0x00, 0x00, 0xDE, 0xAD, 0xBE, 0xEF];
+
+/// Registrar and outbound proxy
+const PROXY: Ipv4Address = Ipv4Address([192, 168, 1, 1]);
+const DOMAIN: &str = "192.168.1.1";
+const USER: &str = "nucleo";
+const PASSWORD: &str = "secret";
+/// Called with the button
+const CALLEE: &str = "alice";
+
+/// Samples per jitter buffer slot, 40 ms of PCMU or PCMA
+const SLOT_LEN: usize = 320;
+const NUM_SLOTS: usize = 16;
+
+/// Samples captured and played per loop, 10 ms
+const PERIOD_LEN: usize = SAMPLE_RATE as usize / 100 * NUM_CHANNELS;
+
+static TIME: Mutex<RefCell<u64>> = Mutex::new(RefCell::new(0));
+
+#[entry]
+fn main() -> ! {
+    let dp = stm32::Peripherals::take().expect("Failed to take stm32::Peripherals");
+    let mut cp =
+        cortex_m::peripheral::Peripherals::take().expect("Failed to take cortex_m::Peripherals");
+
+    stm32_eth::setup(&dp.RCC, &dp.SYSCFG);
+
+    let rcc = dp.RCC.constrain();
+    let clocks = rcc.cfgr.sysclk(180.mhz()).freeze();
+
+    setup_systick(&mut cp.SYST, clocks.sysclk().0);
+
+    let gpioa = dp.GPIOA.split();
+    let gpiob = dp.GPIOB.split();
+    let gpioc = dp.GPIOC.split();
+    let gpiod = dp.GPIOD.split();
+    let gpiog = dp.GPIOG.split();
+
+    let serial = Serial::usart3(
+        dp.USART3,
+        (
+            gpiod.pd8.into_alternate_af7(),
+            gpiod.pd9.into_alternate_af7(),
+        ),
+        Config {
+            baudrate: 115_200.bps(),
+            ..Default::default()
+        },
+        clocks,
+    )
+    .unwrap();
+    let (mut stdout, _rx) = serial.split();
+
+    // Active low, see the ssd1306 example
+    let btn = gpioc.pc13.into_pull_down_input();
+
+    writeln!(stdout, "Init Wm8960").unwrap();
+
+    let scl = gpiob.pb8.into_alternate_af4().set_open_drain();
+    let sda = gpiob.pb9.into_alternate_af4().set_open_drain();
+    let i2c = I2c::i2c1(dp.I2C1, (scl, sda), 100.khz(), clocks);
+
+    let i2s_ck = gpiob.pb10.into_alternate_af5();
+    let i2s_ws = gpiob.pb12.into_alternate_af5();
+    let i2s_sd = gpiob.pb15.into_alternate_af5();
+    let i2s_mck = gpioc.pc6.into_alternate_af5();
+    let i2s = I2s::i2s2(dp.SPI2, (i2s_sd, i2s_ck, i2s_ws, i2s_mck), clocks)
+        .into_master_output::<u16>(I2sStandard::Philips);
+
+    // Keeps the I2s, both directions go through I2S2ext from here on
+    let mut codec = Wm8960::new(i2c, i2s).unwrap();
+    codec.enable_capture().unwrap();
+    let mut i2s_ext = I2sExt::new(dp.I2S2EXT, gpioc.pc2.into_alternate_af6());
+
+    // Keep what the decimation would fold back out of the 8 kHz payloads
+    let cutoff = 8000.0 * 0.425;
+    let mut lowpass = BiquadCascade::new();
+    for q in &[0.54, 1.31] {
+        let coeffs = Coefficients::design(FilterType::LowPass, cutoff, *q, 0.0, SAMPLE_RATE)
+            .expect("Bad low-pass");
+        lowpass.push(coeffs).unwrap();
+    }
+
+    writeln!(stdout, "Enabling ethernet...").unwrap();
+
+    stm32_eth::setup_pins(
+        gpioa.pa1, gpioa.pa2, gpioa.pa7, gpiob.pb13, gpioc.pc1, gpioc.pc4, gpioc.pc5, gpiog.pg11,
+        gpiog.pg13,
+    );
+
+    // Polled once per period, packets queue here meanwhile
+    let mut rx_ring: [RingEntry<_>; 16] = Default::default();
+    let mut tx_ring: [RingEntry<_>; 8] = Default::default();
+    let mut eth = Eth::new(
+        dp.ETHERNET_MAC,
+        dp.ETHERNET_DMA,
+        SRC_MAC,
+        &mut rx_ring[..],
+        &mut tx_ring[..],
+    );
+
+    let ethernet_addr = EthernetAddress(SRC_MAC);
+    let fallback = dhcp::link_local(ethernet_addr);
+    let mut ip_addrs = [IpCidr::Ipv4(fallback)];
+    let mut neighbor_storage = [None; 16];
+    let neighbor_cache = NeighborCache::new(&mut neighbor_storage[..]);
+    let mut routes_storage = [None; 1];
+    let routes = Routes::new(&mut routes_storage[..]);
+    let mut iface = EthernetInterfaceBuilder::new(DhcpDevice::new(&mut eth))
+        .ethernet_addr(ethernet_addr)
+        .ip_addrs(&mut ip_addrs[..])
+        .neighbor_cache(neighbor_cache)
+        .routes(routes)
+        .finalize();
+
+    let mut dhcp_rx_metadata = [RawPacketMetadata::EMPTY; 4];
+    let mut dhcp_tx_metadata = [RawPacketMetadata::EMPTY; 1];
+    let mut dhcp_rx_buffer = [0; 4 * 576];
+    let mut dhcp_tx_buffer = [0; dhcp::PACKET_LEN];
+    let mut sip_rx_metadata = [UdpPacketMetadata::EMPTY; 4];
+    let mut sip_rx_buffer = [0; 4 * sip::MAX_MESSAGE_LEN];
+    let mut sip_tx_metadata = [UdpPacketMetadata::EMPTY; 4];
+    let mut sip_tx_buffer = [0; 4 * sip::MAX_MESSAGE_LEN];
+    let mut rtp_rx_metadata = [UdpPacketMetadata::EMPTY; 16];
+    let mut rtp_rx_buffer = [0; 16 * 512];
+    let mut rtp_tx_metadata = [UdpPacketMetadata::EMPTY; 4];
+    let mut rtp_tx_buffer = [0; 4 * 512];
+    let mut sockets_storage = [None, None, None, None];
+    let mut sockets = SocketSet::new(&mut sockets_storage[..]);
+
+    let time: u64 = cortex_m::interrupt::free(|cs| *TIME.borrow(cs).borrow());
+    let mut dhcp = DhcpClient::new(
+        &mut sockets,
+        RawSocketBuffer::new(&mut dhcp_rx_metadata[..], &mut dhcp_rx_buffer[..]),
+        RawSocketBuffer::new(&mut dhcp_tx_metadata[..], &mut dhcp_tx_buffer[..]),
+        ethernet_addr,
+        fallback,
+        Instant::from_millis(time as i64),
+    );
+
+    // Seeds should differ between phones and boots, the MAC and the boot
+    // time stand in
+    let seed = u32::from_be_bytes([SRC_MAC[2], SRC_MAC[3], SRC_MAC[4], SRC_MAC[5]]) ^ time as u32;
+    let config = sip::Config {
+        user: USER,
+        password: PASSWORD,
+        domain: DOMAIN,
+        proxy: IpEndpoint::new(PROXY.into(), sip::DEFAULT_PORT),
+        expires: 300,
+        rtp_port: rtp::DEFAULT_PORT,
+        ptime: Ptime::Ms20,
+    };
+    let mut ua = UserAgent::new(
+        &mut sockets,
+        UdpSocketBuffer::new(&mut sip_rx_metadata[..], &mut sip_rx_buffer[..]),
+        UdpSocketBuffer::new(&mut sip_tx_metadata[..], &mut sip_tx_buffer[..]),
+        sip::DEFAULT_PORT,
+        config,
+        seed,
+    );
+
+    // The receiver's socket comes first so it gets the packets for the port
+    // both are bound to, the far end sees RTP coming from where it sends it
+    let mut jitter_storage = [0; NUM_SLOTS * SLOT_LEN];
+    let jitter = JitterBuffer::new(
+        &mut jitter_storage[..],
+        jitter::Config {
+            output_rate: SAMPLE_RATE,
+            slot_len: SLOT_LEN,
+            ..jitter::Config::default()
+        },
+    );
+    let mut receiver = Receiver::new(
+        &mut sockets,
+        UdpSocketBuffer::new(&mut rtp_rx_metadata[..], &mut rtp_rx_buffer[..]),
+        UdpSocketBuffer::new(&mut [][..], &mut [][..]),
+        rtp::DEFAULT_PORT,
+        jitter,
+    );
+    // Restarted with the negotiated media for every call
+    let mut sender = Sender::new(
+        &mut sockets,
+        UdpSocketBuffer::new(&mut [][..], &mut [][..]),
+        UdpSocketBuffer::new(&mut rtp_tx_metadata[..], &mut rtp_tx_buffer[..]),
+        rtp::DEFAULT_PORT,
+        config.proxy,
+        SenderConfig::new(Format::PCMU, SAMPLE_RATE, seed),
+    )
+    .expect("Unsupported sender configuration");
+
+    writeln!(stdout, "Waiting for DHCP to register {}@{}", USER, DOMAIN).unwrap();
+
+    let mut playback = [0_i16; PERIOD_LEN];
+    let mut capture = [0_i16; PERIOD_LEN];
+    let mut ringing = false;
+    let mut in_call = false;
+    let mut was_pressed = btn.is_low().unwrap();
+
+    loop {
+        let time: u64 = cortex_m::interrupt::free(|cs| *TIME.borrow(cs).borrow());
+        let now = Instant::from_millis(time as i64);
+
+        match dhcp.poll(&mut iface, &mut sockets, now) {
+            Some(dhcp::Event::Bound(lease)) | Some(dhcp::Event::Renewed(lease)) => {
+                writeln!(stdout, "DHCP bound {}", lease.address).unwrap();
+                ua.set_address(lease.address.address());
+            }
+            Some(dhcp::Event::Expired(fallback)) => {
+                // The registrar is out of reach from a link-local address
+                writeln!(stdout, "DHCP lease expired, using {}", fallback).unwrap();
+                ua.set_address(Ipv4Address::UNSPECIFIED);
+            }
+            None => (),
+        }
+
+        let is_pressed = btn.is_low().unwrap();
+        if !was_pressed && is_pressed {
+            let result = if ringing {
+                ua.answer()
+            } else if ua.in_call() {
+                ua.hang_up()
+            } else {
+                ua.call(CALLEE)
+            };
+            if let Err(e) = result {
+                writeln!(stdout, "Error: {:?}", e).unwrap();
+            }
+        }
+        was_pressed = is_pressed;
+
+        if let Err(e) = iface.poll(&mut sockets, now) {
+            // Ignore malformed packets
+            writeln!(stdout, "Error: {:?}", e).unwrap();
+        }
+        receiver.poll(&mut sockets, now);
+
+        while let Some(event) = ua.poll(&mut sockets, now) {
+            writeln!(stdout, "{:?}", event).unwrap();
+            match event {
+                sip::Event::IncomingCall => {
+                    writeln!(stdout, "Call from {}", ua.peer().unwrap_or("?")).unwrap();
+                    ringing = true;
+                }
+                sip::Event::MediaStarted(media) => {
+                    // The SSRC should be random, the seed and the time
+                    // stand in
+                    let ssrc = seed ^ time as u32;
+                    sender
+                        .restart(media.remote, media.sender_config(SAMPLE_RATE, ssrc))
+                        .expect("Unsupported media");
+                    ringing = false;
+                    in_call = true;
+                }
+                sip::Event::CallEnded(_) => {
+                    let (sent, received) = (sender.stats(), receiver.stats());
+                    writeln!(
+                        stdout,
+                        "sent {} dropped {}, received {} lost {} late {}, {} capture overruns",
+                        sent.packets,
+                        sent.dropped,
+                        received.received,
+                        received.lost,
+                        received.late,
+                        i2s_ext.overruns()
+                    )
+                    .unwrap();
+                    receiver.reset();
+                    ringing = false;
+                    in_call = false;
+                }
+                _ => (),
+            }
+        }
+
+        // Silence outside calls and while buffering, blocks for the period
+        let len = receiver.fill(&mut playback);
+        i2s_ext.transfer(&playback[..len], &mut capture);
+        if in_call {
+            lowpass.process(&mut capture);
+            sender.send(&mut sockets, &capture);
+        }
+        sender.poll(&mut sockets, now);
+    }
+}
+
+/// 1 ms ticks
+fn setup_systick(syst: &mut stm32::SYST, sysclk: u32) {
+    syst.set_clock_source(SystClkSource::Core);
+    syst.set_reload(sysclk / 1000 - 1);
+    syst.clear_current();
+    syst.enable_counter();
+    syst.enable_interrupt();
+}
+
+#[exception]
+fn SysTick() {
+    cortex_m::interrupt::free(|cs| {
+        let mut time = TIME.borrow(cs).borrow_mut();
+        *time += 1;
+    })
+}
+
+#[exception]
+fn HardFault(ef: &ExceptionFrame) -> ! {
+    panic!("HardFault at {:#?}", ef);
+}
+
+#[exception]
+fn DefaultHandler(irqn: i16) {
+    panic!("Unhandled exception (IRQn = {})", irqn);
+}
@@ -0,0 +1,137 @@
+// Plays clips from a sound bank programmed into the second flash bank.
+//
+// sound-bank-gen -o sounds.bank 1=ring.wav 2=prompt.wav
+// openocd -f openocd.cfg -c "program sounds.bank 0x08100000 verify reset exit"
+
+#![no_main]
+#![no_std]
+
+extern crate stm32f4xx_hal as hal;
+
+#[allow(unused_imports)]
+use panic_semihosting;
+
+use crate::hal::{
+    i2c::I2c,
+    i2s::{I2s, I2sStandard},
+    prelude::*,
+    serial::config::Config,
+    serial::Serial,
+    stm32,
+};
+use core::fmt::Write;
+use cortex_m_rt::ExceptionFrame;
+use cortex_m_rt::{entry, exception};
+use wm8960::sound_bank::SoundBank;
+use wm8960::Wm8960;
+
+/// BANK2 in memory.x, the firmware stays in FLASH below it
+const SOUND_BANK_ADDRESS: usize = 0x0810_0000;
+const SOUND_BANK_MAX_LEN: usize = 1024 * 1024;
+
+/// Number of 1024 sample buffers to hold a looping clip for, ~5 s
+const LOOP_BUFFERS: usize = 240;
+
+#[entry]
+fn main() -> ! {
+    let dp = stm32::Peripherals::take().expect("Failed to take stm32::Peripherals");
+    let cp =
+        cortex_m::peripheral::Peripherals::take().expect("Failed to take cortex_m::Peripherals");
+
+    let rcc = dp.RCC.constrain();
+    let clocks = rcc.cfgr.sysclk(180.mhz()).freeze();
+
+    let mut delay = hal::delay::Delay::new(cp.SYST, clocks);
+
+    let gpiob = dp.GPIOB.split();
+    let gpioc = dp.GPIOC.split();
+    let gpiod = dp.GPIOD.split();
+
+    let scl = gpiob.pb8.into_alternate_af4().set_open_drain();
+    let sda = gpiob.pb9.into_alternate_af4().set_open_drain();
+
+    let i2s_ck = gpiob.pb13.into_alternate_af5();
+    let i2s_ws = gpiob.pb12.into_alternate_af5();
+    let i2s_sd = gpiob.pb15.into_alternate_af5();
+    let i2s_mck = gpioc.pc6.into_alternate_af5();
+
+    let serial_tx = gpiod.pd8.into_alternate_af7();
+    let serial_rx = gpiod.pd9.into_alternate_af7();
+
+    let serial = Serial::usart3(
+        dp.USART3,
+        (serial_tx, serial_rx),
+        Config {
+            baudrate: 115_200.bps(),
+            ..Default::default()
+        },
+        clocks,
+    )
+    .unwrap();
+    let (mut stdout, _rx) = serial.split();
+
+    writeln!(stdout, "Init Wm8960").unwrap();
+
+    let i2c = I2c::i2c1(dp.I2C1, (scl, sda), 100.khz(), clocks);
+    let i2s = I2s::i2s2(dp.SPI2, (i2s_sd, i2s_ck, i2s_ws, i2s_mck), clocks)
+        .into_master_output::<u16>(I2sStandard::Philips);
+    let mut wm8960 = Wm8960::new(i2c, i2s).unwrap();
+
+    // Flash is always mapped and the bank is only ever read
+    let flash =
+        unsafe { core::slice::from_raw_parts(SOUND_BANK_ADDRESS as *const u8, SOUND_BANK_MAX_LEN) };
+    let bank = SoundBank::new(flash).unwrap();
+
+    writeln!(stdout, "{:#?}", bank.header()).unwrap();
+    for entry in bank.entries() {
+        writeln!(stdout, "{:?}", entry).unwrap();
+    }
+
+    let mut buf = [0_i16; 1024];
+
+    loop {
+        for entry in bank.entries() {
+            let clip = match bank.clip(entry.id) {
+                Ok(clip) => clip,
+                Err(e) => {
+                    writeln!(stdout, "Clip {}: {:?}", entry.id, e).unwrap();
+                    continue;
+                }
+            };
+
+            writeln!(
+                stdout,
+                "Playing clip {}, {} frames at {} Hz",
+                clip.id,
+                clip.len(),
+                clip.sample_rate
+            )
+            .unwrap();
+            if let Some(name) = clip.info.and_then(|i| i.name) {
+                writeln!(stdout, "  {}", name).unwrap();
+            }
+
+            let mut source = clip.source();
+            if source.is_looping() {
+                writeln!(stdout, "  loop {:?}", clip.sample_loop).unwrap();
+                for _ in 0..LOOP_BUFFERS {
+                    wm8960.play_source(&mut source, &mut buf).unwrap();
+                }
+                source.release();
+            }
+            while wm8960.play_source(&mut source, &mut buf).unwrap() != 0 {}
+
+            delay.delay_ms(500_u32);
+        }
+    }
+}
+
+#[exception]
+fn HardFault(ef: &ExceptionFrame) -> ! {
+    panic!("HardFault at {:#?}", ef);
+}
+
+#[exception]
+fn DefaultHandler(irqn: i16) {
+    panic!("Unhandled exception (IRQn = {})", irqn);
+}
@@ -0,0 +1,124 @@
+#![no_main]
+#![no_std]
+#![deny(unsafe_code)]
+
+extern crate stm32f4xx_hal as hal;
+
+#[allow(unused_imports)]
+use panic_semihosting;
+
+use crate::hal::{
+    i2c::I2c,
+    i2s::{I2s, I2sStandard},
+    prelude::*,
+    serial::config::Config,
+    serial::Serial,
+    stm32,
+};
+use core::fmt::Write;
+use cortex_m_rt::ExceptionFrame;
+use cortex_m_rt::{entry, exception};
+use wm8960::audio::{Processed, SAMPLE_RATE};
+use wm8960::meter::LevelMeter;
+use wm8960::tone::{CallProgress, CountryTones, ToneGenerator};
+use wm8960::Wm8960;
+
+/// Number of 1024 sample buffers to play each call progress signal for, ~5 s
+const SIGNAL_BUFFERS: usize = 240;
+
+#[entry]
+fn main() -> ! {
+    let dp = stm32::Peripherals::take().expect("Failed to take stm32::Peripherals");
+    let _cp =
+        cortex_m::peripheral::Peripherals::take().expect("Failed to take cortex_m::Peripherals");
+
+    let rcc = dp.RCC.constrain();
+    let clocks = rcc.cfgr.sysclk(180.mhz()).freeze();
+
+    let gpiob = dp.GPIOB.split();
+    let gpioc = dp.GPIOC.split();
+    let gpiod = dp.GPIOD.split();
+
+    let scl = gpiob.pb8.into_alternate_af4().set_open_drain();
+    let sda = gpiob.pb9.into_alternate_af4().set_open_drain();
+
+    let i2s_ck = gpiob.pb13.into_alternate_af5();
+    let i2s_ws = gpiob.pb12.into_alternate_af5();
+    let i2s_sd = gpiob.pb15.into_alternate_af5();
+    let i2s_mck = gpioc.pc6.into_alternate_af5();
+
+    let serial_tx = gpiod.pd8.into_alternate_af7();
+    let serial_rx = gpiod.pd9.into_alternate_af7();
+
+    let serial = Serial::usart3(
+        dp.USART3,
+        (serial_tx, serial_rx),
+        Config {
+            baudrate: 115_200.bps(),
+            ..Default::default()
+        },
+        clocks,
+    )
+    .unwrap();
+    let (mut stdout, _rx) = serial.split();
+
+    writeln!(stdout, "Init Wm8960").unwrap();
+
+    let i2c = I2c::i2c1(dp.I2C1, (scl, sda), 100.khz(), clocks);
+    let i2s = I2s::i2s2(dp.SPI2, (i2s_sd, i2s_ck, i2s_ws, i2s_mck), clocks)
+        .into_master_output::<u16>(I2sStandard::Philips);
+    let mut wm8960 = Wm8960::new(i2c, i2s).unwrap();
+
+    let mut tones = ToneGenerator::new(SAMPLE_RATE);
+    tones.set_country(CountryTones::NORTH_AMERICA);
+    let mut tones = Processed::new(tones, LevelMeter::new(SAMPLE_RATE));
+
+    let mut buf = [0_i16; 1024];
+
+    loop {
+        for signal in [
+            CallProgress::DialTone,
+            CallProgress::Ringback,
+            CallProgress::Busy,
+            CallProgress::Congestion,
+        ]
+        .iter()
+        {
+            writeln!(stdout, "Playing {:?}", signal).unwrap();
+            tones.source.play_call_progress(*signal).unwrap();
+            for _ in 0..SIGNAL_BUFFERS {
+                wm8960.play_source(&mut tones, &mut buf).unwrap();
+            }
+
+            let meter = &mut tones.processor;
+            for (ch, level) in meter.channels().iter().enumerate() {
+                writeln!(
+                    stdout,
+                    "  ch{} peak {:.1} dBFS, rms {:.1} dBFS",
+                    ch,
+                    level.peak_dbfs(),
+                    level.rms_dbfs()
+                )
+                .unwrap();
+            }
+            let clips = meter.take_clips();
+            if clips != 0 {
+                writeln!(stdout, "  clipped {} samples", clips).unwrap();
+            }
+        }
+
+        writeln!(stdout, "Dialing").unwrap();
+        tones.source.play_digits("5551234*#").unwrap();
+        while wm8960.play_source(&mut tones, &mut buf).unwrap() != 0 {}
+    }
+}
+
+#[exception]
+fn HardFault(ef: &ExceptionFrame) -> ! {
+    panic!("HardFault at {:#?}", ef);
+}
+
+#[exception]
+fn DefaultHandler(irqn: i16) {
+    panic!("Unhandled exception (IRQn = {})", irqn);
+}
@@ -14,14 +14,19 @@ use crate::hal::{
     serial::Serial,
     stm32,
 };
-use crate::wave_data::WAVE_DATA;
 use core::fmt::Write;
 use cortex_m_rt::ExceptionFrame;
 use cortex_m_rt::{entry, exception};
-use wm8960::wave_header::parse_header;
+use wm8960::player::Player;
 use wm8960::Wm8960;
+use wm8960_macros::include_wav;
 
-mod wave_data;
+// Validated at build time and resampled to the codec's rate. Mono, the
+// Player plays it on both channels, stereo wouldn't fit in bank 1's 1 MiB
+include_wav!(RING, "assets/ring.wav", convert, channels = 1);
+
+/// Number of 1024 sample buffers to hold a looping clip for, ~5 s
+const RING_BUFFERS: usize = 240;
 
 #[entry]
 fn main() -> ! {
@@ -84,31 +89,30 @@ fn main() -> ! {
 
     writeln!(stdout, "Init Wm8960").unwrap();
 
-    writeln!(stdout, "WAVE_DATA ([u16]) len: {}", WAVE_DATA.len()).unwrap();
-
-    let (head, input, tail) = unsafe { WAVE_DATA.align_to::<u8>() };
-
-    assert!(head.is_empty());
-    assert!(tail.is_empty());
-    assert_eq!(input.len(), WAVE_DATA.len() * 2);
+    let wave = RING.parsed();
+    writeln!(stdout, "{:#?}", wave.header()).unwrap();
+    writeln!(stdout, "data len: {}", wave.data().len()).unwrap();
 
-    writeln!(stdout, "WAVE_DATA ([u8]) len: {}", input.len()).unwrap();
+    let mut player = Player::from_parsed(&wave).unwrap();
+    writeln!(stdout, "Loop: {:?}", player.sample_loop()).unwrap();
 
-    let (_input, header) = parse_header(input).unwrap();
-    writeln!(stdout, "{:#?}", header).unwrap();
-
-    assert_eq!(header.riff.chunk_size as usize, input.len() - 8);
-    let data_offset = header.data_offset();
-
-    writeln!(stdout, "data_offset: {}", data_offset).unwrap();
-    assert_eq!(header.data.chunk_size as usize, input.len() - data_offset);
+    let mut buf = [0_i16; 1024];
 
     loop {
         writeln!(stdout, "Playing").unwrap();
 
-        wm8960.play_audio(&WAVE_DATA[data_offset..]).unwrap();
-
-        delay.delay_ms(1000_u32);
+        player.rewind();
+        if player.is_looping() {
+            // Hold the loop for a while, then play out the tail
+            for _ in 0..RING_BUFFERS {
+                wm8960.play_source(&mut player, &mut buf).unwrap();
+            }
+            player.release();
+            while wm8960.play_source(&mut player, &mut buf).unwrap() != 0 {}
+        } else {
+            while wm8960.play_source(&mut player, &mut buf).unwrap() != 0 {}
+            delay.delay_ms(1000_u32);
+        }
     }
 }
 
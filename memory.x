@@ -1,7 +1,9 @@
 MEMORY
 {
   /* NOTE K = KiBi = 1024 bytes */
-  FLASH : ORIGIN = 0x08000000, LENGTH = 2M
+  /* The firmware gets bank 1, bank 2 holds the sound bank */
+  FLASH : ORIGIN = 0x08000000, LENGTH = 1M
+  BANK2 : ORIGIN = 0x08100000, LENGTH = 1M
   RAM : ORIGIN = 0x20000000, LENGTH = 192K
 }
 
@@ -0,0 +1,65 @@
+// DHCP client on a Linux tap interface
+//
+// sudo ip tuntap add name tap0 mode tap user $USER
+// sudo ip link set tap0 up
+// sudo ip addr add 192.168.69.1/24 dev tap0
+// sudo dnsmasq -d -p 0 -i tap0 --bind-interfaces \
+//     --dhcp-range=192.168.69.50,192.168.69.60,2m
+// cargo run --example dhcp_tap --target x86_64-unknown-linux-gnu -- tap0
+//
+// A 2 minute lease renews after a minute.
+
+use net::dhcp::{self, DhcpClient, DhcpDevice};
+use smoltcp::iface::{EthernetInterfaceBuilder, NeighborCache, Routes};
+use smoltcp::phy::{wait, TapInterface};
+use smoltcp::socket::{RawPacketMetadata, RawSocketBuffer, SocketSet};
+use smoltcp::time::{Duration, Instant};
+use smoltcp::wire::{EthernetAddress, IpCidr};
+use std::collections::BTreeMap;
+use std::env;
+use std::os::unix::io::AsRawFd;
+
+const SRC_MAC: [u8; 6] = [0x02, 0x00, 0xDE, 0xAD, 0xBE, 0xEF];
+
+fn main() {
+    let name = env::args().nth(1).unwrap_or_else(|| "tap0".to_string());
+    let device = TapInterface::new(&name).expect("Failed to open the tap interface");
+    let fd = device.as_raw_fd();
+
+    let ethernet_addr = EthernetAddress(SRC_MAC);
+    let fallback = dhcp::link_local(ethernet_addr);
+    let mut ip_addrs = [IpCidr::Ipv4(fallback)];
+    let mut routes_storage = [None; 1];
+    let mut iface = EthernetInterfaceBuilder::new(DhcpDevice::new(device))
+        .ethernet_addr(ethernet_addr)
+        .neighbor_cache(NeighborCache::new(BTreeMap::new()))
+        .ip_addrs(&mut ip_addrs[..])
+        .routes(Routes::new(&mut routes_storage[..]))
+        .finalize();
+
+    let mut sockets = SocketSet::new(vec![]);
+    let rx_buffer = RawSocketBuffer::new(vec![RawPacketMetadata::EMPTY; 4], vec![0; 4 * 576]);
+    let tx_buffer = RawSocketBuffer::new(vec![RawPacketMetadata::EMPTY], vec![0; dhcp::PACKET_LEN]);
+    let mut dhcp = DhcpClient::new(
+        &mut sockets,
+        rx_buffer,
+        tx_buffer,
+        ethernet_addr,
+        fallback,
+        Instant::now(),
+    );
+
+    println!("Fallback address {}", fallback);
+
+    loop {
+        let now = Instant::now();
+        if let Some(event) = dhcp.poll(&mut iface, &mut sockets, now) {
+            println!("{:?}", event);
+            println!("Address {}", dhcp.address());
+        }
+        if let Err(e) = iface.poll(&mut sockets, now) {
+            println!("Error: {:?}", e);
+        }
+        wait(fd, Some(Duration::from_millis(100))).expect("Failed to wait on the tap interface");
+    }
+}
@@ -0,0 +1,128 @@
+// HTTP server on a Linux tap interface
+//
+// sudo ip tuntap add name tap0 mode tap user $USER
+// sudo ip link set tap0 up
+// sudo ip addr add 192.168.69.1/24 dev tap0
+// cargo run --example http_tap --target x86_64-unknown-linux-gnu -- tap0
+//
+// curl http://192.168.69.2/
+// curl http://192.168.69.2/hello/world
+// curl -d 'some data' http://192.168.69.2/echo
+// curl http://192.168.69.2/count?n=20
+
+use core::fmt::Write;
+use net::http::{Method, Request, Response, Route, Server, Slot, Status};
+use smoltcp::iface::{EthernetInterfaceBuilder, NeighborCache};
+use smoltcp::phy::{wait, TapInterface};
+use smoltcp::socket::{SocketSet, TcpSocketBuffer};
+use smoltcp::time::{Duration, Instant};
+use smoltcp::wire::{EthernetAddress, IpAddress, IpCidr};
+use std::collections::BTreeMap;
+use std::env;
+use std::os::unix::io::AsRawFd;
+
+const SRC_MAC: [u8; 6] = [0x02, 0x00, 0xDE, 0xAD, 0xBE, 0xEF];
+
+struct State {
+    requests: u32,
+}
+
+static ROUTES: &[Route<State>] = &[
+    Route {
+        method: Method::GET,
+        path: "/",
+        handler: index,
+    },
+    Route {
+        method: Method::GET,
+        path: "/hello/{name}",
+        handler: hello,
+    },
+    Route {
+        method: Method::POST,
+        path: "/echo",
+        handler: echo,
+    },
+    Route {
+        method: Method::GET,
+        path: "/count",
+        handler: count,
+    },
+];
+
+fn index(state: &mut State, _request: &Request, response: &mut Response) {
+    state.requests += 1;
+    response.start(Status::Ok, "text/html");
+    let _ = writeln!(
+        response,
+        "<html><body><p>{} requests</p></body></html>",
+        state.requests
+    );
+}
+
+fn hello(state: &mut State, request: &Request, response: &mut Response) {
+    state.requests += 1;
+    response.start(Status::Ok, "text/plain");
+    let _ = writeln!(response, "hello {}", request.param("name").unwrap_or(""));
+}
+
+fn echo(state: &mut State, request: &Request, response: &mut Response) {
+    state.requests += 1;
+    let content_type = request.header("Content-Type").unwrap_or("text/plain");
+    response.send(Status::Ok, content_type, request.body);
+}
+
+fn count(state: &mut State, request: &Request, response: &mut Response) {
+    state.requests += 1;
+    let n = match request.query_param("n").map(str::parse::<u32>) {
+        Some(Ok(n)) => n,
+        Some(Err(_)) => return response.error(Status::BadRequest),
+        None => 10,
+    };
+    response.start(Status::Ok, "text/plain");
+    for i in 0..n {
+        let _ = writeln!(response, "{}", i);
+    }
+}
+
+fn main() {
+    let name = env::args().nth(1).unwrap_or_else(|| "tap0".to_string());
+    let device = TapInterface::new(&name).expect("Failed to open the tap interface");
+    let fd = device.as_raw_fd();
+
+    let ethernet_addr = EthernetAddress(SRC_MAC);
+    let mut ip_addrs = [IpCidr::new(IpAddress::v4(192, 168, 69, 2), 24)];
+    let mut iface = EthernetInterfaceBuilder::new(device)
+        .ethernet_addr(ethernet_addr)
+        .neighbor_cache(NeighborCache::new(BTreeMap::new()))
+        .ip_addrs(&mut ip_addrs[..])
+        .finalize();
+
+    let mut sockets = SocketSet::new(vec![]);
+    let mut slots: Vec<Slot> = (0..4)
+        .map(|_| {
+            Slot::new(
+                &mut sockets,
+                TcpSocketBuffer::new(vec![0; 1024]),
+                TcpSocketBuffer::new(vec![0; 2048]),
+            )
+        })
+        .collect();
+    let mut server = Server::new(80, ROUTES, &mut slots[..]);
+    let mut state = State { requests: 0 };
+
+    println!("Listening on port {}", server.port());
+
+    loop {
+        let now = Instant::now();
+        if let Err(e) = iface.poll(&mut sockets, now) {
+            println!("Error: {:?}", e);
+        }
+        server.poll(&mut sockets, &mut state);
+        let delay = match iface.poll_delay(&sockets, now) {
+            Some(delay) => delay.min(Duration::from_millis(100)),
+            None => Duration::from_millis(100),
+        };
+        wait(fd, Some(delay)).expect("Failed to wait on the tap interface");
+    }
+}
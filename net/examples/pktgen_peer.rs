@@ -0,0 +1,121 @@
+// pktgen peer on a Linux host
+//
+// Talks to the pktgen example over an Ethernet interface, through a raw
+// socket, or to another peer over a tap interface:
+//
+// sudo cargo run --example pktgen_peer --target x86_64-unknown-linux-gnu -- raw eth0
+// sudo cargo run --example pktgen_peer --target x86_64-unknown-linux-gnu -- raw tap0 10
+// cargo run --example pktgen_peer --target x86_64-unknown-linux-gnu -- tap tap0
+//
+// Reflects the frames of the other side's stream and, given a rate in
+// Mbit/s, sends its own stream of 1000 byte frames to the broadcast address.
+// Prints loss, reordering and latencies every 5 seconds.
+
+use net::pktgen::{self, Analyzer, Config, Generator, Rate, Size};
+use smoltcp::phy::{wait, Device, RawSocket, RxToken, TapInterface, TxToken};
+use smoltcp::time::{Duration, Instant};
+use smoltcp::wire::EthernetAddress;
+use std::env;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::time;
+
+const SRC_MAC: [u8; 6] = [0x02, 0x00, 0xDE, 0xAD, 0xBE, 0xF0];
+
+const REPORT_INTERVAL: u64 = 5_000_000;
+
+fn main() {
+    let kind = env::args().nth(1).unwrap_or_else(|| "tap".to_string());
+    let name = env::args().nth(2).unwrap_or_else(|| "tap0".to_string());
+    let rate = env::args()
+        .nth(3)
+        .map(|mbps| Rate::Mbps(mbps.parse().expect("Bad rate")));
+
+    let generator = rate.map(|rate| {
+        Generator::new(Config {
+            src: EthernetAddress(SRC_MAC),
+            size: Size::Fixed(1000),
+            rate,
+            ..Config::default()
+        })
+        .expect("Bad pktgen config")
+    });
+
+    match kind.as_str() {
+        "raw" => {
+            let device = RawSocket::new(&name).expect("Failed to open the raw socket");
+            let fd = device.as_raw_fd();
+            run(device, fd, generator);
+        }
+        "tap" => {
+            let device = TapInterface::new(&name).expect("Failed to open the tap interface");
+            let fd = device.as_raw_fd();
+            run(device, fd, generator);
+        }
+        _ => println!("raw or tap"),
+    }
+}
+
+fn run<D>(mut device: D, fd: RawFd, mut generator: Option<Generator>)
+where
+    D: for<'d> Device<'d>,
+{
+    let start = time::Instant::now();
+    let now = || start.elapsed().as_micros() as u64;
+    let mut analyzer = Analyzer::new();
+    let mut sent = 0;
+    let mut reflected = 0;
+    let mut last_report = 0;
+
+    loop {
+        while let Some((rx, tx)) = device.receive() {
+            let mut frame = [0; 1536];
+            let mut len = 0;
+            let mut forward = false;
+            let _ = rx.consume(Instant::now(), |buf| {
+                len = buf.len().min(frame.len());
+                frame[..len].copy_from_slice(&buf[..len]);
+                forward = analyzer
+                    .receive(buf, now())
+                    .map_or(false, |header| !header.reflected);
+                Ok(())
+            });
+            if forward && pktgen::reflect(&mut frame[..len]) {
+                let result = tx.consume(Instant::now(), len, |buf| {
+                    buf.copy_from_slice(&frame[..len]);
+                    Ok(())
+                });
+                if result.is_ok() {
+                    reflected += 1;
+                }
+            }
+        }
+
+        if let Some(generator) = generator.as_mut() {
+            while let Some(len) = generator.poll(now()) {
+                let tx = match device.transmit() {
+                    Some(tx) => tx,
+                    None => break,
+                };
+                let result = tx.consume(Instant::now(), len, |buf| {
+                    generator.write(buf, now());
+                    Ok(())
+                });
+                if result.is_err() {
+                    break;
+                }
+                sent += 1;
+            }
+        }
+
+        if now() >= last_report + REPORT_INTERVAL {
+            last_report = now();
+            println!("Sent {}, reflected {}", sent, reflected);
+            println!("Received {:?}", analyzer.received.counters);
+            println!("  one-way {}", analyzer.received.latency);
+            println!("Returned {:?}", analyzer.returned.counters);
+            println!("  round trip {}", analyzer.returned.latency);
+        }
+
+        wait(fd, Some(Duration::from_millis(1))).expect("Failed to wait on the interface");
+    }
+}
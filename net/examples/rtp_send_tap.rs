@@ -0,0 +1,169 @@
+// RTP sender on a Linux tap interface
+//
+// sudo ip tuntap add name tap0 mode tap user $USER
+// sudo ip link set tap0 up
+// sudo ip addr add 192.168.69.1/24 dev tap0
+// cargo run --example rtp_send_tap --target x86_64-unknown-linux-gnu -- tap0 pcmu 20
+//
+// Sends ringback, DTMF digits and busy in turn, standing in for the codec's
+// capture path, from 192.168.69.2 to port 5004 on the host with sender
+// reports to 5005. The encoding is pcmu, pcma or l16 (16 kHz mono, payload
+// type 96), the ptime 10, 20 or 40 ms.
+//
+// ffplay -protocol_whitelist file,udp,rtp pcmu.sdp
+//
+// with pcmu.sdp:
+//
+// v=0
+// o=- 0 0 IN IP4 192.168.69.2
+// s=tones
+// c=IN IP4 192.168.69.2
+// t=0 0
+// m=audio 5004 RTP/AVP 0
+
+use net::rtp::{self, Encoding, Format, Ptime, Sender, SenderConfig};
+use smoltcp::iface::{EthernetInterfaceBuilder, NeighborCache};
+use smoltcp::phy::{wait, TapInterface};
+use smoltcp::socket::{SocketSet, UdpPacketMetadata, UdpSocketBuffer};
+use smoltcp::time::{Duration, Instant};
+use smoltcp::wire::{EthernetAddress, IpAddress, IpCidr, IpEndpoint};
+use std::collections::BTreeMap;
+use std::env;
+use std::os::unix::io::AsRawFd;
+use wm8960::audio::{AudioSource, Processed, NUM_CHANNELS, SAMPLE_RATE};
+use wm8960::biquad::{BiquadCascade, Coefficients, FilterType};
+use wm8960::tone::{CallProgress, ToneGenerator};
+
+const SRC_MAC: [u8; 6] = [0x02, 0x00, 0xDE, 0xAD, 0xBE, 0xEF];
+
+/// ~10 ms at the codec's rate
+const PERIOD_FRAMES: usize = SAMPLE_RATE as usize / 100;
+
+const CNAME: &str = "tones@192.168.69.2";
+
+fn main() {
+    let name = env::args().nth(1).unwrap_or_else(|| "tap0".to_string());
+    let (format, payload_type) = match env::args().nth(2).as_ref().map(String::as_str) {
+        None | Some("pcmu") => (Format::PCMU, 0),
+        Some("pcma") => (Format::PCMA, 8),
+        Some("l16") => (
+            Format {
+                encoding: Encoding::L16,
+                clock_rate: 16_000,
+                channels: 1,
+            },
+            rtp::DYNAMIC_PAYLOAD_TYPE,
+        ),
+        Some(other) => panic!("Unknown encoding {}", other),
+    };
+    let ptime = env::args()
+        .nth(3)
+        .map(|ms| ms.parse().ok().and_then(Ptime::from_ms).expect("Bad ptime"))
+        .unwrap_or(Ptime::Ms20);
+    let device = TapInterface::new(&name).expect("Failed to open the tap interface");
+    let fd = device.as_raw_fd();
+
+    let ethernet_addr = EthernetAddress(SRC_MAC);
+    let mut ip_addrs = [IpCidr::new(IpAddress::v4(192, 168, 69, 2), 24)];
+    let mut iface = EthernetInterfaceBuilder::new(device)
+        .ethernet_addr(ethernet_addr)
+        .neighbor_cache(NeighborCache::new(BTreeMap::new()))
+        .ip_addrs(&mut ip_addrs[..])
+        .finalize();
+
+    let mut sockets = SocketSet::new(vec![]);
+    let config = SenderConfig {
+        payload_type,
+        ptime,
+        ..SenderConfig::new(format, SAMPLE_RATE, 0x5EED_0001)
+    };
+    let destination = IpEndpoint::new(IpAddress::v4(192, 168, 69, 1), rtp::DEFAULT_PORT);
+    let mut sender = Sender::new(
+        &mut sockets,
+        UdpSocketBuffer::new(vec![], vec![]),
+        UdpSocketBuffer::new(vec![UdpPacketMetadata::EMPTY; 8], vec![0; 8 * 1500]),
+        rtp::DEFAULT_PORT,
+        destination,
+        config,
+    )
+    .expect("Unsupported sender configuration");
+    sender.enable_rtcp(
+        &mut sockets,
+        UdpSocketBuffer::new(vec![], vec![]),
+        UdpSocketBuffer::new(vec![UdpPacketMetadata::EMPTY; 1], vec![0; 128]),
+        CNAME,
+    );
+
+    // Keep what the decimation would fold back out of the payload's band
+    let cutoff = format.clock_rate as f32 * 0.425;
+    let mut lowpass = BiquadCascade::new();
+    for q in &[0.54, 1.31] {
+        let coeffs = Coefficients::design(FilterType::LowPass, cutoff, *q, 0.0, SAMPLE_RATE)
+            .expect("Bad low-pass");
+        lowpass.push(coeffs).unwrap();
+    }
+    let mut tones = ToneGenerator::new(SAMPLE_RATE);
+    let mut capture = Processed::new(&mut tones, lowpass);
+
+    println!(
+        "Sending {:?} {} Hz, {} ms packets to {}",
+        format.encoding,
+        format.clock_rate,
+        ptime.ms(),
+        destination
+    );
+
+    let start = Instant::now();
+    let mut periods = 0;
+    let mut next_report = start + Duration::from_secs(1);
+    let mut buf = [0_i16; PERIOD_FRAMES * NUM_CHANNELS];
+    let mut cycle = 0;
+
+    loop {
+        let now = Instant::now();
+
+        // Capture in periods as they come due
+        let due = (now - start).total_millis() as usize * SAMPLE_RATE as usize / 1000;
+        while (periods + 1) * PERIOD_FRAMES <= due {
+            // Something new every ~4 s
+            if periods % 400 == 0 {
+                match cycle % 3 {
+                    0 => capture
+                        .source
+                        .play_call_progress(CallProgress::Ringback)
+                        .unwrap(),
+                    1 => {
+                        capture.source.play_digits("0123456789*#").unwrap();
+                    }
+                    _ => capture
+                        .source
+                        .play_call_progress(CallProgress::Busy)
+                        .unwrap(),
+                }
+                cycle += 1;
+            }
+            let len = capture.fill(&mut buf);
+            for s in buf[len..].iter_mut() {
+                *s = 0;
+            }
+            sender.send(&mut sockets, &buf);
+            periods += 1;
+        }
+        sender.poll(&mut sockets, now);
+
+        if let Err(e) = iface.poll(&mut sockets, now) {
+            println!("Error: {:?}", e);
+        }
+
+        if now >= next_report {
+            next_report += Duration::from_secs(1);
+            println!("{:?}", sender.stats());
+        }
+
+        let delay = match iface.poll_delay(&sockets, now) {
+            Some(delay) => delay.min(Duration::from_millis(5)),
+            None => Duration::from_millis(5),
+        };
+        wait(fd, Some(delay)).expect("Failed to wait on the tap interface");
+    }
+}
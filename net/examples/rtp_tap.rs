@@ -0,0 +1,119 @@
+// RTP receiver on a Linux tap interface
+//
+// sudo ip tuntap add name tap0 mode tap user $USER
+// sudo ip link set tap0 up
+// sudo ip addr add 192.168.69.1/24 dev tap0
+// cargo run --example rtp_tap --target x86_64-unknown-linux-gnu -- tap0 out.raw
+//
+// Playout is clocked from the host's time at 48 kHz, like the codec's I2S
+// clock on the board, and written to the file as raw s16le stereo.
+//
+// ffmpeg -re -i some.wav -ac 1 -ar 8000 -c:a pcm_mulaw -f rtp rtp://192.168.69.2:5004
+// ffmpeg -re -i some.wav -ac 2 -ar 48000 -c:a pcm_s16be -payload_type 96 \
+//     -f rtp rtp://192.168.69.2:5004
+// ffplay -f s16le -ar 48000 -ac 2 out.raw
+//
+// smoltcp doesn't reassemble IP fragments, keep packets under the MTU.
+
+use net::jitter::{Config, JitterBuffer};
+use net::rtp::{self, Encoding, Format, Receiver};
+use smoltcp::iface::{EthernetInterfaceBuilder, NeighborCache};
+use smoltcp::phy::{wait, TapInterface};
+use smoltcp::socket::{SocketSet, UdpPacketMetadata, UdpSocketBuffer};
+use smoltcp::time::{Duration, Instant};
+use smoltcp::wire::{EthernetAddress, IpAddress, IpCidr};
+use std::collections::BTreeMap;
+use std::env;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::os::unix::io::AsRawFd;
+
+const SRC_MAC: [u8; 6] = [0x02, 0x00, 0xDE, 0xAD, 0xBE, 0xEF];
+
+/// 10 ms at 48 kHz
+const PERIOD_FRAMES: usize = 480;
+
+fn main() {
+    let name = env::args().nth(1).unwrap_or_else(|| "tap0".to_string());
+    let path = env::args().nth(2).unwrap_or_else(|| "rtp.raw".to_string());
+    let device = TapInterface::new(&name).expect("Failed to open the tap interface");
+    let fd = device.as_raw_fd();
+    let mut out = BufWriter::new(File::create(&path).expect("Failed to create the output file"));
+
+    let ethernet_addr = EthernetAddress(SRC_MAC);
+    let mut ip_addrs = [IpCidr::new(IpAddress::v4(192, 168, 69, 2), 24)];
+    let mut iface = EthernetInterfaceBuilder::new(device)
+        .ethernet_addr(ethernet_addr)
+        .neighbor_cache(NeighborCache::new(BTreeMap::new()))
+        .ip_addrs(&mut ip_addrs[..])
+        .finalize();
+
+    // 16 slots of 20 ms L16 at 48 kHz stereo
+    let config = Config {
+        slot_len: 1920,
+        ..Config::default()
+    };
+    let mut storage = vec![0; 16 * config.slot_len];
+    let jitter = JitterBuffer::new(&mut storage[..], config);
+
+    let mut sockets = SocketSet::new(vec![]);
+    let mut receiver = Receiver::new(
+        &mut sockets,
+        UdpSocketBuffer::new(vec![UdpPacketMetadata::EMPTY; 32], vec![0; 32 * 1500]),
+        UdpSocketBuffer::new(vec![], vec![]),
+        rtp::DEFAULT_PORT,
+        jitter,
+    );
+    receiver.set_payload_type(
+        rtp::DYNAMIC_PAYLOAD_TYPE,
+        Format {
+            encoding: Encoding::L16,
+            clock_rate: 48_000,
+            channels: 2,
+        },
+    );
+
+    println!("Receiving on port {}, writing {}", rtp::DEFAULT_PORT, path);
+
+    let start = Instant::now();
+    let mut played = 0;
+    let mut next_report = start + Duration::from_secs(1);
+    let mut buf = [0_i16; PERIOD_FRAMES * 2];
+
+    loop {
+        let now = Instant::now();
+        if let Err(e) = iface.poll(&mut sockets, now) {
+            println!("Error: {:?}", e);
+        }
+        receiver.poll(&mut sockets, now);
+
+        // Play out in periods as they come due
+        let due = (now - start).total_millis() as usize * config.output_rate as usize / 1000;
+        while played + PERIOD_FRAMES <= due {
+            let len = receiver.fill(&mut buf);
+            for sample in &buf[..len] {
+                out.write_all(&sample.to_le_bytes()).unwrap();
+            }
+            played += PERIOD_FRAMES;
+        }
+
+        if now >= next_report {
+            next_report += Duration::from_secs(1);
+            let jb = receiver.jitter_buffer();
+            if let (Some(ssrc), Some(format)) = (jb.ssrc(), jb.format()) {
+                let stats = receiver.stats();
+                println!(
+                    "{:08X} {:?} {} Hz from {}, playing {}",
+                    ssrc,
+                    format.encoding,
+                    format.clock_rate,
+                    receiver.source().unwrap(),
+                    jb.is_playing()
+                );
+                println!("  {:?}", stats);
+            }
+        }
+
+        wait(fd, Some(Duration::from_millis(5))).expect("Failed to wait on the tap interface");
+    }
+}
@@ -0,0 +1,197 @@
+// SIP phone on a Linux tap interface
+//
+// sudo ip tuntap add name tap0 mode tap user $USER
+// sudo ip link set tap0 up
+// sudo ip addr add 192.168.69.1/24 dev tap0
+// cargo run --example sip_tap --target x86_64-unknown-linux-gnu -- tap0 alice secret
+//
+// Registers alice@192.168.69.1 with the registrar on the host, a PBX or a
+// stand-in, from 192.168.69.2. Commands on stdin:
+//
+// call <user or sip: URI>
+// answer
+// hangup
+//
+// Calls send a dial tone standing in for the codec's capture path and write
+// what's received to sip.raw as s16le stereo at the codec's 48.828 kHz.
+//
+// ffplay -f s16le -ar 48828 -ac 2 sip.raw
+
+use net::jitter::{Config, JitterBuffer};
+use net::rtp::{self, Format, Ptime, Receiver, Sender, SenderConfig};
+use net::sip::{self, Event, UserAgent};
+use smoltcp::iface::{EthernetInterfaceBuilder, NeighborCache};
+use smoltcp::phy::{wait, TapInterface};
+use smoltcp::socket::{SocketSet, UdpPacketMetadata, UdpSocketBuffer};
+use smoltcp::time::{Duration, Instant};
+use smoltcp::wire::{EthernetAddress, IpAddress, IpCidr, IpEndpoint, Ipv4Address};
+use std::collections::BTreeMap;
+use std::env;
+use std::fs::File;
+use std::io::{self, BufRead, BufWriter, Write};
+use std::os::unix::io::AsRawFd;
+use std::sync::mpsc;
+use std::thread;
+use wm8960::audio::{AudioSource, Processed, NUM_CHANNELS, SAMPLE_RATE};
+use wm8960::biquad::{BiquadCascade, Coefficients, FilterType};
+use wm8960::tone::{CallProgress, ToneGenerator};
+
+const SRC_MAC: [u8; 6] = [0x02, 0x00, 0xDE, 0xAD, 0xBE, 0xEF];
+
+/// ~10 ms at the codec's rate
+const PERIOD_FRAMES: usize = SAMPLE_RATE as usize / 100;
+
+fn main() {
+    let name = env::args().nth(1).unwrap_or_else(|| "tap0".to_string());
+    let user = env::args().nth(2).unwrap_or_else(|| "alice".to_string());
+    let password = env::args().nth(3).unwrap_or_else(|| "secret".to_string());
+    let device = TapInterface::new(&name).expect("Failed to open the tap interface");
+    let fd = device.as_raw_fd();
+    let mut out = BufWriter::new(File::create("sip.raw").expect("Failed to create sip.raw"));
+
+    let ethernet_addr = EthernetAddress(SRC_MAC);
+    let address = Ipv4Address::new(192, 168, 69, 2);
+    let mut ip_addrs = [IpCidr::new(IpAddress::Ipv4(address), 24)];
+    let mut iface = EthernetInterfaceBuilder::new(device)
+        .ethernet_addr(ethernet_addr)
+        .neighbor_cache(NeighborCache::new(BTreeMap::new()))
+        .ip_addrs(&mut ip_addrs[..])
+        .finalize();
+
+    let mut sockets = SocketSet::new(vec![]);
+    let config = sip::Config {
+        // The config outlives main
+        user: Box::leak(user.into_boxed_str()),
+        password: Box::leak(password.into_boxed_str()),
+        domain: "192.168.69.1",
+        proxy: IpEndpoint::new(IpAddress::v4(192, 168, 69, 1), sip::DEFAULT_PORT),
+        expires: 300,
+        rtp_port: rtp::DEFAULT_PORT,
+        ptime: Ptime::Ms20,
+    };
+    let mut ua = UserAgent::new(
+        &mut sockets,
+        UdpSocketBuffer::new(vec![UdpPacketMetadata::EMPTY; 4], vec![0; 4 * 1500]),
+        UdpSocketBuffer::new(vec![UdpPacketMetadata::EMPTY; 4], vec![0; 4 * 1500]),
+        sip::DEFAULT_PORT,
+        config,
+        u32::from_be_bytes([SRC_MAC[2], SRC_MAC[3], SRC_MAC[4], SRC_MAC[5]])
+            ^ Instant::now().total_millis() as u32,
+    );
+    ua.set_address(address);
+
+    // The receiver's socket comes first so it gets the packets for the port
+    // both are bound to, the far end sees RTP coming from where it sends it
+    let jitter_config = Config {
+        output_rate: SAMPLE_RATE,
+        slot_len: 320,
+        ..Config::default()
+    };
+    let mut storage = vec![0; 16 * jitter_config.slot_len];
+    let mut receiver = Receiver::new(
+        &mut sockets,
+        UdpSocketBuffer::new(vec![UdpPacketMetadata::EMPTY; 16], vec![0; 16 * 512]),
+        UdpSocketBuffer::new(vec![], vec![]),
+        rtp::DEFAULT_PORT,
+        JitterBuffer::new(&mut storage[..], jitter_config),
+    );
+    let mut sender = Sender::new(
+        &mut sockets,
+        UdpSocketBuffer::new(vec![], vec![]),
+        UdpSocketBuffer::new(vec![UdpPacketMetadata::EMPTY; 8], vec![0; 8 * 512]),
+        rtp::DEFAULT_PORT,
+        config.proxy,
+        SenderConfig::new(Format::PCMU, SAMPLE_RATE, 0),
+    )
+    .expect("Unsupported sender configuration");
+
+    let cutoff = 8000.0 * 0.425;
+    let mut lowpass = BiquadCascade::new();
+    for q in &[0.54, 1.31] {
+        let coeffs = Coefficients::design(FilterType::LowPass, cutoff, *q, 0.0, SAMPLE_RATE)
+            .expect("Bad low-pass");
+        lowpass.push(coeffs).unwrap();
+    }
+    let mut tones = ToneGenerator::new(SAMPLE_RATE);
+    tones.play_call_progress(CallProgress::DialTone).unwrap();
+    let mut capture = Processed::new(&mut tones, lowpass);
+
+    let (commands, rx) = mpsc::channel();
+    thread::spawn(move || {
+        for line in io::stdin().lock().lines() {
+            if commands.send(line.unwrap()).is_err() {
+                break;
+            }
+        }
+    });
+
+    println!("Registering {}@{}", config.user, config.domain);
+
+    let start = Instant::now();
+    let mut periods = 0;
+    let mut in_call = false;
+    let mut buf = [0_i16; PERIOD_FRAMES * NUM_CHANNELS];
+
+    loop {
+        let now = Instant::now();
+
+        while let Ok(line) = rx.try_recv() {
+            let mut words = line.split_whitespace();
+            let result = match (words.next(), words.next()) {
+                (Some("call"), Some(target)) => ua.call(target),
+                (Some("answer"), None) => ua.answer(),
+                (Some("hangup"), None) => ua.hang_up(),
+                _ => {
+                    println!("call <user>, answer or hangup");
+                    Ok(())
+                }
+            };
+            if let Err(e) = result {
+                println!("Error: {:?}", e);
+            }
+        }
+
+        if let Err(e) = iface.poll(&mut sockets, now) {
+            println!("Error: {:?}", e);
+        }
+        receiver.poll(&mut sockets, now);
+
+        while let Some(event) = ua.poll(&mut sockets, now) {
+            println!("{:?}", event);
+            match event {
+                Event::IncomingCall => println!("Call from {}", ua.peer().unwrap_or("?")),
+                Event::MediaStarted(media) => {
+                    let ssrc = now.total_millis() as u32 ^ 0x5EED_0000;
+                    sender
+                        .restart(media.remote, media.sender_config(SAMPLE_RATE, ssrc))
+                        .expect("Unsupported media");
+                    in_call = true;
+                }
+                Event::CallEnded(_) => {
+                    println!("{:?}", sender.stats());
+                    println!("{:?}", receiver.stats());
+                    receiver.reset();
+                    in_call = false;
+                }
+                _ => (),
+            }
+        }
+
+        // Capture and play out in periods as they come due
+        let due = (now - start).total_millis() as usize * SAMPLE_RATE as usize / 1000;
+        while (periods + 1) * PERIOD_FRAMES <= due {
+            if in_call {
+                capture.fill(&mut buf);
+                sender.send(&mut sockets, &buf);
+            }
+            let len = receiver.fill(&mut buf);
+            for sample in &buf[..len] {
+                out.write_all(&sample.to_le_bytes()).unwrap();
+            }
+            periods += 1;
+        }
+        sender.poll(&mut sockets, now);
+
+        wait(fd, Some(Duration::from_millis(5))).expect("Failed to wait on the tap interface");
+    }
+}
@@ -0,0 +1,1065 @@
+// DHCPv4 client, RFC 2131
+//
+// smoltcp 0.5 has the DHCP wire format but no client. This one runs over a
+// raw IPv4/UDP socket so it sees replies addressed to an IP the interface
+// doesn't have yet. The interface replaces the 0.0.0.0 source of messages
+// sent without a lease by its first address, the fallback, where RFC 2131
+// wants 0.0.0.0 until the client is bound. `DhcpDevice` wraps the
+// interface's device and puts it back, along with the checksums.
+//
+// The interface needs one IPv4 address slot and one route slot. The address
+// slot holds the fallback, a static or link-local address, until a lease is
+// bound and again once a lease expires or is refused. The lease's router, if
+// any, becomes the default route.
+//
+// smoltcp 0.5 answers unicast replies, renewals, with an ICMP port
+// unreachable as no UDP socket is bound to port 68. Servers ignore it.
+
+use smoltcp::iface::EthernetInterface;
+use smoltcp::phy::{self, Checksum, ChecksumCapabilities, Device, DeviceCapabilities};
+use smoltcp::socket::{RawSocket, RawSocketBuffer, SocketHandle, SocketSet};
+use smoltcp::time::{Duration, Instant};
+use smoltcp::wire::{
+    DhcpMessageType, DhcpPacket, DhcpRepr, EthernetAddress, EthernetFrame, EthernetProtocol,
+    IpAddress, IpCidr, IpProtocol, IpVersion, Ipv4Address, Ipv4Cidr, Ipv4Packet, Ipv4Repr,
+    UdpPacket, UdpRepr,
+};
+
+pub const CLIENT_PORT: u16 = 68;
+pub const SERVER_PORT: u16 = 67;
+
+/// Subnet mask, router and DNS servers
+const PARAMETER_REQUEST_LIST: &[u8] = &[1, 3, 6];
+
+const OPT_PAD: u8 = 0;
+const OPT_LEASE_TIME: u8 = 51;
+const OPT_RENEWAL_TIME: u8 = 58;
+const OPT_REBINDING_TIME: u8 = 59;
+const OPT_END: u8 = 255;
+
+/// Smallest BOOTP message, shorter ones are dropped by some servers
+const MESSAGE_LEN: usize = 300;
+const IPV4_HEADER_LEN: usize = 20;
+const UDP_HEADER_LEN: usize = 8;
+/// Length of every packet sent
+pub const PACKET_LEN: usize = IPV4_HEADER_LEN + UDP_HEADER_LEN + MESSAGE_LEN;
+
+/// First retransmission timeout, doubled up to `MAX_RETRY_MS`
+const RETRY_MS: u64 = 4_000;
+const MAX_RETRY_MS: u64 = 64_000;
+/// Renewing and rebinding retransmit after half the time left to T2 or the
+/// end of the lease, but no sooner than this
+const MIN_RENEW_RETRY_MS: u64 = 60_000;
+/// DHCPREQUESTs sent for an offer before discovering again
+const MAX_REQUESTS: u32 = 4;
+/// Used when the server doesn't send a lease time
+const DEFAULT_LEASE_SECS: u32 = 3600;
+
+/// A bound lease, times are from the `now` passed to `DhcpClient::poll`
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Lease {
+    pub address: Ipv4Cidr,
+    pub router: Option<Ipv4Address>,
+    pub dns_servers: [Option<Ipv4Address>; 3],
+    /// Server identifier, renewals are sent here
+    pub server: Ipv4Address,
+    /// T1, start renewing with `server`
+    pub renew_at: Instant,
+    /// T2, start renewing with any server
+    pub rebind_at: Instant,
+    pub expires_at: Instant,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Event {
+    /// A new lease was applied to the interface
+    Bound(Lease),
+    /// The lease was extended, the address didn't change
+    Renewed(Lease),
+    /// The lease expired or was refused, the fallback is back in place
+    Expired(Ipv4Cidr),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum State {
+    Discovering,
+    Requesting {
+        offer: Ipv4Address,
+        server: Ipv4Address,
+    },
+    Bound,
+    Renewing,
+    Rebinding,
+}
+
+/// The parts of a DHCPOFFER, DHCPACK or DHCPNAK the client uses
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct Reply {
+    message_type: DhcpMessageType,
+    your_ip: Ipv4Address,
+    server: Option<Ipv4Address>,
+    subnet_mask: Option<Ipv4Address>,
+    router: Option<Ipv4Address>,
+    dns_servers: Option<[Option<Ipv4Address>; 3]>,
+    lease_secs: Option<u32>,
+    renew_secs: Option<u32>,
+    rebind_secs: Option<u32>,
+}
+
+#[derive(Debug)]
+pub struct DhcpClient {
+    handle: SocketHandle,
+    mac: EthernetAddress,
+    fallback: Ipv4Cidr,
+    state: State,
+    lease: Option<Lease>,
+    xid: u32,
+    retries: u32,
+    next_tx: Instant,
+}
+
+impl DhcpClient {
+    /// Add the client's raw socket to `sockets` and start discovering.
+    ///
+    /// `tx_buffer` needs room for a `PACKET_LEN` packet, `rx_buffer` for a
+    /// few 576 byte packets. The interface's first address should be
+    /// `fallback`.
+    pub fn new<'b, 'c>(
+        sockets: &mut SocketSet<'_, 'b, 'c>,
+        rx_buffer: RawSocketBuffer<'b, 'c>,
+        tx_buffer: RawSocketBuffer<'b, 'c>,
+        mac: EthernetAddress,
+        fallback: Ipv4Cidr,
+        now: Instant,
+    ) -> Self {
+        let socket = RawSocket::new(IpVersion::Ipv4, IpProtocol::Udp, rx_buffer, tx_buffer);
+        let m = mac.as_bytes();
+        let mut client = DhcpClient {
+            handle: sockets.add(socket),
+            mac,
+            fallback,
+            state: State::Discovering,
+            lease: None,
+            xid: u32::from_be_bytes([m[2], m[3], m[4], m[5]]) | 1,
+            retries: 0,
+            next_tx: now,
+        };
+        client.discover(now);
+        client
+    }
+
+    pub fn lease(&self) -> Option<&Lease> {
+        self.lease.as_ref()
+    }
+
+    /// The leased address, or the fallback
+    pub fn address(&self) -> Ipv4Cidr {
+        self.lease.map_or(self.fallback, |l| l.address)
+    }
+
+    /// Process replies, run the lease timers and queue (re)transmissions.
+    /// Call it with every `EthernetInterface::poll`.
+    pub fn poll<DeviceT>(
+        &mut self,
+        iface: &mut EthernetInterface<DeviceT>,
+        sockets: &mut SocketSet,
+        now: Instant,
+    ) -> Option<Event>
+    where
+        DeviceT: for<'d> Device<'d>,
+    {
+        let mut socket = sockets.get::<RawSocket>(self.handle);
+        let mut event = None;
+
+        while let Ok(packet) = socket.recv() {
+            if let Some(reply) = parse_reply(packet, self.mac, self.xid) {
+                event = self.process(&reply, iface, now).or(event);
+            }
+        }
+
+        if let Some(lease) = self.lease {
+            if now >= lease.expires_at {
+                self.expire(iface, now);
+                event = Some(Event::Expired(self.fallback));
+            } else if self.state == State::Bound && now >= lease.renew_at {
+                self.retransmit(State::Renewing, now);
+            } else if self.state == State::Renewing && now >= lease.rebind_at {
+                self.retransmit(State::Rebinding, now);
+            }
+        }
+
+        if self.state != State::Bound && now >= self.next_tx {
+            if let State::Requesting { .. } = self.state {
+                if self.retries >= MAX_REQUESTS {
+                    self.discover(now);
+                }
+            }
+            // A full buffer is retried with the next timeout
+            let _ = self.send(&mut socket);
+            self.next_tx = now + self.retry_timeout(now);
+            self.retries += 1;
+        }
+
+        event
+    }
+
+    fn process<DeviceT>(
+        &mut self,
+        reply: &Reply,
+        iface: &mut EthernetInterface<DeviceT>,
+        now: Instant,
+    ) -> Option<Event>
+    where
+        DeviceT: for<'d> Device<'d>,
+    {
+        match (self.state, reply.message_type) {
+            (State::Discovering, DhcpMessageType::Offer) => {
+                let server = reply.server?;
+                if !reply.your_ip.is_unicast() {
+                    return None;
+                }
+                self.retransmit(
+                    State::Requesting {
+                        offer: reply.your_ip,
+                        server,
+                    },
+                    now,
+                );
+                None
+            }
+            (State::Discovering, _) | (State::Bound, _) => None,
+            (_, DhcpMessageType::Ack) => {
+                let lease = lease(reply, self.lease.map(|l| l.server), now)?;
+                let renewed = self.lease.map(|l| l.address) == Some(lease.address);
+                self.lease = Some(lease);
+                self.state = State::Bound;
+                apply(iface, lease.address, lease.router);
+                if renewed {
+                    Some(Event::Renewed(lease))
+                } else {
+                    Some(Event::Bound(lease))
+                }
+            }
+            (_, DhcpMessageType::Nak) => {
+                let expired = self.lease.is_some();
+                self.expire(iface, now);
+                if expired {
+                    Some(Event::Expired(self.fallback))
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        }
+    }
+
+    fn expire<DeviceT>(&mut self, iface: &mut EthernetInterface<DeviceT>, now: Instant)
+    where
+        DeviceT: for<'d> Device<'d>,
+    {
+        if self.lease.take().is_some() {
+            apply(iface, self.fallback, None);
+        }
+        self.discover(now);
+    }
+
+    fn discover(&mut self, now: Instant) {
+        // xorshift32, new transaction ID per exchange
+        self.xid ^= now.total_millis() as u32;
+        self.xid ^= self.xid << 13;
+        self.xid ^= self.xid >> 17;
+        self.xid ^= self.xid << 5;
+        self.retransmit(State::Discovering, now);
+    }
+
+    /// RFC 2131 4.1 while acquiring a lease, 4.4.5 while extending one
+    fn retry_timeout(&self, now: Instant) -> Duration {
+        let until = match (self.state, self.lease) {
+            (State::Renewing, Some(lease)) => lease.rebind_at,
+            (State::Rebinding, Some(lease)) => lease.expires_at,
+            _ => {
+                let timeout = (RETRY_MS << self.retries.min(4)).min(MAX_RETRY_MS);
+                return Duration::from_millis(timeout);
+            }
+        };
+        Duration::from_millis(((until - now).total_millis() / 2).max(MIN_RENEW_RETRY_MS))
+    }
+
+    fn retransmit(&mut self, state: State, now: Instant) {
+        self.state = state;
+        self.retries = 0;
+        self.next_tx = now;
+    }
+
+    fn send(&self, socket: &mut RawSocket) -> smoltcp::Result<()> {
+        let unspecified = Ipv4Address::UNSPECIFIED;
+        let (message_type, client_ip, dst_addr, requested_ip, server_identifier) =
+            match (self.state, self.lease) {
+                (State::Discovering, _) => (
+                    DhcpMessageType::Discover,
+                    unspecified,
+                    Ipv4Address::BROADCAST,
+                    None,
+                    None,
+                ),
+                (State::Requesting { offer, server }, _) => (
+                    DhcpMessageType::Request,
+                    unspecified,
+                    Ipv4Address::BROADCAST,
+                    Some(offer),
+                    Some(server),
+                ),
+                (State::Renewing, Some(lease)) => (
+                    DhcpMessageType::Request,
+                    lease.address.address(),
+                    lease.server,
+                    None,
+                    None,
+                ),
+                (State::Rebinding, Some(lease)) => (
+                    DhcpMessageType::Request,
+                    lease.address.address(),
+                    Ipv4Address::BROADCAST,
+                    None,
+                    None,
+                ),
+                _ => return Ok(()),
+            };
+
+        let repr = DhcpRepr {
+            message_type,
+            transaction_id: self.xid,
+            client_hardware_address: self.mac,
+            client_ip,
+            your_ip: unspecified,
+            server_ip: unspecified,
+            router: None,
+            subnet_mask: None,
+            relay_agent_ip: unspecified,
+            // Without an address the reply can't be unicast to us
+            broadcast: client_ip.is_unspecified(),
+            requested_ip,
+            client_identifier: Some(self.mac),
+            server_identifier,
+            parameter_request_list: Some(PARAMETER_REQUEST_LIST),
+            dns_servers: None,
+        };
+        let mut message = [0; MESSAGE_LEN];
+        repr.emit(&mut DhcpPacket::new_unchecked(&mut message[..]))?;
+
+        let ipv4_repr = Ipv4Repr {
+            src_addr: client_ip,
+            dst_addr,
+            protocol: IpProtocol::Udp,
+            payload_len: UDP_HEADER_LEN + MESSAGE_LEN,
+            hop_limit: 64,
+        };
+        let udp_repr = UdpRepr {
+            src_port: CLIENT_PORT,
+            dst_port: SERVER_PORT,
+            payload: &message,
+        };
+
+        let mut checksum_caps = ChecksumCapabilities::default();
+        // The source changes on the way out, `DhcpDevice` fills it in then
+        if client_ip.is_unspecified() {
+            checksum_caps.udp = Checksum::None;
+        }
+        let buf = socket.send(PACKET_LEN)?;
+        ipv4_repr.emit(&mut Ipv4Packet::new_unchecked(&mut buf[..]), &checksum_caps);
+        udp_repr.emit(
+            &mut UdpPacket::new_unchecked(&mut buf[IPV4_HEADER_LEN..]),
+            &IpAddress::Ipv4(client_ip),
+            &IpAddress::Ipv4(dst_addr),
+            &checksum_caps,
+        );
+        Ok(())
+    }
+}
+
+/// The interface's device, sends what the client sends without an address
+/// from 0.0.0.0
+#[derive(Debug)]
+pub struct DhcpDevice<D> {
+    inner: D,
+}
+
+impl<D> DhcpDevice<D> {
+    pub fn new(inner: D) -> Self {
+        DhcpDevice { inner }
+    }
+
+    pub fn get_ref(&self) -> &D {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut D {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> D {
+        self.inner
+    }
+}
+
+impl<'a, D> Device<'a> for DhcpDevice<D>
+where
+    D: for<'b> Device<'b>,
+{
+    type RxToken = <D as Device<'a>>::RxToken;
+    type TxToken = TxToken<<D as Device<'a>>::TxToken>;
+
+    fn receive(&'a mut self) -> Option<(Self::RxToken, Self::TxToken)> {
+        let checksum_caps = self.inner.capabilities().checksum;
+        self.inner
+            .receive()
+            .map(|(rx, tx)| (rx, TxToken::new(tx, checksum_caps)))
+    }
+
+    fn transmit(&'a mut self) -> Option<Self::TxToken> {
+        let checksum_caps = self.inner.capabilities().checksum;
+        self.inner
+            .transmit()
+            .map(|tx| TxToken::new(tx, checksum_caps))
+    }
+
+    fn capabilities(&self) -> DeviceCapabilities {
+        self.inner.capabilities()
+    }
+}
+
+#[derive(Debug)]
+pub struct TxToken<T> {
+    inner: T,
+    checksum_caps: ChecksumCapabilities,
+}
+
+impl<T> TxToken<T> {
+    fn new(inner: T, checksum_caps: ChecksumCapabilities) -> Self {
+        TxToken {
+            inner,
+            checksum_caps,
+        }
+    }
+}
+
+impl<T: phy::TxToken> phy::TxToken for TxToken<T> {
+    fn consume<R, F>(self, timestamp: Instant, len: usize, f: F) -> smoltcp::Result<R>
+    where
+        F: FnOnce(&mut [u8]) -> smoltcp::Result<R>,
+    {
+        let checksum_caps = self.checksum_caps;
+        self.inner.consume(timestamp, len, |frame| {
+            let result = f(frame)?;
+            unbind_source(frame, &checksum_caps);
+            Ok(result)
+        })
+    }
+}
+
+/// Give a client message without a client address the 0.0.0.0 source back
+fn unbind_source(frame: &mut [u8], checksum_caps: &ChecksumCapabilities) -> Option<()> {
+    let mut eth = EthernetFrame::new_checked(frame).ok()?;
+    if eth.ethertype() != EthernetProtocol::Ipv4 {
+        return None;
+    }
+    let mut ipv4 = Ipv4Packet::new_checked(eth.payload_mut()).ok()?;
+    let src_addr = Ipv4Address::UNSPECIFIED;
+    let dst_addr = ipv4.dst_addr();
+    if ipv4.protocol() != IpProtocol::Udp || ipv4.src_addr() == src_addr {
+        return None;
+    }
+
+    let mut udp = UdpPacket::new_checked(ipv4.payload_mut()).ok()?;
+    if udp.src_port() != CLIENT_PORT || udp.dst_port() != SERVER_PORT {
+        return None;
+    }
+    let dhcp = DhcpPacket::new_checked(udp.payload_mut()).ok()?;
+    if !dhcp.client_ip().is_unspecified() {
+        return None;
+    }
+    if checksum_caps.udp.tx() {
+        udp.fill_checksum(&IpAddress::Ipv4(src_addr), &IpAddress::Ipv4(dst_addr));
+    }
+
+    ipv4.set_src_addr(src_addr);
+    if checksum_caps.ipv4.tx() {
+        ipv4.fill_checksum();
+    }
+    Some(())
+}
+
+/// A 169.254/16 address derived from `mac`, RFC 3927 without the probing
+pub fn link_local(mac: EthernetAddress) -> Ipv4Cidr {
+    let m = mac.as_bytes();
+    Ipv4Cidr::new(Ipv4Address::new(169, 254, 1 + m[4] % 254, m[5]), 16)
+}
+
+/// Replace the interface's first address and the default route
+fn apply<DeviceT>(
+    iface: &mut EthernetInterface<DeviceT>,
+    address: Ipv4Cidr,
+    router: Option<Ipv4Address>,
+) where
+    DeviceT: for<'d> Device<'d>,
+{
+    iface.update_ip_addrs(|addrs| {
+        if let Some(addr) = addrs.iter_mut().next() {
+            *addr = IpCidr::Ipv4(address);
+        }
+    });
+    let routes = iface.routes_mut();
+    match router {
+        // Only fails without a route slot
+        Some(router) => {
+            let _ = routes.add_default_ipv4_route(router);
+        }
+        None => routes.update(|routes| {
+            routes.remove(&IpCidr::new(IpAddress::v4(0, 0, 0, 0), 0));
+        }),
+    }
+}
+
+/// A lease from a DHCPACK, renewals keep the server if the ACK omits it
+fn lease(reply: &Reply, server: Option<Ipv4Address>, now: Instant) -> Option<Lease> {
+    if !reply.your_ip.is_unicast() {
+        return None;
+    }
+    let address = reply
+        .subnet_mask
+        .and_then(|mask| Ipv4Cidr::from_netmask(reply.your_ip, mask).ok())
+        .unwrap_or_else(|| Ipv4Cidr::new(reply.your_ip, 24));
+
+    let lease_secs = reply.lease_secs.unwrap_or(DEFAULT_LEASE_SECS);
+    let renew_secs = reply.renew_secs.unwrap_or(lease_secs / 2);
+    let rebind_secs = reply
+        .rebind_secs
+        .unwrap_or((u64::from(lease_secs) * 7 / 8) as u32);
+    let at = |secs: u32| now + Duration::from_secs(u64::from(secs));
+
+    Some(Lease {
+        address,
+        router: reply.router,
+        dns_servers: reply.dns_servers.unwrap_or([None; 3]),
+        server: reply.server.or(server)?,
+        renew_at: at(renew_secs),
+        rebind_at: at(rebind_secs.max(renew_secs)),
+        expires_at: at(lease_secs.max(rebind_secs)),
+    })
+}
+
+/// A reply to us in an IPv4 packet from the raw socket
+fn parse_reply(packet: &[u8], mac: EthernetAddress, xid: u32) -> Option<Reply> {
+    let checksum_caps = ChecksumCapabilities::default();
+    let ipv4 = Ipv4Packet::new_checked(packet).ok()?;
+    let ipv4_repr = Ipv4Repr::parse(&ipv4, &checksum_caps).ok()?;
+    let udp = UdpPacket::new_checked(ipv4.payload()).ok()?;
+    let udp_repr = UdpRepr::parse(
+        &udp,
+        &IpAddress::Ipv4(ipv4_repr.src_addr),
+        &IpAddress::Ipv4(ipv4_repr.dst_addr),
+        &checksum_caps,
+    )
+    .ok()?;
+    if udp_repr.src_port != SERVER_PORT || udp_repr.dst_port != CLIENT_PORT {
+        return None;
+    }
+
+    let dhcp = DhcpPacket::new_checked(udp_repr.payload).ok()?;
+    let repr = DhcpRepr::parse(&dhcp).ok()?;
+    if repr.transaction_id != xid || repr.client_hardware_address != mac {
+        return None;
+    }
+    let options = dhcp.options().ok()?;
+
+    Some(Reply {
+        message_type: repr.message_type,
+        your_ip: repr.your_ip,
+        server: repr.server_identifier,
+        subnet_mask: repr.subnet_mask,
+        router: repr.router,
+        dns_servers: repr.dns_servers,
+        lease_secs: option_u32(options, OPT_LEASE_TIME),
+        renew_secs: option_u32(options, OPT_RENEWAL_TIME),
+        rebind_secs: option_u32(options, OPT_REBINDING_TIME),
+    })
+}
+
+/// The timer options `DhcpRepr` skips
+fn option_u32(mut options: &[u8], kind: u8) -> Option<u32> {
+    while let Some((&code, rest)) = options.split_first() {
+        match code {
+            OPT_PAD => options = rest,
+            OPT_END => break,
+            _ => {
+                let (&len, rest) = rest.split_first()?;
+                let data = rest.get(..usize::from(len))?;
+                if code == kind && data.len() == 4 {
+                    return Some(u32::from_be_bytes([data[0], data[1], data[2], data[3]]));
+                }
+                options = &rest[usize::from(len)..];
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use smoltcp::iface::{EthernetInterfaceBuilder, NeighborCache, Routes};
+    use smoltcp::phy::RxToken;
+    use smoltcp::socket::RawPacketMetadata;
+    use smoltcp::wire::{ArpOperation, ArpPacket, ArpRepr, EthernetRepr};
+    use std::cell::RefCell;
+    use std::collections::{BTreeMap, VecDeque};
+    use std::rc::Rc;
+    use std::vec;
+    use std::vec::Vec;
+
+    const MAC: EthernetAddress = EthernetAddress([0x02, 0x00, 0x00, 0x00, 0x00, 0x01]);
+    const SERVER_MAC: EthernetAddress = EthernetAddress([0x02, 0x00, 0x00, 0x00, 0x00, 0x02]);
+    const SERVER: Ipv4Address = Ipv4Address([192, 168, 1, 1]);
+    const OFFER: Ipv4Address = Ipv4Address([192, 168, 1, 50]);
+
+    /// Lease, T1 and T2 in seconds
+    const LEASE: (u32, u32, u32) = (1000, 500, 875);
+
+    #[derive(Default)]
+    struct Frames {
+        rx: VecDeque<Vec<u8>>,
+        tx: Vec<Vec<u8>>,
+    }
+
+    /// Frames in and out, answers ARP for the server itself
+    #[derive(Default)]
+    struct TestDevice(Rc<RefCell<Frames>>);
+
+    struct TestRxToken(Vec<u8>);
+
+    impl RxToken for TestRxToken {
+        fn consume<R, F>(self, _timestamp: Instant, f: F) -> smoltcp::Result<R>
+        where
+            F: FnOnce(&[u8]) -> smoltcp::Result<R>,
+        {
+            f(&self.0)
+        }
+    }
+
+    struct TestTxToken<'a>(&'a TestDevice);
+
+    impl<'a> phy::TxToken for TestTxToken<'a> {
+        fn consume<R, F>(self, _timestamp: Instant, len: usize, f: F) -> smoltcp::Result<R>
+        where
+            F: FnOnce(&mut [u8]) -> smoltcp::Result<R>,
+        {
+            let mut frame = vec![0; len];
+            let result = f(&mut frame)?;
+            let mut frames = (self.0).0.borrow_mut();
+            match arp_reply(&frame) {
+                Some(reply) => frames.rx.push_back(reply),
+                None => frames.tx.push(frame),
+            }
+            Ok(result)
+        }
+    }
+
+    impl<'a> Device<'a> for TestDevice {
+        type RxToken = TestRxToken;
+        type TxToken = TestTxToken<'a>;
+
+        fn receive(&'a mut self) -> Option<(Self::RxToken, Self::TxToken)> {
+            let frame = self.0.borrow_mut().rx.pop_front()?;
+            Some((TestRxToken(frame), TestTxToken(self)))
+        }
+
+        fn transmit(&'a mut self) -> Option<Self::TxToken> {
+            Some(TestTxToken(self))
+        }
+
+        fn capabilities(&self) -> DeviceCapabilities {
+            let mut caps = DeviceCapabilities::default();
+            caps.max_transmission_unit = 1514;
+            caps
+        }
+    }
+
+    /// The server's answer to an ARP request for it
+    fn arp_reply(frame: &[u8]) -> Option<Vec<u8>> {
+        let eth = EthernetFrame::new_checked(frame).ok()?;
+        if eth.ethertype() != EthernetProtocol::Arp {
+            return None;
+        }
+        let request = ArpRepr::parse(&ArpPacket::new_checked(eth.payload()).ok()?).ok()?;
+        let (source_hardware_addr, source_protocol_addr) = match request {
+            ArpRepr::EthernetIpv4 {
+                operation: ArpOperation::Request,
+                source_hardware_addr,
+                source_protocol_addr,
+                target_protocol_addr,
+                ..
+            } if target_protocol_addr == SERVER => (source_hardware_addr, source_protocol_addr),
+            _ => return None,
+        };
+        let reply = ArpRepr::EthernetIpv4 {
+            operation: ArpOperation::Reply,
+            source_hardware_addr: SERVER_MAC,
+            source_protocol_addr: SERVER,
+            target_hardware_addr: source_hardware_addr,
+            target_protocol_addr: source_protocol_addr,
+        };
+        let mut frame = vec![0; EthernetFrame::<&[u8]>::buffer_len(reply.buffer_len())];
+        let mut eth = EthernetFrame::new_unchecked(&mut frame[..]);
+        EthernetRepr {
+            src_addr: SERVER_MAC,
+            dst_addr: source_hardware_addr,
+            ethertype: EthernetProtocol::Arp,
+        }
+        .emit(&mut eth);
+        reply.emit(&mut ArpPacket::new_unchecked(eth.payload_mut()));
+        Some(frame)
+    }
+
+    /// A DHCPOFFER, DHCPACK or DHCPNAK from the server, broadcast
+    fn reply(message_type: DhcpMessageType, xid: u32, your_ip: Ipv4Address) -> Vec<u8> {
+        let repr = DhcpRepr {
+            message_type,
+            transaction_id: xid,
+            client_hardware_address: MAC,
+            client_ip: Ipv4Address::UNSPECIFIED,
+            your_ip,
+            server_ip: Ipv4Address::UNSPECIFIED,
+            router: Some(SERVER),
+            subnet_mask: Some(Ipv4Address::new(255, 255, 255, 0)),
+            relay_agent_ip: Ipv4Address::UNSPECIFIED,
+            broadcast: true,
+            requested_ip: None,
+            client_identifier: None,
+            server_identifier: Some(SERVER),
+            parameter_request_list: None,
+            dns_servers: None,
+        };
+        let mut message = [0; MESSAGE_LEN];
+        repr.emit(&mut DhcpPacket::new_unchecked(&mut message[..]))
+            .unwrap();
+        // The timers go over the end of the options
+        {
+            let mut packet = DhcpPacket::new_unchecked(&mut message[..]);
+            let options = packet.options_mut().unwrap();
+            let mut end = 0;
+            while options[end] != OPT_END {
+                end += 2 + usize::from(options[end + 1]);
+            }
+            let (lease, renew, rebind) = LEASE;
+            let timers = [
+                (OPT_LEASE_TIME, lease),
+                (OPT_RENEWAL_TIME, renew),
+                (OPT_REBINDING_TIME, rebind),
+            ];
+            for (option, &(kind, secs)) in options[end..].chunks_exact_mut(6).zip(timers.iter()) {
+                option[..2].copy_from_slice(&[kind, 4]);
+                option[2..].copy_from_slice(&secs.to_be_bytes());
+            }
+            options[end + 6 * timers.len()] = OPT_END;
+        }
+
+        let checksum_caps = ChecksumCapabilities::default();
+        let udp_repr = UdpRepr {
+            src_port: SERVER_PORT,
+            dst_port: CLIENT_PORT,
+            payload: &message,
+        };
+        let ipv4_repr = Ipv4Repr {
+            src_addr: SERVER,
+            dst_addr: Ipv4Address::BROADCAST,
+            protocol: IpProtocol::Udp,
+            payload_len: udp_repr.buffer_len(),
+            hop_limit: 64,
+        };
+        let mut frame = vec![0; 14 + PACKET_LEN];
+        let mut eth = EthernetFrame::new_unchecked(&mut frame[..]);
+        EthernetRepr {
+            src_addr: SERVER_MAC,
+            dst_addr: EthernetAddress::BROADCAST,
+            ethertype: EthernetProtocol::Ipv4,
+        }
+        .emit(&mut eth);
+        let mut ipv4 = Ipv4Packet::new_unchecked(eth.payload_mut());
+        ipv4_repr.emit(&mut ipv4, &checksum_caps);
+        udp_repr.emit(
+            &mut UdpPacket::new_unchecked(ipv4.payload_mut()),
+            &IpAddress::Ipv4(SERVER),
+            &IpAddress::Ipv4(Ipv4Address::BROADCAST),
+            &checksum_caps,
+        );
+        frame
+    }
+
+    /// What the client sent, the checksums checked
+    #[derive(Debug, PartialEq)]
+    struct Sent {
+        src_addr: Ipv4Address,
+        dst_addr: Ipv4Address,
+        message_type: DhcpMessageType,
+        xid: u32,
+        client_ip: Ipv4Address,
+        requested_ip: Option<Ipv4Address>,
+    }
+
+    fn sent(frame: &[u8]) -> Sent {
+        let checksum_caps = ChecksumCapabilities::default();
+        let eth = EthernetFrame::new_checked(frame).unwrap();
+        assert_eq!(eth.src_addr(), MAC);
+        let ipv4 = Ipv4Packet::new_checked(eth.payload()).unwrap();
+        let ipv4_repr = Ipv4Repr::parse(&ipv4, &checksum_caps).unwrap();
+        let udp = UdpPacket::new_checked(ipv4.payload()).unwrap();
+        let src_addr = IpAddress::Ipv4(ipv4_repr.src_addr);
+        let dst_addr = IpAddress::Ipv4(ipv4_repr.dst_addr);
+        let udp_repr = UdpRepr::parse(&udp, &src_addr, &dst_addr, &checksum_caps).unwrap();
+        assert_eq!(
+            (udp_repr.src_port, udp_repr.dst_port),
+            (CLIENT_PORT, SERVER_PORT)
+        );
+        let dhcp = DhcpPacket::new_checked(udp_repr.payload).unwrap();
+        let repr = DhcpRepr::parse(&dhcp).unwrap();
+        Sent {
+            src_addr: ipv4_repr.src_addr,
+            dst_addr: ipv4_repr.dst_addr,
+            message_type: repr.message_type,
+            xid: repr.transaction_id,
+            client_ip: repr.client_ip,
+            requested_ip: repr.requested_ip,
+        }
+    }
+
+    struct Harness {
+        iface: EthernetInterface<'static, 'static, 'static, DhcpDevice<TestDevice>>,
+        sockets: SocketSet<'static, 'static, 'static>,
+        client: DhcpClient,
+        fallback: Ipv4Cidr,
+        frames: Rc<RefCell<Frames>>,
+    }
+
+    impl Harness {
+        fn new() -> Self {
+            let fallback = link_local(MAC);
+            let device = TestDevice::default();
+            let frames = device.0.clone();
+            let iface = EthernetInterfaceBuilder::new(DhcpDevice::new(device))
+                .ethernet_addr(MAC)
+                .neighbor_cache(NeighborCache::new(BTreeMap::new()))
+                .ip_addrs(vec![IpCidr::Ipv4(fallback)])
+                .routes(Routes::new(BTreeMap::new()))
+                .finalize();
+            let mut sockets = SocketSet::new(vec![]);
+            let client = DhcpClient::new(
+                &mut sockets,
+                RawSocketBuffer::new(vec![RawPacketMetadata::EMPTY; 4], vec![0; 4 * 576]),
+                RawSocketBuffer::new(vec![RawPacketMetadata::EMPTY], vec![0; PACKET_LEN]),
+                MAC,
+                fallback,
+                Instant::from_millis(0),
+            );
+            Harness {
+                iface,
+                sockets,
+                client,
+                fallback,
+                frames,
+            }
+        }
+
+        /// Deliver `frame`, run the client and return what it sent
+        fn step(&mut self, frame: Option<Vec<u8>>, now_ms: i64) -> (Option<Event>, Vec<Sent>) {
+            let now = Instant::from_millis(now_ms);
+            self.frames.borrow_mut().rx.extend(frame);
+            let _ = self.iface.poll(&mut self.sockets, now);
+            let event = self.client.poll(&mut self.iface, &mut self.sockets, now);
+            // Again for the ARP reply
+            let _ = self.iface.poll(&mut self.sockets, now);
+            let _ = self.iface.poll(&mut self.sockets, now);
+            let tx = self.frames.borrow_mut().tx.drain(..).collect::<Vec<_>>();
+            (event, tx.iter().map(|frame| sent(frame)).collect())
+        }
+
+        fn address(&self) -> IpCidr {
+            self.iface.ip_addrs()[0]
+        }
+
+        /// Discover, request and bind at 200 ms
+        fn bind(&mut self) -> Lease {
+            let (_, tx) = self.step(None, 0);
+            assert_eq!(
+                tx,
+                [Sent {
+                    src_addr: Ipv4Address::UNSPECIFIED,
+                    dst_addr: Ipv4Address::BROADCAST,
+                    message_type: DhcpMessageType::Discover,
+                    xid: self.client.xid,
+                    client_ip: Ipv4Address::UNSPECIFIED,
+                    requested_ip: None,
+                }]
+            );
+            assert_eq!(self.client.state, State::Discovering);
+
+            let offer = reply(DhcpMessageType::Offer, self.client.xid, OFFER);
+            let (event, tx) = self.step(Some(offer), 100);
+            assert_eq!(event, None);
+            assert_eq!(
+                self.client.state,
+                State::Requesting {
+                    offer: OFFER,
+                    server: SERVER
+                }
+            );
+            assert_eq!(
+                tx,
+                [Sent {
+                    src_addr: Ipv4Address::UNSPECIFIED,
+                    dst_addr: Ipv4Address::BROADCAST,
+                    message_type: DhcpMessageType::Request,
+                    xid: self.client.xid,
+                    client_ip: Ipv4Address::UNSPECIFIED,
+                    requested_ip: Some(OFFER),
+                }]
+            );
+
+            let ack = reply(DhcpMessageType::Ack, self.client.xid, OFFER);
+            let (event, tx) = self.step(Some(ack), 200);
+            let lease = match event {
+                Some(Event::Bound(lease)) => lease,
+                e => panic!("{:?}", e),
+            };
+            assert_eq!(self.client.state, State::Bound);
+            assert_eq!(tx, []);
+            assert_eq!(lease.address, Ipv4Cidr::new(OFFER, 24));
+            assert_eq!(lease.router, Some(SERVER));
+            assert_eq!(lease.server, SERVER);
+            assert_eq!(lease.renew_at, Instant::from_millis(500_200));
+            assert_eq!(lease.rebind_at, Instant::from_millis(875_200));
+            assert_eq!(lease.expires_at, Instant::from_millis(1_000_200));
+            assert_eq!(self.address(), IpCidr::Ipv4(lease.address));
+            lease
+        }
+
+        /// The DHCPREQUEST extending the lease
+        fn extending(&self, dst_addr: Ipv4Address) -> Sent {
+            Sent {
+                src_addr: OFFER,
+                dst_addr,
+                message_type: DhcpMessageType::Request,
+                xid: self.client.xid,
+                client_ip: OFFER,
+                requested_ip: None,
+            }
+        }
+    }
+
+    #[test]
+    fn bind() {
+        Harness::new().bind();
+    }
+
+    #[test]
+    fn renew_rebind_expire() {
+        let mut h = Harness::new();
+        h.bind();
+        assert_eq!(h.step(None, 500_199).1, []);
+
+        // T1, unicast to the server
+        let (_, tx) = h.step(None, 500_200);
+        assert_eq!(h.client.state, State::Renewing);
+        assert_eq!(tx, [h.extending(SERVER)]);
+        // Half the time left to T2, then 60 s at least
+        for &(before, at) in [(687_699, 687_700), (781_449, 781_450), (841_449, 841_450)].iter() {
+            assert_eq!(h.step(None, before).1, []);
+            assert_eq!(h.step(None, at).1, [h.extending(SERVER)]);
+        }
+
+        // T2, broadcast
+        let (_, tx) = h.step(None, 875_200);
+        assert_eq!(h.client.state, State::Rebinding);
+        assert_eq!(tx, [h.extending(Ipv4Address::BROADCAST)]);
+        // Half the time left on the lease, then 60 s at least
+        for &(before, at) in [(937_699, 937_700), (997_699, 997_700)].iter() {
+            assert_eq!(h.step(None, before).1, []);
+            assert_eq!(h.step(None, at).1, [h.extending(Ipv4Address::BROADCAST)]);
+        }
+        assert_eq!(h.step(None, 1_000_199).1, []);
+
+        let (event, tx) = h.step(None, 1_000_200);
+        assert_eq!(event, Some(Event::Expired(h.fallback)));
+        assert_eq!(h.client.state, State::Discovering);
+        assert_eq!(h.address(), IpCidr::Ipv4(h.fallback));
+        assert_eq!(tx.len(), 1);
+        assert_eq!(tx[0].message_type, DhcpMessageType::Discover);
+        assert_eq!(tx[0].src_addr, Ipv4Address::UNSPECIFIED);
+    }
+
+    #[test]
+    fn renewed() {
+        let mut h = Harness::new();
+        let lease = h.bind();
+        h.step(None, 500_200);
+        assert_eq!(h.client.state, State::Renewing);
+
+        let ack = reply(DhcpMessageType::Ack, h.client.xid, OFFER);
+        let (event, tx) = h.step(Some(ack), 501_000);
+        let renewed = match event {
+            Some(Event::Renewed(renewed)) => renewed,
+            e => panic!("{:?}", e),
+        };
+        assert_eq!(renewed.address, lease.address);
+        assert_eq!(renewed.renew_at, Instant::from_millis(1_001_000));
+        assert_eq!(h.client.state, State::Bound);
+        assert_eq!(tx, []);
+    }
+
+    #[test]
+    fn nak_while_requesting() {
+        let mut h = Harness::new();
+        h.step(None, 0);
+        let offer = reply(DhcpMessageType::Offer, h.client.xid, OFFER);
+        h.step(Some(offer), 100);
+        let xid = h.client.xid;
+
+        let nak = reply(DhcpMessageType::Nak, xid, Ipv4Address::UNSPECIFIED);
+        let (event, tx) = h.step(Some(nak), 200);
+        assert_eq!(event, None);
+        assert_eq!(h.client.state, State::Discovering);
+        assert_ne!(h.client.xid, xid);
+        assert_eq!(tx.len(), 1);
+        assert_eq!(tx[0].message_type, DhcpMessageType::Discover);
+        assert_eq!(h.address(), IpCidr::Ipv4(h.fallback));
+    }
+
+    #[test]
+    fn nak_while_renewing() {
+        let mut h = Harness::new();
+        h.bind();
+        h.step(None, 500_200);
+        assert_eq!(h.client.state, State::Renewing);
+
+        let nak = reply(DhcpMessageType::Nak, h.client.xid, Ipv4Address::UNSPECIFIED);
+        let (event, tx) = h.step(Some(nak), 501_000);
+        assert_eq!(event, Some(Event::Expired(h.fallback)));
+        assert_eq!(h.client.state, State::Discovering);
+        assert_eq!(h.address(), IpCidr::Ipv4(h.fallback));
+        assert_eq!(tx.len(), 1);
+        assert_eq!(tx[0].message_type, DhcpMessageType::Discover);
+        assert_eq!(tx[0].src_addr, Ipv4Address::UNSPECIFIED);
+    }
+
+    #[test]
+    fn ignores_other_transactions() {
+        let mut h = Harness::new();
+        h.step(None, 0);
+        let offer = reply(DhcpMessageType::Offer, h.client.xid ^ 1, OFFER);
+        let (event, tx) = h.step(Some(offer), 100);
+        assert_eq!(event, None);
+        assert_eq!(h.client.state, State::Discovering);
+        assert_eq!(tx, []);
+    }
+}
@@ -0,0 +1,73 @@
+// G.711 companding, ITU-T G.711
+//
+// 8-bit mu-law (PCMU) and A-law (PCMA) to and from 16-bit linear samples,
+// computed rather than looked up to keep them out of flash.
+
+/// Added to mu-law magnitudes so every segment has a leading one, 16-bit
+const ULAW_BIAS: i32 = 0x84;
+/// Largest 14-bit mu-law magnitude before the bias
+const ULAW_CLIP: i32 = 8159;
+
+pub fn ulaw_encode(sample: i16) -> u8 {
+    // 14-bit magnitude
+    let mut magnitude = i32::from(sample) >> 2;
+    let mask = if magnitude < 0 {
+        magnitude = -magnitude;
+        0x7F
+    } else {
+        0xFF
+    };
+    magnitude = magnitude.min(ULAW_CLIP) + (ULAW_BIAS >> 2);
+    let segment = (0..8).find(|s| magnitude < (0x40 << s)).unwrap_or(8);
+    if segment == 8 {
+        return 0x7F ^ mask;
+    }
+    let code = (segment << 4) as u8 | ((magnitude >> (segment + 1)) & 0x0F) as u8;
+    code ^ mask
+}
+
+pub fn ulaw_decode(code: u8) -> i16 {
+    let code = !code;
+    let exponent = (code >> 4) & 0x07;
+    let mantissa = i32::from(code & 0x0F);
+    let magnitude = (((mantissa << 3) + ULAW_BIAS) << exponent) - ULAW_BIAS;
+    if code & 0x80 != 0 {
+        -magnitude as i16
+    } else {
+        magnitude as i16
+    }
+}
+
+pub fn alaw_encode(sample: i16) -> u8 {
+    // 13-bit magnitude, negative values are one's complement
+    let mut magnitude = i32::from(sample) >> 3;
+    let mask = if magnitude >= 0 {
+        0xD5
+    } else {
+        magnitude = -magnitude - 1;
+        0x55
+    };
+    let segment = (0..8).find(|s| magnitude < (0x20 << s)).unwrap_or(8);
+    if segment == 8 {
+        return 0x7F ^ mask;
+    }
+    let shift = if segment < 2 { 1 } else { segment };
+    let code = (segment << 4) as u8 | ((magnitude >> shift) & 0x0F) as u8;
+    code ^ mask
+}
+
+pub fn alaw_decode(code: u8) -> i16 {
+    let code = code ^ 0x55;
+    let mut magnitude = i32::from(code & 0x0F) << 4;
+    let segment = (code & 0x70) >> 4;
+    match segment {
+        0 => magnitude += 8,
+        1 => magnitude += 0x108,
+        _ => magnitude = (magnitude + 0x108) << (segment - 1),
+    }
+    if code & 0x80 != 0 {
+        magnitude as i16
+    } else {
+        -magnitude as i16
+    }
+}
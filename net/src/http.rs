@@ -0,0 +1,815 @@
+// Minimal HTTP/1.1 server, RFC 7230
+//
+// Each connection slot owns a TCP socket listening on the server's port and
+// a buffer its requests are collected in, so the number of slots is the
+// number of concurrent connections. A request, headers and body, must fit
+// in the slot's buffer and a response in the socket's transmit buffer.
+// Requests on a connection are handled one at a time, a pipelined request
+// once the previous response has been sent.
+//
+// Request bodies need a Content-Length. Responses written without a length
+// are sent chunked to HTTP/1.1 clients and end the connection of HTTP/1.0
+// ones.
+
+use core::fmt::{self, Write};
+use core::str;
+use smoltcp::socket::{SocketHandle, SocketSet, TcpSocket, TcpSocketBuffer};
+use smoltcp::time::Duration;
+
+/// Size of a slot's request buffer
+pub const REQUEST_LEN: usize = 1024;
+/// Path parameters a route can capture
+pub const MAX_PARAMS: usize = 4;
+
+/// Idle connections are dropped after this
+const TIMEOUT_MS: u64 = 10_000;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Method {
+    GET,
+    HEAD,
+    POST,
+    PUT,
+    DELETE,
+    OPTIONS,
+    PATCH,
+}
+
+impl Method {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "GET" => Some(Method::GET),
+            "HEAD" => Some(Method::HEAD),
+            "POST" => Some(Method::POST),
+            "PUT" => Some(Method::PUT),
+            "DELETE" => Some(Method::DELETE),
+            "OPTIONS" => Some(Method::OPTIONS),
+            "PATCH" => Some(Method::PATCH),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Method::GET => "GET",
+            Method::HEAD => "HEAD",
+            Method::POST => "POST",
+            Method::PUT => "PUT",
+            Method::DELETE => "DELETE",
+            Method::OPTIONS => "OPTIONS",
+            Method::PATCH => "PATCH",
+        }
+    }
+}
+
+/// Set of methods, the `Allow` header of a 405 response
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct Allow(u8);
+
+impl Allow {
+    const METHODS: [Method; 7] = [
+        Method::GET,
+        Method::HEAD,
+        Method::POST,
+        Method::PUT,
+        Method::DELETE,
+        Method::OPTIONS,
+        Method::PATCH,
+    ];
+
+    fn insert(&mut self, method: Method) {
+        self.0 |= 1 << method as u8;
+    }
+
+    fn contains(self, method: Method) -> bool {
+        self.0 & (1 << method as u8) != 0
+    }
+
+    fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+}
+
+impl fmt::Display for Allow {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut methods = Allow::METHODS.iter().filter(|m| self.contains(**m));
+        if let Some(first) = methods.next() {
+            f.write_str(first.as_str())?;
+        }
+        for method in methods {
+            write!(f, ", {}", method.as_str())?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Version {
+    Http10,
+    Http11,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Status {
+    Ok,
+    Created,
+    Accepted,
+    NoContent,
+    BadRequest,
+    NotFound,
+    MethodNotAllowed,
+    Conflict,
+    PayloadTooLarge,
+    UnsupportedMediaType,
+    UnprocessableEntity,
+    InternalServerError,
+    NotImplemented,
+    ServiceUnavailable,
+    VersionNotSupported,
+}
+
+impl Status {
+    pub fn code(self) -> u16 {
+        match self {
+            Status::Ok => 200,
+            Status::Created => 201,
+            Status::Accepted => 202,
+            Status::NoContent => 204,
+            Status::BadRequest => 400,
+            Status::NotFound => 404,
+            Status::MethodNotAllowed => 405,
+            Status::Conflict => 409,
+            Status::PayloadTooLarge => 413,
+            Status::UnsupportedMediaType => 415,
+            Status::UnprocessableEntity => 422,
+            Status::InternalServerError => 500,
+            Status::NotImplemented => 501,
+            Status::ServiceUnavailable => 503,
+            Status::VersionNotSupported => 505,
+        }
+    }
+
+    pub fn reason(self) -> &'static str {
+        match self {
+            Status::Ok => "OK",
+            Status::Created => "Created",
+            Status::Accepted => "Accepted",
+            Status::NoContent => "No Content",
+            Status::BadRequest => "Bad Request",
+            Status::NotFound => "Not Found",
+            Status::MethodNotAllowed => "Method Not Allowed",
+            Status::Conflict => "Conflict",
+            Status::PayloadTooLarge => "Payload Too Large",
+            Status::UnsupportedMediaType => "Unsupported Media Type",
+            Status::UnprocessableEntity => "Unprocessable Entity",
+            Status::InternalServerError => "Internal Server Error",
+            Status::NotImplemented => "Not Implemented",
+            Status::ServiceUnavailable => "Service Unavailable",
+            Status::VersionNotSupported => "HTTP Version Not Supported",
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Error {
+    /// Malformed request line or header
+    Malformed,
+    /// Request doesn't fit in the buffer
+    TooLarge,
+    UnknownMethod,
+    UnsupportedVersion,
+    /// Chunked or otherwise encoded request body
+    UnsupportedTransferEncoding,
+}
+
+impl Error {
+    /// Status of the response sent before closing the connection
+    pub fn status(self) -> Status {
+        match self {
+            Error::Malformed => Status::BadRequest,
+            Error::TooLarge => Status::PayloadTooLarge,
+            Error::UnknownMethod => Status::NotImplemented,
+            Error::UnsupportedVersion => Status::VersionNotSupported,
+            Error::UnsupportedTransferEncoding => Status::NotImplemented,
+        }
+    }
+}
+
+/// Path parameters captured by a route's `{name}` segments
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Params<'a> {
+    entries: [(&'a str, &'a str); MAX_PARAMS],
+    len: usize,
+}
+
+impl<'a> Params<'a> {
+    fn new() -> Self {
+        Params {
+            entries: [("", ""); MAX_PARAMS],
+            len: 0,
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&'a str> {
+        self.entries[..self.len]
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, v)| *v)
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Request<'a> {
+    pub method: Method,
+    /// Target path without the query, not percent-decoded
+    pub path: &'a str,
+    pub query: Option<&'a str>,
+    pub version: Version,
+    pub body: &'a [u8],
+    pub params: Params<'a>,
+    /// Header lines, CRLF separated
+    headers: &'a str,
+}
+
+impl<'a> Request<'a> {
+    /// Header fields as (name, value), values trimmed
+    pub fn headers(&self) -> impl Iterator<Item = (&'a str, &'a str)> {
+        self.headers.split("\r\n").filter_map(|line| {
+            let colon = line.find(':')?;
+            Some((&line[..colon], line[colon + 1..].trim()))
+        })
+    }
+
+    /// Value of the first header field named `name`, ignoring case
+    pub fn header(&self, name: &str) -> Option<&'a str> {
+        self.headers()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v)
+    }
+
+    /// Path parameter captured by the route
+    pub fn param(&self, name: &str) -> Option<&'a str> {
+        self.params.get(name)
+    }
+
+    /// Value of `name=value` in the query, not percent-decoded
+    pub fn query_param(&self, name: &str) -> Option<&'a str> {
+        self.query?.split('&').find_map(|pair| {
+            let mut kv = pair.splitn(2, '=');
+            if kv.next()? == name {
+                Some(kv.next().unwrap_or(""))
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Whether the client wants the connection kept open after the response
+    pub fn keep_alive(&self) -> bool {
+        let mut keep_alive = self.version == Version::Http11;
+        for (_, value) in self
+            .headers()
+            .filter(|(n, _)| n.eq_ignore_ascii_case("Connection"))
+        {
+            for token in value.split(',').map(str::trim) {
+                if token.eq_ignore_ascii_case("close") {
+                    return false;
+                } else if token.eq_ignore_ascii_case("keep-alive") {
+                    keep_alive = true;
+                }
+            }
+        }
+        keep_alive
+    }
+}
+
+/// Parse the request at the start of `input`, `Ok(None)` until all of it,
+/// including the body, is there. Also returns the length of the request.
+pub fn parse_request(input: &[u8]) -> Result<Option<(Request<'_>, usize)>, Error> {
+    let head_len = match input.windows(4).position(|w| w == b"\r\n\r\n") {
+        Some(pos) => pos,
+        None => return Ok(None),
+    };
+    let head = str::from_utf8(&input[..head_len]).map_err(|_| Error::Malformed)?;
+    let (request_line, headers) = match head.find("\r\n") {
+        Some(pos) => (&head[..pos], &head[pos + 2..]),
+        None => (head, ""),
+    };
+
+    let mut parts = request_line.split(' ');
+    let method = parts.next().ok_or(Error::Malformed)?;
+    let target = parts.next().ok_or(Error::Malformed)?;
+    let version = match parts.next().ok_or(Error::Malformed)? {
+        "HTTP/1.1" => Version::Http11,
+        "HTTP/1.0" => Version::Http10,
+        v if v.starts_with("HTTP/") => return Err(Error::UnsupportedVersion),
+        _ => return Err(Error::Malformed),
+    };
+    if parts.next().is_some() || !target.starts_with('/') {
+        return Err(Error::Malformed);
+    }
+    let method = Method::parse(method).ok_or(Error::UnknownMethod)?;
+    let (path, query) = match target.find('?') {
+        Some(pos) => (&target[..pos], Some(&target[pos + 1..])),
+        None => (target, None),
+    };
+
+    let mut request = Request {
+        method,
+        path,
+        query,
+        version,
+        body: &[],
+        params: Params::new(),
+        headers,
+    };
+    if !headers.is_empty() && headers.split("\r\n").any(|line| !is_header(line)) {
+        return Err(Error::Malformed);
+    }
+    if request.header("Transfer-Encoding").is_some() {
+        return Err(Error::UnsupportedTransferEncoding);
+    }
+    let body_len = match request.header("Content-Length") {
+        Some(len) => len.parse::<usize>().map_err(|_| Error::Malformed)?,
+        None => 0,
+    };
+
+    let body_start = head_len + 4;
+    if body_len > REQUEST_LEN {
+        return Err(Error::TooLarge);
+    }
+    if input.len() < body_start + body_len {
+        return Ok(None);
+    }
+    request.body = &input[body_start..body_start + body_len];
+    Ok(Some((request, body_start + body_len)))
+}
+
+/// `name: value` with a token name
+fn is_header(line: &str) -> bool {
+    match line.find(':') {
+        Some(colon) => {
+            colon > 0
+                && line[..colon]
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+        }
+        None => false,
+    }
+}
+
+/// Match `path` against a route pattern, `{name}` segments match any
+/// non-empty segment
+fn match_path<'a>(pattern: &'a str, path: &'a str) -> Option<Params<'a>> {
+    let mut params = Params::new();
+    let mut segments = path.split('/');
+    for expected in pattern.split('/') {
+        let segment = segments.next()?;
+        if expected.starts_with('{') && expected.ends_with('}') {
+            if segment.is_empty() || params.len == MAX_PARAMS {
+                return None;
+            }
+            params.entries[params.len] = (&expected[1..expected.len() - 1], segment);
+            params.len += 1;
+        } else if expected != segment {
+            return None;
+        }
+    }
+    if segments.next().is_some() {
+        return None;
+    }
+    Some(params)
+}
+
+pub type Handler<S> = fn(&mut S, &Request<'_>, &mut Response<'_, '_>);
+
+/// Entry of a server's route table. GET routes also answer HEAD.
+///
+/// `S` can be a trait object, which keeps board specific types out of a
+/// static table.
+pub struct Route<S: ?Sized> {
+    pub method: Method,
+    /// Absolute path, `{name}` segments are captured as parameters
+    pub path: &'static str,
+    pub handler: Handler<S>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Body {
+    /// Nothing written yet
+    Empty,
+    /// Status line and headers written, body has a length or is suppressed
+    Sized,
+    Chunked,
+    /// Unsized body to an HTTP/1.0 client, ended by closing the connection
+    Close,
+}
+
+/// Response to a request, written straight into the connection's socket
+pub struct Response<'r, 'a: 'r> {
+    socket: &'r mut TcpSocket<'a>,
+    keep_alive: bool,
+    /// Response to HEAD, only the headers are sent
+    head: bool,
+    version: Version,
+    body: Body,
+    overflow: bool,
+}
+
+impl<'r, 'a: 'r> Response<'r, 'a> {
+    fn new(socket: &'r mut TcpSocket<'a>, request: Option<&Request<'_>>) -> Self {
+        Response {
+            socket,
+            keep_alive: request.map(Request::keep_alive).unwrap_or(false),
+            head: request.map(|r| r.method == Method::HEAD).unwrap_or(false),
+            version: request.map(|r| r.version).unwrap_or(Version::Http11),
+            body: Body::Empty,
+            overflow: false,
+        }
+    }
+
+    /// Send a complete response
+    pub fn send(&mut self, status: Status, content_type: &str, body: &[u8]) {
+        if self.body != Body::Empty {
+            return;
+        }
+        self.body = Body::Sized;
+        self.status_line(status);
+        if !body.is_empty() || status != Status::NoContent {
+            self.fmt(format_args!(
+                "Content-Type: {}\r\nContent-Length: {}\r\n",
+                content_type,
+                body.len()
+            ));
+        }
+        self.end_headers();
+        if !self.head {
+            self.raw(body);
+        }
+    }
+
+    /// Send the status's reason as a plain text response
+    pub fn error(&mut self, status: Status) {
+        self.error_allow(status, Allow(0));
+    }
+
+    /// `error`, with an `Allow` header unless `allow` is empty
+    fn error_allow(&mut self, status: Status, allow: Allow) {
+        let reason = status.reason();
+        if self.body != Body::Empty {
+            return;
+        }
+        self.body = Body::Sized;
+        self.status_line(status);
+        if !allow.is_empty() {
+            self.fmt(format_args!("Allow: {}\r\n", allow));
+        }
+        self.fmt(format_args!(
+            "Content-Type: text/plain\r\nContent-Length: {}\r\n",
+            reason.len() + 1
+        ));
+        self.end_headers();
+        if !self.head {
+            self.raw(reason.as_bytes());
+            self.raw(b"\n");
+        }
+    }
+
+    /// Start a response whose body is written afterwards, with `write_body`
+    /// or `write!`
+    pub fn start(&mut self, status: Status, content_type: &str) {
+        if self.body != Body::Empty {
+            return;
+        }
+        self.status_line(status);
+        self.fmt(format_args!("Content-Type: {}\r\n", content_type));
+        if self.version == Version::Http11 {
+            self.body = Body::Chunked;
+            self.raw(b"Transfer-Encoding: chunked\r\n");
+        } else {
+            self.body = Body::Close;
+            self.keep_alive = false;
+        }
+        self.end_headers();
+    }
+
+    /// Append to the body of a started response
+    pub fn write_body(&mut self, data: &[u8]) {
+        if self.head || data.is_empty() {
+            return;
+        }
+        match self.body {
+            Body::Chunked => {
+                self.fmt(format_args!("{:x}\r\n", data.len()));
+                self.raw(data);
+                self.raw(b"\r\n");
+            }
+            Body::Close => self.raw(data),
+            Body::Empty | Body::Sized => (),
+        }
+    }
+
+    /// The response didn't fit in the socket's transmit buffer, the
+    /// connection is closed once it's handled
+    pub fn overflowed(&self) -> bool {
+        self.overflow
+    }
+
+    /// Complete the response, returns whether to keep the connection open
+    fn finish(&mut self) -> bool {
+        match self.body {
+            Body::Empty => self.error(Status::InternalServerError),
+            Body::Chunked if !self.head => self.raw(b"0\r\n\r\n"),
+            _ => (),
+        }
+        self.keep_alive && !self.overflow
+    }
+
+    fn status_line(&mut self, status: Status) {
+        self.fmt(format_args!(
+            "HTTP/1.1 {} {}\r\n",
+            status.code(),
+            status.reason()
+        ));
+    }
+
+    fn end_headers(&mut self) {
+        if self.keep_alive {
+            // HTTP/1.0 clients need to be told, 1.1 ones assume it
+            if self.version == Version::Http10 {
+                self.raw(b"Connection: keep-alive\r\n");
+            }
+        } else {
+            self.raw(b"Connection: close\r\n");
+        }
+        self.raw(b"\r\n");
+    }
+
+    fn fmt(&mut self, args: fmt::Arguments) {
+        let mut writer = Raw(self);
+        let _ = writer.write_fmt(args);
+    }
+
+    fn raw(&mut self, data: &[u8]) {
+        if self.overflow {
+            return;
+        }
+        match self.socket.send_slice(data) {
+            Ok(n) if n == data.len() => (),
+            _ => self.overflow = true,
+        }
+    }
+}
+
+impl<'r, 'a: 'r> fmt::Write for Response<'r, 'a> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write_body(s.as_bytes());
+        if self.overflow {
+            Err(fmt::Error)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Writes formatted status lines and headers unchunked
+struct Raw<'w, 'r, 'a: 'r>(&'w mut Response<'r, 'a>);
+
+impl<'w, 'r, 'a: 'r> fmt::Write for Raw<'w, 'r, 'a> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.0.raw(s.as_bytes());
+        Ok(())
+    }
+}
+
+/// A connection, its socket and request buffer
+pub struct Slot {
+    handle: SocketHandle,
+    buffer: [u8; REQUEST_LEN],
+    len: usize,
+}
+
+impl Slot {
+    /// Add the connection's socket to `sockets`, the transmit buffer bounds
+    /// the size of a response
+    pub fn new<'b>(
+        sockets: &mut SocketSet<'_, 'b, 'static>,
+        rx_buffer: TcpSocketBuffer<'b>,
+        tx_buffer: TcpSocketBuffer<'b>,
+    ) -> Self {
+        let handle = sockets.add(TcpSocket::new(rx_buffer, tx_buffer));
+        Slot {
+            handle,
+            buffer: [0; REQUEST_LEN],
+            len: 0,
+        }
+    }
+
+    pub fn handle(&self) -> SocketHandle {
+        self.handle
+    }
+}
+
+impl fmt::Debug for Slot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Slot")
+            .field("handle", &self.handle)
+            .field("len", &self.len)
+            .finish()
+    }
+}
+
+/// Serves `routes` on every slot, handlers get the `S` passed to `poll`
+pub struct Server<'s, S: ?Sized + 'static> {
+    port: u16,
+    routes: &'static [Route<S>],
+    slots: &'s mut [Slot],
+}
+
+impl<'s, S: ?Sized + 'static> Server<'s, S> {
+    pub fn new(port: u16, routes: &'static [Route<S>], slots: &'s mut [Slot]) -> Self {
+        Server {
+            port,
+            routes,
+            slots,
+        }
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// Listen on closed slots and handle the requests received, call after
+    /// `EthernetInterface::poll`
+    pub fn poll(&mut self, sockets: &mut SocketSet, state: &mut S) {
+        for slot in self.slots.iter_mut() {
+            let mut socket = sockets.get::<TcpSocket>(slot.handle);
+            if !socket.is_open() {
+                slot.len = 0;
+                if socket.listen(self.port).is_ok() {
+                    socket.set_timeout(Some(Duration::from_millis(TIMEOUT_MS)));
+                }
+                continue;
+            }
+
+            if socket.can_recv() && slot.len < REQUEST_LEN {
+                slot.len += socket.recv_slice(&mut slot.buffer[slot.len..]).unwrap_or(0);
+            }
+
+            // One request at a time, once the previous response is out
+            if slot.len > 0 && socket.may_send() && socket.send_queue() == 0 {
+                let keep_alive = match parse_request(&slot.buffer[..slot.len]) {
+                    Ok(Some((request, len))) => {
+                        let keep_alive = dispatch(self.routes, state, &mut socket, &request);
+                        slot.buffer.copy_within(len..slot.len, 0);
+                        slot.len -= len;
+                        keep_alive
+                    }
+                    Ok(None) if slot.len < REQUEST_LEN => true,
+                    Ok(None) => respond_error(&mut socket, Error::TooLarge),
+                    Err(e) => respond_error(&mut socket, e),
+                };
+                if !keep_alive {
+                    slot.len = 0;
+                    socket.close();
+                }
+            }
+
+            // The client is done sending and everything it sent is answered
+            if !socket.may_recv() && slot.len == 0 {
+                socket.close();
+            }
+        }
+    }
+}
+
+impl<'s, S: ?Sized + 'static> fmt::Debug for Server<'s, S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Server")
+            .field("port", &self.port)
+            .field("routes", &self.routes.len())
+            .field("slots", &self.slots)
+            .finish()
+    }
+}
+
+fn dispatch<S: ?Sized>(
+    routes: &[Route<S>],
+    state: &mut S,
+    socket: &mut TcpSocket,
+    request: &Request<'_>,
+) -> bool {
+    let mut response = Response::new(socket, Some(request));
+    match find_route(routes, request) {
+        Ok((route, params)) => {
+            let request = Request { params, ..*request };
+            (route.handler)(state, &request, &mut response);
+        }
+        Err(allow) if allow.is_empty() => response.error(Status::NotFound),
+        Err(allow) => response.error_allow(Status::MethodNotAllowed, allow),
+    }
+    response.finish()
+}
+
+/// The route answering `request`, or the methods of the routes matching
+/// its path, none if no route does
+fn find_route<'r, 'a, S: ?Sized>(
+    routes: &'r [Route<S>],
+    request: &Request<'a>,
+) -> Result<(&'r Route<S>, Params<'a>), Allow> {
+    let mut allow = Allow(0);
+    for route in routes {
+        let params = match match_path(route.path, request.path) {
+            Some(params) => params,
+            None => continue,
+        };
+        if route.method == request.method
+            || (request.method == Method::HEAD && route.method == Method::GET)
+        {
+            return Ok((route, params));
+        }
+        allow.insert(route.method);
+        if route.method == Method::GET {
+            allow.insert(Method::HEAD);
+        }
+    }
+    Err(allow)
+}
+
+fn respond_error(socket: &mut TcpSocket, error: Error) -> bool {
+    let mut response = Response::new(socket, None);
+    response.error(error.status());
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use std::string::ToString;
+
+    fn handler(_: &mut (), _: &Request<'_>, _: &mut Response<'_, '_>) {}
+
+    const ROUTES: [Route<()>; 4] = [
+        Route {
+            method: Method::GET,
+            path: "/audio/volume",
+            handler,
+        },
+        Route {
+            method: Method::PUT,
+            path: "/audio/volume",
+            handler,
+        },
+        Route {
+            method: Method::DELETE,
+            path: "/audio/play/{clip}",
+            handler,
+        },
+        Route {
+            method: Method::PUT,
+            path: "/audio/play/{clip}",
+            handler,
+        },
+    ];
+
+    fn find(input: &[u8]) -> Result<(Method, Option<&str>), Allow> {
+        let (request, _) = parse_request(input).unwrap().unwrap();
+        find_route(&ROUTES, &request).map(|(route, params)| (route.method, params.get("clip")))
+    }
+
+    #[test]
+    fn routes() {
+        assert_eq!(
+            find(b"PUT /audio/volume HTTP/1.1\r\n\r\n"),
+            Ok((Method::PUT, None))
+        );
+        assert_eq!(
+            find(b"HEAD /audio/volume HTTP/1.1\r\n\r\n"),
+            Ok((Method::GET, None))
+        );
+        assert_eq!(
+            find(b"PUT /audio/play/ring HTTP/1.1\r\n\r\n"),
+            Ok((Method::PUT, Some("ring")))
+        );
+        assert_eq!(find(b"GET /audio HTTP/1.1\r\n\r\n"), Err(Allow(0)));
+    }
+
+    #[test]
+    fn allow() {
+        let allow = find(b"POST /audio/volume HTTP/1.1\r\n\r\n").unwrap_err();
+        assert_eq!(allow.to_string(), "GET, HEAD, PUT");
+        let allow = find(b"GET /audio/play/ring HTTP/1.1\r\n\r\n").unwrap_err();
+        assert_eq!(allow.to_string(), "PUT, DELETE");
+        assert_eq!(Allow(0).to_string(), "");
+    }
+}
@@ -0,0 +1,829 @@
+// Jitter buffer and playout for RTP audio
+//
+// Packets are decoded into caller provided storage split into slots, one
+// packet per slot picked by sequence number. Playout starts once
+// `Config::depth_ms` of audio is buffered and then steps through the
+// sequence numbers at the output rate. A packet that hasn't arrived by then
+// is concealed by repeating the last few milliseconds with a fading gain and
+// dropped if it turns up later. A timestamp jump between packets, silence
+// suppression, is played as silence.
+//
+// The sender's clock and the codec's drift apart, which shows as the buffer
+// slowly filling up or draining. The averaged depth steers the resampler's
+// ratio by up to `MAX_DRIFT_PPM` to hold it at the target.
+
+use crate::rtp::{Format, Header};
+use core::fmt;
+use smoltcp::time::Instant;
+
+/// Upper bound on the number of slots
+pub const MAX_SLOTS: usize = 32;
+
+/// Frames repeated while concealing, 30 ms at 8 kHz
+const HISTORY_LEN: usize = 240;
+/// Concealment fades to silence over this
+const FADE_OUT_MS: u32 = 40;
+/// Audio after a loss fades back in over this
+const FADE_IN_MS: u32 = 5;
+/// Concealment after which playout stops and buffers again
+const MAX_CONCEAL_MS: u32 = 500;
+/// Longer timestamp jumps are played straight through
+const MAX_GAP_MS: u32 = 1000;
+/// Packets up to this far behind are late, further ones start a new
+/// sequence, RFC 3550 A.1
+const MAX_MISORDER: i32 = 100;
+/// Assumed packet length until the first one is played
+const DEFAULT_PACKET_MS: u32 = 20;
+
+/// Q15 gain of 1
+const UNITY: i32 = 1 << 15;
+
+/// Resampling correction when the depth is twice the target
+const DRIFT_GAIN_PPM: f32 = 2000.0;
+pub const MAX_DRIFT_PPM: f32 = 5000.0;
+/// Time constant of the averaged depth
+const DEPTH_AVERAGE_MS: u32 = 2000;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Config {
+    /// Audio buffered before playout starts and held by the drift
+    /// compensation
+    pub depth_ms: u32,
+    /// Sample rate of the output, the rate it's actually played at since
+    /// the drift compensation only makes up `MAX_DRIFT_PPM`
+    pub output_rate: u32,
+    /// Samples per slot, larger packets are truncated
+    pub slot_len: usize,
+}
+
+impl Default for Config {
+    /// 60 ms at 48 kHz, a slot holds 40 ms of PCMU
+    fn default() -> Self {
+        Config {
+            depth_ms: 60,
+            output_rate: 48_000,
+            slot_len: 320,
+        }
+    }
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct Stats {
+    pub received: u32,
+    /// Packets concealed
+    pub lost: u32,
+    /// Packets that arrived after they were concealed
+    pub late: u32,
+    pub duplicates: u32,
+    /// Packets that weren't RTP or had an unknown payload type, counted by
+    /// the receiver
+    pub invalid: u32,
+    /// New sources, formats and sequence number jumps
+    pub resyncs: u32,
+    /// Times the buffer ran dry and playout stopped
+    pub underruns: u32,
+    /// Interarrival jitter, RFC 3550, in timestamp units
+    pub jitter: u32,
+    /// Averaged buffer depth
+    pub depth_ms: u32,
+    /// Current resampling correction, positive when playing faster
+    pub drift_ppm: i32,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum State {
+    /// Waiting for a packet
+    Idle,
+    /// Waiting for the target depth, playout starts at `start`
+    Buffering {
+        start: u16,
+    },
+    Playing,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct Slot {
+    sequence: u16,
+    timestamp: u32,
+    frames: usize,
+    valid: bool,
+}
+
+impl Slot {
+    const EMPTY: Slot = Slot {
+        sequence: 0,
+        timestamp: 0,
+        frames: 0,
+        valid: false,
+    };
+}
+
+pub struct JitterBuffer<'a> {
+    storage: &'a mut [i16],
+    slots: [Slot; MAX_SLOTS],
+    num_slots: usize,
+    config: Config,
+    stats: Stats,
+    state: State,
+    ssrc: u32,
+    format: Option<Format>,
+    /// Packets with a sequence number out of the window until one follows on
+    bad_sequence: Option<u16>,
+    /// Last transit time and the jitter estimate in 1/16 timestamp units
+    transit: Option<i32>,
+    jitter: u32,
+
+    // Playout, at the stream's rate
+    target_frames: usize,
+    /// Sequence number of the packet being played or concealed
+    next: u16,
+    offset: usize,
+    frames: usize,
+    concealing: bool,
+    /// Frames concealed in a row
+    concealed: usize,
+    max_concealed: usize,
+    /// Silence played before the current packet
+    gap: usize,
+    expected_timestamp: Option<u32>,
+    last_frames: usize,
+    gain: i32,
+    fade_in_step: i32,
+    fade_out_step: i32,
+    history: [[i16; 2]; HISTORY_LEN],
+    history_pos: usize,
+    history_len: usize,
+    conceal_pos: usize,
+
+    // Resampling to the output rate
+    ratio: f32,
+    step: f32,
+    phase: f32,
+    previous: [i16; 2],
+    current: [i16; 2],
+    depth: f32,
+}
+
+impl<'a> JitterBuffer<'a> {
+    /// `storage` is split into `config.slot_len` sample slots, as many as
+    /// fit rounded down to a power of two and up to `MAX_SLOTS`.
+    ///
+    /// Panics if it doesn't hold two slots.
+    pub fn new(storage: &'a mut [i16], config: Config) -> Self {
+        let mut num_slots = (storage.len() / config.slot_len.max(1)).min(MAX_SLOTS);
+        // Sequence numbers wrap onto the same slots
+        while num_slots & num_slots.wrapping_sub(1) != 0 {
+            num_slots &= num_slots - 1;
+        }
+        assert!(
+            num_slots >= 2,
+            "Jitter buffer storage holds less than two slots"
+        );
+        JitterBuffer {
+            storage,
+            slots: [Slot::EMPTY; MAX_SLOTS],
+            num_slots,
+            config,
+            stats: Stats::default(),
+            state: State::Idle,
+            ssrc: 0,
+            format: None,
+            bad_sequence: None,
+            transit: None,
+            jitter: 0,
+            target_frames: 0,
+            next: 0,
+            offset: 0,
+            frames: 0,
+            concealing: false,
+            concealed: 0,
+            max_concealed: 0,
+            gap: 0,
+            expected_timestamp: None,
+            last_frames: 0,
+            gain: 0,
+            fade_in_step: UNITY,
+            fade_out_step: UNITY,
+            history: [[0; 2]; HISTORY_LEN],
+            history_pos: 0,
+            history_len: 0,
+            conceal_pos: 0,
+            ratio: 1.0,
+            step: 1.0,
+            phase: 0.0,
+            previous: [0; 2],
+            current: [0; 2],
+            depth: 0.0,
+        }
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    pub fn stats(&self) -> &Stats {
+        &self.stats
+    }
+
+    pub fn num_slots(&self) -> usize {
+        self.num_slots
+    }
+
+    /// SSRC of the stream being received
+    pub fn ssrc(&self) -> Option<u32> {
+        self.format.map(|_| self.ssrc)
+    }
+
+    pub fn format(&self) -> Option<Format> {
+        self.format
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.state == State::Playing
+    }
+
+    /// Drop the stream, playout is silent until the next packet
+    pub fn reset(&mut self) {
+        self.format = None;
+        self.state = State::Idle;
+        self.clear_slots();
+    }
+
+    /// Decode and store a packet that arrived at `now`.
+    ///
+    /// A new SSRC or format replaces the stream being played. Formats with
+    /// more than two channels are ignored.
+    pub fn insert(&mut self, header: &Header, format: Format, payload: &[u8], now: Instant) {
+        if format.channels == 0 || format.channels > 2 {
+            return;
+        }
+        if self.format != Some(format) || self.ssrc != header.ssrc {
+            if self.format.is_some() {
+                self.stats.resyncs += 1;
+            }
+            self.start_stream(header.ssrc, format);
+        }
+        self.update_jitter(header.timestamp, format.clock_rate, now);
+
+        let sequence = header.sequence;
+        let window = match self.state {
+            State::Idle => None,
+            // Room up to the slot of the packet being played
+            State::Buffering { start } => Some((start, 0, self.num_slots as i32)),
+            State::Playing => Some((self.next, 1, self.num_slots as i32)),
+        };
+        if let Some((reference, first, end)) = window {
+            let delta = i32::from(sequence.wrapping_sub(reference) as i16);
+            if delta < first && delta >= first - MAX_MISORDER {
+                self.stats.late += 1;
+                return;
+            }
+            if delta < first || delta >= end {
+                if self.bad_sequence != Some(sequence) {
+                    self.bad_sequence = Some(sequence.wrapping_add(1));
+                    return;
+                }
+                // Two packets in a row, the sender restarted or jumped ahead
+                self.stats.resyncs += 1;
+                self.state = State::Idle;
+                self.clear_slots();
+            }
+        }
+        self.bad_sequence = None;
+        if self.state == State::Idle {
+            self.state = State::Buffering { start: sequence };
+        }
+
+        let index = self.index(sequence);
+        let slot = self.slots[index];
+        if slot.valid && slot.sequence == sequence {
+            self.stats.duplicates += 1;
+            return;
+        }
+        let channels = usize::from(format.channels);
+        let slot_len = self.config.slot_len;
+        let len = slot_len - slot_len % channels;
+        let samples = format.decode(payload, &mut self.storage[index * slot_len..][..len]);
+        let frames = samples / channels;
+        if frames == 0 {
+            return;
+        }
+        self.slots[index] = Slot {
+            sequence,
+            timestamp: header.timestamp,
+            frames,
+            valid: true,
+        };
+        self.stats.received += 1;
+    }
+
+    /// Fill `buf` with interleaved stereo samples at the output rate,
+    /// silence while buffering. Returns the samples written, all of `buf`
+    /// rounded down to whole frames.
+    pub fn fill(&mut self, buf: &mut [i16]) -> usize {
+        let frames = buf.len() / 2;
+        for out in buf.chunks_exact_mut(2) {
+            while self.phase >= 1.0 {
+                self.previous = self.current;
+                self.current = self.next_frame();
+                self.phase -= 1.0;
+            }
+            for (channel, sample) in out.iter_mut().enumerate() {
+                let previous = f32::from(self.previous[channel]);
+                let current = f32::from(self.current[channel]);
+                *sample = (previous + (current - previous) * self.phase) as i16;
+            }
+            self.phase += self.step;
+        }
+        self.track_drift(frames);
+        frames * 2
+    }
+
+    fn index(&self, sequence: u16) -> usize {
+        usize::from(sequence) & (self.num_slots - 1)
+    }
+
+    fn clear_slots(&mut self) {
+        for slot in self.slots.iter_mut() {
+            slot.valid = false;
+        }
+    }
+
+    fn start_stream(&mut self, ssrc: u32, format: Format) {
+        let rate = format.clock_rate.max(1);
+        let ms_frames = |ms: u32| (u64::from(rate) * u64::from(ms) / 1000).max(1) as usize;
+        self.ssrc = ssrc;
+        self.format = Some(format);
+        self.state = State::Idle;
+        self.bad_sequence = None;
+        self.transit = None;
+        self.jitter = 0;
+        self.stats.jitter = 0;
+        self.clear_slots();
+        self.target_frames = ms_frames(self.config.depth_ms);
+        self.max_concealed = ms_frames(MAX_CONCEAL_MS);
+        self.last_frames = ms_frames(DEFAULT_PACKET_MS);
+        self.fade_in_step = UNITY / ms_frames(FADE_IN_MS) as i32;
+        self.fade_out_step = UNITY / ms_frames(FADE_OUT_MS) as i32;
+        self.history_pos = 0;
+        self.history_len = 0;
+        self.ratio = rate as f32 / self.config.output_rate.max(1) as f32;
+        self.step = self.ratio;
+    }
+
+    fn update_jitter(&mut self, timestamp: u32, clock_rate: u32, now: Instant) {
+        let arrival = (now.total_millis() as u64 * u64::from(clock_rate) / 1000) as u32;
+        let transit = arrival.wrapping_sub(timestamp) as i32;
+        if let Some(last) = self.transit {
+            let d = transit.wrapping_sub(last).wrapping_abs() as u32;
+            self.jitter = self
+                .jitter
+                .saturating_add(d)
+                .saturating_sub((self.jitter + 8) >> 4);
+            self.stats.jitter = self.jitter >> 4;
+        }
+        self.transit = Some(transit);
+    }
+
+    /// Frames stored from the playout position on
+    fn buffered(&self) -> usize {
+        let stored: usize = self.slots[..self.num_slots]
+            .iter()
+            .filter(|slot| slot.valid)
+            .map(|slot| slot.frames)
+            .sum();
+        if self.state == State::Playing && !self.concealing {
+            stored - self.offset
+        } else {
+            stored
+        }
+    }
+
+    /// The target depth is buffered or there's no room for more
+    fn is_ready(&self) -> bool {
+        self.buffered() >= self.target_frames
+            || self.slots[..self.num_slots].iter().all(|slot| slot.valid)
+    }
+
+    fn start_playing(&mut self, start: u16) {
+        self.state = State::Playing;
+        // The first advance moves on to `start`
+        self.next = start.wrapping_sub(1);
+        self.offset = 0;
+        self.frames = 0;
+        self.concealing = false;
+        self.concealed = 0;
+        self.gap = 0;
+        self.expected_timestamp = None;
+        self.gain = 0;
+        self.depth = self.target_frames as f32;
+    }
+
+    /// Next frame at the stream's rate
+    fn next_frame(&mut self) -> [i16; 2] {
+        match self.state {
+            State::Playing => (),
+            State::Buffering { start } if self.is_ready() => self.start_playing(start),
+            _ => return [0; 2],
+        }
+        if self.offset == self.frames {
+            self.advance();
+            if self.state != State::Playing {
+                return [0; 2];
+            }
+        }
+        if self.gap > 0 {
+            self.gap -= 1;
+            return self.conceal();
+        }
+        let frame = if self.concealing {
+            self.conceal()
+        } else {
+            self.play()
+        };
+        self.offset += 1;
+        frame
+    }
+
+    /// Move on to the next sequence number
+    fn advance(&mut self) {
+        let index = self.index(self.next);
+        if self.slots[index].sequence == self.next {
+            self.slots[index].valid = false;
+        }
+        self.next = self.next.wrapping_add(1);
+        self.offset = 0;
+
+        let slot = self.slots[self.index(self.next)];
+        if slot.valid && slot.sequence == self.next {
+            if let Some(expected) = self.expected_timestamp {
+                let gap = slot.timestamp.wrapping_sub(expected) as i32;
+                let max_gap = self.format.map_or(0, |f| f.clock_rate * MAX_GAP_MS / 1000);
+                if gap > 0 && gap as u32 <= max_gap {
+                    self.gap = gap as usize;
+                }
+            }
+            self.frames = slot.frames;
+            self.last_frames = slot.frames;
+            self.concealing = false;
+            self.concealed = 0;
+            self.expected_timestamp = Some(slot.timestamp.wrapping_add(slot.frames as u32));
+        } else {
+            self.stats.lost += 1;
+            self.frames = self.last_frames;
+            self.concealing = true;
+            self.concealed += self.frames;
+            self.expected_timestamp = self
+                .expected_timestamp
+                .map(|t| t.wrapping_add(self.frames as u32));
+            if self.concealed > self.max_concealed {
+                // The sender stopped or the network is down
+                self.stats.underruns += 1;
+                self.state = State::Idle;
+                self.clear_slots();
+            }
+        }
+    }
+
+    fn play(&mut self) -> [i16; 2] {
+        let channels = self.format.map_or(1, |f| usize::from(f.channels));
+        let i = self.index(self.next) * self.config.slot_len + self.offset * channels;
+        let left = self.storage[i];
+        let right = if channels == 2 {
+            self.storage[i + 1]
+        } else {
+            left
+        };
+        let frame = [left, right];
+
+        self.history[self.history_pos] = frame;
+        self.history_pos = (self.history_pos + 1) % HISTORY_LEN;
+        self.history_len = (self.history_len + 1).min(HISTORY_LEN);
+        // Concealment repeats the history from its oldest frame
+        self.conceal_pos = if self.history_len == HISTORY_LEN {
+            self.history_pos
+        } else {
+            0
+        };
+
+        self.gain = (self.gain + self.fade_in_step).min(UNITY);
+        scale(frame, self.gain)
+    }
+
+    fn conceal(&mut self) -> [i16; 2] {
+        self.gain = (self.gain - self.fade_out_step).max(0);
+        if self.gain == 0 || self.history_len == 0 {
+            return [0; 2];
+        }
+        let frame = self.history[self.conceal_pos];
+        self.conceal_pos = (self.conceal_pos + 1) % self.history_len;
+        scale(frame, self.gain)
+    }
+
+    fn track_drift(&mut self, frames: usize) {
+        if self.state != State::Playing {
+            self.step = self.ratio;
+            self.stats.drift_ppm = 0;
+            self.stats.depth_ms = 0;
+            return;
+        }
+        let rate = self.format.map_or(1, |f| f.clock_rate.max(1)) as f32;
+        let time_constant = (self.config.output_rate * DEPTH_AVERAGE_MS / 1000) as f32;
+        let alpha = (frames as f32 / time_constant).min(1.0);
+        self.depth += (self.buffered() as f32 - self.depth) * alpha;
+
+        let target = self.target_frames as f32;
+        let ppm = ((self.depth - target) / target * DRIFT_GAIN_PPM)
+            .max(-MAX_DRIFT_PPM)
+            .min(MAX_DRIFT_PPM);
+        self.step = self.ratio * (1.0 + ppm * 1e-6);
+        self.stats.drift_ppm = ppm as i32;
+        self.stats.depth_ms = (self.depth * 1000.0 / rate) as u32;
+    }
+}
+
+impl<'a> fmt::Debug for JitterBuffer<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("JitterBuffer")
+            .field("num_slots", &self.num_slots)
+            .field("config", &self.config)
+            .field("state", &self.state)
+            .field("format", &self.format)
+            .field("stats", &self.stats)
+            .finish()
+    }
+}
+
+fn scale(frame: [i16; 2], gain: i32) -> [i16; 2] {
+    [
+        ((i32::from(frame[0]) * gain) >> 15) as i16,
+        ((i32::from(frame[1]) * gain) >> 15) as i16,
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::rtp::Encoding;
+    use std::vec::Vec;
+
+    /// 16-bit mono at the output rate, so the buffer doesn't resample
+    const FORMAT: Format = Format {
+        encoding: Encoding::L16,
+        clock_rate: 8000,
+        channels: 1,
+    };
+    const CONFIG: Config = Config {
+        depth_ms: 40,
+        output_rate: 8000,
+        slot_len: FRAMES,
+    };
+    /// 20 ms packets
+    const FRAMES: usize = 160;
+    const SSRC: u32 = 0x1234_5678;
+
+    /// Every sample of a packet is `1000 + sequence`
+    fn value(sequence: u16) -> i16 {
+        1000 + (sequence % 1000) as i16
+    }
+
+    /// Feeds 20 ms packets and plays 20 ms after each step
+    struct Stream<'a> {
+        jitter: JitterBuffer<'a>,
+        now: i64,
+        out: Vec<i16>,
+    }
+
+    impl<'a> Stream<'a> {
+        fn new(storage: &'a mut [i16]) -> Self {
+            Stream {
+                jitter: JitterBuffer::new(storage, CONFIG),
+                now: 0,
+                out: Vec::new(),
+            }
+        }
+
+        fn insert(&mut self, sequence: u16) {
+            let header = Header {
+                marker: false,
+                payload_type: 11,
+                sequence,
+                timestamp: u32::from(sequence).wrapping_mul(FRAMES as u32),
+                ssrc: SSRC,
+            };
+            let mut payload = [0; FRAMES * 2];
+            for bytes in payload.chunks_exact_mut(2) {
+                bytes.copy_from_slice(&value(sequence).to_be_bytes());
+            }
+            let now = Instant::from_millis(self.now);
+            self.jitter.insert(&header, FORMAT, &payload, now);
+        }
+
+        /// Insert `packets` then play a packet's length
+        fn step(&mut self, packets: &[u16]) {
+            for sequence in packets {
+                self.insert(*sequence);
+            }
+            let mut buf = [0; FRAMES * 2];
+            assert_eq!(self.jitter.fill(&mut buf), buf.len());
+            // Mono is played on both channels
+            for frame in buf.chunks_exact(2) {
+                assert_eq!(frame[0], frame[1]);
+                self.out.push(frame[0]);
+            }
+            self.now += 20;
+        }
+
+        /// Packets played at full gain, in the order they were played.
+        /// Fades and the drift compensation's interpolation are skipped by
+        /// only counting runs of at least a millisecond.
+        fn played(&self) -> Vec<u16> {
+            let mut played = Vec::new();
+            for run in self.out.chunks(8) {
+                if run[0] >= 1000 && run.iter().all(|s| *s == run[0]) {
+                    played.push((run[0] - 1000) as u16);
+                }
+            }
+            played.dedup();
+            played
+        }
+
+        fn stats(&self) -> Stats {
+            *self.jitter.stats()
+        }
+    }
+
+    fn storage() -> [i16; FRAMES * 8] {
+        [0; FRAMES * 8]
+    }
+
+    #[test]
+    fn in_order() {
+        let mut storage = storage();
+        let mut s = Stream::new(&mut storage);
+        assert_eq!(s.jitter.num_slots(), 8);
+
+        s.step(&[0]);
+        assert!(!s.jitter.is_playing());
+        assert!(s.out.iter().all(|s| *s == 0));
+        // Playout starts at the target depth of two packets
+        for sequence in 1..10 {
+            s.step(&[sequence]);
+            assert!(s.jitter.is_playing());
+        }
+
+        let stats = s.stats();
+        assert_eq!(stats.received, 10);
+        assert_eq!(stats.lost, 0);
+        assert_eq!(stats.late, 0);
+        assert_eq!(stats.duplicates, 0);
+        assert_eq!(stats.resyncs, 0);
+        assert_eq!(stats.underruns, 0);
+        assert_eq!(stats.jitter, 0);
+        assert_eq!(s.played(), [0, 1, 2, 3, 4, 5, 6, 7, 8]);
+        assert_eq!(s.jitter.ssrc(), Some(SSRC));
+    }
+
+    #[test]
+    fn sequence_wraps() {
+        let mut storage = storage();
+        let mut s = Stream::new(&mut storage);
+        for sequence in [65_533, 65_534, 65_535, 0, 1, 2].iter() {
+            s.step(&[*sequence]);
+        }
+        let stats = s.stats();
+        assert_eq!((stats.received, stats.lost, stats.resyncs), (6, 0, 0));
+        assert_eq!(s.played(), [533, 534, 535, 0, 1]);
+    }
+
+    #[test]
+    fn out_of_order() {
+        let mut storage = storage();
+        let mut s = Stream::new(&mut storage);
+        // 3 overtakes 2, and 6 and 5 arrive together, all before their turn
+        s.step(&[0]);
+        s.step(&[1]);
+        s.step(&[3]);
+        s.step(&[2]);
+        s.step(&[4]);
+        s.step(&[6, 5]);
+        s.step(&[7]);
+
+        let stats = s.stats();
+        assert_eq!(stats.received, 8);
+        assert_eq!(stats.lost, 0);
+        assert_eq!(stats.late, 0);
+        assert_eq!(stats.duplicates, 0);
+        assert_eq!(s.played(), [0, 1, 2, 3, 4, 5]);
+        // The arrival times of 2 and 3 are 20 ms off
+        assert!(stats.jitter > 0);
+    }
+
+    #[test]
+    fn lost_and_late() {
+        let mut storage = storage();
+        let mut s = Stream::new(&mut storage);
+        s.step(&[0]);
+        s.step(&[1]);
+        s.step(&[2]);
+        // 3 is due while this plays and hasn't arrived
+        s.step(&[]);
+        s.step(&[4]);
+        assert_eq!(s.stats().lost, 1);
+        assert_eq!(s.stats().late, 0);
+
+        // Too late, it was concealed
+        s.step(&[3, 5]);
+        s.step(&[6]);
+
+        let stats = s.stats();
+        assert_eq!(stats.received, 6);
+        assert_eq!(stats.lost, 1);
+        assert_eq!(stats.late, 1);
+        assert_eq!(stats.underruns, 0);
+        assert_eq!(s.played(), [0, 1, 2, 4, 5]);
+    }
+
+    #[test]
+    fn duplicates() {
+        let mut storage = storage();
+        let mut s = Stream::new(&mut storage);
+        s.step(&[0, 0]);
+        s.step(&[1, 2, 1]);
+        s.step(&[2]);
+        assert_eq!(s.stats().duplicates, 3);
+
+        // A copy of a packet that was already played is late
+        s.step(&[0, 3]);
+        s.step(&[4]);
+
+        let stats = s.stats();
+        assert_eq!(stats.received, 5);
+        assert_eq!(stats.duplicates, 3);
+        assert_eq!(stats.late, 1);
+        assert_eq!(stats.lost, 0);
+        assert_eq!(s.played(), [0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn jumps_and_restarts() {
+        let mut storage = storage();
+        let mut s = Stream::new(&mut storage);
+        s.step(&[0]);
+        s.step(&[1]);
+
+        // A single stray packet is dropped
+        s.step(&[2, 5000]);
+        s.step(&[3]);
+        assert_eq!(s.stats().resyncs, 0);
+        assert_eq!(s.stats().received, 4);
+
+        // Two in a row restart from the second
+        s.step(&[5000, 5001]);
+        assert_eq!(s.stats().resyncs, 1);
+        assert_eq!(s.stats().received, 5);
+
+        // A new SSRC starts a new stream
+        let header = Header {
+            marker: false,
+            payload_type: 11,
+            sequence: 7,
+            timestamp: 0,
+            ssrc: SSRC + 1,
+        };
+        s.jitter.insert(
+            &header,
+            FORMAT,
+            &[0; FRAMES * 2],
+            Instant::from_millis(s.now),
+        );
+        assert_eq!(s.stats().resyncs, 2);
+        assert_eq!(s.jitter.ssrc(), Some(SSRC + 1));
+        assert!(!s.jitter.is_playing());
+    }
+
+    #[test]
+    fn underrun() {
+        let mut storage = storage();
+        let mut s = Stream::new(&mut storage);
+        s.step(&[0]);
+        s.step(&[1]);
+        // Concealment gives up after MAX_CONCEAL_MS
+        for _ in 0..MAX_CONCEAL_MS / 20 + 2 {
+            s.step(&[]);
+        }
+        let stats = s.stats();
+        assert_eq!(stats.underruns, 1);
+        assert!(!s.jitter.is_playing());
+        assert_eq!(stats.lost, MAX_CONCEAL_MS / 20 + 1);
+        assert_eq!(*s.out.last().unwrap(), 0);
+
+        // Buffers again from the next packet
+        s.step(&[30]);
+        s.step(&[31]);
+        assert!(s.jitter.is_playing());
+        assert_eq!(s.stats().received, 4);
+    }
+}
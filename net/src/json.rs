@@ -0,0 +1,693 @@
+// Serde-free JSON, RFC 8259
+//
+// `object` writes an object member by member into any `fmt::Write`, an HTTP
+// `Response` included, without building it in memory first. `parse` checks
+// a whole document and returns its top-level value. Strings, numbers and
+// nested objects and arrays are borrowed from the input as they appear in
+// it, string escapes are not decoded.
+
+use core::fmt::{self, Write};
+use core::str;
+
+/// Nesting depth `parse` accepts
+pub const MAX_DEPTH: usize = 8;
+
+/// Value that can be written as JSON
+pub trait ToJson {
+    fn to_json<W: Write>(&self, w: &mut W) -> fmt::Result;
+}
+
+impl ToJson for bool {
+    fn to_json<W: Write>(&self, w: &mut W) -> fmt::Result {
+        w.write_str(if *self { "true" } else { "false" })
+    }
+}
+
+macro_rules! impl_to_json_int {
+    ($($t:ty),*) => {
+        $(impl ToJson for $t {
+            fn to_json<W: Write>(&self, w: &mut W) -> fmt::Result {
+                write!(w, "{}", self)
+            }
+        })*
+    };
+}
+
+impl_to_json_int!(u8, u16, u32, u64, usize, i8, i16, i32, i64, isize);
+
+impl ToJson for f32 {
+    /// `null` for NaN and infinities, JSON has no representation for them
+    fn to_json<W: Write>(&self, w: &mut W) -> fmt::Result {
+        if self.is_finite() {
+            write!(w, "{}", self)
+        } else {
+            w.write_str("null")
+        }
+    }
+}
+
+impl ToJson for str {
+    fn to_json<W: Write>(&self, w: &mut W) -> fmt::Result {
+        w.write_char('"')?;
+        Escape(w).write_str(self)?;
+        w.write_char('"')
+    }
+}
+
+impl<T: ToJson + ?Sized> ToJson for &T {
+    fn to_json<W: Write>(&self, w: &mut W) -> fmt::Result {
+        (**self).to_json(w)
+    }
+}
+
+impl<T: ToJson> ToJson for Option<T> {
+    fn to_json<W: Write>(&self, w: &mut W) -> fmt::Result {
+        match self {
+            Some(v) => v.to_json(w),
+            None => w.write_str("null"),
+        }
+    }
+}
+
+/// Writes a `Display` value as a JSON string, e.g. an IP address
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Quoted<T>(pub T);
+
+impl<T: fmt::Display> ToJson for Quoted<T> {
+    fn to_json<W: Write>(&self, w: &mut W) -> fmt::Result {
+        w.write_char('"')?;
+        write!(Escape(w), "{}", self.0)?;
+        w.write_char('"')
+    }
+}
+
+/// Escapes what it writes for the inside of a JSON string
+struct Escape<'w, W>(&'w mut W);
+
+impl<'w, W: Write> Write for Escape<'w, W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut plain = 0;
+        for (i, c) in s.char_indices() {
+            let escaped = match c {
+                '"' => "\\\"",
+                '\\' => "\\\\",
+                '\n' => "\\n",
+                '\r' => "\\r",
+                '\t' => "\\t",
+                c if (c as u32) < 0x20 => "",
+                _ => continue,
+            };
+            self.0.write_str(&s[plain..i])?;
+            if escaped.is_empty() {
+                write!(self.0, "\\u{:04x}", c as u32)?;
+            } else {
+                self.0.write_str(escaped)?;
+            }
+            plain = i + c.len_utf8();
+        }
+        self.0.write_str(&s[plain..])
+    }
+}
+
+/// Start writing an object into `w`
+pub fn object<W: Write>(w: &mut W) -> ObjectWriter<'_, W> {
+    let result = w.write_char('{');
+    ObjectWriter {
+        w,
+        first: true,
+        result,
+    }
+}
+
+/// Writes the members of an object, errors are kept until `finish`
+pub struct ObjectWriter<'w, W: Write> {
+    w: &'w mut W,
+    first: bool,
+    result: fmt::Result,
+}
+
+impl<'w, W: Write> ObjectWriter<'w, W> {
+    pub fn member<T: ToJson + ?Sized>(&mut self, name: &str, value: &T) -> &mut Self {
+        if self.name(name).is_ok() {
+            self.result = value.to_json(self.w);
+        }
+        self
+    }
+
+    /// Nested object, written by `f`
+    pub fn object<F>(&mut self, name: &str, f: F) -> &mut Self
+    where
+        F: FnOnce(&mut ObjectWriter<'_, W>),
+    {
+        if self.name(name).is_ok() {
+            let mut nested = object(&mut *self.w);
+            f(&mut nested);
+            self.result = nested.finish();
+        }
+        self
+    }
+
+    pub fn array<I>(&mut self, name: &str, items: I) -> &mut Self
+    where
+        I: IntoIterator,
+        I::Item: ToJson,
+    {
+        if self.name(name).is_ok() {
+            self.result = write_array(self.w, items);
+        }
+        self
+    }
+
+    /// Close the object
+    pub fn finish(&mut self) -> fmt::Result {
+        self.result?;
+        self.w.write_char('}')
+    }
+
+    fn name(&mut self, name: &str) -> fmt::Result {
+        self.result?;
+        if !self.first {
+            self.result = self.w.write_char(',');
+        }
+        self.first = false;
+        self.result = self
+            .result
+            .and_then(|_| name.to_json(self.w))
+            .and_then(|_| self.w.write_char(':'));
+        self.result
+    }
+}
+
+fn write_array<W, I>(w: &mut W, items: I) -> fmt::Result
+where
+    W: Write,
+    I: IntoIterator,
+    I::Item: ToJson,
+{
+    w.write_char('[')?;
+    for (i, item) in items.into_iter().enumerate() {
+        if i > 0 {
+            w.write_char(',')?;
+        }
+        item.to_json(w)?;
+    }
+    w.write_char(']')
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Error {
+    /// Not UTF-8 or not JSON, at this byte offset
+    Syntax(usize),
+    /// Nested deeper than `MAX_DEPTH`
+    TooDeep,
+}
+
+/// A parsed value, borrowed from the input
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Value<'a> {
+    Null,
+    Bool(bool),
+    /// As written, e.g. `-1.5e3`
+    Number(&'a str),
+    /// Between the quotes, escapes not decoded
+    String(&'a str),
+    /// Including the braces, see `members`
+    Object(&'a str),
+    /// Including the brackets, see `elements`
+    Array(&'a str),
+}
+
+impl<'a> Value<'a> {
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    /// `None` unless the number is an integer that fits an `i64`
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            Value::Number(n) => n.parse().ok(),
+            _ => None,
+        }
+    }
+
+    /// `None` unless the number is a non-negative integer that fits a `u64`
+    pub fn as_u64(&self) -> Option<u64> {
+        match self {
+            Value::Number(n) => n.parse().ok(),
+            _ => None,
+        }
+    }
+
+    pub fn as_f32(&self) -> Option<f32> {
+        match self {
+            Value::Number(n) => n.parse().ok(),
+            _ => None,
+        }
+    }
+
+    /// Content of a string without escapes, `None` for other values and
+    /// strings with escapes
+    pub fn as_str(&self) -> Option<&'a str> {
+        match self {
+            Value::String(s) if !s.contains('\\') => Some(*s),
+            _ => None,
+        }
+    }
+
+    pub fn is_null(&self) -> bool {
+        *self == Value::Null
+    }
+
+    /// Members of an object in order, empty for other values
+    pub fn members(&self) -> Members<'a> {
+        let inner = match self {
+            Value::Object(s) => &s[1..s.len() - 1],
+            _ => "",
+        };
+        Members {
+            parser: Parser::new(inner),
+        }
+    }
+
+    /// Value of the first member named `name`
+    pub fn get(&self, name: &str) -> Option<Value<'a>> {
+        self.members().find(|(n, _)| *n == name).map(|(_, v)| v)
+    }
+
+    /// Elements of an array in order, empty for other values
+    pub fn elements(&self) -> Elements<'a> {
+        let inner = match self {
+            Value::Array(s) => &s[1..s.len() - 1],
+            _ => "",
+        };
+        Elements {
+            parser: Parser::new(inner),
+        }
+    }
+}
+
+/// Parse a complete document, surrounding whitespace is allowed
+pub fn parse(input: &[u8]) -> Result<Value<'_>, Error> {
+    let input = str::from_utf8(input).map_err(|e| Error::Syntax(e.valid_up_to()))?;
+    let mut parser = Parser::new(input);
+    let value = parser.value(0)?;
+    parser.skip_whitespace();
+    if parser.pos != input.len() {
+        return Err(Error::Syntax(parser.pos));
+    }
+    Ok(value)
+}
+
+/// Iterator over the (name, value) members of a parsed object
+#[derive(Debug, Clone)]
+pub struct Members<'a> {
+    parser: Parser<'a>,
+}
+
+impl<'a> Iterator for Members<'a> {
+    type Item = (&'a str, Value<'a>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // The object was checked by `parse`
+        let p = &mut self.parser;
+        p.skip_whitespace();
+        p.eat(b',');
+        p.skip_whitespace();
+        if p.pos == p.input.len() {
+            return None;
+        }
+        let name = p.string().ok()?;
+        p.skip_whitespace();
+        p.eat(b':');
+        let value = p.value(0).ok()?;
+        Some((name, value))
+    }
+}
+
+/// Iterator over the elements of a parsed array
+#[derive(Debug, Clone)]
+pub struct Elements<'a> {
+    parser: Parser<'a>,
+}
+
+impl<'a> Iterator for Elements<'a> {
+    type Item = Value<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let p = &mut self.parser;
+        p.skip_whitespace();
+        p.eat(b',');
+        p.skip_whitespace();
+        if p.pos == p.input.len() {
+            return None;
+        }
+        p.value(0).ok()
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Parser<'a> {
+    input: &'a str,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(input: &'a str) -> Self {
+        Parser { input, pos: 0 }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.input.as_bytes().get(self.pos).cloned()
+    }
+
+    fn eat(&mut self, b: u8) -> bool {
+        if self.peek() == Some(b) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, b: u8) -> Result<(), Error> {
+        if self.eat(b) {
+            Ok(())
+        } else {
+            Err(Error::Syntax(self.pos))
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(b' ') | Some(b'\t') | Some(b'\n') | Some(b'\r') = self.peek() {
+            self.pos += 1;
+        }
+    }
+
+    /// Value after optional whitespace
+    fn value(&mut self, depth: usize) -> Result<Value<'a>, Error> {
+        self.skip_whitespace();
+        let start = self.pos;
+        match self.peek() {
+            Some(b'{') => {
+                self.composite(depth, b'}', true)?;
+                Ok(Value::Object(&self.input[start..self.pos]))
+            }
+            Some(b'[') => {
+                self.composite(depth, b']', false)?;
+                Ok(Value::Array(&self.input[start..self.pos]))
+            }
+            Some(b'"') => self.string().map(Value::String),
+            Some(b'-') | Some(b'0'..=b'9') => self.number().map(Value::Number),
+            Some(b't') => self.literal("true").map(|_| Value::Bool(true)),
+            Some(b'f') => self.literal("false").map(|_| Value::Bool(false)),
+            Some(b'n') => self.literal("null").map(|_| Value::Null),
+            _ => Err(Error::Syntax(self.pos)),
+        }
+    }
+
+    /// Object or array, the opening brace or bracket is next
+    fn composite(&mut self, depth: usize, close: u8, object: bool) -> Result<(), Error> {
+        if depth == MAX_DEPTH {
+            return Err(Error::TooDeep);
+        }
+        self.pos += 1;
+        self.skip_whitespace();
+        if self.eat(close) {
+            return Ok(());
+        }
+        loop {
+            if object {
+                self.skip_whitespace();
+                self.string()?;
+                self.skip_whitespace();
+                self.expect(b':')?;
+            }
+            self.value(depth + 1)?;
+            self.skip_whitespace();
+            if self.eat(close) {
+                return Ok(());
+            }
+            self.expect(b',')?;
+        }
+    }
+
+    /// String content, the opening quote is next
+    fn string(&mut self) -> Result<&'a str, Error> {
+        self.expect(b'"')?;
+        let start = self.pos;
+        loop {
+            match self.peek() {
+                Some(b'"') => {
+                    self.pos += 1;
+                    return Ok(&self.input[start..self.pos - 1]);
+                }
+                Some(b'\\') => {
+                    self.pos += 1;
+                    match self.peek() {
+                        Some(b'"') | Some(b'\\') | Some(b'/') | Some(b'b') | Some(b'f')
+                        | Some(b'n') | Some(b'r') | Some(b't') => self.pos += 1,
+                        Some(b'u') => {
+                            self.pos += 1;
+                            for _ in 0..4 {
+                                match self.peek() {
+                                    Some(b) if b.is_ascii_hexdigit() => self.pos += 1,
+                                    _ => return Err(Error::Syntax(self.pos)),
+                                }
+                            }
+                        }
+                        _ => return Err(Error::Syntax(self.pos)),
+                    }
+                }
+                Some(b) if b >= 0x20 => self.pos += 1,
+                _ => return Err(Error::Syntax(self.pos)),
+            }
+        }
+    }
+
+    fn number(&mut self) -> Result<&'a str, Error> {
+        let start = self.pos;
+        self.eat(b'-');
+        if !self.eat(b'0') && self.digits() == 0 {
+            return Err(Error::Syntax(self.pos));
+        }
+        if self.eat(b'.') && self.digits() == 0 {
+            return Err(Error::Syntax(self.pos));
+        }
+        if self.eat(b'e') || self.eat(b'E') {
+            if !self.eat(b'+') {
+                self.eat(b'-');
+            }
+            if self.digits() == 0 {
+                return Err(Error::Syntax(self.pos));
+            }
+        }
+        Ok(&self.input[start..self.pos])
+    }
+
+    fn digits(&mut self) -> usize {
+        let start = self.pos;
+        while let Some(b'0'..=b'9') = self.peek() {
+            self.pos += 1;
+        }
+        self.pos - start
+    }
+
+    fn literal(&mut self, literal: &str) -> Result<(), Error> {
+        if self.input[self.pos..].starts_with(literal) {
+            self.pos += literal.len();
+            Ok(())
+        } else {
+            Err(Error::Syntax(self.pos))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use std::string::String;
+
+    #[test]
+    fn values() {
+        let doc = r#" {"name": "ring", "volume": -12, "gain": 1.5e1,
+            "muted": false, "clip": null, "tags": ["a", 2, {}], "esc": "é\n"} "#;
+        let v = parse(doc.as_bytes()).unwrap();
+        assert_eq!(v.get("name").and_then(|v| v.as_str()), Some("ring"));
+        assert_eq!(v.get("volume").and_then(|v| v.as_i64()), Some(-12));
+        assert_eq!(v.get("volume").and_then(|v| v.as_u64()), None);
+        assert_eq!(v.get("gain"), Some(Value::Number("1.5e1")));
+        assert_eq!(v.get("muted").and_then(|v| v.as_bool()), Some(false));
+        assert!(v.get("clip").map_or(false, |v| v.is_null()));
+        assert_eq!(v.get("missing"), None);
+        assert_eq!(v.get("esc"), Some(Value::String(r"é\n")));
+        assert_eq!(v.get("esc").and_then(|v| v.as_str()), None);
+
+        let tags = v.get("tags").unwrap();
+        let mut elements = tags.elements();
+        assert_eq!(elements.next(), Some(Value::String("a")));
+        assert_eq!(elements.next(), Some(Value::Number("2")));
+        assert_eq!(elements.next(), Some(Value::Object("{}")));
+        assert_eq!(elements.next(), None);
+        assert_eq!(v.members().count(), 7);
+    }
+
+    #[test]
+    fn trailing_commas() {
+        assert_eq!(parse(br#"{"a":1,}"#), Err(Error::Syntax(7)));
+        assert_eq!(parse(b"[1,2,]"), Err(Error::Syntax(5)));
+        assert_eq!(parse(b"[,]"), Err(Error::Syntax(1)));
+        assert_eq!(parse(b"[1 2]"), Err(Error::Syntax(3)));
+        assert_eq!(parse(b"1,"), Err(Error::Syntax(1)));
+    }
+
+    #[test]
+    fn numbers() {
+        for n in ["0", "-0", "10", "-0.5", "1e3", "2E-7", "-1.25e+10"].iter() {
+            assert_eq!(parse(n.as_bytes()), Ok(Value::Number(n)));
+        }
+
+        // Leading zeros
+        assert_eq!(parse(b"01"), Err(Error::Syntax(1)));
+        assert_eq!(parse(b"-01"), Err(Error::Syntax(2)));
+        assert_eq!(parse(b"[00]"), Err(Error::Syntax(2)));
+
+        assert_eq!(parse(b"-"), Err(Error::Syntax(1)));
+        assert_eq!(parse(b"+1"), Err(Error::Syntax(0)));
+        assert_eq!(parse(b".5"), Err(Error::Syntax(0)));
+        assert_eq!(parse(b"1."), Err(Error::Syntax(2)));
+        assert_eq!(parse(b"1e"), Err(Error::Syntax(2)));
+        assert_eq!(parse(b"1e+"), Err(Error::Syntax(3)));
+        assert_eq!(parse(b"0x10"), Err(Error::Syntax(1)));
+    }
+
+    #[test]
+    fn escapes() {
+        let ok = br#""\" \\ \/ \b \f \n \r \t \u0041 \uFFfe""#;
+        assert!(parse(ok).is_ok());
+
+        assert_eq!(parse(br#""\u12G4""#), Err(Error::Syntax(5)));
+        assert_eq!(parse(br#""\u12""#), Err(Error::Syntax(5)));
+        assert_eq!(parse(br#""\u""#), Err(Error::Syntax(3)));
+        assert_eq!(parse(br#""\x41""#), Err(Error::Syntax(2)));
+        assert_eq!(parse(br#""\'""#), Err(Error::Syntax(2)));
+        assert_eq!(parse(br#""\"#), Err(Error::Syntax(2)));
+    }
+
+    #[test]
+    fn control_characters() {
+        assert_eq!(parse(b"\"a\tb\""), Err(Error::Syntax(2)));
+        assert_eq!(parse(b"\"a\nb\""), Err(Error::Syntax(2)));
+        assert_eq!(parse(b"\"\x00\""), Err(Error::Syntax(1)));
+        assert_eq!(parse(b"\"\x1f\""), Err(Error::Syntax(1)));
+        assert_eq!(parse(b"\"\x7f\""), Ok(Value::String("\x7f")));
+        // Unterminated
+        assert_eq!(parse(b"\"abc"), Err(Error::Syntax(4)));
+
+        // Between tokens only the four whitespace characters are allowed
+        assert_eq!(parse(b" \t\r\n1\n"), Ok(Value::Number("1")));
+        assert_eq!(parse(b"\x0c1"), Err(Error::Syntax(0)));
+    }
+
+    #[test]
+    fn max_depth() {
+        let mut doc = String::new();
+        for _ in 0..MAX_DEPTH {
+            doc.push('[');
+        }
+        for _ in 0..MAX_DEPTH {
+            doc.push(']');
+        }
+        assert!(parse(doc.as_bytes()).is_ok());
+
+        let deeper = std::format!("[{}]", doc);
+        assert_eq!(parse(deeper.as_bytes()), Err(Error::TooDeep));
+        let deeper = std::format!(r#"{{"a":{}}}"#, doc);
+        assert_eq!(parse(deeper.as_bytes()), Err(Error::TooDeep));
+    }
+
+    #[test]
+    fn invalid_utf8() {
+        assert_eq!(parse(b"\"\xff\""), Err(Error::Syntax(1)));
+        // Truncated two byte sequence
+        assert_eq!(parse(b"[\"ok\",\"\xc3\"]"), Err(Error::Syntax(7)));
+        // Overlong encoding of '/'
+        assert_eq!(parse(b"\"\xc0\xaf\""), Err(Error::Syntax(1)));
+        // UTF-16 surrogate
+        assert_eq!(parse(b"\"\xed\xa0\x80\""), Err(Error::Syntax(1)));
+
+        assert_eq!(parse("\"é\"".as_bytes()), Ok(Value::String("é")));
+    }
+
+    #[test]
+    fn object_writer() {
+        let mut out = String::new();
+        object(&mut out)
+            .member("a\"b", "line\nbreak\t\\ \u{1} é")
+            .member("n", &-3_i32)
+            .member("gain", &0.5_f32)
+            .member("nan", &core::f32::NAN)
+            .member("none", &None::<u8>)
+            .member("addr", &Quoted("say \"hi\""))
+            .object("nested", |o| {
+                o.member("ok", &true);
+            })
+            .array("list", [1_u8, 2].iter())
+            .array("empty", core::iter::empty::<u8>())
+            .finish()
+            .unwrap();
+        assert_eq!(
+            out,
+            r#"{"a\"b":"line\nbreak\t\\ \u0001 é","n":-3,"gain":0.5,"nan":null,"none":null,"addr":"say \"hi\"","nested":{"ok":true},"list":[1,2],"empty":[]}"#
+        );
+
+        // What is written parses
+        let v = parse(out.as_bytes()).unwrap();
+        assert_eq!(
+            v.get(r#"a\"b"#),
+            Some(Value::String(r#"line\nbreak\t\\ \u0001 é"#))
+        );
+        assert_eq!(
+            v.get("nested").and_then(|n| n.get("ok")),
+            Some(Value::Bool(true))
+        );
+        assert_eq!(v.members().count(), 9);
+
+        let mut out = String::new();
+        object(&mut out).finish().unwrap();
+        assert_eq!(out, "{}");
+    }
+
+    /// Fails writes that don't fit in the bytes left
+    struct Limited(String, usize);
+
+    impl Write for Limited {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            if s.len() > self.1 {
+                return Err(fmt::Error);
+            }
+            self.1 -= s.len();
+            self.0.push_str(s);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn object_writer_errors() {
+        let mut out = Limited(String::new(), 11);
+        let result = object(&mut out)
+            .member("first", &1_u8)
+            .member("second", &2_u8)
+            .object("third", |o| {
+                o.member("x", &3_u8);
+            })
+            .finish();
+        assert_eq!(result, Err(fmt::Error));
+        // Nothing is written after the first error
+        assert_eq!(out.0, r#"{"first":1,"#);
+    }
+}
@@ -0,0 +1,18 @@
+#![no_std]
+#![deny(unsafe_code)]
+
+// Network protocols on top of smoltcp, independent of the board so they
+// also build and run on the host
+
+pub mod dhcp;
+pub mod g711;
+pub mod http;
+pub mod jitter;
+pub mod json;
+pub mod link;
+pub mod md5;
+pub mod pktgen;
+pub mod rtcp;
+pub mod rtp;
+pub mod sdp;
+pub mod sip;
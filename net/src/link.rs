@@ -0,0 +1,422 @@
+// Ethernet link supervision
+//
+// Debounces the link status the PHY reports, which bounces while a cable
+// goes in or autonegotiation settles, restarts the DMA when it stalls and
+// keeps count of it all along with the MAC's error counters. Reading the
+// PHY and driving the DMA is up to the board, behind `Mac`.
+//
+// smoltcp 0.5 can't flush an interface's neighbor cache, applications
+// build a new interface on `Event::Up` instead.
+
+use smoltcp::time::{Duration, Instant};
+
+/// A link that is up
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Link {
+    /// Mbit/s
+    pub speed: u32,
+    pub full_duplex: bool,
+}
+
+/// State of a DMA process, as far as stalls go
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Process {
+    Running,
+    /// Out of descriptors, where the transmitter idles
+    Suspended,
+    Stopped,
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct Errors {
+    pub rx_crc: u32,
+    pub rx_alignment: u32,
+    /// Frames dropped for the lack of a receive descriptor
+    pub rx_missed: u32,
+    /// Frames dropped for a full receive FIFO
+    pub rx_overflow: u32,
+}
+
+impl Errors {
+    fn add(&mut self, other: &Errors) {
+        self.rx_crc = self.rx_crc.wrapping_add(other.rx_crc);
+        self.rx_alignment = self.rx_alignment.wrapping_add(other.rx_alignment);
+        self.rx_missed = self.rx_missed.wrapping_add(other.rx_missed);
+        self.rx_overflow = self.rx_overflow.wrapping_add(other.rx_overflow);
+    }
+}
+
+/// The board's MAC and PHY
+pub trait Mac {
+    /// The link as the PHY has it
+    fn link(&mut self) -> Option<Link>;
+
+    fn rx_process(&mut self) -> Process;
+
+    fn tx_process(&mut self) -> Process;
+
+    /// Start the receive DMA again and have it poll its descriptors
+    fn restart_rx(&mut self);
+
+    fn restart_tx(&mut self);
+
+    /// Counts since the last call
+    fn errors(&mut self) -> Errors;
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Config {
+    /// How often to read the PHY and check on the DMA
+    pub interval: Duration,
+    /// How long a change has to last
+    pub debounce: Duration,
+    /// How long the receive DMA can be suspended before it's restarted
+    pub stall: Duration,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            interval: Duration::from_millis(100),
+            debounce: Duration::from_millis(500),
+            stall: Duration::from_millis(200),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct Counters {
+    pub ups: u32,
+    pub downs: u32,
+    /// Changes that didn't last
+    pub bounces: u32,
+    pub rx_restarts: u32,
+    pub tx_restarts: u32,
+    pub errors: Errors,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Event {
+    /// Also when a link comes up again at another speed or duplex
+    Up(Link),
+    Down,
+    RxRestarted,
+    TxRestarted,
+}
+
+#[derive(Debug)]
+pub struct LinkManager {
+    config: Config,
+    /// Debounced
+    link: Option<Link>,
+    /// Last read from the PHY and since when
+    sample: Option<Link>,
+    sample_since: Instant,
+    next_poll: Instant,
+    rx_suspended_since: Option<Instant>,
+    counters: Counters,
+}
+
+impl LinkManager {
+    /// The link starts out down
+    pub fn new(config: Config, now: Instant) -> Self {
+        LinkManager {
+            config,
+            link: None,
+            sample: None,
+            sample_since: now,
+            next_poll: now,
+            rx_suspended_since: None,
+            counters: Counters::default(),
+        }
+    }
+
+    pub fn link(&self) -> Option<Link> {
+        self.link
+    }
+
+    pub fn is_up(&self) -> bool {
+        self.link.is_some()
+    }
+
+    pub fn counters(&self) -> &Counters {
+        &self.counters
+    }
+
+    /// Read the PHY and check on the DMA every `interval`, returns what
+    /// changed
+    pub fn poll<M: Mac>(&mut self, mac: &mut M, now: Instant) -> Option<Event> {
+        if now < self.next_poll {
+            return None;
+        }
+        self.next_poll = now + self.config.interval;
+        self.counters.errors.add(&mac.errors());
+
+        let sample = mac.link();
+        if sample != self.sample {
+            // Replacing a change that hadn't taken yet
+            if self.sample != self.link {
+                self.counters.bounces += 1;
+            }
+            self.sample = sample;
+            self.sample_since = now;
+        }
+        if self.sample != self.link && now - self.sample_since >= self.config.debounce {
+            self.link = self.sample;
+            self.rx_suspended_since = None;
+            return Some(match self.link {
+                Some(link) => {
+                    self.counters.ups += 1;
+                    Event::Up(link)
+                }
+                None => {
+                    self.counters.downs += 1;
+                    Event::Down
+                }
+            });
+        }
+        // Nothing to move without a link
+        self.link?;
+
+        if mac.tx_process() == Process::Stopped {
+            mac.restart_tx();
+            self.counters.tx_restarts += 1;
+            return Some(Event::TxRestarted);
+        }
+        let stalled = match mac.rx_process() {
+            Process::Running => {
+                self.rx_suspended_since = None;
+                false
+            }
+            Process::Suspended => {
+                let since = *self.rx_suspended_since.get_or_insert(now);
+                now - since >= self.config.stall
+            }
+            Process::Stopped => true,
+        };
+        if stalled {
+            mac.restart_rx();
+            self.rx_suspended_since = None;
+            self.counters.rx_restarts += 1;
+            Some(Event::RxRestarted)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use core::mem;
+    use std::vec::Vec;
+
+    const FAST: Link = Link {
+        speed: 100,
+        full_duplex: true,
+    };
+    const SLOW: Link = Link {
+        speed: 10,
+        full_duplex: false,
+    };
+
+    /// The PHY and DMA as the test sets them, restarts get the DMA running
+    struct FakeMac {
+        link: Option<Link>,
+        rx: Process,
+        tx: Process,
+        rx_restarts: u32,
+        tx_restarts: u32,
+        errors: Errors,
+    }
+
+    impl Mac for FakeMac {
+        fn link(&mut self) -> Option<Link> {
+            self.link
+        }
+
+        fn rx_process(&mut self) -> Process {
+            self.rx
+        }
+
+        fn tx_process(&mut self) -> Process {
+            self.tx
+        }
+
+        fn restart_rx(&mut self) {
+            self.rx = Process::Running;
+            self.rx_restarts += 1;
+        }
+
+        fn restart_tx(&mut self) {
+            self.tx = Process::Suspended;
+            self.tx_restarts += 1;
+        }
+
+        fn errors(&mut self) -> Errors {
+            mem::replace(&mut self.errors, Errors::default())
+        }
+    }
+
+    struct Harness {
+        manager: LinkManager,
+        mac: FakeMac,
+    }
+
+    impl Harness {
+        /// The default config, a 100 ms interval
+        fn new(link: Option<Link>) -> Self {
+            Harness {
+                manager: LinkManager::new(Config::default(), Instant::from_millis(0)),
+                mac: FakeMac {
+                    link,
+                    rx: Process::Running,
+                    tx: Process::Suspended,
+                    rx_restarts: 0,
+                    tx_restarts: 0,
+                    errors: Errors::default(),
+                },
+            }
+        }
+
+        /// Poll every 10 ms from `from_ms` up to `to_ms`, returns the events
+        /// and when
+        fn run(&mut self, from_ms: i64, to_ms: i64) -> Vec<(i64, Event)> {
+            (from_ms..to_ms)
+                .step_by(10)
+                .filter_map(|ms| {
+                    let event = self.manager.poll(&mut self.mac, Instant::from_millis(ms));
+                    event.map(|event| (ms, event))
+                })
+                .collect()
+        }
+
+        /// Up at 500 ms
+        fn up(link: Link) -> Self {
+            let mut h = Harness::new(Some(link));
+            assert_eq!(h.run(0, 1000), [(500, Event::Up(link))]);
+            h
+        }
+    }
+
+    #[test]
+    fn bounce_then_settle() {
+        let mut h = Harness::new(None);
+        assert_eq!(h.run(0, 100), []);
+        h.mac.link = Some(FAST);
+        assert_eq!(h.run(100, 200), []);
+        h.mac.link = None;
+        assert_eq!(h.run(200, 300), []);
+        h.mac.link = Some(FAST);
+        // Half a second after the last change
+        assert_eq!(h.run(300, 2000), [(800, Event::Up(FAST))]);
+        assert_eq!(h.manager.link(), Some(FAST));
+        assert_eq!(
+            *h.manager.counters(),
+            Counters {
+                ups: 1,
+                bounces: 1,
+                ..Counters::default()
+            }
+        );
+    }
+
+    #[test]
+    fn down_and_up_at_another_speed() {
+        let mut h = Harness::up(FAST);
+        h.mac.link = None;
+        assert_eq!(h.run(1000, 2000), [(1500, Event::Down)]);
+        assert!(!h.manager.is_up());
+        h.mac.link = Some(SLOW);
+        assert_eq!(h.run(2000, 3000), [(2500, Event::Up(SLOW))]);
+        // Renegotiated without going down
+        h.mac.link = Some(FAST);
+        assert_eq!(h.run(3000, 4000), [(3500, Event::Up(FAST))]);
+        assert_eq!(
+            *h.manager.counters(),
+            Counters {
+                ups: 3,
+                downs: 1,
+                ..Counters::default()
+            }
+        );
+    }
+
+    #[test]
+    fn rx_stall() {
+        let mut h = Harness::up(FAST);
+        // Suspended for less than the stall, buffers freed in time
+        h.mac.rx = Process::Suspended;
+        assert_eq!(h.run(1000, 1200), []);
+        h.mac.rx = Process::Running;
+        assert_eq!(h.run(1200, 1300), []);
+
+        // Counted from the first poll that sees it
+        h.mac.rx = Process::Suspended;
+        assert_eq!(h.run(1300, 2000), [(1500, Event::RxRestarted)]);
+        assert_eq!(h.mac.rx, Process::Running);
+        assert_eq!(h.mac.rx_restarts, 1);
+
+        // Right away once stopped
+        h.mac.rx = Process::Stopped;
+        assert_eq!(h.run(2000, 2100), [(2000, Event::RxRestarted)]);
+        assert_eq!(h.manager.counters().rx_restarts, 2);
+    }
+
+    #[test]
+    fn tx_stopped() {
+        let mut h = Harness::up(FAST);
+        h.mac.tx = Process::Stopped;
+        assert_eq!(h.run(1000, 1100), [(1000, Event::TxRestarted)]);
+        assert_eq!(h.mac.tx_restarts, 1);
+        assert_eq!(h.manager.counters().tx_restarts, 1);
+    }
+
+    #[test]
+    fn dma_left_alone_while_down() {
+        let mut h = Harness::new(None);
+        h.mac.rx = Process::Stopped;
+        h.mac.tx = Process::Stopped;
+        assert_eq!(h.run(0, 1000), []);
+        assert_eq!((h.mac.rx_restarts, h.mac.tx_restarts), (0, 0));
+
+        // The suspension before the link came up doesn't count
+        h.mac.rx = Process::Suspended;
+        h.mac.tx = Process::Suspended;
+        h.mac.link = Some(FAST);
+        assert_eq!(h.run(1000, 1600), [(1500, Event::Up(FAST))]);
+        assert_eq!(h.run(1600, 1800), []);
+        assert_eq!(h.run(1800, 1900), [(1800, Event::RxRestarted)]);
+    }
+
+    #[test]
+    fn errors_add_up() {
+        let mut h = Harness::new(None);
+        h.mac.errors = Errors {
+            rx_crc: 1,
+            rx_missed: 2,
+            ..Errors::default()
+        };
+        h.run(0, 50);
+        h.mac.errors = Errors {
+            rx_crc: 3,
+            rx_overflow: 4,
+            ..Errors::default()
+        };
+        // Read with the next poll, not before
+        h.run(50, 100);
+        assert_eq!(h.manager.counters().errors.rx_overflow, 0);
+        h.run(100, 150);
+        assert_eq!(
+            h.manager.counters().errors,
+            Errors {
+                rx_crc: 4,
+                rx_alignment: 0,
+                rx_missed: 2,
+                rx_overflow: 4,
+            }
+        );
+    }
+}
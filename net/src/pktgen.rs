@@ -0,0 +1,875 @@
+// Ethernet traffic generator
+//
+// Builds the frames for `examples/pktgen.rs`: raw frames of an ethertype or
+// UDP/IPv4 datagrams with valid checksums, optionally VLAN tagged. The
+// frame lengths are fixed, sweep a range or follow the simple IMIX, and
+// frames are paced to a rate in frames or Mbit/s, sent alone or in bursts.
+//
+// The payload starts with a header carrying a sequence number and the time
+// the frame was built, so a receiver can count loss and reordering and
+// measure latency:
+//
+// magic (4) | sequence number (4) | timestamp in microseconds (8)
+//
+// All in network byte order. Time is whatever the caller counts
+// microseconds from, boot on the board.
+//
+// A peer can reflect frames back to where they came from with another
+// magic, the sender then measures the round trip against its own clock.
+// The `Analyzer` keeps count of the frames of the peer's stream and of the
+// own stream coming back, and their latencies. One-way latencies are
+// relative to the lowest seen, the clocks of the two sides aren't
+// synchronized.
+
+use core::fmt;
+use core::str::FromStr;
+use smoltcp::phy::ChecksumCapabilities;
+use smoltcp::wire::{
+    EthernetAddress, IpAddress, IpProtocol, Ipv4Address, Ipv4Packet, Ipv4Repr, UdpPacket,
+};
+
+/// IEEE 802 local experimental ethertype
+pub const ETHERTYPE: u16 = 0x88B5;
+
+pub const MAGIC: [u8; 4] = *b"PKTG";
+/// Magic of reflected frames
+pub const REFLECTED: [u8; 4] = *b"PKTR";
+
+/// Length of the payload header
+pub const HEADER_LEN: usize = 16;
+
+/// Frame lengths are without the FCS, which the MAC appends
+pub const MIN_FRAME_LEN: usize = 60;
+pub const MAX_FRAME_LEN: usize = 1514;
+
+const ETHERNET_HEADER_LEN: usize = 14;
+const VLAN_TAG_LEN: usize = 4;
+const IPV4_HEADER_LEN: usize = 20;
+const UDP_HEADER_LEN: usize = 8;
+const TPID: u16 = 0x8100;
+const ETHERTYPE_IPV4: u16 = 0x0800;
+
+/// Preamble, start of frame delimiter, FCS and inter-frame gap, the part of
+/// a frame on the wire that isn't in the buffer
+const WIRE_OVERHEAD: usize = 8 + 4 + 12;
+
+/// Frames due longer than this ago are dropped rather than sent back to back,
+/// in nanoseconds
+const MAX_LAG: u64 = 10_000_000;
+
+/// Latency histogram buckets, powers of two microseconds
+pub const NUM_BUCKETS: usize = 24;
+
+/// Sequence numbers behind the newest that are told apart as duplicates or
+/// reordered, older ones mean the sender restarted
+const WINDOW: u32 = 64;
+
+/// Commands of the pktgen example's console
+pub const USAGE: &str = "start | stop | stats | reset
+src <mac> | dst <mac>
+size <len> | size sweep <min> <max> <step> | size imix
+rate <n> pps | rate <n> mbps | rate max
+burst <n>
+interval <seconds>, 0 for none
+";
+
+/// Simple IMIX, 7 small, 4 medium and 1 large frame, spread out
+const IMIX: [usize; 12] = [60, 590, 60, 60, 1514, 60, 590, 60, 60, 590, 60, 590];
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Error {
+    /// A frame length is outside `MIN_FRAME_LEN..=MAX_FRAME_LEN` or too
+    /// short for the headers
+    Size,
+    /// A rate or burst of 0
+    Rate,
+}
+
+/// Frame lengths, without the FCS or VLAN tag
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Size {
+    Fixed(usize),
+    /// From `min` up to `max` in `step`s, then over again
+    Sweep {
+        min: usize,
+        max: usize,
+        step: usize,
+    },
+    /// 60, 590 and 1514 bytes, 7:4:1
+    IMIX,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Rate {
+    /// As fast as the transmit ring takes them
+    Unlimited,
+    /// Frames per second
+    Pps(u32),
+    /// Megabits per second on the wire, counting the preamble, FCS and
+    /// inter-frame gap
+    Mbps(u32),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Vlan {
+    /// 12 bits
+    pub id: u16,
+    /// 3 bits
+    pub priority: u8,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Kind {
+    /// The payload header right after the Ethernet header
+    Raw { ethertype: u16 },
+    /// The payload header in a UDP datagram
+    Udp {
+        src_addr: Ipv4Address,
+        src_port: u16,
+        dst_addr: Ipv4Address,
+        dst_port: u16,
+    },
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Config {
+    pub src: EthernetAddress,
+    pub dst: EthernetAddress,
+    pub kind: Kind,
+    pub vlan: Option<Vlan>,
+    pub size: Size,
+    pub rate: Rate,
+    /// Frames sent back to back each time, the rate is the average
+    pub burst: u32,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            src: EthernetAddress([0x02, 0x00, 0xDE, 0xAD, 0xBE, 0xEF]),
+            dst: EthernetAddress::BROADCAST,
+            kind: Kind::Raw {
+                ethertype: ETHERTYPE,
+            },
+            vlan: None,
+            size: Size::Fixed(MAX_FRAME_LEN),
+            rate: Rate::Unlimited,
+            burst: 1,
+        }
+    }
+}
+
+impl Config {
+    /// Length of the headers ahead of the payload header, without the VLAN
+    /// tag
+    pub fn headers_len(&self) -> usize {
+        match self.kind {
+            Kind::Raw { .. } => ETHERNET_HEADER_LEN,
+            Kind::Udp { .. } => ETHERNET_HEADER_LEN + IPV4_HEADER_LEN + UDP_HEADER_LEN,
+        }
+    }
+
+    fn check(&self) -> Result<(), Error> {
+        let (min, max) = match self.size {
+            Size::Fixed(len) => (len, len),
+            Size::Sweep { min, max, step } if step > 0 && min <= max => (min, max),
+            Size::Sweep { .. } => return Err(Error::Size),
+            Size::IMIX => (MIN_FRAME_LEN, MAX_FRAME_LEN),
+        };
+        if min < MIN_FRAME_LEN.max(self.headers_len() + HEADER_LEN) || max > MAX_FRAME_LEN {
+            return Err(Error::Size);
+        }
+        match self.rate {
+            Rate::Pps(0) | Rate::Mbps(0) => Err(Error::Rate),
+            _ if self.burst == 0 => Err(Error::Rate),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The payload header of a frame
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Header {
+    pub sequence: u32,
+    /// Microseconds
+    pub timestamp: u64,
+    /// On its way back to the sender
+    pub reflected: bool,
+}
+
+impl Header {
+    /// Read the header at the start of `payload`, None if it isn't one
+    pub fn parse(payload: &[u8]) -> Option<Self> {
+        if payload.len() < HEADER_LEN {
+            return None;
+        }
+        let reflected = match &payload[..4] {
+            m if m == MAGIC => false,
+            m if m == REFLECTED => true,
+            _ => return None,
+        };
+        let mut sequence = [0; 4];
+        let mut timestamp = [0; 8];
+        sequence.copy_from_slice(&payload[4..8]);
+        timestamp.copy_from_slice(&payload[8..16]);
+        Some(Header {
+            sequence: u32::from_be_bytes(sequence),
+            timestamp: u64::from_be_bytes(timestamp),
+            reflected,
+        })
+    }
+
+    /// Write the header to the start of `payload`.
+    ///
+    /// Panics if `payload` is shorter than `HEADER_LEN`.
+    pub fn emit(&self, payload: &mut [u8]) {
+        payload[..4].copy_from_slice(if self.reflected { &REFLECTED } else { &MAGIC });
+        payload[4..8].copy_from_slice(&self.sequence.to_be_bytes());
+        payload[8..16].copy_from_slice(&self.timestamp.to_be_bytes());
+    }
+}
+
+#[derive(Debug)]
+pub struct Generator {
+    config: Config,
+    sequence: u32,
+    /// Length of the next frame, VLAN tag included
+    len: usize,
+    /// Position in the IMIX pattern
+    imix: usize,
+    /// When the next burst is due, in nanoseconds
+    next_burst: u64,
+    /// Frames left of the current burst
+    burst_left: u32,
+}
+
+impl Generator {
+    pub fn new(config: Config) -> Result<Self, Error> {
+        config.check()?;
+        let mut generator = Generator {
+            config,
+            sequence: 0,
+            len: 0,
+            imix: 0,
+            next_burst: 0,
+            burst_left: 0,
+        };
+        generator.len = generator.first_len();
+        Ok(generator)
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Change the config, the sequence numbers carry on
+    pub fn set_config(&mut self, config: Config) -> Result<(), Error> {
+        config.check()?;
+        self.config = config;
+        self.imix = 0;
+        self.burst_left = 0;
+        self.len = self.first_len();
+        Ok(())
+    }
+
+    /// Sequence number of the next frame
+    pub fn sequence(&self) -> u32 {
+        self.sequence
+    }
+
+    /// Length of the next frame if it's due at `now`, in microseconds.
+    /// Passing it to `write` sends it. Frames that came due since the last
+    /// poll are sent back to back, poll at least every few milliseconds.
+    pub fn poll(&mut self, now: u64) -> Option<usize> {
+        if self.burst_left == 0 {
+            let now = now * 1000;
+            if self.config.rate != Rate::Unlimited && now < self.next_burst {
+                return None;
+            }
+            if now > self.next_burst + MAX_LAG {
+                self.next_burst = now;
+            }
+            self.burst_left = self.config.burst;
+        }
+        Some(self.len)
+    }
+
+    /// Build the frame `poll` returned the length of into `buf` at `now`, in
+    /// microseconds, returns its length.
+    ///
+    /// Panics if `buf` is shorter than that.
+    pub fn write(&mut self, buf: &mut [u8], now: u64) -> usize {
+        let len = self.len;
+        let buf = &mut buf[..len];
+        let config = &self.config;
+
+        buf[0..6].copy_from_slice(config.dst.as_bytes());
+        buf[6..12].copy_from_slice(config.src.as_bytes());
+        let mut offset = 12;
+        if let Some(vlan) = config.vlan {
+            let tci = u16::from(vlan.priority & 0x7) << 13 | vlan.id & 0xFFF;
+            buf[12..14].copy_from_slice(&TPID.to_be_bytes());
+            buf[14..16].copy_from_slice(&tci.to_be_bytes());
+            offset += VLAN_TAG_LEN;
+        }
+        let ethertype = match config.kind {
+            Kind::Raw { ethertype } => ethertype,
+            Kind::Udp { .. } => ETHERTYPE_IPV4,
+        };
+        buf[offset..offset + 2].copy_from_slice(&ethertype.to_be_bytes());
+        offset += 2;
+
+        let header = Header {
+            sequence: self.sequence,
+            timestamp: now,
+            reflected: false,
+        };
+        let packet = &mut buf[offset..];
+        match config.kind {
+            Kind::Raw { .. } => fill_payload(packet, &header),
+            Kind::Udp {
+                src_addr,
+                src_port,
+                dst_addr,
+                dst_port,
+            } => {
+                let ip_repr = Ipv4Repr {
+                    src_addr,
+                    dst_addr,
+                    protocol: IpProtocol::Udp,
+                    payload_len: packet.len() - IPV4_HEADER_LEN,
+                    hop_limit: 64,
+                };
+                let mut ip = Ipv4Packet::new_unchecked(&mut *packet);
+                ip_repr.emit(&mut ip, &ChecksumCapabilities::default());
+
+                let mut udp = UdpPacket::new_unchecked(ip.payload_mut());
+                udp.set_src_port(src_port);
+                udp.set_dst_port(dst_port);
+                udp.set_len(ip_repr.payload_len as u16);
+                fill_payload(udp.payload_mut(), &header);
+                udp.fill_checksum(&IpAddress::Ipv4(src_addr), &IpAddress::Ipv4(dst_addr));
+            }
+        }
+
+        self.sequence = self.sequence.wrapping_add(1);
+        self.next_burst += self.frame_time(len);
+        self.burst_left -= 1;
+        self.len = self.next_len();
+        len
+    }
+
+    /// Time a frame of `len` bytes takes at the rate, in nanoseconds
+    fn frame_time(&self, len: usize) -> u64 {
+        match self.config.rate {
+            Rate::Unlimited => 0,
+            Rate::Pps(pps) => 1_000_000_000 / u64::from(pps),
+            Rate::Mbps(mbps) => (len + WIRE_OVERHEAD) as u64 * 8 * 1000 / u64::from(mbps),
+        }
+    }
+
+    fn vlan_len(&self) -> usize {
+        if self.config.vlan.is_some() {
+            VLAN_TAG_LEN
+        } else {
+            0
+        }
+    }
+
+    fn first_len(&self) -> usize {
+        let len = match self.config.size {
+            Size::Fixed(len) => len,
+            Size::Sweep { min, .. } => min,
+            Size::IMIX => IMIX[0],
+        };
+        len + self.vlan_len()
+    }
+
+    fn next_len(&mut self) -> usize {
+        let len = match self.config.size {
+            Size::Fixed(len) => len,
+            Size::Sweep { min, max, step } => {
+                let next = self.len - self.vlan_len() + step;
+                if next > max {
+                    min
+                } else {
+                    next
+                }
+            }
+            Size::IMIX => {
+                self.imix = (self.imix + 1) % IMIX.len();
+                IMIX[self.imix]
+            }
+        };
+        len + self.vlan_len()
+    }
+}
+
+/// Latencies in microseconds, bucket `n` counts those from `2^n` up to
+/// `2^(n + 1)`, the first from 0 and the last all longer ones too
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Histogram {
+    pub buckets: [u32; NUM_BUCKETS],
+    pub count: u32,
+    pub min: u64,
+    pub max: u64,
+    sum: u64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Histogram {
+    pub fn new() -> Self {
+        Histogram {
+            buckets: [0; NUM_BUCKETS],
+            count: 0,
+            min: u64::max_value(),
+            max: 0,
+            sum: 0,
+        }
+    }
+
+    pub fn record(&mut self, latency: u64) {
+        let log2 = (64 - latency.leading_zeros() as usize).saturating_sub(1);
+        self.buckets[log2.min(NUM_BUCKETS - 1)] += 1;
+        self.count += 1;
+        self.min = self.min.min(latency);
+        self.max = self.max.max(latency);
+        self.sum += latency;
+    }
+
+    pub fn mean(&self) -> Option<u64> {
+        if self.count == 0 {
+            None
+        } else {
+            Some(self.sum / u64::from(self.count))
+        }
+    }
+}
+
+/// `min 12 mean 20 max 70 us, 8-16: 5 16-32: 90 64-128: 5`, empty buckets
+/// left out
+impl fmt::Display for Histogram {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mean = match self.mean() {
+            Some(mean) => mean,
+            None => return f.write_str("none"),
+        };
+        write!(f, "min {} mean {} max {} us,", self.min, mean, self.max)?;
+        for (n, count) in self.buckets.iter().enumerate().filter(|(_, c)| **c != 0) {
+            let low = if n == 0 { 0 } else { 1_u64 << n };
+            if n == NUM_BUCKETS - 1 {
+                write!(f, " {}+: {}", low, count)?;
+            } else {
+                write!(f, " {}-{}: {}", low, 1_u64 << (n + 1), count)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct Counters {
+    /// Not counting duplicates
+    pub received: u32,
+    /// Missing, less those that turned up late
+    pub lost: u32,
+    pub duplicates: u32,
+    /// Came after a later one
+    pub reordered: u32,
+    /// Sequence numbers jumped back further than the window
+    pub restarts: u32,
+}
+
+/// A stream of frames from one sender
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Flow {
+    pub counters: Counters,
+    pub latency: Histogram,
+    /// The sequence number in order next
+    next: Option<u32>,
+    /// Bit `n` is set if `next - 1 - n` was received
+    seen: u64,
+    /// Lowest one-way delay, the receiver's clock less the sender's
+    offset: Option<i64>,
+}
+
+impl Default for Flow {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Flow {
+    pub fn new() -> Self {
+        Flow {
+            counters: Counters::default(),
+            latency: Histogram::new(),
+            next: None,
+            seen: 0,
+            offset: None,
+        }
+    }
+
+    fn sequence(&mut self, sequence: u32) {
+        let next = match self.next {
+            Some(next) => next,
+            None => return self.restart(sequence),
+        };
+        let ahead = sequence.wrapping_sub(next);
+        if ahead < 1 << 31 {
+            // In order or after a gap
+            self.counters.lost += ahead;
+            self.seen = if ahead < 63 {
+                self.seen << (ahead + 1)
+            } else {
+                0
+            } | 1;
+            self.next = Some(sequence.wrapping_add(1));
+            self.counters.received += 1;
+            return;
+        }
+        let behind = next.wrapping_sub(sequence) - 1;
+        if behind >= WINDOW {
+            self.counters.restarts += 1;
+            self.restart(sequence);
+        } else if self.seen & 1 << behind != 0 {
+            self.counters.duplicates += 1;
+        } else {
+            self.seen |= 1 << behind;
+            self.counters.reordered += 1;
+            self.counters.lost = self.counters.lost.saturating_sub(1);
+            self.counters.received += 1;
+        }
+    }
+
+    fn restart(&mut self, sequence: u32) {
+        self.next = Some(sequence.wrapping_add(1));
+        self.seen = 1;
+        self.counters.received += 1;
+    }
+
+    /// Relative to the lowest so far
+    fn one_way(&mut self, timestamp: u64, now: u64) {
+        let delay = now.wrapping_sub(timestamp) as i64;
+        let offset = match self.offset {
+            Some(offset) if offset <= delay => offset,
+            _ => {
+                self.offset = Some(delay);
+                delay
+            }
+        };
+        self.latency.record(delay.wrapping_sub(offset) as u64);
+    }
+}
+
+/// Counts what comes in of the peer's stream and of the own one it
+/// reflected
+#[derive(Debug, Default)]
+pub struct Analyzer {
+    /// One-way latencies
+    pub received: Flow,
+    /// Round-trip latencies
+    pub returned: Flow,
+}
+
+impl Analyzer {
+    pub fn new() -> Self {
+        Analyzer::default()
+    }
+
+    /// Count `frame`, received at `now` in microseconds, returns its header
+    /// or None if it isn't a pktgen frame
+    pub fn receive(&mut self, frame: &[u8], now: u64) -> Option<Header> {
+        let header = parse(frame)?;
+        if header.reflected {
+            self.returned.sequence(header.sequence);
+            self.returned
+                .latency
+                .record(now.saturating_sub(header.timestamp));
+        } else {
+            self.received.sequence(header.sequence);
+            self.received.one_way(header.timestamp, now);
+        }
+        Some(header)
+    }
+
+    pub fn reset(&mut self) {
+        *self = Analyzer::default();
+    }
+}
+
+/// The payload header of an Ethernet `frame`, None if it isn't a pktgen
+/// frame
+pub fn parse(frame: &[u8]) -> Option<Header> {
+    let (payload, _) = locate(frame)?;
+    Header::parse(&frame[payload..])
+}
+
+/// Turn a frame of the peer's stream around, swapping the addresses and
+/// ports and marking it reflected. Returns false and leaves `frame` alone if
+/// it isn't one.
+pub fn reflect(frame: &mut [u8]) -> bool {
+    let (payload, ip) = match locate(frame) {
+        Some(offsets) => offsets,
+        None => return false,
+    };
+    match Header::parse(&frame[payload..]) {
+        Some(header) if !header.reflected => (),
+        _ => return false,
+    }
+    for i in 0..6 {
+        frame.swap(i, i + 6);
+    }
+    frame[payload..payload + 4].copy_from_slice(&REFLECTED);
+
+    if let Some(offset) = ip {
+        let mut ip = Ipv4Packet::new_unchecked(&mut frame[offset..]);
+        let (src_addr, dst_addr) = (ip.src_addr(), ip.dst_addr());
+        ip.set_src_addr(dst_addr);
+        ip.set_dst_addr(src_addr);
+        ip.fill_checksum();
+
+        let mut udp = UdpPacket::new_unchecked(ip.payload_mut());
+        let (src_port, dst_port) = (udp.src_port(), udp.dst_port());
+        udp.set_src_port(dst_port);
+        udp.set_dst_port(src_port);
+        udp.fill_checksum(&IpAddress::Ipv4(dst_addr), &IpAddress::Ipv4(src_addr));
+    }
+    true
+}
+
+/// A line of `USAGE`
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Command {
+    Start,
+    Stop,
+    /// Print the counters
+    Stats,
+    /// Clear the counters
+    Reset,
+    Src(EthernetAddress),
+    Dst(EthernetAddress),
+    Size(Size),
+    Rate(Rate),
+    Burst(u32),
+    /// Seconds between printing the counters, 0 for never
+    Interval(u32),
+}
+
+impl Command {
+    /// None if `line` isn't a command
+    pub fn parse(line: &str) -> Option<Self> {
+        let mut words = line.split_whitespace();
+        let command = match words.next()? {
+            "start" => Command::Start,
+            "stop" => Command::Stop,
+            "stats" => Command::Stats,
+            "reset" => Command::Reset,
+            "src" => Command::Src(value(words.next())?),
+            "dst" => Command::Dst(value(words.next())?),
+            "size" => Command::Size(match words.next()? {
+                "imix" => Size::IMIX,
+                "sweep" => Size::Sweep {
+                    min: value(words.next())?,
+                    max: value(words.next())?,
+                    step: value(words.next())?,
+                },
+                len => Size::Fixed(value(Some(len))?),
+            }),
+            "rate" => Command::Rate(match (words.next()?, words.next()) {
+                ("max", None) => Rate::Unlimited,
+                (n, Some("pps")) => Rate::Pps(value(Some(n))?),
+                (n, Some("mbps")) => Rate::Mbps(value(Some(n))?),
+                _ => return None,
+            }),
+            "burst" => Command::Burst(value(words.next())?),
+            "interval" => Command::Interval(value(words.next())?),
+            _ => return None,
+        };
+        if words.next().is_some() {
+            None
+        } else {
+            Some(command)
+        }
+    }
+}
+
+fn value<T: FromStr>(word: Option<&str>) -> Option<T> {
+    word?.parse().ok()
+}
+
+/// The header followed by zeros
+fn fill_payload(payload: &mut [u8], header: &Header) {
+    header.emit(payload);
+    for b in &mut payload[HEADER_LEN..] {
+        *b = 0;
+    }
+}
+
+/// Offsets of the payload header in `frame` and of the IPv4 header if it's
+/// in a UDP datagram
+fn locate(frame: &[u8]) -> Option<(usize, Option<usize>)> {
+    let ethertype = |offset: usize| {
+        frame
+            .get(offset..offset + 2)
+            .map(|b| u16::from_be_bytes([b[0], b[1]]))
+    };
+    let mut offset = 12;
+    let mut kind = ethertype(offset)?;
+    if kind == TPID {
+        offset += VLAN_TAG_LEN;
+        kind = ethertype(offset)?;
+    }
+    offset += 2;
+    if kind != ETHERTYPE_IPV4 {
+        return Some((offset, None));
+    }
+
+    let ip = Ipv4Packet::new_checked(&frame[offset..]).ok()?;
+    if ip.protocol() != IpProtocol::Udp {
+        return None;
+    }
+    UdpPacket::new_checked(ip.payload()).ok()?;
+    let payload = offset + usize::from(ip.header_len()) + UDP_HEADER_LEN;
+    Some((payload, Some(offset)))
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use std::string::ToString;
+    use std::vec::Vec;
+
+    fn flow(sequences: &[u32]) -> Flow {
+        let mut flow = Flow::new();
+        for &sequence in sequences {
+            flow.sequence(sequence);
+        }
+        flow
+    }
+
+    #[test]
+    fn lost_duplicate_reordered() {
+        let flow = flow(&[0, 1, 3, 2, 2, 5, 9, 4]);
+        assert_eq!(
+            flow.counters,
+            Counters {
+                received: 7,
+                // 6, 7 and 8
+                lost: 3,
+                duplicates: 1,
+                reordered: 2,
+                restarts: 0,
+            }
+        );
+        assert_eq!(flow.next, Some(10));
+    }
+
+    #[test]
+    fn window() {
+        let mut flow = flow(&(0..100).collect::<Vec<_>>());
+        // The oldest told apart
+        flow.sequence(36);
+        assert_eq!(flow.counters.duplicates, 1);
+        assert_eq!(flow.counters.restarts, 0);
+
+        // Just out of the window, counted from here on
+        flow.sequence(35);
+        assert_eq!(flow.counters.restarts, 1);
+        assert_eq!(flow.counters.received, 101);
+        flow.sequence(36);
+        assert_eq!(
+            flow.counters,
+            Counters {
+                received: 102,
+                lost: 0,
+                duplicates: 1,
+                reordered: 0,
+                restarts: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn long_gap() {
+        // Further ahead than the bits kept
+        let mut flow = flow(&[0, 71]);
+        assert_eq!(flow.counters.lost, 70);
+        flow.sequence(40);
+        assert_eq!(flow.counters.reordered, 1);
+        assert_eq!(flow.counters.lost, 69);
+        flow.sequence(40);
+        assert_eq!(flow.counters.duplicates, 1);
+        assert_eq!(flow.counters.received, 3);
+    }
+
+    #[test]
+    fn wraps() {
+        let max = u32::max_value();
+        let mut flow = flow(&[max - 1, max, 0, 1]);
+        flow.sequence(max);
+        assert_eq!(
+            flow.counters,
+            Counters {
+                received: 4,
+                lost: 0,
+                duplicates: 1,
+                reordered: 0,
+                restarts: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn histogram_buckets() {
+        let edges: &[(u64, usize)] = &[
+            (0, 0),
+            (1, 0),
+            (2, 1),
+            (3, 1),
+            (4, 2),
+            (1023, 9),
+            (1024, 10),
+            ((1 << 23) - 1, 22),
+            (1 << 23, 23),
+            (1 << 40, 23),
+        ];
+        for &(latency, bucket) in edges {
+            let mut histogram = Histogram::new();
+            histogram.record(latency);
+            let mut buckets = [0; NUM_BUCKETS];
+            buckets[bucket] = 1;
+            assert_eq!(histogram.buckets, buckets, "{}", latency);
+        }
+    }
+
+    #[test]
+    fn histogram_display() {
+        let mut histogram = Histogram::new();
+        assert_eq!(histogram.mean(), None);
+        assert_eq!(histogram.to_string(), "none");
+
+        for &latency in &[12, 20, 70] {
+            histogram.record(latency);
+        }
+        assert_eq!(
+            histogram.to_string(),
+            "min 12 mean 34 max 70 us, 8-16: 1 16-32: 1 64-128: 1"
+        );
+
+        let mut histogram = Histogram::new();
+        histogram.record(1);
+        histogram.record(1 << 30);
+        assert_eq!(
+            histogram.to_string(),
+            "min 1 mean 536870912 max 1073741824 us, 0-2: 1 8388608+: 1"
+        );
+    }
+}
@@ -0,0 +1,107 @@
+// RTCP sender reports, RFC 3550 6.4.1
+//
+// Only what a sender needs: a compound packet of a sender report without
+// report blocks followed by a source description carrying the CNAME, which
+// every compound packet has to include. Receiver reports aren't parsed.
+//
+// The board has no wall clock, NTP timestamps count from the `Instant`
+// epoch, boot on the board. Receivers only use them to relate RTP
+// timestamps of the same sender to each other.
+
+use smoltcp::time::Instant;
+
+pub const VERSION: u8 = 2;
+
+/// Packet types
+pub const SR: u8 = 200;
+pub const SDES: u8 = 202;
+
+/// SDES item types
+const CNAME: u8 = 1;
+const END: u8 = 0;
+
+/// Longest SDES item text
+pub const MAX_CNAME_LEN: usize = 255;
+
+/// Sender info of a sender report
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SenderReport {
+    pub ssrc: u32,
+    /// Seconds in the upper 32 bits, fraction in the lower
+    pub ntp_timestamp: u64,
+    /// RTP timestamp of the same instant
+    pub rtp_timestamp: u32,
+    /// Packets and payload octets sent since the stream started
+    pub packets: u32,
+    pub octets: u32,
+}
+
+impl SenderReport {
+    /// Without report blocks
+    pub const LEN: usize = 28;
+
+    /// Write the report to the start of `buf`.
+    ///
+    /// Panics if `buf` is shorter than `SenderReport::LEN`.
+    pub fn emit(&self, buf: &mut [u8]) {
+        emit_header(buf, 0, SR, Self::LEN);
+        buf[4..8].copy_from_slice(&self.ssrc.to_be_bytes());
+        buf[8..16].copy_from_slice(&self.ntp_timestamp.to_be_bytes());
+        buf[16..20].copy_from_slice(&self.rtp_timestamp.to_be_bytes());
+        buf[20..24].copy_from_slice(&self.packets.to_be_bytes());
+        buf[24..28].copy_from_slice(&self.octets.to_be_bytes());
+    }
+}
+
+/// NTP format timestamp of `now`
+pub fn ntp_timestamp(now: Instant) -> u64 {
+    let millis = now.total_millis().max(0) as u64;
+    let fraction = (millis % 1000) * (1 << 32) / 1000;
+    (millis / 1000) << 32 | fraction
+}
+
+/// Length of an SDES packet with a CNAME item, `cname` is cut to
+/// `MAX_CNAME_LEN`
+pub fn sdes_len(cname: &str) -> usize {
+    // Header, SSRC, item type and length, text, end, padded to 32 bits
+    (8 + 2 + cname.len().min(MAX_CNAME_LEN) + 1 + 3) & !3
+}
+
+/// Write an SDES packet for `ssrc` with a CNAME item to the start of `buf`,
+/// returns its length.
+///
+/// Panics if `buf` is shorter than `sdes_len(cname)`.
+pub fn emit_sdes(ssrc: u32, cname: &str, buf: &mut [u8]) -> usize {
+    let text = &cname.as_bytes()[..cname.len().min(MAX_CNAME_LEN)];
+    let len = sdes_len(cname);
+    emit_header(buf, 1, SDES, len);
+    buf[4..8].copy_from_slice(&ssrc.to_be_bytes());
+    buf[8] = CNAME;
+    buf[9] = text.len() as u8;
+    buf[10..10 + text.len()].copy_from_slice(text);
+    for b in buf[10 + text.len()..len].iter_mut() {
+        *b = END;
+    }
+    len
+}
+
+/// Length of the compound packet `emit_compound` writes
+pub fn compound_len(cname: &str) -> usize {
+    SenderReport::LEN + sdes_len(cname)
+}
+
+/// Write a sender report followed by the source description, returns the
+/// length.
+///
+/// Panics if `buf` is shorter than `compound_len(cname)`.
+pub fn emit_compound(report: &SenderReport, cname: &str, buf: &mut [u8]) -> usize {
+    report.emit(buf);
+    SenderReport::LEN + emit_sdes(report.ssrc, cname, &mut buf[SenderReport::LEN..])
+}
+
+/// `len` in bytes, a multiple of 4
+fn emit_header(buf: &mut [u8], count: u8, packet_type: u8, len: usize) {
+    buf[0] = VERSION << 6 | (count & 0x1F);
+    buf[1] = packet_type;
+    buf[2..4].copy_from_slice(&(len as u16 / 4 - 1).to_be_bytes());
+}
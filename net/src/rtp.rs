@@ -0,0 +1,629 @@
+// RTP, RFC 3550, carrying the audio payloads of RFC 3551
+//
+// The receiver takes packets from one UDP port, the stream is whichever
+// SSRC sent last. Static payload types map to PCMU, PCMA and L16 at
+// 44.1 kHz, dynamic ones have to be set up with
+// `Receiver::set_payload_type`. Packets are decoded into a `JitterBuffer`
+// which the playback path drains at its own rate.
+//
+// The sender takes interleaved stereo frames at the codec's rate, as the
+// capture path delivers them, and sends a packet every ptime. Frames are
+// mixed down and decimated by averaging when the payload has fewer channels
+// or a lower clock rate. The average is a poor low-pass, what's above the
+// payload's band has to be filtered out before `Sender::send` or it aliases,
+// e.g. with a `wm8960::biquad` cascade as in `examples/rtp_send_tap.rs`.
+// It can also send RTCP sender reports from the port above its own.
+
+use crate::g711;
+use crate::jitter::{JitterBuffer, Stats};
+use crate::rtcp::{self, SenderReport};
+use core::fmt;
+use smoltcp::socket::{SocketHandle, SocketSet, UdpSocket, UdpSocketBuffer};
+use smoltcp::time::{Duration, Instant};
+use smoltcp::wire::IpEndpoint;
+
+pub const VERSION: u8 = 2;
+/// Fixed header, without CSRCs or extension
+pub const HEADER_LEN: usize = 12;
+
+/// Commonly used port, RTCP goes to the port above
+pub const DEFAULT_PORT: u16 = 5004;
+
+/// First dynamic payload type
+pub const DYNAMIC_PAYLOAD_TYPE: u8 = 96;
+
+/// Largest payload sent, what fits in an Ethernet frame
+pub const MAX_PAYLOAD_LEN: usize = 1500 - 20 - 8 - HEADER_LEN;
+
+/// Sender reports go out this often, the minimum of RFC 3550
+pub const RTCP_INTERVAL_MS: u64 = 5000;
+/// Channels of the frames passed to `Sender::send`
+const INPUT_CHANNELS: usize = 2;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Error {
+    /// Shorter than its header or padding says
+    Truncated,
+    /// Not version 2
+    Version(u8),
+    /// No format for the payload type
+    UnknownPayloadType(u8),
+    /// A packet of the ptime doesn't fit in `MAX_PAYLOAD_LEN`
+    PayloadTooLarge,
+    /// The sender's input rate is below the clock rate
+    InputRate,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Header {
+    pub marker: bool,
+    pub payload_type: u8,
+    pub sequence: u16,
+    pub timestamp: u32,
+    pub ssrc: u32,
+}
+
+impl Header {
+    /// Split a packet into its header and payload, CSRCs, the header
+    /// extension and padding are skipped
+    pub fn parse(packet: &[u8]) -> Result<(Header, &[u8]), Error> {
+        if packet.len() < HEADER_LEN {
+            return Err(Error::Truncated);
+        }
+        let version = packet[0] >> 6;
+        if version != VERSION {
+            return Err(Error::Version(version));
+        }
+        let padding = packet[0] & 0x20 != 0;
+        let extension = packet[0] & 0x10 != 0;
+        let csrc_count = usize::from(packet[0] & 0x0F);
+        let header = Header {
+            marker: packet[1] & 0x80 != 0,
+            payload_type: packet[1] & 0x7F,
+            sequence: u16::from_be_bytes([packet[2], packet[3]]),
+            timestamp: u32::from_be_bytes([packet[4], packet[5], packet[6], packet[7]]),
+            ssrc: u32::from_be_bytes([packet[8], packet[9], packet[10], packet[11]]),
+        };
+
+        let mut start = HEADER_LEN + 4 * csrc_count;
+        if extension {
+            let words = packet.get(start + 2..start + 4).ok_or(Error::Truncated)?;
+            start += 4 + 4 * usize::from(u16::from_be_bytes([words[0], words[1]]));
+        }
+        let mut end = packet.len();
+        if padding {
+            end = end.saturating_sub(usize::from(packet[end - 1]));
+        }
+        if start > end {
+            return Err(Error::Truncated);
+        }
+        Ok((header, &packet[start..end]))
+    }
+
+    /// Write the fixed header to the start of `buf`.
+    ///
+    /// Panics if `buf` is shorter than `HEADER_LEN`.
+    pub fn emit(&self, buf: &mut [u8]) {
+        buf[0] = VERSION << 6;
+        buf[1] = (self.marker as u8) << 7 | (self.payload_type & 0x7F);
+        buf[2..4].copy_from_slice(&self.sequence.to_be_bytes());
+        buf[4..8].copy_from_slice(&self.timestamp.to_be_bytes());
+        buf[8..12].copy_from_slice(&self.ssrc.to_be_bytes());
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Encoding {
+    /// G.711 mu-law
+    PCMU,
+    /// G.711 A-law
+    PCMA,
+    /// 16-bit linear, network byte order
+    L16,
+}
+
+impl Encoding {
+    /// Bytes per sample
+    pub fn sample_size(self) -> usize {
+        match self {
+            Encoding::PCMU | Encoding::PCMA => 1,
+            Encoding::L16 => 2,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Format {
+    pub encoding: Encoding,
+    /// Timestamp units per second, the sample rate for these encodings
+    pub clock_rate: u32,
+    pub channels: u8,
+}
+
+impl Format {
+    pub const PCMU: Format = Format {
+        encoding: Encoding::PCMU,
+        clock_rate: 8000,
+        channels: 1,
+    };
+    pub const PCMA: Format = Format {
+        encoding: Encoding::PCMA,
+        clock_rate: 8000,
+        channels: 1,
+    };
+
+    /// Static payload types of RFC 3551
+    pub fn from_payload_type(payload_type: u8) -> Option<Format> {
+        let l16 = |channels| Format {
+            encoding: Encoding::L16,
+            clock_rate: 44_100,
+            channels,
+        };
+        match payload_type {
+            0 => Some(Format::PCMU),
+            8 => Some(Format::PCMA),
+            10 => Some(l16(2)),
+            11 => Some(l16(1)),
+            _ => None,
+        }
+    }
+
+    /// Static payload type of the format, if it has one
+    pub fn payload_type(self) -> Option<u8> {
+        (0..DYNAMIC_PAYLOAD_TYPE).find(|pt| Format::from_payload_type(*pt) == Some(self))
+    }
+
+    /// Decode `payload` into interleaved samples, as many as fit in `dst`.
+    /// Returns the number of samples written.
+    pub fn decode(self, payload: &[u8], dst: &mut [i16]) -> usize {
+        let len = (payload.len() / self.encoding.sample_size()).min(dst.len());
+        let dst = &mut dst[..len];
+        match self.encoding {
+            Encoding::PCMU => {
+                for (sample, code) in dst.iter_mut().zip(payload) {
+                    *sample = g711::ulaw_decode(*code);
+                }
+            }
+            Encoding::PCMA => {
+                for (sample, code) in dst.iter_mut().zip(payload) {
+                    *sample = g711::alaw_decode(*code);
+                }
+            }
+            Encoding::L16 => {
+                for (sample, bytes) in dst.iter_mut().zip(payload.chunks_exact(2)) {
+                    *sample = i16::from_be_bytes([bytes[0], bytes[1]]);
+                }
+            }
+        }
+        len
+    }
+
+    /// Encode interleaved samples into `dst`, as many as fit. Returns the
+    /// number of bytes written.
+    pub fn encode(self, samples: &[i16], dst: &mut [u8]) -> usize {
+        let size = self.encoding.sample_size();
+        let len = samples.len().min(dst.len() / size);
+        let samples = &samples[..len];
+        match self.encoding {
+            Encoding::PCMU => {
+                for (code, sample) in dst.iter_mut().zip(samples) {
+                    *code = g711::ulaw_encode(*sample);
+                }
+            }
+            Encoding::PCMA => {
+                for (code, sample) in dst.iter_mut().zip(samples) {
+                    *code = g711::alaw_encode(*sample);
+                }
+            }
+            Encoding::L16 => {
+                for (bytes, sample) in dst.chunks_exact_mut(2).zip(samples) {
+                    bytes.copy_from_slice(&sample.to_be_bytes());
+                }
+            }
+        }
+        len * size
+    }
+}
+
+/// Receives a stream on a UDP port into a jitter buffer
+#[derive(Debug)]
+pub struct Receiver<'a> {
+    handle: SocketHandle,
+    jitter: JitterBuffer<'a>,
+    dynamic: Option<(u8, Format)>,
+    source: Option<IpEndpoint>,
+    invalid: u32,
+}
+
+impl<'a> Receiver<'a> {
+    /// Add a UDP socket bound to `port` to `sockets`.
+    ///
+    /// The receiver doesn't send, `tx_buffer` can be empty. `rx_buffer`
+    /// should hold the packets arriving between two polls.
+    pub fn new<'b, 'c>(
+        sockets: &mut SocketSet<'_, 'b, 'c>,
+        rx_buffer: UdpSocketBuffer<'b, 'c>,
+        tx_buffer: UdpSocketBuffer<'b, 'c>,
+        port: u16,
+        jitter: JitterBuffer<'a>,
+    ) -> Self {
+        let mut socket = UdpSocket::new(rx_buffer, tx_buffer);
+        // Only fails for port 0
+        let _ = socket.bind(port);
+        Receiver {
+            handle: sockets.add(socket),
+            jitter,
+            dynamic: None,
+            source: None,
+            invalid: 0,
+        }
+    }
+
+    /// Map a dynamic payload type, e.g. 96 to L16 at 48 kHz stereo
+    pub fn set_payload_type(&mut self, payload_type: u8, format: Format) {
+        self.dynamic = Some((payload_type, format));
+    }
+
+    /// Sender of the last packet
+    pub fn source(&self) -> Option<IpEndpoint> {
+        self.source
+    }
+
+    pub fn jitter_buffer(&self) -> &JitterBuffer<'a> {
+        &self.jitter
+    }
+
+    pub fn stats(&self) -> Stats {
+        Stats {
+            invalid: self.invalid,
+            ..*self.jitter.stats()
+        }
+    }
+
+    /// Drop the stream, e.g. when a call ends, see `JitterBuffer::reset`
+    pub fn reset(&mut self) {
+        self.jitter.reset();
+        self.source = None;
+    }
+
+    /// Take all packets received since the last poll
+    pub fn poll(&mut self, sockets: &mut SocketSet, now: Instant) {
+        let mut socket = sockets.get::<UdpSocket>(self.handle);
+        while let Ok((packet, source)) = socket.recv() {
+            if self.receive(packet, now).is_ok() {
+                self.source = Some(source);
+            }
+        }
+    }
+
+    /// Take a packet that arrived some other way
+    pub fn receive(&mut self, packet: &[u8], now: Instant) -> Result<(), Error> {
+        let result = Header::parse(packet).and_then(|(header, payload)| {
+            let format = match self.dynamic {
+                Some((payload_type, format)) if payload_type == header.payload_type => Some(format),
+                _ => Format::from_payload_type(header.payload_type),
+            };
+            match format {
+                Some(format) => {
+                    self.jitter.insert(&header, format, payload, now);
+                    Ok(())
+                }
+                None => Err(Error::UnknownPayloadType(header.payload_type)),
+            }
+        });
+        if result.is_err() {
+            self.invalid += 1;
+        }
+        result
+    }
+
+    /// Interleaved stereo samples at the jitter buffer's output rate, see
+    /// `JitterBuffer::fill`
+    pub fn fill(&mut self, buf: &mut [i16]) -> usize {
+        self.jitter.fill(buf)
+    }
+}
+
+/// Audio per packet
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Ptime {
+    Ms10,
+    Ms20,
+    Ms40,
+}
+
+impl Ptime {
+    pub fn from_ms(ms: u32) -> Option<Self> {
+        match ms {
+            10 => Some(Ptime::Ms10),
+            20 => Some(Ptime::Ms20),
+            40 => Some(Ptime::Ms40),
+            _ => None,
+        }
+    }
+
+    pub fn ms(self) -> u32 {
+        match self {
+            Ptime::Ms10 => 10,
+            Ptime::Ms20 => 20,
+            Ptime::Ms40 => 40,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SenderConfig {
+    pub format: Format,
+    pub payload_type: u8,
+    pub ptime: Ptime,
+    /// Rate of the frames passed to `Sender::send`, at least the format's
+    /// clock rate. It needn't be a multiple, the codec's 48.828 kHz gives
+    /// PCMU frames of 6 or 7 input frames.
+    pub input_rate: u32,
+    /// Should be random, it also seeds the first sequence number and
+    /// timestamp
+    pub ssrc: u32,
+}
+
+impl SenderConfig {
+    /// 20 ms packets of a format with a static payload type, from frames
+    /// at `input_rate`
+    pub fn new(format: Format, input_rate: u32, ssrc: u32) -> Self {
+        SenderConfig {
+            format,
+            payload_type: format.payload_type().unwrap_or(DYNAMIC_PAYLOAD_TYPE),
+            ptime: Ptime::Ms20,
+            input_rate,
+            ssrc,
+        }
+    }
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct SenderStats {
+    pub packets: u32,
+    /// Payload bytes sent
+    pub octets: u32,
+    /// Packets that didn't fit in the socket's buffer
+    pub dropped: u32,
+    pub reports: u32,
+}
+
+/// Sends captured audio to one destination
+pub struct Sender {
+    handle: SocketHandle,
+    /// RTCP socket and CNAME
+    rtcp: Option<(SocketHandle, &'static str)>,
+    destination: IpEndpoint,
+    config: SenderConfig,
+    frames_per_packet: usize,
+    sequence: u16,
+    timestamp: u32,
+    marker: bool,
+    payload: [u8; MAX_PAYLOAD_LEN],
+    len: usize,
+    frames: usize,
+    sum: [i32; INPUT_CHANNELS],
+    summed: usize,
+    /// Goes up by the clock rate per input frame, a payload frame is due
+    /// whenever it reaches the input rate
+    phase: u32,
+    next_report: Option<Instant>,
+    stats: SenderStats,
+}
+
+impl Sender {
+    /// Add a UDP socket bound to `port` to `sockets`, packets go to
+    /// `destination`.
+    ///
+    /// `tx_buffer` should hold the packets sent between two polls, the
+    /// sender ignores anything received, `rx_buffer` can be empty.
+    pub fn new<'b, 'c>(
+        sockets: &mut SocketSet<'_, 'b, 'c>,
+        rx_buffer: UdpSocketBuffer<'b, 'c>,
+        tx_buffer: UdpSocketBuffer<'b, 'c>,
+        port: u16,
+        destination: IpEndpoint,
+        config: SenderConfig,
+    ) -> Result<Self, Error> {
+        let frames_per_packet = packetization(&config)?;
+        let mut socket = UdpSocket::new(rx_buffer, tx_buffer);
+        // Only fails for port 0
+        let _ = socket.bind(port);
+        Ok(Sender {
+            handle: sockets.add(socket),
+            rtcp: None,
+            destination,
+            config,
+            frames_per_packet,
+            sequence: (config.ssrc >> 16) as u16,
+            timestamp: config.ssrc.rotate_left(8),
+            marker: true,
+            payload: [0; MAX_PAYLOAD_LEN],
+            len: 0,
+            frames: 0,
+            sum: [0; INPUT_CHANNELS],
+            summed: 0,
+            phase: 0,
+            next_report: None,
+            stats: SenderStats::default(),
+        })
+    }
+
+    /// Send sender reports from the port above the RTP port to the one
+    /// above the destination's.
+    ///
+    /// `tx_buffer` needs room for `rtcp::compound_len(cname)`.
+    pub fn enable_rtcp<'b, 'c>(
+        &mut self,
+        sockets: &mut SocketSet<'_, 'b, 'c>,
+        rx_buffer: UdpSocketBuffer<'b, 'c>,
+        tx_buffer: UdpSocketBuffer<'b, 'c>,
+        cname: &'static str,
+    ) {
+        let port = sockets.get::<UdpSocket>(self.handle).endpoint().port;
+        let mut socket = UdpSocket::new(rx_buffer, tx_buffer);
+        let _ = socket.bind(port.wrapping_add(1));
+        self.rtcp = Some((sockets.add(socket), cname));
+    }
+
+    pub fn config(&self) -> &SenderConfig {
+        &self.config
+    }
+
+    pub fn stats(&self) -> &SenderStats {
+        &self.stats
+    }
+
+    pub fn destination(&self) -> IpEndpoint {
+        self.destination
+    }
+
+    /// Send to a new destination, the stream carries on
+    pub fn set_destination(&mut self, destination: IpEndpoint) {
+        self.destination = destination;
+    }
+
+    /// Start a new stream to `destination`, e.g. for the next call. The
+    /// sockets stay as they are, the SSRC, sequence and timestamp come from
+    /// the new config and the statistics start over.
+    ///
+    /// The current stream carries on if `config` isn't supported.
+    pub fn restart(&mut self, destination: IpEndpoint, config: SenderConfig) -> Result<(), Error> {
+        let frames_per_packet = packetization(&config)?;
+        self.destination = destination;
+        self.config = config;
+        self.frames_per_packet = frames_per_packet;
+        self.sequence = (config.ssrc >> 16) as u16;
+        self.timestamp = config.ssrc.rotate_left(8);
+        self.marker = true;
+        self.len = 0;
+        self.frames = 0;
+        self.sum = [0; INPUT_CHANNELS];
+        self.summed = 0;
+        self.phase = 0;
+        self.next_report = None;
+        self.stats = SenderStats::default();
+        Ok(())
+    }
+
+    /// Packetize interleaved stereo frames at the input rate, a packet is
+    /// sent whenever one is complete. The frames should already be
+    /// low-passed below half the format's clock rate.
+    ///
+    /// Each payload frame is the average of the input frames since the last
+    /// one, as many as the input rate runs ahead of the clock rate by then,
+    /// so fractional ratios alternate between two lengths.
+    pub fn send(&mut self, sockets: &mut SocketSet, samples: &[i16]) {
+        let format = self.config.format;
+        for frame in samples.chunks_exact(INPUT_CHANNELS) {
+            self.sum[0] += i32::from(frame[0]);
+            self.sum[1] += i32::from(frame[1]);
+            self.summed += 1;
+            self.phase += format.clock_rate;
+            if self.phase < self.config.input_rate {
+                continue;
+            }
+            self.phase -= self.config.input_rate;
+            let divisor = self.summed as i32;
+            let out = if format.channels == 1 {
+                [((self.sum[0] + self.sum[1]) / (2 * divisor)) as i16, 0]
+            } else {
+                [
+                    (self.sum[0] / divisor) as i16,
+                    (self.sum[1] / divisor) as i16,
+                ]
+            };
+            self.sum = [0; INPUT_CHANNELS];
+            self.summed = 0;
+
+            let channels = usize::from(format.channels);
+            self.len += format.encode(&out[..channels], &mut self.payload[self.len..]);
+            self.frames += 1;
+            if self.frames == self.frames_per_packet {
+                self.send_packet(sockets);
+            }
+        }
+    }
+
+    /// Send a sender report when one is due
+    pub fn poll(&mut self, sockets: &mut SocketSet, now: Instant) {
+        let (handle, cname) = match self.rtcp {
+            Some(rtcp) => rtcp,
+            None => return,
+        };
+        let due = self.next_report.map_or(true, |at| now >= at);
+        if !due || self.stats.packets == 0 {
+            return;
+        }
+        self.next_report = Some(now + Duration::from_millis(RTCP_INTERVAL_MS));
+        let report = SenderReport {
+            ssrc: self.config.ssrc,
+            ntp_timestamp: rtcp::ntp_timestamp(now),
+            // Frames being packetized are what the codec captured last
+            rtp_timestamp: self.timestamp.wrapping_add(self.frames as u32),
+            packets: self.stats.packets,
+            octets: self.stats.octets,
+        };
+        let destination =
+            IpEndpoint::new(self.destination.addr, self.destination.port.wrapping_add(1));
+        let mut socket = sockets.get::<UdpSocket>(handle);
+        if let Ok(buf) = socket.send(rtcp::compound_len(cname), destination) {
+            rtcp::emit_compound(&report, cname, buf);
+            self.stats.reports += 1;
+        }
+    }
+
+    fn send_packet(&mut self, sockets: &mut SocketSet) {
+        let header = Header {
+            marker: self.marker,
+            payload_type: self.config.payload_type,
+            sequence: self.sequence,
+            timestamp: self.timestamp,
+            ssrc: self.config.ssrc,
+        };
+        let mut socket = sockets.get::<UdpSocket>(self.handle);
+        match socket.send(HEADER_LEN + self.len, self.destination) {
+            Ok(buf) => {
+                header.emit(buf);
+                buf[HEADER_LEN..].copy_from_slice(&self.payload[..self.len]);
+                self.sequence = self.sequence.wrapping_add(1);
+                self.marker = false;
+                self.stats.packets += 1;
+                self.stats.octets = self.stats.octets.wrapping_add(self.len as u32);
+            }
+            // The receiver sees the timestamp jump and plays silence
+            Err(_) => self.stats.dropped += 1,
+        }
+        self.timestamp = self.timestamp.wrapping_add(self.frames as u32);
+        self.len = 0;
+        self.frames = 0;
+    }
+}
+
+/// Payload frames per packet
+fn packetization(config: &SenderConfig) -> Result<usize, Error> {
+    let format = config.format;
+    if format.clock_rate == 0 || config.input_rate < format.clock_rate {
+        return Err(Error::InputRate);
+    }
+    let frames_per_packet = (format.clock_rate * config.ptime.ms() / 1000) as usize;
+    let frame_len = usize::from(format.channels) * format.encoding.sample_size();
+    if frame_len == 0 || frames_per_packet * frame_len > MAX_PAYLOAD_LEN {
+        return Err(Error::PayloadTooLarge);
+    }
+    Ok(frames_per_packet)
+}
+
+impl fmt::Debug for Sender {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Sender")
+            .field("handle", &self.handle)
+            .field("destination", &self.destination)
+            .field("config", &self.config)
+            .field("sequence", &self.sequence)
+            .field("timestamp", &self.timestamp)
+            .field("stats", &self.stats)
+            .finish()
+    }
+}
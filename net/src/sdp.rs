@@ -0,0 +1,268 @@
+// SDP, RFC 4566, for a call with one audio stream
+//
+// Writes the offers and answers of RFC 3264 for the payload types the phone
+// supports and reads the other side's: where its first audio stream wants
+// RTP, which payload types it takes, in order of preference, and its ptime.
+// Further streams, IPv6 and everything else are ignored.
+
+use crate::rtp::{Format, Ptime};
+use core::fmt;
+use core::str;
+use smoltcp::wire::{IpAddress, IpEndpoint, Ipv4Address};
+
+/// Payload types kept of an audio stream
+pub const MAX_PAYLOAD_TYPES: usize = 16;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Error {
+    /// Not text or a line isn't `<type>=<value>`
+    Malformed,
+    /// No audio stream or no IPv4 connection address for it
+    NoAudio,
+}
+
+/// The audio stream of the other side's description
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Description {
+    pub address: Ipv4Address,
+    /// RTP port, 0 if the stream is rejected
+    pub port: u16,
+    /// Packet time asked for, if any
+    pub ptime: Option<u32>,
+    payload_types: [u8; MAX_PAYLOAD_TYPES],
+    num_payload_types: usize,
+}
+
+impl Description {
+    pub fn parse(text: &[u8]) -> Result<Self, Error> {
+        let text = str::from_utf8(text).map_err(|_| Error::Malformed)?;
+        let mut session_address = None;
+        let mut media_address = None;
+        let mut session_ptime = None;
+        let mut description: Option<Description> = None;
+        // Past the first audio stream
+        let mut done = false;
+        // In a stream before it, video or the like
+        let mut other_media = false;
+
+        for line in text.lines().filter(|l| !l.is_empty()) {
+            let mut parts = line.splitn(2, '=');
+            let (kind, value) = match (parts.next(), parts.next()) {
+                (Some(kind), Some(value)) if kind.len() == 1 => (kind, value.trim()),
+                _ => return Err(Error::Malformed),
+            };
+            if done {
+                continue;
+            }
+            let in_audio = description.is_some();
+            match kind {
+                "m" if in_audio => done = true,
+                "m" => {
+                    description = parse_media(value);
+                    other_media = description.is_none();
+                }
+                "c" | "a" if other_media => (),
+                "c" => {
+                    let address = parse_connection(value);
+                    if in_audio {
+                        media_address = address;
+                    } else {
+                        session_address = address;
+                    }
+                }
+                "a" => {
+                    if let Some(ms) = attribute(value, "ptime").and_then(|v| v.parse().ok()) {
+                        match description.as_mut() {
+                            Some(d) => d.ptime = Some(ms),
+                            None => session_ptime = Some(ms),
+                        }
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        let mut description = description.ok_or(Error::NoAudio)?;
+        description.address = media_address.or(session_address).ok_or(Error::NoAudio)?;
+        description.ptime = description.ptime.or(session_ptime);
+        Ok(description)
+    }
+
+    pub fn payload_types(&self) -> &[u8] {
+        &self.payload_types[..self.num_payload_types]
+    }
+
+    /// The first payload type of the stream that is in `supported`
+    pub fn negotiate(&self, supported: &[u8]) -> Option<u8> {
+        self.payload_types()
+            .iter()
+            .find(|pt| supported.contains(pt))
+            .cloned()
+    }
+
+    /// Where to send RTP
+    pub fn endpoint(&self) -> IpEndpoint {
+        IpEndpoint::new(IpAddress::Ipv4(self.address), self.port)
+    }
+}
+
+/// Identifies a description, the version goes up with every change
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Origin {
+    pub session_id: u32,
+    pub version: u32,
+}
+
+/// Write a description of an audio stream received at `address` and
+/// `port`, with static `payload_types` in order of preference
+pub fn write<W: fmt::Write>(
+    w: &mut W,
+    origin: Origin,
+    address: Ipv4Address,
+    port: u16,
+    payload_types: &[u8],
+    ptime: Ptime,
+) -> fmt::Result {
+    writeln!(w, "v=0\r")?;
+    writeln!(
+        w,
+        "o=- {} {} IN IP4 {}\r",
+        origin.session_id, origin.version, address
+    )?;
+    writeln!(w, "s=-\r\nc=IN IP4 {}\r\nt=0 0\r", address)?;
+    write!(w, "m=audio {} RTP/AVP", port)?;
+    for pt in payload_types {
+        write!(w, " {}", pt)?;
+    }
+    w.write_str("\r\n")?;
+    for (pt, format) in payload_types
+        .iter()
+        .filter_map(|&pt| Format::from_payload_type(pt).map(|f| (pt, f)))
+    {
+        write!(
+            w,
+            "a=rtpmap:{} {:?}/{}",
+            pt, format.encoding, format.clock_rate
+        )?;
+        if format.channels != 1 {
+            write!(w, "/{}", format.channels)?;
+        }
+        w.write_str("\r\n")?;
+    }
+    writeln!(w, "a=ptime:{}\r\na=sendrecv\r", ptime.ms())
+}
+
+/// `audio <port> RTP/AVP <fmt> ...`, None for other media
+fn parse_media(value: &str) -> Option<Description> {
+    let mut fields = value.split_whitespace();
+    if fields.next() != Some("audio") {
+        return None;
+    }
+    // Ignores the port count of `<port>/<count>`
+    let port = fields.next()?.split('/').next()?.parse().ok()?;
+    if fields.next() != Some("RTP/AVP") {
+        return None;
+    }
+    let mut description = Description {
+        address: Ipv4Address::UNSPECIFIED,
+        port,
+        ptime: None,
+        payload_types: [0; MAX_PAYLOAD_TYPES],
+        num_payload_types: 0,
+    };
+    for pt in fields.filter_map(|f| f.parse().ok()) {
+        if description.num_payload_types == MAX_PAYLOAD_TYPES {
+            break;
+        }
+        description.payload_types[description.num_payload_types] = pt;
+        description.num_payload_types += 1;
+    }
+    Some(description)
+}
+
+/// `IN IP4 <address>`
+fn parse_connection(value: &str) -> Option<Ipv4Address> {
+    let mut fields = value.split_whitespace();
+    match (fields.next(), fields.next(), fields.next()) {
+        // Ignores the TTL of multicast addresses
+        (Some("IN"), Some("IP4"), Some(address)) => address.split('/').next()?.parse().ok(),
+        _ => None,
+    }
+}
+
+/// Value of `<name>:<value>`
+fn attribute<'a>(value: &'a str, name: &str) -> Option<&'a str> {
+    let mut parts = value.splitn(2, ':');
+    if parts.next()? == name {
+        parts.next().map(str::trim)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AUDIO: &[u8] = b"v=0\r
+o=- 1 1 IN IP4 192.168.69.1\r
+s=-\r
+c=IN IP4 192.168.69.1\r
+t=0 0\r
+a=ptime:30\r
+m=audio 4000 RTP/AVP 8 0\r
+";
+
+    #[test]
+    fn session_level() {
+        let d = Description::parse(AUDIO).unwrap();
+        assert_eq!(d.address, Ipv4Address::new(192, 168, 69, 1));
+        assert_eq!(d.port, 4000);
+        assert_eq!(d.ptime, Some(30));
+        assert_eq!(d.payload_types(), &[8, 0]);
+    }
+
+    #[test]
+    fn skips_other_media() {
+        let text = b"v=0\r
+o=- 1 1 IN IP4 192.168.69.1\r
+s=-\r
+c=IN IP4 192.168.69.1\r
+t=0 0\r
+m=video 5000 RTP/AVP 31\r
+c=IN IP4 192.168.69.9\r
+a=ptime:10\r
+m=audio 4000 RTP/AVP 0\r
+m=audio 4002 RTP/AVP 8\r
+c=IN IP4 192.168.69.8\r
+";
+        let d = Description::parse(text).unwrap();
+        assert_eq!(d.address, Ipv4Address::new(192, 168, 69, 1));
+        assert_eq!(d.port, 4000);
+        assert_eq!(d.ptime, None);
+        assert_eq!(d.payload_types(), &[0]);
+    }
+
+    #[test]
+    fn media_level() {
+        let text = b"v=0\r
+c=IN IP4 192.168.69.1\r
+m=video 5000 RTP/AVP 31\r
+m=audio 4000 RTP/AVP 0\r
+c=IN IP4 192.168.69.7\r
+a=ptime:40\r
+";
+        let d = Description::parse(text).unwrap();
+        assert_eq!(d.address, Ipv4Address::new(192, 168, 69, 7));
+        assert_eq!(d.ptime, Some(40));
+    }
+
+    #[test]
+    fn no_audio() {
+        let text = b"v=0\r
+c=IN IP4 192.168.69.1\r
+m=video 5000 RTP/AVP 31\r
+";
+        assert_eq!(Description::parse(text), Err(Error::NoAudio));
+    }
+}
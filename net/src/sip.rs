@@ -0,0 +1,2179 @@
+// SIP user agent, RFC 3261, over UDP
+//
+// Enough for a phone on a small PBX: the UA registers its address of record
+// with the registrar, answering digest challenges, and places or takes one
+// call at a time with INVITE, ACK and BYE, CANCEL for calls not answered
+// yet. SDP offers and answers settle on PCMU or PCMA.
+//
+// Every request goes to the registrar, acting as outbound proxy, responses
+// go back to where their request came from. Route sets aren't kept, so
+// in-dialog requests also go to the registrar, with the peer's contact as
+// Request-URI. INVITEs have to carry an offer, re-INVITEs get the same
+// answer again.
+//
+// The UA doesn't touch the RTP streams. Once a call is established it
+// reports the negotiated `Media` with `Event::MediaStarted`, the application
+// points its `rtp::Sender` and `rtp::Receiver` at it until `Event::CallEnded`.
+
+use crate::md5::{Md5, DIGEST_LEN};
+use crate::rtp::{Format, Ptime, SenderConfig};
+use crate::sdp::{self, Description, Origin};
+use core::fmt::{self, Write};
+use core::{mem, str};
+use smoltcp::socket::{SocketHandle, SocketSet, UdpSocket, UdpSocketBuffer};
+use smoltcp::time::{Duration, Instant};
+use smoltcp::wire::{IpEndpoint, Ipv4Address};
+
+pub const DEFAULT_PORT: u16 = 5060;
+
+/// Largest message sent or received, what fits in an Ethernet frame
+pub const MAX_MESSAGE_LEN: usize = 1500 - 20 - 8;
+
+/// Payload types offered and answered, in order of preference
+pub const PAYLOAD_TYPES: &[u8] = &[0, 8];
+
+const SIP_VERSION: &str = "SIP/2.0";
+/// Marks branches as RFC 3261 ones
+const BRANCH_MAGIC: &str = "z9hG4bK";
+const USER_AGENT: &str = "nucleo-f429zi";
+const ALLOW: &str = "INVITE, ACK, CANCEL, BYE, OPTIONS";
+
+/// Longest Call-ID, tag or URI kept for a call
+const MAX_TEXT_LEN: usize = 128;
+
+/// First retransmission interval, doubled with every retransmission
+const T1_MS: u64 = 500;
+/// Longest interval of non-INVITE requests and responses to INVITEs
+const T2_MS: u64 = 4000;
+/// Transactions give up after 64 T1
+const TIMEOUT_MS: u64 = 64 * T1_MS;
+/// Failed registrations are retried this long after
+const REGISTER_RETRY_MS: u64 = 60_000;
+/// Challenges answered in a row, a second one may say the nonce was stale
+const MAX_AUTH_ATTEMPTS: u32 = 2;
+
+/// Header names and their compact forms
+const COMPACT_NAMES: &[(&str, &str)] = &[
+    ("Call-ID", "i"),
+    ("Contact", "m"),
+    ("Content-Length", "l"),
+    ("Content-Type", "c"),
+    ("From", "f"),
+    ("To", "t"),
+    ("Via", "v"),
+];
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Error {
+    /// Not a SIP message
+    Malformed,
+    /// A message or one of its fields is too long for the buffers
+    Overflow,
+    /// `UserAgent::set_address` wasn't called yet
+    NoAddress,
+    /// There's a call already
+    Busy,
+    /// No call to answer or hang up
+    NoCall,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum StartLine<'a> {
+    Request { method: &'a str, uri: &'a str },
+    Response { status: u16, reason: &'a str },
+}
+
+/// A request or response, borrowing from the packet
+#[derive(Debug, Copy, Clone)]
+pub struct Message<'a> {
+    pub start: StartLine<'a>,
+    headers: &'a str,
+    pub body: &'a [u8],
+}
+
+impl<'a> Message<'a> {
+    /// Lines have to end in CRLF, folded headers aren't supported
+    pub fn parse(packet: &'a [u8]) -> Result<Self, Error> {
+        let end = packet
+            .windows(4)
+            .position(|w| w == b"\r\n\r\n")
+            .ok_or(Error::Malformed)?;
+        let head = str::from_utf8(&packet[..end]).map_err(|_| Error::Malformed)?;
+        let (start, headers) = match head.find("\r\n") {
+            Some(i) => (&head[..i], &head[i + 2..]),
+            None => (head, ""),
+        };
+        let mut message = Message {
+            start: parse_start_line(start)?,
+            headers,
+            body: &packet[end + 4..],
+        };
+        if let Some(len) = message.header("Content-Length") {
+            let len = len.parse().map_err(|_| Error::Malformed)?;
+            message.body = message.body.get(..len).ok_or(Error::Malformed)?;
+        }
+        Ok(message)
+    }
+
+    /// Names and values in order, a header may appear more than once
+    pub fn headers(&self) -> impl Iterator<Item = (&'a str, &'a str)> {
+        self.headers.split("\r\n").filter_map(|line| {
+            let colon = line.find(':')?;
+            Some((line[..colon].trim(), line[colon + 1..].trim()))
+        })
+    }
+
+    /// First value of a header, by its full name
+    pub fn header(&self, name: &str) -> Option<&'a str> {
+        self.headers()
+            .find(|(n, _)| is_header(n, name))
+            .map(|(_, value)| value)
+    }
+
+    /// Sequence number and method of the CSeq header
+    pub fn cseq(&self) -> Option<(u32, &'a str)> {
+        let mut fields = self.header("CSeq")?.split_whitespace();
+        Some((fields.next()?.parse().ok()?, fields.next()?))
+    }
+}
+
+/// A digest challenge of a WWW-Authenticate or Proxy-Authenticate header,
+/// RFC 2617
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Challenge<'a> {
+    pub realm: &'a str,
+    pub nonce: &'a str,
+    pub opaque: Option<&'a str>,
+    /// qop=auth is offered, the response then covers a client nonce
+    pub qop_auth: bool,
+}
+
+impl<'a> Challenge<'a> {
+    /// None for other schemes and algorithms than MD5
+    pub fn parse(value: &'a str) -> Option<Self> {
+        let scheme = value.get(..7)?;
+        if !scheme.eq_ignore_ascii_case("Digest ") {
+            return None;
+        }
+        let mut challenge = Challenge {
+            realm: "",
+            nonce: "",
+            opaque: None,
+            qop_auth: false,
+        };
+        let mut rest = &value[7..];
+        while let Some((name, value, tail)) = next_param(rest) {
+            if name.eq_ignore_ascii_case("realm") {
+                challenge.realm = value;
+            } else if name.eq_ignore_ascii_case("nonce") {
+                challenge.nonce = value;
+            } else if name.eq_ignore_ascii_case("opaque") {
+                challenge.opaque = Some(value);
+            } else if name.eq_ignore_ascii_case("qop") {
+                challenge.qop_auth = value.split(',').any(|q| q.trim() == "auth");
+            } else if name.eq_ignore_ascii_case("algorithm") && !value.eq_ignore_ascii_case("MD5") {
+                return None;
+            }
+            rest = tail;
+        }
+        if challenge.nonce.is_empty() {
+            None
+        } else {
+            Some(challenge)
+        }
+    }
+
+    /// Write the credentials answering the challenge for a request
+    pub fn write_credentials<W: Write>(
+        &self,
+        w: &mut W,
+        user: &str,
+        password: &str,
+        method: &str,
+        uri: &str,
+        cnonce: u32,
+    ) -> fmt::Result {
+        let ha1 = hex(&digest(&[user, ":", self.realm, ":", password]));
+        let ha2 = hex(&digest(&[method, ":", uri]));
+        let (ha1, ha2) = (as_str(&ha1), as_str(&ha2));
+        let mut cnonce_hex = Text::new();
+        write!(cnonce_hex, "{:08x}", cnonce)?;
+        let cnonce = cnonce_hex.as_str();
+        let response = if self.qop_auth {
+            digest(&[ha1, ":", self.nonce, ":00000001:", cnonce, ":auth:", ha2])
+        } else {
+            digest(&[ha1, ":", self.nonce, ":", ha2])
+        };
+        let response = hex(&response);
+        write!(
+            w,
+            "Digest username=\"{}\", realm=\"{}\", nonce=\"{}\", uri=\"{}\", \
+             response=\"{}\", algorithm=MD5",
+            user,
+            self.realm,
+            self.nonce,
+            uri,
+            as_str(&response)
+        )?;
+        if self.qop_auth {
+            write!(w, ", qop=auth, nc=00000001, cnonce=\"{}\"", cnonce)?;
+        }
+        if let Some(opaque) = self.opaque {
+            write!(w, ", opaque=\"{}\"", opaque)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Config {
+    /// User part of the address of record, also the digest username
+    pub user: &'static str,
+    pub password: &'static str,
+    /// Host part of the address of record
+    pub domain: &'static str,
+    /// Registrar and outbound proxy
+    pub proxy: IpEndpoint,
+    /// Registration lifetime asked for, in seconds
+    pub expires: u32,
+    /// Where RTP is received, offered in SDP
+    pub rtp_port: u16,
+    /// Packet time asked for
+    pub ptime: Ptime,
+}
+
+/// The audio stream negotiated for a call
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Media {
+    /// Where to send RTP, RTCP goes to the port above
+    pub remote: IpEndpoint,
+    pub payload_type: u8,
+    pub format: Format,
+    /// The peer's packet time, or the configured one
+    pub ptime: Ptime,
+}
+
+impl Media {
+    /// Sender config for the stream from a capture at `input_rate`, the
+    /// codec's `SAMPLE_RATE`
+    pub fn sender_config(self, input_rate: u32, ssrc: u32) -> SenderConfig {
+        SenderConfig {
+            payload_type: self.payload_type,
+            ptime: self.ptime,
+            ..SenderConfig::new(self.format, input_rate, ssrc)
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum EndReason {
+    /// `UserAgent::hang_up`
+    Local,
+    /// The peer hung up or cancelled
+    Remote,
+    /// The call failed with a status, 408 if nothing answered
+    Failed(u16),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Event {
+    /// The registrar accepted the binding for `expires` seconds, it's
+    /// refreshed halfway through
+    Registered { expires: u32 },
+    /// The registrar refused with a status, 408 if it didn't answer. Retried
+    /// after a minute.
+    RegistrationFailed(u16),
+    /// An INVITE came in, see `UserAgent::peer`. Ringing until it's
+    /// answered or hung up.
+    IncomingCall,
+    /// The callee is being alerted
+    Ringing,
+    /// The call is established, or its media changed. Start sending and
+    /// receiving RTP.
+    MediaStarted(Media),
+    /// Stop sending and receiving
+    CallEnded(EndReason),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Registration {
+    /// No address yet
+    Idle,
+    /// Register with the next poll
+    Pending,
+    Registering,
+    Registered {
+        refresh_at: Instant,
+    },
+    Failed {
+        retry_at: Instant,
+    },
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum CallState {
+    Idle,
+    /// INVITE sent
+    Calling {
+        ringing: bool,
+    },
+    /// Hung up before an answer, the CANCEL goes out once a provisional
+    /// response came back
+    Cancelling {
+        sent: bool,
+    },
+    /// INVITE received, waiting for `answer` or `hang_up`
+    Ringing,
+    /// 200 sent, until the ACK comes
+    Answered,
+    Established,
+    /// BYE sent
+    Ending,
+}
+
+#[derive(Debug)]
+struct Call {
+    state: CallState,
+    incoming: bool,
+    call_id: Text,
+    local_tag: u32,
+    remote_tag: Text,
+    /// The peer's address of record, To of our requests
+    peer: Text,
+    /// The peer's contact, Request-URI of in-dialog requests
+    target: Text,
+    /// CSeq of the INVITE, ours or the peer's
+    invite_cseq: u32,
+    /// Branch of our INVITE, CANCEL and non-2xx ACKs repeat it
+    invite_branch: u32,
+    /// CSeq of our last request
+    local_cseq: u32,
+    /// A provisional response to our INVITE came back
+    provisional: bool,
+    auth_attempts: u32,
+    origin: Origin,
+    media: Option<Media>,
+    /// Headers of the peer's INVITE that its responses repeat
+    invite_headers: Buffer,
+    /// Where responses to the peer's INVITE go
+    invite_source: IpEndpoint,
+}
+
+/// Retransmission timer of a transaction
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Timer {
+    /// Sent once, kept to answer retransmissions
+    Once,
+    /// Doubling until the timeout, INVITEs
+    Unbounded,
+    /// Doubling up to T2
+    Capped,
+}
+
+/// A request or response retransmitted until it's answered
+#[derive(Debug)]
+struct Transaction {
+    message: Buffer,
+    destination: IpEndpoint,
+    timer: Timer,
+    /// Goes out with the next poll
+    queued: bool,
+    next_tx: Option<Instant>,
+    interval_ms: u64,
+    deadline: Option<Instant>,
+}
+
+#[derive(Debug)]
+pub struct UserAgent {
+    handle: SocketHandle,
+    config: Config,
+    port: u16,
+    address: Ipv4Address,
+    rng: u32,
+    /// To tag of responses outside calls and From tag of registrations
+    tag: u32,
+    registration: Registration,
+    register_call_id: Text,
+    register_cseq: u32,
+    register_auth_attempts: u32,
+    register_tx: Transaction,
+    call: Call,
+    call_tx: Transaction,
+    response_tx: Transaction,
+    /// Reported with the next poll
+    event: Option<Event>,
+}
+
+/// Fields of a request the UA sends
+struct Request<'r> {
+    method: &'r str,
+    uri: &'r str,
+    branch: u32,
+    call_id: &'r str,
+    cseq: u32,
+    from_tag: u32,
+    /// URI and tag of To
+    to: &'r str,
+    to_tag: Option<&'r str>,
+}
+
+/// A challenge and the header answering it
+struct Auth<'a> {
+    header: &'static str,
+    challenge: Challenge<'a>,
+}
+
+impl UserAgent {
+    /// Add a UDP socket bound to `port` to `sockets`.
+    ///
+    /// Both buffers need room for a few `MAX_MESSAGE_LEN` messages. `seed`
+    /// makes Call-IDs, tags and branches unique, it should differ between
+    /// phones and boots. Nothing happens until there's an address.
+    pub fn new<'b, 'c>(
+        sockets: &mut SocketSet<'_, 'b, 'c>,
+        rx_buffer: UdpSocketBuffer<'b, 'c>,
+        tx_buffer: UdpSocketBuffer<'b, 'c>,
+        port: u16,
+        config: Config,
+        seed: u32,
+    ) -> Self {
+        let mut socket = UdpSocket::new(rx_buffer, tx_buffer);
+        // Only fails for port 0
+        let _ = socket.bind(port);
+        let mut ua = UserAgent {
+            handle: sockets.add(socket),
+            config,
+            port,
+            address: Ipv4Address::UNSPECIFIED,
+            rng: seed | 1,
+            tag: 0,
+            registration: Registration::Idle,
+            register_call_id: Text::new(),
+            register_cseq: 0,
+            register_auth_attempts: 0,
+            register_tx: Transaction::new(),
+            call: Call::new(),
+            call_tx: Transaction::new(),
+            response_tx: Transaction::new(),
+            event: None,
+        };
+        ua.tag = ua.random();
+        let (a, b) = (ua.random(), ua.random());
+        let _ = write!(ua.register_call_id, "{:08x}{:08x}", a, b);
+        ua
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    pub fn is_registered(&self) -> bool {
+        match self.registration {
+            Registration::Registered { .. } => true,
+            _ => false,
+        }
+    }
+
+    pub fn in_call(&self) -> bool {
+        self.call.state != CallState::Idle
+    }
+
+    /// Address of record of the other side of the call
+    pub fn peer(&self) -> Option<&str> {
+        if self.in_call() {
+            Some(self.call.peer.as_str())
+        } else {
+            None
+        }
+    }
+
+    /// Media of the established call
+    pub fn media(&self) -> Option<Media> {
+        match self.call.state {
+            CallState::Answered | CallState::Established => self.call.media,
+            _ => None,
+        }
+    }
+
+    /// Use the interface's address, e.g. from a DHCP lease, and register it
+    pub fn set_address(&mut self, address: Ipv4Address) {
+        if address == self.address {
+            return;
+        }
+        self.address = address;
+        self.register_tx.stop();
+        self.registration = if address.is_unspecified() {
+            Registration::Idle
+        } else {
+            Registration::Pending
+        };
+    }
+
+    /// Call a user of the domain, or any `sip:` URI
+    pub fn call(&mut self, target: &str) -> Result<(), Error> {
+        if self.in_call() {
+            return Err(Error::Busy);
+        }
+        if self.address.is_unspecified() {
+            return Err(Error::NoAddress);
+        }
+        let (a, b) = (self.random(), self.random());
+        let (address, domain) = (self.address, self.config.domain);
+        let call = &mut self.call;
+        call.incoming = false;
+        call.local_tag = a;
+        call.local_cseq = 0;
+        call.provisional = false;
+        call.auth_attempts = 0;
+        call.origin = Origin {
+            session_id: b,
+            version: 1,
+        };
+        call.media = None;
+        call.remote_tag.clear();
+        call.call_id.clear();
+        call.peer.clear();
+        let written = write!(call.call_id, "{:08x}{:08x}@{}", b, a, address).and_then(|_| {
+            if target.starts_with("sip:") {
+                call.peer.write_str(target)
+            } else {
+                write!(call.peer, "sip:{}@{}", target, domain)
+            }
+        });
+        written.map_err(|_| Error::Overflow)?;
+        self.start_invite(None)?;
+        self.call.state = CallState::Calling { ringing: false };
+        Ok(())
+    }
+
+    /// Answer the incoming call, `Event::MediaStarted` follows
+    pub fn answer(&mut self) -> Result<(), Error> {
+        let media = match (self.call.state, self.call.media) {
+            (CallState::Ringing, Some(media)) => media,
+            _ => return Err(Error::NoCall),
+        };
+        let mut sdp = Buffer::new();
+        let mut message = Buffer::new();
+        self.write_answer(&mut sdp, media)
+            .and_then(|_| self.write_invite_response(&mut message, 200, sdp.as_str()))
+            .map_err(|_| Error::Overflow)?;
+        self.response_tx
+            .start(message, self.call.invite_source, Timer::Capped);
+        self.call.state = CallState::Answered;
+        self.event = Some(Event::MediaStarted(media));
+        Ok(())
+    }
+
+    /// End, cancel or reject the call, `Event::CallEnded` follows
+    pub fn hang_up(&mut self) -> Result<(), Error> {
+        match self.call.state {
+            CallState::Calling { .. } => {
+                let sent = self.call.provisional;
+                if sent {
+                    self.start_cancel()?;
+                }
+                self.call.state = CallState::Cancelling { sent };
+            }
+            CallState::Ringing => {
+                let mut message = Buffer::new();
+                self.write_invite_response(&mut message, 486, "")
+                    .map_err(|_| Error::Overflow)?;
+                self.response_tx
+                    .start(message, self.call.invite_source, Timer::Capped);
+                self.call.state = CallState::Idle;
+            }
+            CallState::Answered | CallState::Established => {
+                self.response_tx.stop();
+                self.start_bye()?;
+                self.call.state = CallState::Ending;
+            }
+            CallState::Idle | CallState::Cancelling { .. } | CallState::Ending => {
+                return Err(Error::NoCall)
+            }
+        }
+        self.event = Some(Event::CallEnded(EndReason::Local));
+        Ok(())
+    }
+
+    /// Process messages, run the timers and (re)transmit. Call it with every
+    /// `EthernetInterface::poll` until it returns None.
+    pub fn poll(&mut self, sockets: &mut SocketSet, now: Instant) -> Option<Event> {
+        let mut socket = sockets.get::<UdpSocket>(self.handle);
+        let mut event = self.event.take().or_else(|| self.check_timers(now));
+        let mut packet = [0; MAX_MESSAGE_LEN];
+        while event.is_none() {
+            match socket.recv_slice(&mut packet) {
+                Ok((len, source)) => event = self.receive(&mut socket, &packet[..len], source, now),
+                Err(_) => break,
+            }
+        }
+        self.register_tx.transmit(&mut socket, now);
+        self.call_tx.transmit(&mut socket, now);
+        self.response_tx.transmit(&mut socket, now);
+        event
+    }
+
+    fn check_timers(&mut self, now: Instant) -> Option<Event> {
+        let register = match self.registration {
+            Registration::Pending => true,
+            Registration::Registered { refresh_at } => now >= refresh_at,
+            Registration::Failed { retry_at } => now >= retry_at,
+            Registration::Idle | Registration::Registering => false,
+        };
+        if register {
+            self.register_auth_attempts = 0;
+            if self.start_register(None).is_err() {
+                return Some(self.registration_failed(500, now));
+            }
+        }
+        if self.register_tx.timed_out(now) {
+            return Some(self.registration_failed(408, now));
+        }
+        if self.call_tx.timed_out(now) {
+            // Nothing to report for CANCEL and BYE
+            if let CallState::Calling { .. } = mem::replace(&mut self.call.state, CallState::Idle) {
+                return Some(Event::CallEnded(EndReason::Failed(408)));
+            }
+        }
+        if self.response_tx.timed_out(now) && self.call.state == CallState::Answered {
+            // The ACK never came
+            self.call.state = CallState::Ending;
+            if self.start_bye().is_err() {
+                self.call.state = CallState::Idle;
+            }
+            return Some(Event::CallEnded(EndReason::Failed(408)));
+        }
+        None
+    }
+
+    fn receive(
+        &mut self,
+        socket: &mut UdpSocket,
+        packet: &[u8],
+        source: IpEndpoint,
+        now: Instant,
+    ) -> Option<Event> {
+        let message = Message::parse(packet).ok()?;
+        let call_id = message.header("Call-ID")?;
+        let (cseq, method) = message.cseq()?;
+        match message.start {
+            StartLine::Request { .. } => self.process_request(socket, &message, source),
+            StartLine::Response { status, .. } if method == "REGISTER" => {
+                let current = self.registration == Registration::Registering
+                    && call_id == self.register_call_id.as_str()
+                    && cseq == self.register_cseq;
+                if current {
+                    self.register_response(&message, status, now)
+                } else {
+                    None
+                }
+            }
+            StartLine::Response { .. } if call_id != self.call.call_id.as_str() => None,
+            StartLine::Response { status, .. } => match method {
+                "INVITE" if !self.call.incoming && cseq == self.call.invite_cseq => {
+                    self.invite_response(socket, &message, status)
+                }
+                "CANCEL" | "BYE" if status >= 200 => {
+                    self.call_tx.stop();
+                    if self.call.state == CallState::Ending {
+                        self.call.state = CallState::Idle;
+                    }
+                    None
+                }
+                _ => None,
+            },
+        }
+    }
+
+    fn process_request(
+        &mut self,
+        socket: &mut UdpSocket,
+        request: &Message,
+        source: IpEndpoint,
+    ) -> Option<Event> {
+        let method = match request.start {
+            StartLine::Request { method, .. } => method,
+            StartLine::Response { .. } => return None,
+        };
+        let (cseq, _) = request.cseq()?;
+        let call = &self.call;
+        let same_call = request.header("Call-ID") == Some(call.call_id.as_str());
+        let same_invite = same_call && call.incoming && cseq == call.invite_cseq;
+        let in_dialog = same_call
+            && request.header("From").and_then(tag) == Some(call.remote_tag.as_str())
+            && request.header("To").and_then(tag).and_then(parse_tag) == Some(call.local_tag);
+
+        match method {
+            // A retransmission
+            "INVITE" if same_invite => {
+                self.response_tx.resend(socket);
+                None
+            }
+            "INVITE" if in_dialog && call.state == CallState::Established => {
+                self.reinvite(socket, request, source)
+            }
+            "INVITE" if self.in_call() => {
+                self.respond(socket, request, source, 486, "");
+                None
+            }
+            "INVITE" => self.incoming_call(socket, request, source),
+            "ACK" => {
+                if same_invite {
+                    self.response_tx.stop();
+                    if call.state == CallState::Answered {
+                        self.call.state = CallState::Established;
+                    }
+                }
+                None
+            }
+            "CANCEL" if same_invite && call.state == CallState::Ringing => {
+                self.respond(socket, request, source, 200, "");
+                let mut message = Buffer::new();
+                if self.write_invite_response(&mut message, 487, "").is_ok() {
+                    self.response_tx
+                        .start(message, self.call.invite_source, Timer::Capped);
+                }
+                self.call.state = CallState::Idle;
+                Some(Event::CallEnded(EndReason::Remote))
+            }
+            // Too late, the call was answered
+            "CANCEL" if same_invite => {
+                self.respond(socket, request, source, 200, "");
+                None
+            }
+            "BYE" if in_dialog && self.in_call() => {
+                self.respond(socket, request, source, 200, "");
+                self.response_tx.stop();
+                self.call_tx.stop();
+                match mem::replace(&mut self.call.state, CallState::Idle) {
+                    // Hung up here already
+                    CallState::Ending => None,
+                    _ => Some(Event::CallEnded(EndReason::Remote)),
+                }
+            }
+            "CANCEL" | "BYE" => {
+                self.respond(socket, request, source, 481, "");
+                None
+            }
+            "OPTIONS" => {
+                self.respond(socket, request, source, 200, "");
+                None
+            }
+            _ => {
+                self.respond(socket, request, source, 405, "");
+                None
+            }
+        }
+    }
+
+    fn incoming_call(
+        &mut self,
+        socket: &mut UdpSocket,
+        invite: &Message,
+        source: IpEndpoint,
+    ) -> Option<Event> {
+        let media = Description::parse(invite.body)
+            .ok()
+            .and_then(|offer| self.negotiate(&offer));
+        let media = match media {
+            Some(media) => media,
+            None => {
+                self.respond(socket, invite, source, 488, "");
+                return None;
+            }
+        };
+        let (a, b) = (self.random(), self.random());
+        let call = &mut self.call;
+        call.incoming = true;
+        call.local_tag = a;
+        call.local_cseq = 0;
+        call.origin = Origin {
+            session_id: b,
+            version: 1,
+        };
+        call.media = Some(media);
+        call.invite_source = source;
+        call.invite_cseq = invite.cseq().map_or(0, |(cseq, _)| cseq);
+        let from = invite.header("From").unwrap_or("");
+        let contact = invite.header("Contact").unwrap_or(from);
+        let stored = call
+            .call_id
+            .set(invite.header("Call-ID").unwrap_or(""))
+            .and_then(|_| call.remote_tag.set(tag(from).unwrap_or("")))
+            .and_then(|_| call.peer.set(uri(from)))
+            .and_then(|_| call.target.set(uri(contact)))
+            .and_then(|_| {
+                call.invite_headers.clear();
+                write_response_headers(&mut call.invite_headers, invite, a)
+                    .map_err(|_| Error::Overflow)
+            });
+        let mut message = Buffer::new();
+        let ringing = stored.is_ok() && self.write_invite_response(&mut message, 180, "").is_ok();
+        if !ringing {
+            self.respond(socket, invite, source, 500, "");
+            return None;
+        }
+        self.response_tx.start(message, source, Timer::Once);
+        self.call.state = CallState::Ringing;
+        Some(Event::IncomingCall)
+    }
+
+    /// Answer an offer within the call, the media may have moved
+    fn reinvite(
+        &mut self,
+        socket: &mut UdpSocket,
+        invite: &Message,
+        source: IpEndpoint,
+    ) -> Option<Event> {
+        let media = Description::parse(invite.body)
+            .ok()
+            .and_then(|offer| self.negotiate(&offer));
+        let media = match media {
+            Some(media) => media,
+            None => {
+                self.respond(socket, invite, source, 488, "");
+                return None;
+            }
+        };
+        let mut sdp = Buffer::new();
+        if self.write_answer(&mut sdp, media).is_err() {
+            return None;
+        }
+        self.respond(socket, invite, source, 200, sdp.as_str());
+        if self.call.media == Some(media) {
+            None
+        } else {
+            self.call.media = Some(media);
+            Some(Event::MediaStarted(media))
+        }
+    }
+
+    fn register_response(
+        &mut self,
+        response: &Message,
+        status: u16,
+        now: Instant,
+    ) -> Option<Event> {
+        match status {
+            100..=199 => None,
+            200..=299 => {
+                self.register_tx.stop();
+                let expires = response
+                    .header("Contact")
+                    .and_then(|contact| param(contact, "expires"))
+                    .or_else(|| response.header("Expires"))
+                    .and_then(|expires| expires.parse().ok())
+                    .unwrap_or(self.config.expires);
+                let refresh = Duration::from_secs(u64::from(expires / 2).max(1));
+                self.registration = Registration::Registered {
+                    refresh_at: now + refresh,
+                };
+                Some(Event::Registered { expires })
+            }
+            401 | 407 if self.register_auth_attempts < MAX_AUTH_ATTEMPTS => {
+                self.register_tx.stop();
+                self.register_auth_attempts += 1;
+                match auth(response, status) {
+                    Some(auth) if self.start_register(Some(&auth)).is_ok() => None,
+                    _ => Some(self.registration_failed(status, now)),
+                }
+            }
+            _ => {
+                self.register_tx.stop();
+                Some(self.registration_failed(status, now))
+            }
+        }
+    }
+
+    fn invite_response(
+        &mut self,
+        socket: &mut UdpSocket,
+        response: &Message,
+        status: u16,
+    ) -> Option<Event> {
+        let to_tag = response.header("To").and_then(tag).unwrap_or("");
+        match (self.call.state, status) {
+            (CallState::Calling { ringing }, 100..=199) => {
+                self.call_tx.stop();
+                self.call.provisional = true;
+                if !ringing && status > 100 {
+                    self.call.state = CallState::Calling { ringing: true };
+                    return Some(Event::Ringing);
+                }
+                None
+            }
+            (CallState::Cancelling { sent: false }, 100..=199) => {
+                self.call_tx.stop();
+                self.call.provisional = true;
+                if self.start_cancel().is_ok() {
+                    self.call.state = CallState::Cancelling { sent: true };
+                }
+                None
+            }
+            (CallState::Calling { .. }, 200..=299) | (CallState::Cancelling { .. }, 200..=299) => {
+                self.call_tx.stop();
+                let contact = response.header("Contact").map(uri);
+                let stored = self
+                    .call
+                    .remote_tag
+                    .set(to_tag)
+                    .and_then(|_| match contact {
+                        Some(contact) => self.call.target.set(contact),
+                        None => Ok(()),
+                    });
+                let media = Description::parse(response.body)
+                    .ok()
+                    .and_then(|answer| self.negotiate(&answer));
+                self.send_ack(socket, to_tag, true);
+                let cancelled = match self.call.state {
+                    CallState::Cancelling { .. } => true,
+                    _ => false,
+                };
+                match media {
+                    Some(media) if stored.is_ok() && !cancelled => {
+                        self.call.media = Some(media);
+                        self.call.state = CallState::Established;
+                        Some(Event::MediaStarted(media))
+                    }
+                    _ => {
+                        self.call.state = CallState::Ending;
+                        if self.start_bye().is_err() {
+                            self.call.state = CallState::Idle;
+                        }
+                        if cancelled {
+                            None
+                        } else {
+                            Some(Event::CallEnded(EndReason::Failed(488)))
+                        }
+                    }
+                }
+            }
+            // The ACK got lost
+            (CallState::Established, 200..=299) | (CallState::Ending, 200..=299) => {
+                self.send_ack(socket, to_tag, true);
+                None
+            }
+            (CallState::Calling { .. }, 401) | (CallState::Calling { .. }, 407)
+                if self.call.auth_attempts < MAX_AUTH_ATTEMPTS =>
+            {
+                self.call_tx.stop();
+                self.send_ack(socket, to_tag, false);
+                self.call.auth_attempts += 1;
+                self.call.provisional = false;
+                match auth(response, status) {
+                    Some(auth) if self.start_invite(Some(&auth)).is_ok() => {
+                        self.call.state = CallState::Calling { ringing: false };
+                        None
+                    }
+                    _ => {
+                        self.call.state = CallState::Idle;
+                        Some(Event::CallEnded(EndReason::Failed(status)))
+                    }
+                }
+            }
+            (CallState::Calling { .. }, 300..=699) | (CallState::Cancelling { .. }, 300..=699) => {
+                self.call_tx.stop();
+                self.send_ack(socket, to_tag, false);
+                match mem::replace(&mut self.call.state, CallState::Idle) {
+                    CallState::Calling { .. } => Some(Event::CallEnded(EndReason::Failed(status))),
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+
+    fn registration_failed(&mut self, status: u16, now: Instant) -> Event {
+        self.register_tx.stop();
+        self.registration = Registration::Failed {
+            retry_at: now + Duration::from_millis(REGISTER_RETRY_MS),
+        };
+        Event::RegistrationFailed(status)
+    }
+
+    fn start_register(&mut self, auth: Option<&Auth>) -> Result<(), Error> {
+        let (branch, cnonce) = (self.random(), self.random());
+        self.register_cseq += 1;
+        let mut aor = Text::new();
+        let mut uri = Text::new();
+        write!(aor, "sip:{}@{}", self.config.user, self.config.domain)
+            .and_then(|_| write!(uri, "sip:{}", self.config.domain))
+            .map_err(|_| Error::Overflow)?;
+        let request = Request {
+            method: "REGISTER",
+            uri: uri.as_str(),
+            branch,
+            call_id: self.register_call_id.as_str(),
+            cseq: self.register_cseq,
+            from_tag: self.tag,
+            to: aor.as_str(),
+            to_tag: None,
+        };
+        let mut message = Buffer::new();
+        self.write_request(&mut message, &request)
+            .and_then(|_| self.write_contact(&mut message))
+            .and_then(|_| writeln!(message, "Expires: {}\r", self.config.expires))
+            .and_then(|_| self.write_auth(&mut message, auth, &request, cnonce))
+            .and_then(|_| finish(&mut message, ""))
+            .map_err(|_| Error::Overflow)?;
+        self.register_tx
+            .start(message, self.config.proxy, Timer::Capped);
+        self.registration = Registration::Registering;
+        Ok(())
+    }
+
+    fn start_invite(&mut self, auth: Option<&Auth>) -> Result<(), Error> {
+        let (branch, cnonce) = (self.random(), self.random());
+        self.call.local_cseq += 1;
+        self.call.invite_cseq = self.call.local_cseq;
+        self.call.invite_branch = branch;
+        let call = &self.call;
+        let mut sdp = Buffer::new();
+        let mut message = Buffer::new();
+        let request = Request {
+            method: "INVITE",
+            uri: call.peer.as_str(),
+            branch,
+            call_id: call.call_id.as_str(),
+            cseq: call.invite_cseq,
+            from_tag: call.local_tag,
+            to: call.peer.as_str(),
+            to_tag: None,
+        };
+        sdp::write(
+            &mut sdp,
+            call.origin,
+            self.address,
+            self.config.rtp_port,
+            PAYLOAD_TYPES,
+            self.config.ptime,
+        )
+        .and_then(|_| self.write_request(&mut message, &request))
+        .and_then(|_| self.write_contact(&mut message))
+        .and_then(|_| self.write_auth(&mut message, auth, &request, cnonce))
+        .and_then(|_| finish(&mut message, sdp.as_str()))
+        .map_err(|_| Error::Overflow)?;
+        self.call_tx
+            .start(message, self.config.proxy, Timer::Unbounded);
+        Ok(())
+    }
+
+    fn start_cancel(&mut self) -> Result<(), Error> {
+        let call = &self.call;
+        let request = Request {
+            method: "CANCEL",
+            uri: call.peer.as_str(),
+            branch: call.invite_branch,
+            call_id: call.call_id.as_str(),
+            cseq: call.invite_cseq,
+            from_tag: call.local_tag,
+            to: call.peer.as_str(),
+            to_tag: None,
+        };
+        let mut message = Buffer::new();
+        self.write_request(&mut message, &request)
+            .and_then(|_| finish(&mut message, ""))
+            .map_err(|_| Error::Overflow)?;
+        self.call_tx
+            .start(message, self.config.proxy, Timer::Capped);
+        Ok(())
+    }
+
+    fn start_bye(&mut self) -> Result<(), Error> {
+        let branch = self.random();
+        self.call.local_cseq += 1;
+        let call = &self.call;
+        let request = Request {
+            method: "BYE",
+            uri: call.target.as_str(),
+            branch,
+            call_id: call.call_id.as_str(),
+            cseq: call.local_cseq,
+            from_tag: call.local_tag,
+            to: call.peer.as_str(),
+            to_tag: Some(call.remote_tag.as_str()),
+        };
+        let mut message = Buffer::new();
+        self.write_request(&mut message, &request)
+            .and_then(|_| finish(&mut message, ""))
+            .map_err(|_| Error::Overflow)?;
+        self.call_tx
+            .start(message, self.config.proxy, Timer::Capped);
+        Ok(())
+    }
+
+    /// ACK a final response to our INVITE. ACKs of a 2xx go to the peer's
+    /// contact as a transaction of their own, the others are part of the
+    /// INVITE transaction.
+    fn send_ack(&mut self, socket: &mut UdpSocket, to_tag: &str, success: bool) {
+        let branch = if success {
+            self.random()
+        } else {
+            self.call.invite_branch
+        };
+        let call = &self.call;
+        let request = Request {
+            method: "ACK",
+            uri: if success {
+                call.target.as_str()
+            } else {
+                call.peer.as_str()
+            },
+            branch,
+            call_id: call.call_id.as_str(),
+            cseq: call.invite_cseq,
+            from_tag: call.local_tag,
+            to: call.peer.as_str(),
+            to_tag: Some(to_tag),
+        };
+        let mut message = Buffer::new();
+        let written = self
+            .write_request(&mut message, &request)
+            .and_then(|_| finish(&mut message, ""));
+        if written.is_ok() {
+            // A lost ACK is sent again for the retransmitted response
+            let _ = socket.send_slice(message.as_bytes(), self.config.proxy);
+        }
+    }
+
+    /// Send a response to `request` right away
+    fn respond(
+        &self,
+        socket: &mut UdpSocket,
+        request: &Message,
+        source: IpEndpoint,
+        status: u16,
+        sdp: &str,
+    ) {
+        let same_call = request.header("Call-ID") == Some(self.call.call_id.as_str());
+        let tag = if same_call {
+            self.call.local_tag
+        } else {
+            self.tag
+        };
+        let mut message = Buffer::new();
+        let written = writeln!(message, "{} {} {}\r", SIP_VERSION, status, reason(status))
+            .and_then(|_| write_response_headers(&mut message, request, tag))
+            .and_then(|_| self.write_response_tail(&mut message, status, sdp));
+        if written.is_ok() {
+            // The peer retransmits the request if it's lost
+            let _ = socket.send_slice(message.as_bytes(), source);
+        }
+    }
+
+    fn write_invite_response(&self, w: &mut Buffer, status: u16, sdp: &str) -> fmt::Result {
+        writeln!(w, "{} {} {}\r", SIP_VERSION, status, reason(status))?;
+        w.write_str(self.call.invite_headers.as_str())?;
+        self.write_response_tail(w, status, sdp)
+    }
+
+    fn write_response_tail(&self, w: &mut Buffer, status: u16, sdp: &str) -> fmt::Result {
+        if status > 100 && status < 300 {
+            self.write_contact(w)?;
+        }
+        writeln!(w, "Allow: {}\r\nUser-Agent: {}\r", ALLOW, USER_AGENT)?;
+        finish(w, sdp)
+    }
+
+    /// Start line and the headers every request has
+    fn write_request(&self, w: &mut Buffer, request: &Request) -> fmt::Result {
+        writeln!(w, "{} {} {}\r", request.method, request.uri, SIP_VERSION)?;
+        writeln!(
+            w,
+            "Via: {}/UDP {}:{};branch={}{:08x};rport\r",
+            SIP_VERSION, self.address, self.port, BRANCH_MAGIC, request.branch
+        )?;
+        writeln!(w, "Max-Forwards: 70\r")?;
+        writeln!(
+            w,
+            "From: <sip:{}@{}>;tag={:08x}\r",
+            self.config.user, self.config.domain, request.from_tag
+        )?;
+        write!(w, "To: <{}>", request.to)?;
+        if let Some(tag) = request.to_tag {
+            write!(w, ";tag={}", tag)?;
+        }
+        writeln!(
+            w,
+            "\r\nCall-ID: {}\r\nCSeq: {} {}\r\nUser-Agent: {}\r",
+            request.call_id, request.cseq, request.method, USER_AGENT
+        )
+    }
+
+    fn write_contact(&self, w: &mut Buffer) -> fmt::Result {
+        writeln!(
+            w,
+            "Contact: <sip:{}@{}:{}>\r",
+            self.config.user, self.address, self.port
+        )
+    }
+
+    fn write_auth(
+        &self,
+        w: &mut Buffer,
+        auth: Option<&Auth>,
+        request: &Request,
+        cnonce: u32,
+    ) -> fmt::Result {
+        let auth = match auth {
+            Some(auth) => auth,
+            None => return Ok(()),
+        };
+        write!(w, "{}: ", auth.header)?;
+        auth.challenge.write_credentials(
+            w,
+            self.config.user,
+            self.config.password,
+            request.method,
+            request.uri,
+            cnonce,
+        )?;
+        w.write_str("\r\n")
+    }
+
+    fn write_answer(&self, sdp: &mut Buffer, media: Media) -> fmt::Result {
+        sdp::write(
+            sdp,
+            self.call.origin,
+            self.address,
+            self.config.rtp_port,
+            &[media.payload_type],
+            media.ptime,
+        )
+    }
+
+    /// The media of an offer or answer, None if nothing fits
+    fn negotiate(&self, description: &Description) -> Option<Media> {
+        if description.port == 0 {
+            return None;
+        }
+        let payload_type = description.negotiate(PAYLOAD_TYPES)?;
+        Some(Media {
+            remote: description.endpoint(),
+            payload_type,
+            format: Format::from_payload_type(payload_type)?,
+            ptime: description
+                .ptime
+                .and_then(Ptime::from_ms)
+                .unwrap_or(self.config.ptime),
+        })
+    }
+
+    /// xorshift32
+    fn random(&mut self) -> u32 {
+        let mut x = self.rng;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.rng = x;
+        x
+    }
+}
+
+impl Call {
+    fn new() -> Self {
+        Call {
+            state: CallState::Idle,
+            incoming: false,
+            call_id: Text::new(),
+            local_tag: 0,
+            remote_tag: Text::new(),
+            peer: Text::new(),
+            target: Text::new(),
+            invite_cseq: 0,
+            invite_branch: 0,
+            local_cseq: 0,
+            provisional: false,
+            auth_attempts: 0,
+            origin: Origin {
+                session_id: 0,
+                version: 0,
+            },
+            media: None,
+            invite_headers: Buffer::new(),
+            invite_source: IpEndpoint::default(),
+        }
+    }
+}
+
+impl Transaction {
+    fn new() -> Self {
+        Transaction {
+            message: Buffer::new(),
+            destination: IpEndpoint::default(),
+            timer: Timer::Once,
+            queued: false,
+            next_tx: None,
+            interval_ms: T1_MS,
+            deadline: None,
+        }
+    }
+
+    /// Send `message` with the next poll, replacing the previous one
+    fn start(&mut self, message: Buffer, destination: IpEndpoint, timer: Timer) {
+        self.message = message;
+        self.destination = destination;
+        self.timer = timer;
+        self.queued = true;
+        self.next_tx = None;
+        self.deadline = None;
+    }
+
+    /// Stop retransmitting, the message is kept for `resend`
+    fn stop(&mut self) {
+        self.queued = false;
+        self.next_tx = None;
+        self.deadline = None;
+    }
+
+    fn resend(&self, socket: &mut UdpSocket) {
+        if !self.message.as_bytes().is_empty() {
+            let _ = socket.send_slice(self.message.as_bytes(), self.destination);
+        }
+    }
+
+    /// Send the message if it's queued or a retransmission is due
+    fn transmit(&mut self, socket: &mut UdpSocket, now: Instant) {
+        let due = self.next_tx.map_or(false, |at| now >= at);
+        if !self.queued && !due {
+            return;
+        }
+        // A full buffer counts as a lost message
+        let _ = socket.send_slice(self.message.as_bytes(), self.destination);
+        if self.queued {
+            self.queued = false;
+            self.interval_ms = T1_MS;
+            if self.timer != Timer::Once {
+                self.deadline = Some(now + Duration::from_millis(TIMEOUT_MS));
+            }
+        } else {
+            self.interval_ms *= 2;
+            if self.timer == Timer::Capped {
+                self.interval_ms = self.interval_ms.min(T2_MS);
+            }
+        }
+        self.next_tx = match self.timer {
+            Timer::Once => None,
+            _ => Some(now + Duration::from_millis(self.interval_ms)),
+        };
+    }
+
+    /// Stops the transaction once it times out
+    fn timed_out(&mut self, now: Instant) -> bool {
+        match self.deadline {
+            Some(deadline) if now >= deadline => {
+                self.stop();
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// A message being written
+struct Buffer {
+    data: [u8; MAX_MESSAGE_LEN],
+    len: usize,
+}
+
+impl Buffer {
+    fn new() -> Self {
+        Buffer {
+            data: [0; MAX_MESSAGE_LEN],
+            len: 0,
+        }
+    }
+
+    fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        &self.data[..self.len]
+    }
+
+    fn as_str(&self) -> &str {
+        // Only ever written with `write_str`
+        str::from_utf8(self.as_bytes()).unwrap_or("")
+    }
+}
+
+impl Write for Buffer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        append(&mut self.data, &mut self.len, s)
+    }
+}
+
+impl fmt::Debug for Buffer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Buffer").field("len", &self.len).finish()
+    }
+}
+
+/// A Call-ID, tag or URI
+struct Text {
+    data: [u8; MAX_TEXT_LEN],
+    len: usize,
+}
+
+impl Text {
+    fn new() -> Self {
+        Text {
+            data: [0; MAX_TEXT_LEN],
+            len: 0,
+        }
+    }
+
+    fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    fn set(&mut self, s: &str) -> Result<(), Error> {
+        self.len = 0;
+        self.write_str(s).map_err(|_| Error::Overflow)
+    }
+
+    fn as_str(&self) -> &str {
+        str::from_utf8(&self.data[..self.len]).unwrap_or("")
+    }
+}
+
+impl Write for Text {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        append(&mut self.data, &mut self.len, s)
+    }
+}
+
+impl fmt::Debug for Text {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+/// Append `s` to the first `len` bytes of `data`, nothing if it doesn't fit
+fn append(data: &mut [u8], len: &mut usize, s: &str) -> fmt::Result {
+    let end = *len + s.len();
+    if end > data.len() {
+        return Err(fmt::Error);
+    }
+    data[*len..end].copy_from_slice(s.as_bytes());
+    *len = end;
+    Ok(())
+}
+
+fn parse_start_line(line: &str) -> Result<StartLine, Error> {
+    let mut fields = line.splitn(3, ' ');
+    let (first, second, third) = match (fields.next(), fields.next(), fields.next()) {
+        (Some(first), Some(second), Some(third)) => (first, second, third),
+        _ => return Err(Error::Malformed),
+    };
+    if first == SIP_VERSION {
+        match second.parse() {
+            Ok(status) if status >= 100 && status < 700 => Ok(StartLine::Response {
+                status,
+                reason: third,
+            }),
+            _ => Err(Error::Malformed),
+        }
+    } else if third == SIP_VERSION {
+        Ok(StartLine::Request {
+            method: first,
+            uri: second,
+        })
+    } else {
+        Err(Error::Malformed)
+    }
+}
+
+/// Compares a header name with a full name, or its compact form
+fn is_header(found: &str, name: &str) -> bool {
+    found.eq_ignore_ascii_case(name)
+        || COMPACT_NAMES
+            .iter()
+            .any(|(full, compact)| *full == name && found.eq_ignore_ascii_case(compact))
+}
+
+/// The URI of a From, To or Contact value
+fn uri(value: &str) -> &str {
+    match (value.find('<'), value.find('>')) {
+        (Some(start), Some(end)) if start < end => &value[start + 1..end],
+        _ => value.split(';').next().unwrap_or("").trim(),
+    }
+}
+
+/// A header parameter of a From, To or Contact value
+fn param<'a>(value: &'a str, name: &str) -> Option<&'a str> {
+    let params = match value.find('>') {
+        Some(end) => &value[end + 1..],
+        None => value,
+    };
+    params.split(';').skip(1).find_map(|p| {
+        let mut parts = p.splitn(2, '=');
+        if parts.next()?.trim().eq_ignore_ascii_case(name) {
+            parts.next().map(str::trim)
+        } else {
+            None
+        }
+    })
+}
+
+fn tag(value: &str) -> Option<&str> {
+    param(value, "tag")
+}
+
+/// Our tags are 32 bits in hex
+fn parse_tag(tag: &str) -> Option<u32> {
+    u32::from_str_radix(tag, 16).ok()
+}
+
+/// Split `name=value` or `name="value"` off a comma separated list
+fn next_param(s: &str) -> Option<(&str, &str, &str)> {
+    let s = s.trim_start_matches(|c: char| c == ',' || c.is_whitespace());
+    let eq = s.find('=')?;
+    let name = s[..eq].trim();
+    let rest = s[eq + 1..].trim_start();
+    if rest.starts_with('"') {
+        let end = rest[1..].find('"')? + 1;
+        Some((name, &rest[1..end], &rest[end + 1..]))
+    } else {
+        let end = rest.find(',').unwrap_or_else(|| rest.len());
+        Some((name, rest[..end].trim(), &rest[end..]))
+    }
+}
+
+/// The first digest challenge of a 401 or 407 response
+fn auth<'a>(response: &Message<'a>, status: u16) -> Option<Auth<'a>> {
+    let (challenge, header) = match status {
+        401 => ("WWW-Authenticate", "Authorization"),
+        407 => ("Proxy-Authenticate", "Proxy-Authorization"),
+        _ => return None,
+    };
+    let challenge = response
+        .headers()
+        .filter(|(name, _)| name.eq_ignore_ascii_case(challenge))
+        .find_map(|(_, value)| Challenge::parse(value))?;
+    Some(Auth { header, challenge })
+}
+
+/// The headers a response repeats from its request, `to_tag` is added to
+/// a To without one
+fn write_response_headers<W: Write>(w: &mut W, request: &Message, to_tag: u32) -> fmt::Result {
+    for (name, value) in request.headers() {
+        if is_header(name, "To") {
+            write!(w, "To: {}", value)?;
+            if tag(value).is_none() {
+                write!(w, ";tag={:08x}", to_tag)?;
+            }
+            w.write_str("\r\n")?;
+        } else if ["Via", "From", "Call-ID", "CSeq"]
+            .iter()
+            .any(|header| is_header(name, header))
+        {
+            writeln!(w, "{}: {}\r", name, value)?;
+        }
+    }
+    Ok(())
+}
+
+/// Content headers, the empty line and the SDP body, if any
+fn finish(w: &mut Buffer, sdp: &str) -> fmt::Result {
+    if !sdp.is_empty() {
+        writeln!(w, "Content-Type: application/sdp\r")?;
+    }
+    write!(w, "Content-Length: {}\r\n\r\n{}", sdp.len(), sdp)
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        180 => "Ringing",
+        200 => "OK",
+        405 => "Method Not Allowed",
+        481 => "Call/Transaction Does Not Exist",
+        486 => "Busy Here",
+        487 => "Request Terminated",
+        488 => "Not Acceptable Here",
+        _ => "Server Internal Error",
+    }
+}
+
+fn digest(parts: &[&str]) -> [u8; DIGEST_LEN] {
+    let mut md5 = Md5::new();
+    for part in parts {
+        md5.update(part.as_bytes());
+    }
+    md5.finish()
+}
+
+/// Lower case hex of a digest
+fn hex(digest: &[u8; DIGEST_LEN]) -> [u8; 2 * DIGEST_LEN] {
+    const DIGITS: &[u8] = b"0123456789abcdef";
+    let mut out = [0; 2 * DIGEST_LEN];
+    for (pair, b) in out.chunks_exact_mut(2).zip(digest) {
+        pair[0] = DIGITS[usize::from(b >> 4)];
+        pair[1] = DIGITS[usize::from(b & 0xF)];
+    }
+    out
+}
+
+fn as_str(hex: &[u8]) -> &str {
+    str::from_utf8(hex).unwrap_or("")
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use smoltcp::iface::{EthernetInterface, EthernetInterfaceBuilder, NeighborCache};
+    use smoltcp::phy::{self, ChecksumCapabilities, Device, DeviceCapabilities, RxToken};
+    use smoltcp::socket::UdpPacketMetadata;
+    use smoltcp::wire::{
+        ArpOperation, ArpPacket, ArpRepr, EthernetAddress, EthernetFrame, EthernetProtocol,
+        EthernetRepr, IpAddress, IpCidr, IpProtocol, Ipv4Packet, Ipv4Repr, UdpPacket, UdpRepr,
+    };
+    use std::cell::RefCell;
+    use std::collections::{BTreeMap, VecDeque};
+    use std::format;
+    use std::rc::Rc;
+    use std::string::String;
+    use std::vec;
+    use std::vec::Vec;
+
+    const MAC: EthernetAddress = EthernetAddress([0x02, 0x00, 0x00, 0x00, 0x00, 0x01]);
+    const PROXY_MAC: EthernetAddress = EthernetAddress([0x02, 0x00, 0x00, 0x00, 0x00, 0x02]);
+    const ADDRESS: Ipv4Address = Ipv4Address([192, 168, 1, 50]);
+    const PROXY: Ipv4Address = Ipv4Address([192, 168, 1, 1]);
+
+    const CALL_ID: &str = "a84b4c76e66710@192.168.1.20";
+    const INVITE_CSEQ: u32 = 314_159;
+    const OFFER: &str = "v=0\r
+o=alice 2890844526 2890844526 IN IP4 192.168.1.20\r
+s=-\r
+c=IN IP4 192.168.1.20\r
+t=0 0\r
+m=audio 4000 RTP/AVP 8 0\r
+";
+
+    #[test]
+    fn parse_request() {
+        let packet = b"OPTIONS sip:nucleo@pbx.local SIP/2.0\r
+v: SIP/2.0/UDP 192.168.1.1:5060;branch=z9hG4bK776asdhds\r
+i: a84b4c76e66710\r
+cseq:  1 OPTIONS \r
+Accept: application/sdp\r
+l: 4\r
+\r
+v=0\r
+";
+        let message = Message::parse(packet).unwrap();
+        assert_eq!(
+            message.start,
+            StartLine::Request {
+                method: "OPTIONS",
+                uri: "sip:nucleo@pbx.local"
+            }
+        );
+        // Compact forms go by the full names
+        assert_eq!(message.header("Call-ID"), Some("a84b4c76e66710"));
+        assert_eq!(
+            message.header("Via"),
+            Some("SIP/2.0/UDP 192.168.1.1:5060;branch=z9hG4bK776asdhds")
+        );
+        assert_eq!(message.header("CSEQ"), Some("1 OPTIONS"));
+        assert_eq!(message.cseq(), Some((1, "OPTIONS")));
+        assert_eq!(message.header("Contact"), None);
+        assert_eq!(message.headers().count(), 5);
+        // Up to the Content-Length
+        assert_eq!(message.body, b"v=0\r");
+    }
+
+    #[test]
+    fn parse_response() {
+        let packet = b"SIP/2.0 486 Busy Here\r\nCSeq: 2 INVITE\r\n\r\n";
+        let message = Message::parse(packet).unwrap();
+        assert_eq!(
+            message.start,
+            StartLine::Response {
+                status: 486,
+                reason: "Busy Here"
+            }
+        );
+        assert_eq!(message.cseq(), Some((2, "INVITE")));
+        assert_eq!(message.body, b"");
+
+        // Only the start line
+        let message = Message::parse(b"SIP/2.0 100 Trying\r\n\r\n").unwrap();
+        assert_eq!(message.headers().count(), 0);
+    }
+
+    #[test]
+    fn parse_malformed() {
+        let packets: &[&[u8]] = &[
+            // No empty line
+            b"SIP/2.0 200 OK\r\nCSeq: 1 BYE\r\n",
+            b"SIP/2.0 200 OK\n\n",
+            b"SIP/2.0 99 Early\r\n\r\n",
+            b"SIP/2.0 700 Late\r\n\r\n",
+            b"SIP/2.0 OK\r\n\r\n",
+            b"BYE sip:nucleo@pbx.local SIP/1.0\r\n\r\n",
+            b"BYE sip:nucleo@pbx.local\r\n\r\n",
+            b"SIP/2.0 200 \xFF\r\n\r\n",
+            // More body than there is
+            b"SIP/2.0 200 OK\r\nContent-Length: 6\r\n\r\nv=0\r\n",
+            b"SIP/2.0 200 OK\r\nContent-Length: -1\r\n\r\n",
+        ];
+        for packet in packets {
+            assert_eq!(
+                Message::parse(packet).err(),
+                Some(Error::Malformed),
+                "{:?}",
+                str::from_utf8(packet)
+            );
+        }
+    }
+
+    #[test]
+    fn digest_rfc2617() {
+        // The example of section 3.5
+        let challenge = Challenge::parse(
+            "Digest realm=\"testrealm@host.com\", qop=\"auth,auth-int\", \
+             nonce=\"dcd98b7102dd2f0e8b11d0f600bfb0c093\", \
+             opaque=\"5ccc069c403ebaf9f0171e9517f40e41\"",
+        )
+        .unwrap();
+        assert_eq!(
+            challenge,
+            Challenge {
+                realm: "testrealm@host.com",
+                nonce: "dcd98b7102dd2f0e8b11d0f600bfb0c093",
+                opaque: Some("5ccc069c403ebaf9f0171e9517f40e41"),
+                qop_auth: true,
+            }
+        );
+        let mut credentials = String::new();
+        challenge
+            .write_credentials(
+                &mut credentials,
+                "Mufasa",
+                "Circle Of Life",
+                "GET",
+                "/dir/index.html",
+                0x0a4f_113b,
+            )
+            .unwrap();
+        assert_eq!(
+            credentials,
+            "Digest username=\"Mufasa\", realm=\"testrealm@host.com\", \
+             nonce=\"dcd98b7102dd2f0e8b11d0f600bfb0c093\", uri=\"/dir/index.html\", \
+             response=\"6629fae49393a05397450978507c4ef1\", algorithm=MD5, \
+             qop=auth, nc=00000001, cnonce=\"0a4f113b\", \
+             opaque=\"5ccc069c403ebaf9f0171e9517f40e41\""
+        );
+    }
+
+    #[test]
+    fn challenge_rejected() {
+        assert_eq!(Challenge::parse("Basic realm=\"pbx.local\""), None);
+        assert_eq!(Challenge::parse("Digest realm=\"pbx.local\""), None);
+        assert_eq!(
+            Challenge::parse("Digest realm=\"pbx.local\", nonce=\"abc\", algorithm=SHA-256"),
+            None
+        );
+        let challenge =
+            Challenge::parse("digest REALM=pbx.local, NONCE=abc, Algorithm=md5, qop=\"auth-int\"")
+                .unwrap();
+        assert_eq!(
+            challenge,
+            Challenge {
+                realm: "pbx.local",
+                nonce: "abc",
+                opaque: None,
+                qop_auth: false,
+            }
+        );
+    }
+
+    #[derive(Default)]
+    struct Frames {
+        rx: VecDeque<Vec<u8>>,
+        tx: Vec<Vec<u8>>,
+    }
+
+    /// Frames in and out, answers ARP for the proxy itself
+    #[derive(Default)]
+    struct TestDevice(Rc<RefCell<Frames>>);
+
+    struct TestRxToken(Vec<u8>);
+
+    impl RxToken for TestRxToken {
+        fn consume<R, F>(self, _timestamp: Instant, f: F) -> smoltcp::Result<R>
+        where
+            F: FnOnce(&[u8]) -> smoltcp::Result<R>,
+        {
+            f(&self.0)
+        }
+    }
+
+    struct TestTxToken<'a>(&'a TestDevice);
+
+    impl<'a> phy::TxToken for TestTxToken<'a> {
+        fn consume<R, F>(self, _timestamp: Instant, len: usize, f: F) -> smoltcp::Result<R>
+        where
+            F: FnOnce(&mut [u8]) -> smoltcp::Result<R>,
+        {
+            let mut frame = vec![0; len];
+            let result = f(&mut frame)?;
+            let mut frames = (self.0).0.borrow_mut();
+            match arp_reply(&frame) {
+                Some(reply) => frames.rx.push_back(reply),
+                None => frames.tx.push(frame),
+            }
+            Ok(result)
+        }
+    }
+
+    impl<'a> Device<'a> for TestDevice {
+        type RxToken = TestRxToken;
+        type TxToken = TestTxToken<'a>;
+
+        fn receive(&'a mut self) -> Option<(Self::RxToken, Self::TxToken)> {
+            let frame = self.0.borrow_mut().rx.pop_front()?;
+            Some((TestRxToken(frame), TestTxToken(self)))
+        }
+
+        fn transmit(&'a mut self) -> Option<Self::TxToken> {
+            Some(TestTxToken(self))
+        }
+
+        fn capabilities(&self) -> DeviceCapabilities {
+            let mut caps = DeviceCapabilities::default();
+            caps.max_transmission_unit = 1514;
+            caps
+        }
+    }
+
+    /// The proxy's answer to an ARP request for it
+    fn arp_reply(frame: &[u8]) -> Option<Vec<u8>> {
+        let eth = EthernetFrame::new_checked(frame).ok()?;
+        if eth.ethertype() != EthernetProtocol::Arp {
+            return None;
+        }
+        let request = ArpRepr::parse(&ArpPacket::new_checked(eth.payload()).ok()?).ok()?;
+        let (source_hardware_addr, source_protocol_addr) = match request {
+            ArpRepr::EthernetIpv4 {
+                operation: ArpOperation::Request,
+                source_hardware_addr,
+                source_protocol_addr,
+                target_protocol_addr,
+                ..
+            } if target_protocol_addr == PROXY => (source_hardware_addr, source_protocol_addr),
+            _ => return None,
+        };
+        let reply = ArpRepr::EthernetIpv4 {
+            operation: ArpOperation::Reply,
+            source_hardware_addr: PROXY_MAC,
+            source_protocol_addr: PROXY,
+            target_hardware_addr: source_hardware_addr,
+            target_protocol_addr: source_protocol_addr,
+        };
+        let mut frame = vec![0; EthernetFrame::<&[u8]>::buffer_len(reply.buffer_len())];
+        let mut eth = EthernetFrame::new_unchecked(&mut frame[..]);
+        EthernetRepr {
+            src_addr: PROXY_MAC,
+            dst_addr: source_hardware_addr,
+            ethertype: EthernetProtocol::Arp,
+        }
+        .emit(&mut eth);
+        reply.emit(&mut ArpPacket::new_unchecked(eth.payload_mut()));
+        Some(frame)
+    }
+
+    /// A message from the proxy
+    fn frame(message: &str) -> Vec<u8> {
+        let checksum_caps = ChecksumCapabilities::default();
+        let udp_repr = UdpRepr {
+            src_port: DEFAULT_PORT,
+            dst_port: DEFAULT_PORT,
+            payload: message.as_bytes(),
+        };
+        let ipv4_repr = Ipv4Repr {
+            src_addr: PROXY,
+            dst_addr: ADDRESS,
+            protocol: IpProtocol::Udp,
+            payload_len: udp_repr.buffer_len(),
+            hop_limit: 64,
+        };
+        let mut frame = vec![0; 14 + 20 + udp_repr.buffer_len()];
+        let mut eth = EthernetFrame::new_unchecked(&mut frame[..]);
+        EthernetRepr {
+            src_addr: PROXY_MAC,
+            dst_addr: MAC,
+            ethertype: EthernetProtocol::Ipv4,
+        }
+        .emit(&mut eth);
+        let mut ipv4 = Ipv4Packet::new_unchecked(eth.payload_mut());
+        ipv4_repr.emit(&mut ipv4, &checksum_caps);
+        udp_repr.emit(
+            &mut UdpPacket::new_unchecked(ipv4.payload_mut()),
+            &IpAddress::Ipv4(PROXY),
+            &IpAddress::Ipv4(ADDRESS),
+            &checksum_caps,
+        );
+        frame
+    }
+
+    /// The message the UA sent, to the proxy
+    fn sent(frame: &[u8]) -> String {
+        let checksum_caps = ChecksumCapabilities::default();
+        let eth = EthernetFrame::new_checked(frame).unwrap();
+        let ipv4 = Ipv4Packet::new_checked(eth.payload()).unwrap();
+        let ipv4_repr = Ipv4Repr::parse(&ipv4, &checksum_caps).unwrap();
+        assert_eq!((ipv4_repr.src_addr, ipv4_repr.dst_addr), (ADDRESS, PROXY));
+        let udp = UdpPacket::new_checked(ipv4.payload()).unwrap();
+        let src_addr = IpAddress::Ipv4(ipv4_repr.src_addr);
+        let dst_addr = IpAddress::Ipv4(ipv4_repr.dst_addr);
+        let udp_repr = UdpRepr::parse(&udp, &src_addr, &dst_addr, &checksum_caps).unwrap();
+        assert_eq!(
+            (udp_repr.src_port, udp_repr.dst_port),
+            (DEFAULT_PORT, DEFAULT_PORT)
+        );
+        String::from_utf8(udp_repr.payload.to_vec()).unwrap()
+    }
+
+    /// A request of Alice's, calling in through the proxy. To carries
+    /// `to_tag` once there's a dialog.
+    fn request(method: &str, cseq: u32, call_id: &str, to_tag: Option<&str>, sdp: &str) -> String {
+        let to_tag = to_tag.map_or(String::new(), |tag| format!(";tag={}", tag));
+        let content_type = if sdp.is_empty() {
+            ""
+        } else {
+            "Content-Type: application/sdp\r\n"
+        };
+        format!(
+            "{method} sip:nucleo@192.168.1.50:5060 SIP/2.0\r\n\
+             Via: SIP/2.0/UDP 192.168.1.1:5060;branch=z9hG4bK{cseq}{method}\r\n\
+             Max-Forwards: 70\r\n\
+             From: \"Alice\" <sip:alice@pbx.local>;tag=1928301774\r\n\
+             To: <sip:nucleo@pbx.local>{to_tag}\r\n\
+             Call-ID: {call_id}\r\n\
+             CSeq: {cseq} {method}\r\n\
+             Contact: <sip:alice@192.168.1.20>\r\n\
+             {content_type}Content-Length: {len}\r\n\r\n{sdp}",
+            method = method,
+            cseq = cseq,
+            to_tag = to_tag,
+            call_id = call_id,
+            content_type = content_type,
+            len = sdp.len(),
+            sdp = sdp
+        )
+    }
+
+    fn invite() -> String {
+        request("INVITE", INVITE_CSEQ, CALL_ID, None, OFFER)
+    }
+
+    /// Status and CSeq method of a response
+    fn status(response: &str) -> (u16, &str) {
+        let message = Message::parse(response.as_bytes()).unwrap();
+        match message.start {
+            StartLine::Response { status, .. } => (status, message.cseq().unwrap().1),
+            start => panic!("{:?}", start),
+        }
+    }
+
+    fn to_tag(response: &str) -> &str {
+        let message = Message::parse(response.as_bytes()).unwrap();
+        tag(message.header("To").unwrap()).unwrap()
+    }
+
+    struct Harness {
+        iface: EthernetInterface<'static, 'static, 'static, TestDevice>,
+        sockets: SocketSet<'static, 'static, 'static>,
+        ua: UserAgent,
+        frames: Rc<RefCell<Frames>>,
+    }
+
+    impl Harness {
+        fn new() -> Self {
+            let device = TestDevice::default();
+            let frames = device.0.clone();
+            let iface = EthernetInterfaceBuilder::new(device)
+                .ethernet_addr(MAC)
+                .neighbor_cache(NeighborCache::new(BTreeMap::new()))
+                .ip_addrs(vec![IpCidr::new(ADDRESS.into(), 24)])
+                .finalize();
+            let mut sockets = SocketSet::new(vec![]);
+            let config = Config {
+                user: "nucleo",
+                password: "secret",
+                domain: "pbx.local",
+                proxy: IpEndpoint::new(PROXY.into(), DEFAULT_PORT),
+                expires: 600,
+                rtp_port: 5004,
+                ptime: Ptime::Ms20,
+            };
+            let buffer = || {
+                UdpSocketBuffer::new(
+                    vec![UdpPacketMetadata::EMPTY; 4],
+                    vec![0; 4 * MAX_MESSAGE_LEN],
+                )
+            };
+            let mut ua = UserAgent::new(
+                &mut sockets,
+                buffer(),
+                buffer(),
+                DEFAULT_PORT,
+                config,
+                0x1234_5678,
+            );
+            // Calls come in whether registered or not, leave the registrar
+            // out of it
+            ua.address = ADDRESS;
+            Harness {
+                iface,
+                sockets,
+                ua,
+                frames,
+            }
+        }
+
+        /// Deliver `message`, run the UA until it's out of events and return
+        /// them with what it sent
+        fn step(&mut self, message: Option<&str>, now_ms: i64) -> (Vec<Event>, Vec<String>) {
+            let now = Instant::from_millis(now_ms);
+            self.frames.borrow_mut().rx.extend(message.map(frame));
+            let _ = self.iface.poll(&mut self.sockets, now);
+            let mut events = Vec::new();
+            while let Some(event) = self.ua.poll(&mut self.sockets, now) {
+                events.push(event);
+            }
+            // Again for the ARP reply
+            let _ = self.iface.poll(&mut self.sockets, now);
+            let _ = self.iface.poll(&mut self.sockets, now);
+            let tx = self.frames.borrow_mut().tx.drain(..).collect::<Vec<_>>();
+            (events, tx.iter().map(|frame| sent(frame)).collect())
+        }
+
+        /// Ring and answer at 100 ms, established with the ACK at 200 ms.
+        /// Returns the UA's tag.
+        fn establish(&mut self) -> String {
+            self.step(Some(&invite()), 0);
+            self.ua.answer().unwrap();
+            let (events, tx) = self.step(None, 100);
+            let media = Media {
+                remote: IpEndpoint::new(Ipv4Address::new(192, 168, 1, 20).into(), 4000),
+                payload_type: 8,
+                format: Format::PCMA,
+                ptime: Ptime::Ms20,
+            };
+            assert_eq!(events, [Event::MediaStarted(media)]);
+            assert_eq!(tx.len(), 1);
+            assert_eq!(status(&tx[0]), (200, "INVITE"));
+            let answer = Message::parse(tx[0].as_bytes()).unwrap();
+            let answer = Description::parse(answer.body).unwrap();
+            assert_eq!(answer.payload_types(), [8]);
+            assert_eq!(answer.port, 5004);
+            let tag = String::from(to_tag(&tx[0]));
+
+            let ack = request("ACK", INVITE_CSEQ, CALL_ID, Some(&tag), "");
+            let (events, tx) = self.step(Some(&ack), 200);
+            assert_eq!(events, []);
+            assert_eq!(tx, Vec::<String>::new());
+            assert_eq!(self.ua.call.state, CallState::Established);
+            assert_eq!(self.ua.media(), Some(media));
+            tag
+        }
+    }
+
+    #[test]
+    fn invite_retransmitted() {
+        let mut h = Harness::new();
+        let (events, tx) = h.step(Some(&invite()), 0);
+        assert_eq!(events, [Event::IncomingCall]);
+        assert_eq!(h.ua.peer(), Some("sip:alice@pbx.local"));
+        assert_eq!(tx.len(), 1);
+        assert_eq!(status(&tx[0]), (180, "INVITE"));
+        assert_eq!(parse_tag(to_tag(&tx[0])), Some(h.ua.call.local_tag));
+
+        // Nothing new, the same 180 again
+        let (events, retransmitted) = h.step(Some(&invite()), 400);
+        assert_eq!(events, []);
+        assert_eq!(retransmitted, tx);
+        assert_eq!(h.ua.call.state, CallState::Ringing);
+    }
+
+    #[test]
+    fn cancel_while_ringing() {
+        let mut h = Harness::new();
+        h.step(Some(&invite()), 0);
+
+        let cancel = request("CANCEL", INVITE_CSEQ, CALL_ID, None, "");
+        let (events, tx) = h.step(Some(&cancel), 100);
+        assert_eq!(events, [Event::CallEnded(EndReason::Remote)]);
+        assert!(!h.ua.in_call());
+        assert_eq!(tx.len(), 2);
+        assert_eq!(status(&tx[0]), (200, "CANCEL"));
+        assert_eq!(status(&tx[1]), (487, "INVITE"));
+        let tag = String::from(to_tag(&tx[1]));
+        assert_eq!(to_tag(&tx[0]), tag);
+
+        // Retransmitted until the ACK
+        let (_, tx) = h.step(None, 600);
+        assert_eq!(tx.len(), 1);
+        assert_eq!(status(&tx[0]), (487, "INVITE"));
+        let ack = request("ACK", INVITE_CSEQ, CALL_ID, Some(&tag), "");
+        let (events, tx) = h.step(Some(&ack), 700);
+        assert_eq!(events, []);
+        assert_eq!(tx, Vec::<String>::new());
+        assert_eq!(h.step(None, 1600).1, Vec::<String>::new());
+
+        // The CANCEL again, too late
+        let (events, tx) = h.step(Some(&cancel), 1700);
+        assert_eq!(events, []);
+        assert_eq!(tx.len(), 1);
+        assert_eq!(status(&tx[0]), (200, "CANCEL"));
+    }
+
+    #[test]
+    fn bye_in_dialog() {
+        let mut h = Harness::new();
+        let tag = h.establish();
+
+        let bye = request("BYE", INVITE_CSEQ + 1, CALL_ID, Some(&tag), "");
+        let (events, tx) = h.step(Some(&bye), 1000);
+        assert_eq!(events, [Event::CallEnded(EndReason::Remote)]);
+        assert!(!h.ua.in_call());
+        assert_eq!(h.ua.media(), None);
+        assert_eq!(tx.len(), 1);
+        assert_eq!(status(&tx[0]), (200, "BYE"));
+        assert_eq!(to_tag(&tx[0]), tag);
+
+        // Retransmitted after the call ended
+        let (events, tx) = h.step(Some(&bye), 1500);
+        assert_eq!(events, []);
+        assert_eq!(tx.len(), 1);
+        assert_eq!(status(&tx[0]), (481, "BYE"));
+    }
+
+    #[test]
+    fn stray_bye() {
+        let mut h = Harness::new();
+        let bye = request("BYE", 1, "0123456789@192.168.1.20", Some("5ca1ab1e"), "");
+        let (events, tx) = h.step(Some(&bye), 0);
+        assert_eq!(events, []);
+        assert_eq!(tx.len(), 1);
+        assert_eq!(status(&tx[0]), (481, "BYE"));
+
+        // The call's Call-ID, but another dialog
+        h.establish();
+        for other in [None, Some("5ca1ab1e")].iter() {
+            let bye = request("BYE", INVITE_CSEQ + 1, CALL_ID, *other, "");
+            let (events, tx) = h.step(Some(&bye), 1000);
+            assert_eq!(events, []);
+            assert_eq!(tx.len(), 1);
+            assert_eq!(status(&tx[0]), (481, "BYE"));
+            assert_eq!(h.ua.call.state, CallState::Established);
+        }
+    }
+}
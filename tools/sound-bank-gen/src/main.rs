@@ -0,0 +1,185 @@
+// Packs WAV and raw PCM clips into a sound bank blob, see
+// wm8960::sound_bank for the format.
+//
+// sound-bank-gen -o sounds.bank 1=ring.wav 2=busy.raw:48000:2
+
+use std::convert::TryFrom;
+use std::env;
+use std::fs;
+use std::process;
+use wm8960::audio::SAMPLE_RATE;
+use wm8960::sound_bank::{
+    align, Entry, EntryKind, Header, SoundBank, ENTRY_LEN, HEADER_LEN, VERSION,
+};
+use wm8960::wave_header::{AudioFormat, ParsedWave};
+
+const USAGE: &str = "Usage: sound-bank-gen -o <output> <clip>...
+
+Clips:
+  <id>=<file.wav>                     WAV file, stored as is
+  <id>=<file.raw>:<rate>:<channels>   raw signed 16-bit little endian PCM";
+
+struct Clip {
+    entry: Entry,
+    payload: Vec<u8>,
+}
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("error: {}", e);
+        process::exit(1);
+    }
+}
+
+fn run() -> Result<(), String> {
+    let mut output = None;
+    let mut specs = Vec::new();
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" => output = args.next(),
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return Ok(());
+            }
+            _ => specs.push(arg),
+        }
+    }
+
+    let output = output.ok_or_else(|| USAGE.to_string())?;
+    if specs.is_empty() {
+        return Err(USAGE.to_string());
+    }
+
+    let mut clips: Vec<Clip> = Vec::new();
+    for spec in specs.iter() {
+        let clip = load_clip(spec)?;
+        if clips.iter().any(|c| c.entry.id == clip.entry.id) {
+            return Err(format!("duplicate clip ID {}", clip.entry.id));
+        }
+        clips.push(clip);
+    }
+
+    let bank = pack(&mut clips)?;
+
+    // Read it back the same way the firmware does
+    let check = SoundBank::new(&bank).map_err(|e| format!("{:?}", e))?;
+    for entry in check.entries() {
+        let clip = check
+            .clip(entry.id)
+            .map_err(|e| format!("clip {}: {:?}", entry.id, e))?;
+        println!(
+            "{:5} {:?} {} ch {} Hz, {} frames",
+            clip.id,
+            entry.kind,
+            clip.channels,
+            clip.sample_rate,
+            clip.len()
+        );
+        if let Some(info) = clip.info {
+            for (id, text) in info.entries() {
+                println!("      {}: {}", String::from_utf8_lossy(&id), text);
+            }
+        }
+        if clip.sample_rate != SAMPLE_RATE {
+            eprintln!(
+                "warning: clip {} is {} Hz, the codec runs at {} Hz",
+                clip.id, clip.sample_rate, SAMPLE_RATE
+            );
+        }
+    }
+
+    fs::write(&output, &bank).map_err(|e| format!("{}: {}", output, e))?;
+    println!(
+        "Wrote {} clips, {} bytes to {}",
+        clips.len(),
+        bank.len(),
+        output
+    );
+
+    Ok(())
+}
+
+fn load_clip(spec: &str) -> Result<Clip, String> {
+    let mut parts = spec.splitn(2, '=');
+    let id = parts.next().unwrap_or("");
+    let id: u16 = id
+        .parse()
+        .map_err(|_| format!("invalid clip ID '{}' in '{}'", id, spec))?;
+    let rest = parts
+        .next()
+        .ok_or_else(|| format!("expected <id>=<file> in '{}'", spec))?;
+
+    let fields: Vec<&str> = rest.split(':').collect();
+    let path = fields[0];
+    let payload = fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+
+    let entry = match fields.len() {
+        1 => {
+            let wave = ParsedWave::parse(&payload)
+                .map_err(|e| format!("{}: invalid WAV file: {:?}", path, e))?;
+            let header = wave.header();
+            if header.fmt.audio_format != AudioFormat::PCM || header.fmt.bits_per_sample != 16 {
+                return Err(format!("{}: only 16-bit PCM is supported", path));
+            }
+            Entry {
+                id,
+                kind: EntryKind::Wave,
+                channels: u8::try_from(header.fmt.num_channels)
+                    .map_err(|_| format!("{}: too many channels", path))?,
+                offset: 0,
+                length: 0,
+                sample_rate: header.fmt.sample_rate,
+            }
+        }
+        3 => Entry {
+            id,
+            kind: EntryKind::Raw,
+            channels: fields[2]
+                .parse()
+                .map_err(|_| format!("invalid channel count in '{}'", spec))?,
+            offset: 0,
+            length: 0,
+            sample_rate: fields[1]
+                .parse()
+                .map_err(|_| format!("invalid sample rate in '{}'", spec))?,
+        },
+        _ => {
+            return Err(format!(
+                "expected <file> or <file>:<rate>:<channels> in '{}'",
+                spec
+            ))
+        }
+    };
+
+    Ok(Clip { entry, payload })
+}
+
+/// Lay out the header, index and aligned payloads
+fn pack(clips: &mut [Clip]) -> Result<Vec<u8>, String> {
+    let num_entries = u16::try_from(clips.len()).map_err(|_| "too many clips".to_string())?;
+
+    let mut offset = Header::payload_offset(num_entries);
+    for clip in clips.iter_mut() {
+        clip.entry.offset = u32::try_from(offset).map_err(|_| "bank exceeds 4 GiB")?;
+        clip.entry.length = u32::try_from(clip.payload.len()).map_err(|_| "clip exceeds 4 GiB")?;
+        offset = align(offset + clip.payload.len());
+    }
+
+    let header = Header {
+        version: VERSION,
+        num_entries,
+        total_len: u32::try_from(offset).map_err(|_| "bank exceeds 4 GiB")?,
+    };
+
+    let mut bank = vec![0; offset];
+    header.write(&mut bank[..HEADER_LEN]);
+    for (i, clip) in clips.iter().enumerate() {
+        let start = HEADER_LEN + i * ENTRY_LEN;
+        clip.entry.write(&mut bank[start..start + ENTRY_LEN]);
+        let start = clip.entry.offset as usize;
+        bank[start..start + clip.payload.len()].copy_from_slice(&clip.payload);
+    }
+
+    Ok(bank)
+}
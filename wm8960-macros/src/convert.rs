@@ -0,0 +1,157 @@
+// Conversion to 16-bit PCM at the codec's rate and channel count
+//
+// Resampling is linear interpolation, good enough for prompts and tones.
+// The "INFO" list is copied as is, "smpl" loop points are scaled to the new
+// rate, other chunks are dropped.
+
+use wm8960::wave_header::{
+    parse_riff, write_header, AudioFormat, Chunk, ChunkId, ParsedWave, Samples, WaveError,
+    WaveHeaderBuilder, MAX_HEADER_LEN,
+};
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Target {
+    pub sample_rate: u32,
+    pub channels: u16,
+}
+
+/// A new WAVE file with the contents of `wave` in the `target` format
+pub fn convert(wave: &ParsedWave, target: Target) -> Result<Vec<u8>, String> {
+    let fmt = wave.fmt();
+    let in_channels = usize::from(fmt.num_channels);
+    let samples = decode(wave)?;
+    let in_frames = samples.len() / in_channels;
+
+    // Mix or spread the channels first, then resample each one
+    let channels: Vec<Vec<f32>> = (0..usize::from(target.channels))
+        .map(|c| {
+            (0..in_frames)
+                .map(|f| {
+                    let frame = &samples[f * in_channels..(f + 1) * in_channels];
+                    if target.channels == 1 {
+                        frame.iter().sum::<f32>() / in_channels as f32
+                    } else {
+                        frame[c % in_channels]
+                    }
+                })
+                .collect()
+        })
+        .collect();
+    let channels: Vec<Vec<f32>> = channels
+        .iter()
+        .map(|c| resample(c, fmt.sample_rate, target.sample_rate))
+        .collect();
+    let out_frames = channels.first().map_or(0, |c| c.len());
+
+    let mut header = WaveHeaderBuilder::new()
+        .audio_format(AudioFormat::PCM)
+        .num_channels(target.channels)
+        .sample_rate(target.sample_rate)
+        .bits_per_sample(16)
+        .num_frames(out_frames as u32)
+        .build();
+
+    let mut out = vec![0; MAX_HEADER_LEN];
+    let len = write_header(&header, &mut out).map_err(|e| format!("{:?}", e))?;
+    out.truncate(len);
+    for f in 0..out_frames {
+        for c in channels.iter() {
+            let s = (c[f] * 32768.0).round().max(-32768.0).min(32767.0) as i16;
+            out.extend_from_slice(&s.to_le_bytes());
+        }
+    }
+
+    let (_, chunks) = parse_riff(wave.as_bytes()).map_err(|e| format!("{:?}", e))?;
+    let info = chunks
+        .filter(|c| c.is(ChunkId::LIST))
+        .find(|c| c.body.starts_with(&ChunkId::INFO.fourcc()));
+    if let Some(chunk) = info {
+        push_chunk(&mut out, &chunk, chunk.body.to_vec());
+    }
+    if let Some(chunk) = wave.find_chunk(ChunkId::SMPL) {
+        let body = scale_loops(&chunk, fmt.sample_rate, target.sample_rate, out_frames);
+        push_chunk(&mut out, &chunk, body);
+    }
+
+    // The RIFF size covers the chunks after the data
+    header.riff.chunk_size = (out.len() - 8) as u32;
+    write_header(&header, &mut out).map_err(|e| format!("{:?}", e))?;
+    Ok(out)
+}
+
+/// Interleaved samples in [-1.0, 1.0)
+fn decode(wave: &ParsedWave) -> Result<Vec<f32>, String> {
+    let fmt = wave.fmt();
+    let samples: Vec<f32> = match (fmt.audio_format, fmt.bits_per_sample) {
+        (AudioFormat::PCM, 8) => {
+            let frames_len = wave.num_frames() * usize::from(fmt.block_align);
+            wave.data()[..frames_len]
+                .iter()
+                .map(|&s| (f32::from(s) - 128.0) / 128.0)
+                .collect()
+        }
+        (AudioFormat::PCM, 16) => samples(wave.samples_i16(), |s| f32::from(s) / 32768.0)?,
+        (AudioFormat::PCM, 24) => samples(wave.samples_i24(), |s| s as f32 / 8_388_608.0)?,
+        (AudioFormat::PCM, 32) => samples(wave.samples_i32(), |s| s as f32 / 2_147_483_648.0)?,
+        (AudioFormat::FLOAT, 32) => samples(wave.samples_f32(), |s| s)?,
+        (_, bits) => return Err(format!("can't convert {}-bit samples", bits)),
+    };
+    Ok(samples)
+}
+
+fn samples<T>(samples: Result<Samples<T>, WaveError>, f: fn(T) -> f32) -> Result<Vec<f32>, String> {
+    samples
+        .map(|s| s.map(f).collect())
+        .map_err(|e| format!("{:?}", e))
+}
+
+fn resample(input: &[f32], from: u32, to: u32) -> Vec<f32> {
+    if from == to || input.is_empty() {
+        return input.to_vec();
+    }
+    let len = (input.len() as u64 * u64::from(to) / u64::from(from)) as usize;
+    let step = f64::from(from) / f64::from(to);
+    (0..len)
+        .map(|i| {
+            let pos = i as f64 * step;
+            let index = pos as usize;
+            let a = input[index.min(input.len() - 1)];
+            let b = input[(index + 1).min(input.len() - 1)];
+            a + (b - a) * (pos - index as f64) as f32
+        })
+        .collect()
+}
+
+/// The "smpl" body with the sample period and loop points at the new rate
+fn scale_loops(chunk: &Chunk, from: u32, to: u32, num_frames: usize) -> Vec<u8> {
+    let mut body = chunk.body.to_vec();
+    let scale = |frame: u32| (u64::from(frame) * u64::from(to) / u64::from(from)) as u32;
+    let last = (num_frames as u32).saturating_sub(1);
+
+    if body.len() >= 12 {
+        let period = (1_000_000_000 / u64::from(to.max(1))) as u32;
+        body[8..12].copy_from_slice(&period.to_le_bytes());
+    }
+    for l in body.get_mut(36..).unwrap_or(&mut []).chunks_exact_mut(24) {
+        let start = u32::from_le_bytes([l[8], l[9], l[10], l[11]]);
+        let end = u32::from_le_bytes([l[12], l[13], l[14], l[15]]);
+        // The end is inclusive, scale the frame after it
+        let new_start = scale(start).min(last);
+        let new_end = scale(end.saturating_add(1))
+            .saturating_sub(1)
+            .max(new_start)
+            .min(last);
+        l[8..12].copy_from_slice(&new_start.to_le_bytes());
+        l[12..16].copy_from_slice(&new_end.to_le_bytes());
+    }
+    body
+}
+
+fn push_chunk(out: &mut Vec<u8>, chunk: &Chunk, body: Vec<u8>) {
+    out.extend_from_slice(&chunk.fourcc);
+    out.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    out.extend_from_slice(&body);
+    if body.len() % 2 != 0 {
+        out.push(0);
+    }
+}
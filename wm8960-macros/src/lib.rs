@@ -0,0 +1,235 @@
+// Build time asset embedding for the wm8960 crate
+//
+// include_wav!(RING, "assets/ring.wav");
+// include_wav!(pub PROMPT, "assets/prompt.wav", convert);
+// include_wav!(TONE, "assets/tone.wav", sample_rate = 8000, channels = 1);
+//
+// Expands to a `static` `wm8960::wave_header::EmbeddedWave`. Paths are
+// relative to the crate being built. The file is parsed and validated when
+// the macro expands, malformed files fail the build. `convert` resamples and
+// converts to 16-bit PCM in the codec format, `sample_rate` and `channels`
+// override it.
+
+extern crate proc_macro;
+
+mod convert;
+
+use crate::convert::Target;
+
+use proc_macro::{Delimiter, Literal, TokenStream, TokenTree};
+use std::env;
+use std::fmt::Write;
+use std::fs;
+use std::path::PathBuf;
+use wm8960::audio::{NUM_CHANNELS, SAMPLE_RATE};
+use wm8960::wave_header::{AudioFormat, ParsedWave, WaveHeader};
+
+#[proc_macro]
+pub fn include_wav(input: TokenStream) -> TokenStream {
+    match expand(input) {
+        Ok(tokens) => tokens,
+        Err(msg) => format!("compile_error!({:?});", format!("include_wav!: {}", msg))
+            .parse()
+            .unwrap(),
+    }
+}
+
+struct Args {
+    public: bool,
+    name: String,
+    path: String,
+    target: Option<Target>,
+}
+
+fn expand(input: TokenStream) -> Result<TokenStream, String> {
+    let args = parse_args(input)?;
+
+    let manifest_dir = env::var("CARGO_MANIFEST_DIR").map_err(|e| e.to_string())?;
+    let path = PathBuf::from(manifest_dir).join(&args.path);
+    let file = fs::read(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+
+    let wave = ParsedWave::parse(&file).map_err(|e| format!("{}: {:?}", args.path, e))?;
+    let validation = wave.header().validate(file.len());
+    if !validation.is_ok() {
+        let violations: Vec<String> = validation.iter().map(|v| format!("{:?}", v)).collect();
+        return Err(format!("{}: {}", args.path, violations.join(", ")));
+    }
+
+    let bytes = match args.target {
+        Some(target) => {
+            convert::convert(&wave, target).map_err(|e| format!("{}: {}", args.path, e))?
+        }
+        None => file.clone(),
+    };
+    let header = *ParsedWave::parse(&bytes)
+        .map_err(|e| format!("{}: converted file is invalid: {:?}", args.path, e))?
+        .header();
+
+    let mut out = String::new();
+    write!(
+        out,
+        "{vis} static {name}: ::wm8960::wave_header::EmbeddedWave = {{
+            use ::wm8960::wave_header::*;
+
+            #[repr(C)]
+            struct Aligned<B: ?Sized> {{
+                _align: [u32; 0],
+                bytes: B,
+            }}
+
+            static ALIGNED: Aligned<[u8; {len}]> = Aligned {{
+                _align: [],
+                bytes: *{bytes},
+            }};
+
+            // Rebuild when the asset changes
+            const _: &[u8] = include_bytes!({path:?});
+
+            EmbeddedWave {{
+                header: {header},
+                bytes: &ALIGNED.bytes,
+            }}
+        }};",
+        vis = if args.public { "pub" } else { "" },
+        name = args.name,
+        len = bytes.len(),
+        bytes = Literal::byte_string(&bytes),
+        path = path.display().to_string(),
+        header = header_expr(&header),
+    )
+    .unwrap();
+
+    out.parse().map_err(|e| format!("{:?}", e))
+}
+
+fn parse_args(input: TokenStream) -> Result<Args, String> {
+    const USAGE: &str =
+        "expected `[pub] NAME, \"file.wav\"[, convert][, sample_rate = N][, channels = N]`";
+
+    // Split on commas
+    let mut args: Vec<Vec<TokenTree>> = vec![Vec::new()];
+    for tt in input {
+        match tt {
+            TokenTree::Punct(ref p) if p.as_char() == ',' => args.push(Vec::new()),
+            TokenTree::Group(ref g) if g.delimiter() == Delimiter::None => {
+                args.last_mut().unwrap().extend(g.stream())
+            }
+            tt => args.last_mut().unwrap().push(tt),
+        }
+    }
+    if args.last().map_or(false, |a| a.is_empty()) {
+        args.pop();
+    }
+    if args.len() < 2 {
+        return Err(USAGE.into());
+    }
+
+    let (public, name) = match args[0].as_slice() {
+        [TokenTree::Ident(name)] => (false, name.to_string()),
+        [TokenTree::Ident(vis), TokenTree::Ident(name)] if vis.to_string() == "pub" => {
+            (true, name.to_string())
+        }
+        _ => return Err(USAGE.into()),
+    };
+
+    let path = match args[1].as_slice() {
+        [TokenTree::Literal(lit)] => parse_str(&lit.to_string()).ok_or(USAGE)?,
+        _ => return Err(USAGE.into()),
+    };
+
+    let mut target = None;
+    let codec = Target {
+        sample_rate: SAMPLE_RATE,
+        channels: NUM_CHANNELS as u16,
+    };
+    for arg in &args[2..] {
+        let t = target.get_or_insert(codec);
+        match arg.as_slice() {
+            [TokenTree::Ident(opt)] if opt.to_string() == "convert" => (),
+            [TokenTree::Ident(opt), TokenTree::Punct(eq), TokenTree::Literal(value)]
+                if eq.as_char() == '=' =>
+            {
+                let value: u32 = value
+                    .to_string()
+                    .parse()
+                    .map_err(|_| format!("invalid value for `{}`", opt))?;
+                match opt.to_string().as_str() {
+                    "sample_rate" if value > 0 => t.sample_rate = value,
+                    "channels" if value == 1 || value == 2 => t.channels = value as u16,
+                    _ => return Err(format!("invalid option `{} = {}`", opt, value)),
+                }
+            }
+            _ => return Err(USAGE.into()),
+        }
+    }
+
+    Ok(Args {
+        public,
+        name,
+        path,
+        target,
+    })
+}
+
+/// The contents of a plain string literal
+fn parse_str(lit: &str) -> Option<String> {
+    if lit.len() >= 2 && lit.starts_with('"') && lit.ends_with('"') && !lit.contains('\\') {
+        Some(lit[1..lit.len() - 1].to_string())
+    } else {
+        None
+    }
+}
+
+/// A const expression for `header`, names are from `wave_header::*`
+fn header_expr(h: &WaveHeader) -> String {
+    let fmt = &h.fmt;
+    let extensible = match fmt.extensible {
+        Some(e) => format!(
+            "Some(FmtExtensible {{ valid_bits_per_sample: {}, channel_mask: {}, sub_format: {:?} }})",
+            e.valid_bits_per_sample, e.channel_mask, e.sub_format
+        ),
+        None => "None".into(),
+    };
+    let fact = match h.fact {
+        Some(f) => format!(
+            "Some(ChunkFact {{ chunk_id: ChunkId::FACT, chunk_size: {}, fact_size: {} }})",
+            f.chunk_size, f.fact_size
+        ),
+        None => "None".into(),
+    };
+    let audio_format = match fmt.audio_format {
+        AudioFormat::PCM => "PCM",
+        AudioFormat::FLOAT => "FLOAT",
+    };
+
+    format!(
+        "WaveHeader {{
+            riff: ChunkRiff {{ chunk_id: ChunkId::RIFF, chunk_size: {riff_size}, format: Format::WAVE }},
+            fmt: ChunkFmt {{
+                chunk_id: ChunkId::FMT,
+                chunk_size: {fmt_size},
+                audio_format: AudioFormat::{audio_format},
+                num_channels: {num_channels},
+                sample_rate: {sample_rate},
+                byte_rate: {byte_rate},
+                block_align: {block_align},
+                bits_per_sample: {bits_per_sample},
+                extensible: {extensible},
+            }},
+            fact: {fact},
+            data: ChunkData {{ chunk_id: ChunkId::DATA, chunk_size: {data_size}, offset: {data_offset} }},
+        }}",
+        riff_size = h.riff.chunk_size,
+        fmt_size = fmt.chunk_size,
+        audio_format = audio_format,
+        num_channels = fmt.num_channels,
+        sample_rate = fmt.sample_rate,
+        byte_rate = fmt.byte_rate,
+        block_align = fmt.block_align,
+        bits_per_sample = fmt.bits_per_sample,
+        extensible = extensible,
+        fact = fact,
+        data_size = h.data.chunk_size,
+        data_offset = h.data.offset,
+    )
+}
@@ -0,0 +1,57 @@
+// Everything reachable from an untrusted WAV file must not panic
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use wm8960::audio::AudioSource;
+use wm8960::player::Player;
+use wm8960::wave_header::{parse_riff, ParsedWave};
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok((_, chunks)) = parse_riff(data) {
+        for chunk in chunks {
+            let _ = chunk.id();
+        }
+    }
+
+    let wave = match ParsedWave::parse(data) {
+        Ok(wave) => wave,
+        Err(_) => return,
+    };
+
+    let mut header = *wave.header();
+    let _ = header.validate(data.len());
+    header.repair(data.len());
+
+    if let Ok(Some(info)) = wave.info() {
+        for _ in info.entries() {}
+    }
+    if let Ok(Some(sampler)) = wave.sampler() {
+        for _ in sampler.loops() {}
+    }
+    if let Ok(Some(cue)) = wave.cue_points() {
+        for _ in cue.iter() {}
+    }
+
+    if let Ok(samples) = wave.samples_i16() {
+        samples.for_each(drop);
+    }
+    if let Ok(samples) = wave.samples_i24() {
+        samples.for_each(drop);
+    }
+    if let Ok(samples) = wave.samples_i32() {
+        samples.for_each(drop);
+    }
+    if let Ok(samples) = wave.samples_f32() {
+        samples.for_each(drop);
+    }
+
+    if let Ok(mut player) = Player::from_parsed(&wave) {
+        let mut buf = [0; 64];
+        for _ in 0..4 {
+            player.fill(&mut buf);
+        }
+        player.release();
+        while player.fill(&mut buf) == buf.len() {}
+    }
+});
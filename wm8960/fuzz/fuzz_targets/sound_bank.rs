@@ -0,0 +1,23 @@
+// A corrupt sound bank in flash must not panic the firmware
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use wm8960::audio::AudioSource;
+use wm8960::sound_bank::SoundBank;
+
+fuzz_target!(|data: &[u8]| {
+    let bank = match SoundBank::new(data) {
+        Ok(bank) => bank,
+        Err(_) => return,
+    };
+
+    for entry in bank.entries() {
+        if let Ok(clip) = bank.clip(entry.id) {
+            let mut source = clip.source();
+            let mut buf = [0; 64];
+            source.release();
+            while source.fill(&mut buf) == buf.len() {}
+        }
+    }
+});
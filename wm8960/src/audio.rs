@@ -0,0 +1,80 @@
+/// Number of interleaved channels in the I2S stream (left, right)
+pub const NUM_CHANNELS: usize = 2;
+
+/// Master clock the codec is fed on MCLK
+pub const MCLK_HZ: u32 = 25_000_000;
+
+/// Codec sample rate, MCLK / 2 / 256 with the clock configuration in
+/// `Wm8960::new`, 48.828 kHz rather than 48 kHz
+pub const SAMPLE_RATE: u32 = MCLK_HZ / 512;
+
+/// A source of interleaved 16-bit stereo samples for the playback path
+pub trait AudioSource {
+    /// Fill `buf` with interleaved samples and return the number written.
+    ///
+    /// Returning fewer than `buf.len()` samples signals the source is
+    /// exhausted, the remainder of `buf` is left untouched.
+    fn fill(&mut self, buf: &mut [i16]) -> usize;
+}
+
+impl<S: AudioSource + ?Sized> AudioSource for &mut S {
+    fn fill(&mut self, buf: &mut [i16]) -> usize {
+        (**self).fill(buf)
+    }
+}
+
+/// An in-place processing stage for interleaved 16-bit stereo samples
+pub trait AudioProcessor {
+    fn process(&mut self, buf: &mut [i16]);
+}
+
+impl<P: AudioProcessor + ?Sized> AudioProcessor for &mut P {
+    fn process(&mut self, buf: &mut [i16]) {
+        (**self).process(buf)
+    }
+}
+
+/// Two stages run back to back
+impl<A: AudioProcessor, B: AudioProcessor> AudioProcessor for (A, B) {
+    fn process(&mut self, buf: &mut [i16]) {
+        self.0.process(buf);
+        self.1.process(buf);
+    }
+}
+
+/// A source with a processing stage applied to its output
+pub struct Processed<S, P> {
+    pub source: S,
+    pub processor: P,
+}
+
+impl<S, P> Processed<S, P>
+where
+    S: AudioSource,
+    P: AudioProcessor,
+{
+    pub fn new(source: S, processor: P) -> Self {
+        Processed { source, processor }
+    }
+
+    pub fn free(self) -> (S, P) {
+        (self.source, self.processor)
+    }
+}
+
+impl<S, P> AudioSource for Processed<S, P>
+where
+    S: AudioSource,
+    P: AudioProcessor,
+{
+    fn fill(&mut self, buf: &mut [i16]) -> usize {
+        let len = self.source.fill(buf);
+        self.processor.process(&mut buf[..len]);
+        len
+    }
+}
+
+/// Saturate to the 16-bit sample range
+pub(crate) fn clamp_i16(x: i32) -> i16 {
+    x.max(i32::from(core::i16::MIN)).min(i32::from(core::i16::MAX)) as i16
+}
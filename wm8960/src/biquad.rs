@@ -0,0 +1,492 @@
+// https://www.w3.org/TR/audio-eq-cookbook/
+
+use crate::audio::{clamp_i16, AudioProcessor, NUM_CHANNELS};
+
+/// Fractional bits of the fixed-point coefficients, Q3.28
+pub const COEFF_FRAC_BITS: u32 = 28;
+
+/// Maximum number of stages in a `BiquadCascade`
+pub const MAX_STAGES: usize = 8;
+
+/// Bound of the samples between stages, ~84 dB above the 16-bit range and
+/// low enough that the products of a stage can't overflow its accumulator
+const MAX_INTERMEDIATE: i32 = 1 << 29;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum FilterType {
+    LowPass,
+    HighPass,
+    Peaking,
+    LowShelf,
+    HighShelf,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum FilterError {
+    /// Frequency must be above 0 and below Nyquist
+    InvalidFrequency,
+    /// Q must be above 0
+    InvalidQ,
+    /// Coefficients don't fit the fixed-point range
+    OutOfRange,
+    /// No free stage or band
+    Full,
+    InvalidIndex,
+}
+
+/// Normalized (a0 = 1) fixed-point biquad coefficients
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Coefficients {
+    pub b0: i32,
+    pub b1: i32,
+    pub b2: i32,
+    pub a1: i32,
+    pub a2: i32,
+}
+
+impl Coefficients {
+    /// Pass-through
+    pub const IDENTITY: Coefficients = Coefficients {
+        b0: 1 << COEFF_FRAC_BITS,
+        b1: 0,
+        b2: 0,
+        a1: 0,
+        a2: 0,
+    };
+
+    /// Calculate coefficients, `gain_db` is ignored by the pass filters
+    pub fn design(
+        filter: FilterType,
+        freq_hz: f32,
+        q: f32,
+        gain_db: f32,
+        sample_rate: u32,
+    ) -> Result<Self, FilterError> {
+        let fs = sample_rate as f32;
+        if !(freq_hz > 0.0 && freq_hz < fs / 2.0) {
+            return Err(FilterError::InvalidFrequency);
+        }
+        if q.is_nan() || q <= 0.0 {
+            return Err(FilterError::InvalidQ);
+        }
+
+        let a = libm::powf(10.0, gain_db / 40.0);
+        let w0 = 2.0 * core::f32::consts::PI * freq_hz / fs;
+        let cos = libm::cosf(w0);
+        let alpha = libm::sinf(w0) / (2.0 * q);
+        let sqrt_a_alpha = 2.0 * libm::sqrtf(a) * alpha;
+
+        let (b0, b1, b2, a0, a1, a2) = match filter {
+            FilterType::LowPass => (
+                (1.0 - cos) / 2.0,
+                1.0 - cos,
+                (1.0 - cos) / 2.0,
+                1.0 + alpha,
+                -2.0 * cos,
+                1.0 - alpha,
+            ),
+            FilterType::HighPass => (
+                (1.0 + cos) / 2.0,
+                -(1.0 + cos),
+                (1.0 + cos) / 2.0,
+                1.0 + alpha,
+                -2.0 * cos,
+                1.0 - alpha,
+            ),
+            FilterType::Peaking => (
+                1.0 + alpha * a,
+                -2.0 * cos,
+                1.0 - alpha * a,
+                1.0 + alpha / a,
+                -2.0 * cos,
+                1.0 - alpha / a,
+            ),
+            FilterType::LowShelf => (
+                a * ((a + 1.0) - (a - 1.0) * cos + sqrt_a_alpha),
+                2.0 * a * ((a - 1.0) - (a + 1.0) * cos),
+                a * ((a + 1.0) - (a - 1.0) * cos - sqrt_a_alpha),
+                (a + 1.0) + (a - 1.0) * cos + sqrt_a_alpha,
+                -2.0 * ((a - 1.0) + (a + 1.0) * cos),
+                (a + 1.0) + (a - 1.0) * cos - sqrt_a_alpha,
+            ),
+            FilterType::HighShelf => (
+                a * ((a + 1.0) + (a - 1.0) * cos + sqrt_a_alpha),
+                -2.0 * a * ((a - 1.0) + (a + 1.0) * cos),
+                a * ((a + 1.0) + (a - 1.0) * cos - sqrt_a_alpha),
+                (a + 1.0) - (a - 1.0) * cos + sqrt_a_alpha,
+                2.0 * ((a - 1.0) - (a + 1.0) * cos),
+                (a + 1.0) - (a - 1.0) * cos - sqrt_a_alpha,
+            ),
+        };
+
+        Ok(Coefficients {
+            b0: to_fixed(b0 / a0)?,
+            b1: to_fixed(b1 / a0)?,
+            b2: to_fixed(b2 / a0)?,
+            a1: to_fixed(a1 / a0)?,
+            a2: to_fixed(a2 / a0)?,
+        })
+    }
+}
+
+impl Default for Coefficients {
+    fn default() -> Self {
+        Coefficients::IDENTITY
+    }
+}
+
+fn to_fixed(x: f32) -> Result<i32, FilterError> {
+    let limit = (1_u32 << (31 - COEFF_FRAC_BITS)) as f32;
+    if !(x > -limit && x < limit) {
+        return Err(FilterError::OutOfRange);
+    }
+    Ok(libm::roundf(x * (1_u32 << COEFF_FRAC_BITS) as f32) as i32)
+}
+
+/// Direct form I history of one channel
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+struct State {
+    x1: i32,
+    x2: i32,
+    y1: i32,
+    y2: i32,
+}
+
+/// Fixed-point biquad filter for interleaved stereo samples
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Biquad {
+    coeffs: Coefficients,
+    state: [State; NUM_CHANNELS],
+}
+
+impl Biquad {
+    pub fn new(coeffs: Coefficients) -> Self {
+        Biquad {
+            coeffs,
+            state: [State::default(); NUM_CHANNELS],
+        }
+    }
+
+    pub fn coefficients(&self) -> &Coefficients {
+        &self.coeffs
+    }
+
+    /// Swap in new coefficients, the history is kept to avoid clicks
+    pub fn set_coefficients(&mut self, coeffs: Coefficients) {
+        self.coeffs = coeffs;
+    }
+
+    pub fn reset(&mut self) {
+        self.state = [State::default(); NUM_CHANNELS];
+    }
+
+    /// Filter one sample of the given channel. The input and output are
+    /// bounded to +-2^29 rather than saturated to 16 bits.
+    pub fn process_sample(&mut self, channel: usize, x: i32) -> i32 {
+        let c = &self.coeffs;
+        let s = &mut self.state[channel];
+        let x = x.max(-MAX_INTERMEDIATE).min(MAX_INTERMEDIATE);
+        let acc = i64::from(c.b0) * i64::from(x)
+            + i64::from(c.b1) * i64::from(s.x1)
+            + i64::from(c.b2) * i64::from(s.x2)
+            - i64::from(c.a1) * i64::from(s.y1)
+            - i64::from(c.a2) * i64::from(s.y2);
+        let y = (acc >> COEFF_FRAC_BITS)
+            .max(i64::from(-MAX_INTERMEDIATE))
+            .min(i64::from(MAX_INTERMEDIATE)) as i32;
+        s.x2 = s.x1;
+        s.x1 = x;
+        s.y2 = s.y1;
+        s.y1 = y;
+        y
+    }
+}
+
+impl Default for Biquad {
+    fn default() -> Self {
+        Biquad::new(Coefficients::IDENTITY)
+    }
+}
+
+impl AudioProcessor for Biquad {
+    fn process(&mut self, buf: &mut [i16]) {
+        for frame in buf.chunks_exact_mut(NUM_CHANNELS) {
+            for (channel, s) in frame.iter_mut().enumerate() {
+                *s = clamp_i16(self.process_sample(channel, i32::from(*s)));
+            }
+        }
+    }
+}
+
+/// Series of up to `MAX_STAGES` biquads
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct BiquadCascade {
+    stages: [Biquad; MAX_STAGES],
+    num_stages: usize,
+}
+
+impl BiquadCascade {
+    pub fn new() -> Self {
+        BiquadCascade {
+            stages: [Biquad::default(); MAX_STAGES],
+            num_stages: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.num_stages
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.num_stages == 0
+    }
+
+    pub fn push(&mut self, coeffs: Coefficients) -> Result<(), FilterError> {
+        if self.num_stages == MAX_STAGES {
+            return Err(FilterError::Full);
+        }
+        self.stages[self.num_stages] = Biquad::new(coeffs);
+        self.num_stages += 1;
+        Ok(())
+    }
+
+    pub fn set(&mut self, index: usize, coeffs: Coefficients) -> Result<(), FilterError> {
+        if index >= self.num_stages {
+            return Err(FilterError::InvalidIndex);
+        }
+        self.stages[index].set_coefficients(coeffs);
+        Ok(())
+    }
+
+    pub fn clear(&mut self) {
+        self.num_stages = 0;
+    }
+
+    /// Drop all stages past `len`
+    pub fn truncate(&mut self, len: usize) {
+        self.num_stages = self.num_stages.min(len);
+    }
+
+    pub fn reset(&mut self) {
+        self.stages.iter_mut().for_each(Biquad::reset);
+    }
+
+    pub fn stages(&self) -> &[Biquad] {
+        &self.stages[..self.num_stages]
+    }
+}
+
+impl Default for BiquadCascade {
+    fn default() -> Self {
+        BiquadCascade::new()
+    }
+}
+
+impl AudioProcessor for BiquadCascade {
+    fn process(&mut self, buf: &mut [i16]) {
+        let stages = &mut self.stages[..self.num_stages];
+        for frame in buf.chunks_exact_mut(NUM_CHANNELS) {
+            for (channel, s) in frame.iter_mut().enumerate() {
+                // Intermediate results are only saturated to 16 bits at
+                // the output, a boost can be followed by a cut
+                let y = stages
+                    .iter_mut()
+                    .fold(i32::from(*s), |x, stage| stage.process_sample(channel, x));
+                *s = clamp_i16(y);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FS: u32 = 48_000;
+
+    /// Magnitude of the response at `freq_hz`
+    fn gain(c: &Coefficients, freq_hz: f64) -> f64 {
+        let scale = f64::from(1_u32 << COEFF_FRAC_BITS);
+        let [b0, b1, b2, a1, a2] = [c.b0, c.b1, c.b2, c.a1, c.a2];
+        let [b0, b1, b2, a1, a2] = [
+            f64::from(b0) / scale,
+            f64::from(b1) / scale,
+            f64::from(b2) / scale,
+            f64::from(a1) / scale,
+            f64::from(a2) / scale,
+        ];
+        let w = 2.0 * core::f64::consts::PI * freq_hz / f64::from(FS);
+        // z^-1 and z^-2 on the unit circle
+        let (c1, s1) = (w.cos(), -w.sin());
+        let (c2, s2) = ((2.0 * w).cos(), -(2.0 * w).sin());
+        let num = (b0 + b1 * c1 + b2 * c2, b1 * s1 + b2 * s2);
+        let den = (1.0 + a1 * c1 + a2 * c2, a1 * s1 + a2 * s2);
+        ((num.0 * num.0 + num.1 * num.1) / (den.0 * den.0 + den.1 * den.1)).sqrt()
+    }
+
+    fn db(gain: f64) -> f64 {
+        20.0 * gain.log10()
+    }
+
+    fn design(filter: FilterType, freq_hz: f32, gain_db: f32) -> Coefficients {
+        Coefficients::design(filter, freq_hz, 0.707, gain_db, FS).unwrap()
+    }
+
+    fn assert_db(c: &Coefficients, freq_hz: f64, expected_db: f64) {
+        let actual = db(gain(c, freq_hz));
+        assert!(
+            (actual - expected_db).abs() < 0.05,
+            "{} Hz: {} dB, expected {} dB",
+            freq_hz,
+            actual,
+            expected_db
+        );
+    }
+
+    fn nyquist() -> f64 {
+        f64::from(FS) / 2.0
+    }
+
+    #[test]
+    fn pass_filters() {
+        let lp = design(FilterType::LowPass, 1000.0, 0.0);
+        assert_db(&lp, 0.0, 0.0);
+        assert_db(&lp, 1000.0, -3.01);
+        assert!(gain(&lp, nyquist()) < 1e-4);
+
+        let hp = design(FilterType::HighPass, 1000.0, 0.0);
+        assert!(gain(&hp, 0.0) < 1e-4);
+        assert_db(&hp, 1000.0, -3.01);
+        assert_db(&hp, nyquist(), 0.0);
+    }
+
+    #[test]
+    fn shelves() {
+        let low = design(FilterType::LowShelf, 200.0, 6.0);
+        assert_db(&low, 0.0, 6.0);
+        assert_db(&low, nyquist(), 0.0);
+
+        let high = design(FilterType::HighShelf, 5000.0, -6.0);
+        assert_db(&high, 0.0, 0.0);
+        assert_db(&high, nyquist(), -6.0);
+    }
+
+    #[test]
+    fn peaking() {
+        let c = design(FilterType::Peaking, 1000.0, 12.0);
+        assert_db(&c, 0.0, 0.0);
+        assert_db(&c, 1000.0, 12.0);
+        assert_db(&c, nyquist(), 0.0);
+    }
+
+    #[test]
+    fn design_errors() {
+        let design = |freq_hz, q, gain_db| {
+            Coefficients::design(FilterType::Peaking, freq_hz, q, gain_db, FS)
+        };
+        assert_eq!(design(0.0, 1.0, 0.0), Err(FilterError::InvalidFrequency));
+        assert_eq!(
+            design(24_000.0, 1.0, 0.0),
+            Err(FilterError::InvalidFrequency)
+        );
+        assert_eq!(design(1000.0, 0.0, 0.0), Err(FilterError::InvalidQ));
+        assert_eq!(
+            design(1000.0, core::f32::NAN, 0.0),
+            Err(FilterError::InvalidQ)
+        );
+        assert_eq!(design(10_000.0, 0.1, 60.0), Err(FilterError::OutOfRange));
+    }
+
+    #[test]
+    fn impulse_response() {
+        let c = design(FilterType::LowPass, 2000.0, 0.0);
+        let scale = f64::from(1_u32 << COEFF_FRAC_BITS);
+        let to_f64 = |c: i32| f64::from(c) / scale;
+        let b = [to_f64(c.b0), to_f64(c.b1), to_f64(c.b2)];
+        let (a1, a2) = (to_f64(c.a1), to_f64(c.a2));
+
+        // Each output from the coefficients and the outputs before it
+        let mut biquad = Biquad::new(c);
+        let (mut y1, mut y2) = (0, 0);
+        let mut sum = 0;
+        for n in 0..64 {
+            let x = if n == 0 { 1 << 14 } else { 0 };
+            let expected = b.get(n).map_or(0.0, |b| b * f64::from(1 << 14))
+                - a1 * f64::from(y1)
+                - a2 * f64::from(y2);
+            let y = biquad.process_sample(0, x);
+            assert!(
+                (f64::from(y) - expected).abs() <= 1.0,
+                "h[{}] = {}, expected {}",
+                n,
+                y,
+                expected
+            );
+            y2 = y1;
+            y1 = y;
+            sum += y;
+        }
+        // Unity DC gain, the response sums to the impulse less what the
+        // rounding down in the recursion loses
+        assert!((sum - (1 << 14)).abs() < (1 << 14) / 32, "{}", sum);
+    }
+
+    #[test]
+    fn channels_are_independent() {
+        let mut biquad = Biquad::new(design(FilterType::LowPass, 1000.0, 0.0));
+        let mut buf = [0; 8];
+        buf[0] = 10_000;
+        biquad.process(&mut buf);
+        assert!(buf[2] != 0);
+        assert!(buf.iter().skip(1).step_by(2).all(|s| *s == 0));
+    }
+
+    #[test]
+    fn cascade_headroom() {
+        let boost = Coefficients::design(FilterType::Peaking, 1000.0, 1.0, 24.0, FS).unwrap();
+        let cut = Coefficients::design(FilterType::Peaking, 1000.0, 1.0, -24.0, FS).unwrap();
+        let mut cascade = BiquadCascade::new();
+        cascade.push(boost).unwrap();
+        cascade.push(cut).unwrap();
+
+        // Boosted ~16x between the stages, way past 16 bits
+        let input = |n: usize| {
+            let w = 2.0 * core::f64::consts::PI * 1000.0 / f64::from(FS);
+            (20_000.0 * (w * n as f64).sin()) as i16
+        };
+        let mut buf = [0; 2 * 960];
+        for (n, frame) in buf.chunks_exact_mut(2).enumerate() {
+            frame[0] = input(n);
+            frame[1] = input(n);
+        }
+        cascade.process(&mut buf);
+        for (n, frame) in buf.chunks_exact(2).enumerate().skip(480) {
+            assert!(
+                (i32::from(frame[0]) - i32::from(input(n))).abs() < 200,
+                "{}: {} vs {}",
+                n,
+                frame[0],
+                input(n)
+            );
+        }
+    }
+
+    #[test]
+    fn cascade_stages() {
+        let mut cascade = BiquadCascade::new();
+        for _ in 0..MAX_STAGES {
+            cascade.push(Coefficients::IDENTITY).unwrap();
+        }
+        assert_eq!(cascade.push(Coefficients::IDENTITY), Err(FilterError::Full));
+        assert_eq!(
+            cascade.set(MAX_STAGES, Coefficients::IDENTITY),
+            Err(FilterError::InvalidIndex)
+        );
+        cascade.truncate(2);
+        assert_eq!(cascade.len(), 2);
+
+        let mut buf = [1234, -32768, 32767, 0];
+        cascade.process(&mut buf);
+        assert_eq!(buf, [1234, -32768, 32767, 0]);
+    }
+}
@@ -0,0 +1,93 @@
+// Analog output levels of the codec
+//
+// The WM8960 control registers can't be read back over I2C, so the driver
+// keeps the output state and reports it from here. Levels are in percent:
+// 1 to 100 map onto the output PGAs' -73 dB to +6 dB, 0 mutes the output.
+
+/// PGA value of `Level::MIN`, -73 dB, anything lower mutes the output
+const PGA_MIN: u8 = 0x30;
+/// PGA value of `Level::MAX`, +6 dB
+const PGA_MAX: u8 = 0x7F;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Output {
+    /// LOUT1/ROUT1
+    Headphone,
+    /// SPK_LP/SPK_RP, class D
+    Speaker,
+}
+
+/// Output level in percent
+#[derive(Debug, Copy, Clone, PartialEq, PartialOrd)]
+pub struct Level(u8);
+
+impl Level {
+    pub const MUTE: Level = Level(0);
+    pub const MIN: Level = Level(1);
+    pub const MAX: Level = Level(100);
+
+    /// `None` above 100 %
+    pub fn new(percent: u8) -> Option<Self> {
+        if percent <= Self::MAX.0 {
+            Some(Level(percent))
+        } else {
+            None
+        }
+    }
+
+    pub fn percent(self) -> u8 {
+        self.0
+    }
+
+    pub fn is_muted(self) -> bool {
+        self.0 == 0
+    }
+
+    /// Value of the LOUT1VOL/ROUT1VOL and SPKLVOL/SPKRVOL fields
+    pub fn register_value(self) -> u8 {
+        if self.is_muted() {
+            0
+        } else {
+            let steps = u16::from(PGA_MAX - PGA_MIN);
+            PGA_MIN + ((u16::from(self.0) - 1) * steps / 99) as u8
+        }
+    }
+
+    /// Gain in dB, `None` when muted
+    pub fn db(self) -> Option<i8> {
+        if self.is_muted() {
+            None
+        } else {
+            Some(self.register_value() as i8 - PGA_MAX as i8 + 6)
+        }
+    }
+}
+
+/// Output state kept by the driver
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Controls {
+    pub headphone: Level,
+    pub speaker: Level,
+    /// DAC soft mute, both outputs
+    pub muted: bool,
+}
+
+impl Controls {
+    pub fn level(self, output: Output) -> Level {
+        match output {
+            Output::Headphone => self.headphone,
+            Output::Speaker => self.speaker,
+        }
+    }
+}
+
+impl Default for Controls {
+    /// What `Wm8960::new` sets up, -10 dB headphones and +6 dB speakers
+    fn default() -> Self {
+        Controls {
+            headphone: Level(80),
+            speaker: Level::MAX,
+            muted: false,
+        }
+    }
+}
@@ -0,0 +1,297 @@
+use crate::audio::AudioSource;
+use crate::control::{Controls, Level, Output};
+use crate::hal::{i2c, i2s};
+use crate::register::*;
+
+const DEVICE_ADDRESS: u8 = 0x1A;
+
+#[derive(Debug)]
+pub enum Error {
+    I2c(i2c::Error),
+    I2s(i2s::Error),
+    InvalidInputData,
+}
+
+pub struct Wm8960<I2C, I2S> {
+    i2c: I2C,
+    i2s: I2S,
+    controls: Controls,
+}
+
+impl<I2C, I2S> Wm8960<I2C, I2S>
+where
+    I2C: embedded_hal::blocking::i2c::Write<Error = i2c::Error>,
+    I2S: i2s::Write<u16, Error = i2s::Error>,
+{
+    pub fn new(i2c: I2C, i2s: I2S) -> Result<Self, Error> {
+        let mut wm = Wm8960 {
+            i2c,
+            i2s,
+            controls: Controls::default(),
+        };
+
+        // Reset
+        wm.write_control_register(Register::Reset, 0)?;
+
+        // Set power source
+        let mut val = PwrMgmt1(0);
+        val.set_vref(true);
+        val.set_vmidsel(0b11);
+        wm.write_control_register(Register::PwrMgmt1, val.0)?;
+        let mut val = PwrMgmt2(0);
+        val.set_spkr(true);
+        val.set_spkl(true);
+        val.set_rout1(true);
+        val.set_lout1(true);
+        val.set_dacr(true);
+        val.set_dacl(true);
+        wm.write_control_register(Register::PwrMgmt2, val.0)?;
+        let mut val = PwrMgmt3(0);
+        val.set_romix(true);
+        val.set_lomix(true);
+        wm.write_control_register(Register::PwrMgmt3, val.0)?;
+
+        // Configure clock
+        // MCLK->div1->SYSCLK->DAC/ADC sample Freq
+        // = 25MHz(MCLK)/2*256 = 48.8kHz, audio::SAMPLE_RATE
+        let val = Clocking(0);
+        wm.write_control_register(Register::Clocking, val.0)?;
+
+        // Configure ADC/DAC
+        let val = Ctr1(0);
+        wm.write_control_register(Register::Ctr1, val.0)?;
+
+        // Configure audio interface
+        // I2S format 16 bits word length
+        let mut val = AudioIface(0);
+        val.set_format(0b10);
+        wm.write_control_register(Register::AudioIface, val.0)?;
+
+        // Configure HP_L and HP_R OUTPUTS
+        wm.set_level(Output::Headphone, wm.controls.headphone)?;
+
+        // Configure SPK_RP and SPK_RN
+        wm.set_level(Output::Speaker, wm.controls.speaker)?;
+
+        // Enable the OUTPUTS
+        let mut val = ClassdCtr1(0);
+        val.set_reserved(0b110111);
+        val.set_spkopen(0b11);
+        wm.write_control_register(Register::ClassdCtr1, val.0)?;
+
+        // Configure DAC volume
+        let mut val = LdacVol(0);
+        val.set_ldacvol(0xFF);
+        val.set_dacvu(true);
+        wm.write_control_register(Register::LdacVol, val.0)?;
+        let mut val = RdacVol(0);
+        val.set_rdacvol(0xFF);
+        val.set_dacvu(true);
+        wm.write_control_register(Register::RdacVol, val.0)?;
+
+        // 3D
+        // wm.write_reg(0x10, 0x001F);
+
+        // Configure MIXER
+        let mut val = LoutMix1(0);
+        val.set_li2lo(true);
+        val.set_ld2lo(true);
+        wm.write_control_register(Register::LoutMix1, val.0)?;
+        let mut val = RoutMix1(0);
+        val.set_ri2ro(true);
+        val.set_rd2ro(true);
+        wm.write_control_register(Register::RoutMix1, val.0)?;
+
+        // Jack Detect
+        let mut val = Addctr2(0);
+        val.set_hpswen(true);
+        wm.write_control_register(Register::Addctr2, val.0)?;
+        let mut val = Addctr1(0);
+        val.set_toen(true);
+        val.set_toclksel(true);
+        val.set_vsel(0b11);
+        val.set_tsden(true);
+        wm.write_control_register(Register::Addctr1, val.0)?;
+        let mut val = Addctr4(0);
+        val.set_mbsel(true);
+        val.set_hpsel(0b10);
+        wm.write_control_register(Register::Addctr4, val.0)?;
+
+        Ok(wm)
+    }
+
+    pub fn play_audio(&mut self, data: &[u16]) -> Result<(), Error> {
+        self.i2s.write(data)?;
+        Ok(())
+    }
+
+    /// Play interleaved signed samples
+    pub fn play_samples(&mut self, data: &[i16]) -> Result<(), Error> {
+        let mut buf = [0_u16; 64];
+        for chunk in data.chunks(buf.len()) {
+            for (dst, src) in buf.iter_mut().zip(chunk.iter()) {
+                *dst = *src as u16;
+            }
+            self.i2s.write(&buf[..chunk.len()])?;
+        }
+        Ok(())
+    }
+
+    /// Fill `buf` from the source once and play it.
+    ///
+    /// Returns the number of samples played, 0 once the source is exhausted.
+    pub fn play_source<S: AudioSource>(
+        &mut self,
+        source: &mut S,
+        buf: &mut [i16],
+    ) -> Result<usize, Error> {
+        let len = source.fill(buf);
+        self.play_samples(&buf[..len])?;
+        Ok(len)
+    }
+
+    /// Output levels and mute as last set
+    pub fn controls(&self) -> &Controls {
+        &self.controls
+    }
+
+    /// Set both channels of an output
+    pub fn set_level(&mut self, output: Output, level: Level) -> Result<(), Error> {
+        let vol = u16::from(level.register_value());
+        match output {
+            Output::Headphone => {
+                let mut val = Lout1Vol(0);
+                val.set_lout1vol(vol);
+                val.set_out1vu(true);
+                self.write_control_register(Register::Lout1Vol, val.0)?;
+                let mut val = Rout1Vol(0);
+                val.set_rout1vol(vol);
+                val.set_out1vu(true);
+                self.write_control_register(Register::Rout1Vol, val.0)?;
+                self.controls.headphone = level;
+            }
+            Output::Speaker => {
+                let mut val = Lout2Vol(0);
+                val.set_spklvol(vol);
+                val.set_spkvu(true);
+                self.write_control_register(Register::Lout2Vol, val.0)?;
+                let mut val = Rout2Vol(0);
+                val.set_spkrvol(vol);
+                val.set_spkvu(true);
+                self.write_control_register(Register::Rout2Vol, val.0)?;
+                self.controls.speaker = level;
+            }
+        }
+        Ok(())
+    }
+
+    /// Soft mute the DAC, both outputs ramp down
+    pub fn set_muted(&mut self, muted: bool) -> Result<(), Error> {
+        let mut val = Ctr1(0);
+        val.set_dacmu(muted);
+        self.write_control_register(Register::Ctr1, val.0)?;
+        self.controls.muted = muted;
+        Ok(())
+    }
+
+    /// Drive the debounced jack detect state onto GPIO1 (ADCLRC), high
+    /// while headphones are plugged in.
+    ///
+    /// The ADC then takes its frame clock from DACLRC as well.
+    pub fn enable_jack_detect_output(&mut self) -> Result<(), Error> {
+        let mut val = AudioIface2(0);
+        val.set_alrcgpio(true);
+        self.write_control_register(Register::AudioIface2, val.0)?;
+        // Keep the jack detect setup of new()
+        let mut val = Addctr4(0);
+        val.set_mbsel(true);
+        val.set_hpsel(0b10);
+        val.set_gpiosel(0b011);
+        self.write_control_register(Register::Addctr4, val.0)?;
+        Ok(())
+    }
+
+    /// Power up the left/right input PGAs and ADCs for capture.
+    ///
+    /// LINPUT1/RINPUT1 are routed through the input PGAs at 0 dB to the
+    /// boost mixers and ADCs, the microphone bias is enabled. The ADC takes
+    /// its frame clock from DACLRC, ADCDAT is read back by the MCU's I2S
+    /// full-duplex extension, see `examples/i2s_ext`.
+    pub fn enable_capture(&mut self) -> Result<(), Error> {
+        // Keep the playback path powered
+        let mut val = PwrMgmt1(0);
+        val.set_vref(true);
+        val.set_vmidsel(0b11);
+        val.set_ainl(true);
+        val.set_ainr(true);
+        val.set_adcl(true);
+        val.set_adcr(true);
+        val.set_micb(true);
+        self.write_control_register(Register::PwrMgmt1, val.0)?;
+        let mut val = PwrMgmt3(0);
+        val.set_romix(true);
+        val.set_lomix(true);
+        val.set_lmic(true);
+        val.set_rmic(true);
+        self.write_control_register(Register::PwrMgmt3, val.0)?;
+
+        // Input PGA, 0 dB
+        let mut val = LeftInputVol(0);
+        val.set_linvol(0x17);
+        val.set_ipvu(true);
+        self.write_control_register(Register::LeftInputVol, val.0)?;
+        let mut val = RightInputVol(0);
+        val.set_rinvol(0x17);
+        val.set_ipvu(true);
+        self.write_control_register(Register::RightInputVol, val.0)?;
+
+        // INPUT1 -> PGA -> boost mixer
+        let mut val = LadcSignalPath(0);
+        val.set_lmn1(true);
+        val.set_lmic2b(true);
+        self.write_control_register(Register::LadcSignalPath, val.0)?;
+        let mut val = RadcSignalPath(0);
+        val.set_rmn1(true);
+        val.set_rmic2b(true);
+        self.write_control_register(Register::RadcSignalPath, val.0)?;
+
+        // ADC volume, 0 dB
+        let mut val = LadcVol(0);
+        val.set_ladcvol(0xC3);
+        val.set_adcvu(true);
+        self.write_control_register(Register::LadcVol, val.0)?;
+        let mut val = RadcVol(0);
+        val.set_radcvol(0xC3);
+        val.set_adcvu(true);
+        self.write_control_register(Register::RadcVol, val.0)?;
+
+        // Only the DAC's frame clock is driven
+        let mut val = AudioIface2(0);
+        val.set_alrcgpio(true);
+        self.write_control_register(Register::AudioIface2, val.0)?;
+
+        Ok(())
+    }
+
+    /// Write a 9-bit control register
+    fn write_control_register(&mut self, reg: Register, data: u16) -> Result<(), Error> {
+        let bytes = [
+            (reg.addr() << 1) | (data >> 8) as u8 & 0x1,
+            (data & 0xFF) as u8,
+        ];
+        self.i2c.write(DEVICE_ADDRESS, &bytes)?;
+        Ok(())
+    }
+}
+
+impl From<i2c::Error> for Error {
+    fn from(e: i2c::Error) -> Self {
+        Error::I2c(e)
+    }
+}
+
+impl From<i2s::Error> for Error {
+    fn from(e: i2s::Error) -> Self {
+        Error::I2s(e)
+    }
+}
@@ -0,0 +1,622 @@
+// https://en.wikipedia.org/wiki/Dual-tone_multi-frequency_signaling
+
+use crate::audio::NUM_CHANNELS;
+
+/// Low group (row) frequencies in Hz
+pub const ROW_FREQUENCIES: [u16; 4] = [697, 770, 852, 941];
+
+/// High group (column) frequencies in Hz
+pub const COLUMN_FREQUENCIES: [u16; 4] = [1209, 1336, 1477, 1633];
+
+/// Keypad layout, indexed by [row][column]
+const KEYPAD: [[DtmfDigit; 4]; 4] = [
+    [DtmfDigit::D1, DtmfDigit::D2, DtmfDigit::D3, DtmfDigit::A],
+    [DtmfDigit::D4, DtmfDigit::D5, DtmfDigit::D6, DtmfDigit::B],
+    [DtmfDigit::D7, DtmfDigit::D8, DtmfDigit::D9, DtmfDigit::C],
+    [
+        DtmfDigit::Star,
+        DtmfDigit::D0,
+        DtmfDigit::Pound,
+        DtmfDigit::D,
+    ],
+];
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum DtmfDigit {
+    D0,
+    D1,
+    D2,
+    D3,
+    D4,
+    D5,
+    D6,
+    D7,
+    D8,
+    D9,
+    /// '*'
+    Star,
+    /// '#'
+    Pound,
+    A,
+    B,
+    C,
+    D,
+}
+
+impl DtmfDigit {
+    pub fn from_char(c: char) -> Option<Self> {
+        let digit = match c {
+            '0' => DtmfDigit::D0,
+            '1' => DtmfDigit::D1,
+            '2' => DtmfDigit::D2,
+            '3' => DtmfDigit::D3,
+            '4' => DtmfDigit::D4,
+            '5' => DtmfDigit::D5,
+            '6' => DtmfDigit::D6,
+            '7' => DtmfDigit::D7,
+            '8' => DtmfDigit::D8,
+            '9' => DtmfDigit::D9,
+            '*' => DtmfDigit::Star,
+            '#' => DtmfDigit::Pound,
+            'A' | 'a' => DtmfDigit::A,
+            'B' | 'b' => DtmfDigit::B,
+            'C' | 'c' => DtmfDigit::C,
+            'D' | 'd' => DtmfDigit::D,
+            _ => return None,
+        };
+        Some(digit)
+    }
+
+    pub fn as_char(self) -> char {
+        match self {
+            DtmfDigit::D0 => '0',
+            DtmfDigit::D1 => '1',
+            DtmfDigit::D2 => '2',
+            DtmfDigit::D3 => '3',
+            DtmfDigit::D4 => '4',
+            DtmfDigit::D5 => '5',
+            DtmfDigit::D6 => '6',
+            DtmfDigit::D7 => '7',
+            DtmfDigit::D8 => '8',
+            DtmfDigit::D9 => '9',
+            DtmfDigit::Star => '*',
+            DtmfDigit::Pound => '#',
+            DtmfDigit::A => 'A',
+            DtmfDigit::B => 'B',
+            DtmfDigit::C => 'C',
+            DtmfDigit::D => 'D',
+        }
+    }
+
+    /// Digit at the given keypad row and column
+    pub fn from_position(row: usize, column: usize) -> Option<Self> {
+        KEYPAD.get(row).and_then(|r| r.get(column)).copied()
+    }
+
+    /// Keypad (row, column) of the digit
+    pub fn position(self) -> (usize, usize) {
+        for (row, digits) in KEYPAD.iter().enumerate() {
+            for (column, d) in digits.iter().enumerate() {
+                if *d == self {
+                    return (row, column);
+                }
+            }
+        }
+        unreachable!()
+    }
+
+    /// (low, high) tone pair in Hz
+    pub fn frequencies(self) -> (u16, u16) {
+        let (row, column) = self.position();
+        (ROW_FREQUENCIES[row], COLUMN_FREQUENCIES[column])
+    }
+}
+
+/// Window length at 8 kHz, gives ~39 Hz bins, scaled with the sample rate
+const BLOCK_LEN_8K: u32 = 205;
+
+/// Full scale sine mean square, the 0 dBFS reference
+const FULL_SCALE_MS: f32 = 0.5;
+
+/// Detection thresholds, see ITU-T Q.24
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct DtmfConfig {
+    /// Minimum level of each tone in dBFS
+    pub min_level_dbfs: f32,
+    /// Maximum amount the low group may exceed the high group, in dB
+    pub max_normal_twist_db: f32,
+    /// Maximum amount the high group may exceed the low group, in dB
+    pub max_reverse_twist_db: f32,
+    /// Minimum ratio of tone power to the remaining block power, in dB
+    pub min_snr_db: f32,
+    /// Minimum margin of each tone over the other tones in its group, in dB
+    pub min_relative_peak_db: f32,
+    /// Consecutive windows a digit must be present before it is reported
+    pub press_blocks: u8,
+    /// Consecutive windows a digit must be absent before it is released
+    pub release_blocks: u8,
+}
+
+impl Default for DtmfConfig {
+    fn default() -> Self {
+        DtmfConfig {
+            min_level_dbfs: -36.0,
+            max_normal_twist_db: 8.0,
+            max_reverse_twist_db: 4.0,
+            min_snr_db: 3.0,
+            min_relative_peak_db: 6.0,
+            press_blocks: 2,
+            release_blocks: 2,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum DtmfEvent {
+    Pressed(DtmfDigit),
+    Released(DtmfDigit),
+}
+
+/// Single frequency Goertzel filter
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Goertzel {
+    coeff: f32,
+    s1: f32,
+    s2: f32,
+}
+
+impl Goertzel {
+    pub fn new(freq_hz: f32, sample_rate: u32) -> Self {
+        let w = 2.0 * core::f32::consts::PI * freq_hz / sample_rate as f32;
+        Goertzel {
+            coeff: 2.0 * libm::cosf(w),
+            s1: 0.0,
+            s2: 0.0,
+        }
+    }
+
+    pub fn process(&mut self, x: f32) {
+        let s0 = x + self.coeff * self.s1 - self.s2;
+        self.s2 = self.s1;
+        self.s1 = s0;
+    }
+
+    /// Squared magnitude of the frequency component over the block so far
+    pub fn power(&self) -> f32 {
+        self.s1 * self.s1 + self.s2 * self.s2 - self.coeff * self.s1 * self.s2
+    }
+
+    pub fn reset(&mut self) {
+        self.s1 = 0.0;
+        self.s2 = 0.0;
+    }
+}
+
+/// Thresholds from `DtmfConfig` as linear power ratios
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct Thresholds {
+    min_level: f32,
+    normal_twist: f32,
+    reverse_twist: f32,
+    snr: f32,
+    relative_peak: f32,
+}
+
+impl From<&DtmfConfig> for Thresholds {
+    fn from(c: &DtmfConfig) -> Self {
+        Thresholds {
+            min_level: FULL_SCALE_MS * db_to_power_ratio(c.min_level_dbfs),
+            normal_twist: db_to_power_ratio(c.max_normal_twist_db),
+            reverse_twist: db_to_power_ratio(c.max_reverse_twist_db),
+            snr: db_to_power_ratio(c.min_snr_db),
+            relative_peak: db_to_power_ratio(c.min_relative_peak_db),
+        }
+    }
+}
+
+fn db_to_power_ratio(db: f32) -> f32 {
+    libm::powf(10.0, db / 10.0)
+}
+
+/// One analysis window of the detector
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct Block {
+    rows: [Goertzel; 4],
+    columns: [Goertzel; 4],
+    count: u32,
+    sum: f32,
+    sum_squares: f32,
+}
+
+impl Block {
+    fn new(sample_rate: u32) -> Self {
+        let filter = |f: u16| Goertzel::new(f32::from(f), sample_rate);
+        Block {
+            rows: [
+                filter(ROW_FREQUENCIES[0]),
+                filter(ROW_FREQUENCIES[1]),
+                filter(ROW_FREQUENCIES[2]),
+                filter(ROW_FREQUENCIES[3]),
+            ],
+            columns: [
+                filter(COLUMN_FREQUENCIES[0]),
+                filter(COLUMN_FREQUENCIES[1]),
+                filter(COLUMN_FREQUENCIES[2]),
+                filter(COLUMN_FREQUENCIES[3]),
+            ],
+            count: 0,
+            sum: 0.0,
+            sum_squares: 0.0,
+        }
+    }
+
+    fn process(&mut self, x: f32) {
+        for g in self.rows.iter_mut().chain(self.columns.iter_mut()) {
+            g.process(x);
+        }
+        self.sum += x;
+        self.sum_squares += x * x;
+        self.count += 1;
+    }
+
+    fn reset(&mut self) {
+        for g in self.rows.iter_mut().chain(self.columns.iter_mut()) {
+            g.reset();
+        }
+        self.count = 0;
+        self.sum = 0.0;
+        self.sum_squares = 0.0;
+    }
+
+    fn classify(&self, t: &Thresholds) -> Option<DtmfDigit> {
+        let n = self.count as f32;
+        // Goertzel power to the mean square of the tone
+        let scale = 2.0 / (n * n);
+        let (row, row_ms) = strongest(&self.rows, scale);
+        let (column, column_ms) = strongest(&self.columns, scale);
+
+        if row_ms < t.min_level || column_ms < t.min_level {
+            return None;
+        }
+
+        if row_ms > column_ms * t.normal_twist || column_ms > row_ms * t.reverse_twist {
+            return None;
+        }
+
+        let peak_ok = |filters: &[Goertzel; 4], peak: usize, peak_ms: f32| {
+            filters
+                .iter()
+                .enumerate()
+                .filter(|(i, _)| *i != peak)
+                .all(|(_, g)| g.power() * scale * t.relative_peak <= peak_ms)
+        };
+        if !peak_ok(&self.rows, row, row_ms) || !peak_ok(&self.columns, column, column_ms) {
+            return None;
+        }
+
+        // AC power of the block, DC offset removed
+        let mean = self.sum / n;
+        let total_ms = self.sum_squares / n - mean * mean;
+        let tone_ms = row_ms + column_ms;
+        let noise_ms = (total_ms - tone_ms).max(core::f32::MIN_POSITIVE);
+        if tone_ms < noise_ms * t.snr {
+            return None;
+        }
+
+        DtmfDigit::from_position(row, column)
+    }
+}
+
+/// Goertzel based DTMF detector for captured audio.
+///
+/// Samples are analysed in ~25 ms windows overlapping by half, each window
+/// is checked for level, twist, SNR and relative peak before the digit is
+/// debounced into press and release events.
+pub struct DtmfDetector {
+    config: DtmfConfig,
+    thresholds: Thresholds,
+    block_len: u32,
+    /// Two windows offset by half a block
+    blocks: [Block; 2],
+    /// The second window starts once half a block has been seen
+    warming_up: bool,
+    candidate: Option<DtmfDigit>,
+    candidate_blocks: u8,
+    current: Option<DtmfDigit>,
+    absent_blocks: u8,
+}
+
+impl DtmfDetector {
+    pub fn new(sample_rate: u32) -> Self {
+        DtmfDetector::with_config(sample_rate, DtmfConfig::default())
+    }
+
+    pub fn with_config(sample_rate: u32, config: DtmfConfig) -> Self {
+        DtmfDetector {
+            config,
+            thresholds: Thresholds::from(&config),
+            block_len: (BLOCK_LEN_8K * sample_rate / 8000).max(2),
+            blocks: [Block::new(sample_rate); 2],
+            warming_up: true,
+            candidate: None,
+            candidate_blocks: 0,
+            current: None,
+            absent_blocks: 0,
+        }
+    }
+
+    pub fn config(&self) -> &DtmfConfig {
+        &self.config
+    }
+
+    /// Samples per analysis window, a window completes every half block
+    pub fn block_len(&self) -> u32 {
+        self.block_len
+    }
+
+    /// The digit currently held down, if any
+    pub fn current(&self) -> Option<DtmfDigit> {
+        self.current
+    }
+
+    pub fn reset(&mut self) {
+        self.blocks.iter_mut().for_each(Block::reset);
+        self.warming_up = true;
+        self.candidate = None;
+        self.candidate_blocks = 0;
+        self.current = None;
+        self.absent_blocks = 0;
+    }
+
+    /// Process mono samples
+    pub fn process<F: FnMut(DtmfEvent)>(&mut self, samples: &[i16], mut f: F) {
+        for s in samples {
+            if let Some(event) = self.process_sample(*s) {
+                f(event);
+            }
+        }
+    }
+
+    /// Process interleaved stereo frames, the channels are mixed to mono
+    pub fn process_frames<F: FnMut(DtmfEvent)>(&mut self, frames: &[i16], mut f: F) {
+        for frame in frames.chunks_exact(NUM_CHANNELS) {
+            let sum: i32 = frame.iter().map(|s| i32::from(*s)).sum();
+            let mono = (sum / NUM_CHANNELS as i32) as i16;
+            if let Some(event) = self.process_sample(mono) {
+                f(event);
+            }
+        }
+    }
+
+    pub fn process_sample(&mut self, sample: i16) -> Option<DtmfEvent> {
+        let x = f32::from(sample) / 32768.0;
+
+        self.blocks[0].process(x);
+        if self.warming_up {
+            self.warming_up = self.blocks[0].count < self.block_len / 2;
+        } else {
+            self.blocks[1].process(x);
+        }
+
+        // At most one window completes per sample
+        let block_len = self.block_len;
+        let block = self.blocks.iter_mut().find(|b| b.count >= block_len)?;
+        let digit = block.classify(&self.thresholds);
+        block.reset();
+        self.debounce(digit)
+    }
+
+    fn debounce(&mut self, digit: Option<DtmfDigit>) -> Option<DtmfEvent> {
+        if digit.is_some() && digit == self.candidate {
+            self.candidate_blocks = self.candidate_blocks.saturating_add(1);
+        } else {
+            self.candidate = digit;
+            self.candidate_blocks = 1;
+        }
+
+        match self.current {
+            Some(current) => {
+                if digit == Some(current) {
+                    self.absent_blocks = 0;
+                    None
+                } else {
+                    self.absent_blocks = self.absent_blocks.saturating_add(1);
+                    if self.absent_blocks >= self.config.release_blocks {
+                        self.current = None;
+                        self.absent_blocks = 0;
+                        Some(DtmfEvent::Released(current))
+                    } else {
+                        None
+                    }
+                }
+            }
+            None => match self.candidate {
+                Some(candidate) if self.candidate_blocks >= self.config.press_blocks => {
+                    self.current = Some(candidate);
+                    self.absent_blocks = 0;
+                    Some(DtmfEvent::Pressed(candidate))
+                }
+                _ => None,
+            },
+        }
+    }
+}
+
+/// Index and mean square of the strongest filter
+fn strongest(filters: &[Goertzel; 4], scale: f32) -> (usize, f32) {
+    let mut index = 0;
+    let mut ms = 0.0;
+    for (i, g) in filters.iter().enumerate() {
+        let p = g.power() * scale;
+        if p > ms {
+            index = i;
+            ms = p;
+        }
+    }
+    (index, ms)
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::audio::{clamp_i16, AudioSource};
+    use crate::tone::{DtmfTiming, Oscillator, ToneGenerator, ToneSpec, DEFAULT_LEVEL};
+    use std::vec::Vec;
+
+    const SAMPLE_RATE: u32 = 8000;
+
+    const DIGITS: &str = "0123456789*#ABCD";
+
+    /// Digit plus the gap and the release, in frames
+    const DIGIT_FRAMES: u32 = SAMPLE_RATE * 2 / 5;
+
+    /// Roughly -30 dBFS of white noise
+    const NOISE: i32 = 1500;
+
+    /// Xorshift, uniform noise
+    struct Noise(u32);
+
+    impl Noise {
+        fn next(&mut self, amplitude: i32) -> i32 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 17;
+            self.0 ^= self.0 << 5;
+            (self.0 % (2 * amplitude as u32 + 1)) as i32 - amplitude
+        }
+    }
+
+    /// Two tones at their own levels, for twist
+    struct TonePair {
+        osc: [Oscillator; 2],
+        levels: [i16; 2],
+    }
+
+    impl TonePair {
+        fn new(digit: DtmfDigit, low_level: i16, high_level: i16) -> Self {
+            let (low, high) = digit.frequencies();
+            TonePair {
+                osc: [
+                    Oscillator::new(low, SAMPLE_RATE).unwrap(),
+                    Oscillator::new(high, SAMPLE_RATE).unwrap(),
+                ],
+                levels: [low_level, high_level],
+            }
+        }
+    }
+
+    impl AudioSource for TonePair {
+        fn fill(&mut self, buf: &mut [i16]) -> usize {
+            for frame in buf.chunks_exact_mut(NUM_CHANNELS) {
+                let mut sample = 0;
+                for (osc, level) in self.osc.iter_mut().zip(self.levels.iter()) {
+                    sample += (i32::from(osc.next_sample()) * i32::from(*level)) >> 15;
+                }
+                for s in frame.iter_mut() {
+                    *s = sample as i16;
+                }
+            }
+            buf.len()
+        }
+    }
+
+    /// Detect over `frames` of `source`, silence once it runs out
+    fn detect<S: AudioSource>(mut source: S, frames: u32, noise: i32) -> Vec<DtmfEvent> {
+        let mut detector = DtmfDetector::new(SAMPLE_RATE);
+        let mut noise_source = Noise(0x1234_5678);
+        let mut events = Vec::new();
+        let mut buf = [0; 80 * NUM_CHANNELS];
+        for _ in 0..frames / 80 {
+            let len = source.fill(&mut buf);
+            for s in buf[len..].iter_mut() {
+                *s = 0;
+            }
+            for s in buf.iter_mut() {
+                *s = clamp_i16(i32::from(*s) + noise_source.next(noise));
+            }
+            detector.process_frames(&buf, |event| events.push(event));
+        }
+        events
+    }
+
+    fn detect_digits(noise: i32) {
+        for c in DIGITS.chars() {
+            let digit = DtmfDigit::from_char(c).unwrap();
+            let mut tones = ToneGenerator::new(SAMPLE_RATE);
+            tones.play_digit(digit).unwrap();
+            assert_eq!(
+                detect(tones, DIGIT_FRAMES, noise),
+                [DtmfEvent::Pressed(digit), DtmfEvent::Released(digit)],
+                "{}",
+                c
+            );
+        }
+    }
+
+    #[test]
+    fn digits() {
+        detect_digits(0);
+    }
+
+    #[test]
+    fn noisy_digits() {
+        detect_digits(NOISE);
+    }
+
+    #[test]
+    fn digit_sequence() {
+        let mut tones = ToneGenerator::new(SAMPLE_RATE);
+        tones.play_digits(DIGITS).unwrap();
+        let events = detect(tones, DIGIT_FRAMES * DIGITS.len() as u32, NOISE);
+        let pressed: Vec<char> = events
+            .iter()
+            .filter_map(|event| match event {
+                DtmfEvent::Pressed(digit) => Some(digit.as_char()),
+                DtmfEvent::Released(_) => None,
+            })
+            .collect();
+        assert_eq!(pressed, DIGITS.chars().collect::<Vec<char>>());
+        assert_eq!(events.len(), 2 * DIGITS.len());
+    }
+
+    #[test]
+    fn off_frequency() {
+        // Halfway between rows 1 and 2 and columns 1 and 2
+        let mut tones = ToneGenerator::new(SAMPLE_RATE);
+        tones.play_tone(ToneSpec::continuous(733, 1272)).unwrap();
+        assert_eq!(detect(tones, DIGIT_FRAMES, 0), []);
+
+        // Dial tone
+        let mut tones = ToneGenerator::new(SAMPLE_RATE);
+        tones.play_tone(ToneSpec::continuous(350, 440)).unwrap();
+        assert_eq!(detect(tones, DIGIT_FRAMES, 0), []);
+    }
+
+    #[test]
+    fn twist() {
+        let digit = DtmfDigit::D5;
+        let level = DEFAULT_LEVEL;
+        // 12 dB, past either limit
+        let low = level / 4;
+
+        let pair = TonePair::new(digit, level, level);
+        assert_eq!(detect(pair, DIGIT_FRAMES, 0), [DtmfEvent::Pressed(digit)]);
+        let normal = TonePair::new(digit, level, low);
+        assert_eq!(detect(normal, DIGIT_FRAMES, 0), []);
+        let reverse = TonePair::new(digit, low, level);
+        assert_eq!(detect(reverse, DIGIT_FRAMES, 0), []);
+    }
+
+    #[test]
+    fn short_press() {
+        // Shy of the windows press_blocks needs
+        let mut tones = ToneGenerator::new(SAMPLE_RATE);
+        tones.set_dtmf_timing(DtmfTiming {
+            on_ms: 20,
+            off_ms: 100,
+        });
+        tones.play_digit(DtmfDigit::D5).unwrap();
+        assert_eq!(detect(tones, DIGIT_FRAMES, 0), []);
+    }
+}
@@ -0,0 +1,297 @@
+use crate::audio::{clamp_i16, AudioProcessor};
+use crate::biquad::{BiquadCascade, Coefficients, FilterError, FilterType, MAX_STAGES};
+
+/// Maximum number of parametric EQ bands
+pub const MAX_BANDS: usize = MAX_STAGES;
+
+/// Unity gain of `Volume`, Q16
+const UNITY_GAIN: i32 = 1 << 16;
+
+/// Digital volume stage, gain is Q16 from muted at `MIN_DB` up to +24 dB
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Volume {
+    gain: i32,
+    gain_db: f32,
+    muted: bool,
+}
+
+impl Volume {
+    pub const MIN_DB: f32 = -96.0;
+    pub const MAX_DB: f32 = 24.0;
+
+    pub fn new() -> Self {
+        Volume {
+            gain: UNITY_GAIN,
+            gain_db: 0.0,
+            muted: false,
+        }
+    }
+
+    /// Set the gain in dB, clamped to `MIN_DB..=MAX_DB`
+    pub fn set_db(&mut self, db: f32) {
+        let db = db.max(Volume::MIN_DB).min(Volume::MAX_DB);
+        self.gain_db = db;
+        self.gain = if db <= Volume::MIN_DB {
+            0
+        } else {
+            libm::roundf(libm::powf(10.0, db / 20.0) * UNITY_GAIN as f32) as i32
+        };
+    }
+
+    pub fn db(&self) -> f32 {
+        self.gain_db
+    }
+
+    pub fn set_mute(&mut self, muted: bool) {
+        self.muted = muted;
+    }
+
+    pub fn is_muted(&self) -> bool {
+        self.muted
+    }
+}
+
+impl Default for Volume {
+    fn default() -> Self {
+        Volume::new()
+    }
+}
+
+impl AudioProcessor for Volume {
+    fn process(&mut self, buf: &mut [i16]) {
+        if self.muted {
+            buf.iter_mut().for_each(|s| *s = 0);
+        } else if self.gain != UNITY_GAIN {
+            for s in buf.iter_mut() {
+                *s = clamp_i16(((i64::from(*s) * i64::from(self.gain)) >> 16) as i32);
+            }
+        }
+    }
+}
+
+/// One parametric EQ band
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct EqBand {
+    pub filter: FilterType,
+    pub freq_hz: f32,
+    pub q: f32,
+    /// Ignored by the low/high pass filter types
+    pub gain_db: f32,
+    pub enabled: bool,
+}
+
+impl EqBand {
+    pub fn new(filter: FilterType, freq_hz: f32, q: f32, gain_db: f32) -> Self {
+        EqBand {
+            filter,
+            freq_hz,
+            q,
+            gain_db,
+            enabled: true,
+        }
+    }
+
+    pub fn coefficients(&self, sample_rate: u32) -> Result<Coefficients, FilterError> {
+        if self.enabled {
+            Coefficients::design(self.filter, self.freq_hz, self.q, self.gain_db, sample_rate)
+        } else {
+            Ok(Coefficients::IDENTITY)
+        }
+    }
+}
+
+/// Parametric equalizer followed by a digital volume stage.
+///
+/// Sits between an `AudioSource` and `Wm8960::play_source`, see
+/// `audio::Processed`. Coefficients are recalculated whenever a band or the
+/// sample rate changes.
+pub struct ParametricEq {
+    sample_rate: u32,
+    bands: [Option<EqBand>; MAX_BANDS],
+    cascade: BiquadCascade,
+    volume: Volume,
+}
+
+impl ParametricEq {
+    pub fn new(sample_rate: u32) -> Self {
+        ParametricEq {
+            sample_rate,
+            bands: [None; MAX_BANDS],
+            cascade: BiquadCascade::new(),
+            volume: Volume::new(),
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Change the active sample rate, all bands are redesigned
+    pub fn set_sample_rate(&mut self, sample_rate: u32) -> Result<(), FilterError> {
+        let old = self.sample_rate;
+        self.sample_rate = sample_rate;
+        self.rebuild().map_err(|e| {
+            self.sample_rate = old;
+            e
+        })
+    }
+
+    pub fn band(&self, index: usize) -> Option<&EqBand> {
+        self.bands.get(index).and_then(|b| b.as_ref())
+    }
+
+    /// Set or replace a band, the previous settings are kept on error
+    pub fn set_band(&mut self, index: usize, band: EqBand) -> Result<(), FilterError> {
+        if index >= MAX_BANDS {
+            return Err(FilterError::InvalidIndex);
+        }
+        band.coefficients(self.sample_rate)?;
+        self.bands[index] = Some(band);
+        self.rebuild()
+    }
+
+    pub fn remove_band(&mut self, index: usize) -> Result<(), FilterError> {
+        if index >= MAX_BANDS {
+            return Err(FilterError::InvalidIndex);
+        }
+        self.bands[index] = None;
+        self.rebuild()
+    }
+
+    pub fn volume(&self) -> &Volume {
+        &self.volume
+    }
+
+    pub fn volume_mut(&mut self) -> &mut Volume {
+        &mut self.volume
+    }
+
+    pub fn reset(&mut self) {
+        self.cascade.reset();
+    }
+
+    /// Redesign all bands, nothing changes on error
+    fn rebuild(&mut self) -> Result<(), FilterError> {
+        let mut coeffs = [Coefficients::IDENTITY; MAX_BANDS];
+        let mut len = 0;
+        for band in self.bands.iter().filter_map(|b| b.as_ref()) {
+            if band.enabled {
+                coeffs[len] = band.coefficients(self.sample_rate)?;
+                len += 1;
+            }
+        }
+
+        // Stages that carry over keep their filter history
+        self.cascade.truncate(len);
+        for (i, c) in coeffs[..len].iter().enumerate() {
+            if i < self.cascade.len() {
+                self.cascade.set(i, *c)?;
+            } else {
+                self.cascade.push(*c)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl AudioProcessor for ParametricEq {
+    fn process(&mut self, buf: &mut [i16]) {
+        self.cascade.process(buf);
+        self.volume.process(buf);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FS: u32 = 48_000;
+
+    #[test]
+    fn volume() {
+        let mut volume = Volume::new();
+        let mut buf = [16_384, -16_384];
+        volume.process(&mut buf);
+        assert_eq!(buf, [16_384, -16_384]);
+
+        volume.set_db(-6.0);
+        volume.process(&mut buf);
+        assert_eq!(buf, [8211, -8212]);
+
+        volume.set_db(40.0);
+        assert!(volume.db() >= Volume::MAX_DB);
+        let mut buf = [1000, 3000];
+        volume.process(&mut buf);
+        assert_eq!(buf, [15_848, core::i16::MAX]);
+
+        volume.set_db(-200.0);
+        assert!(volume.db() <= Volume::MIN_DB);
+        let mut buf = [core::i16::MAX, core::i16::MIN];
+        volume.process(&mut buf);
+        assert_eq!(buf, [0, 0]);
+    }
+
+    #[test]
+    fn mute() {
+        let mut volume = Volume::new();
+        volume.set_mute(true);
+        let mut buf = [1000, -1000];
+        volume.process(&mut buf);
+        assert_eq!(buf, [0, 0]);
+        volume.set_mute(false);
+        let mut buf = [1000, -1000];
+        volume.process(&mut buf);
+        assert_eq!(buf, [1000, -1000]);
+    }
+
+    #[test]
+    fn bands() {
+        let mut eq = ParametricEq::new(FS);
+        let low = EqBand::new(FilterType::LowShelf, 100.0, 0.707, 6.0);
+        eq.set_band(0, low).unwrap();
+        eq.set_band(2, EqBand::new(FilterType::Peaking, 1000.0, 1.0, -3.0))
+            .unwrap();
+        assert_eq!(eq.cascade.len(), 2);
+
+        // Invalid bands leave the previous ones
+        let bad = EqBand::new(FilterType::Peaking, 30_000.0, 1.0, 3.0);
+        assert_eq!(eq.set_band(0, bad), Err(FilterError::InvalidFrequency));
+        assert_eq!(eq.band(0), Some(&low));
+        assert_eq!(eq.set_band(MAX_BANDS, low), Err(FilterError::InvalidIndex));
+
+        let mut disabled = low;
+        disabled.enabled = false;
+        eq.set_band(0, disabled).unwrap();
+        assert_eq!(eq.cascade.len(), 1);
+        eq.remove_band(2).unwrap();
+        assert!(eq.cascade.is_empty());
+    }
+
+    #[test]
+    fn sample_rate() {
+        let mut eq = ParametricEq::new(FS);
+        eq.set_band(0, EqBand::new(FilterType::HighShelf, 12_000.0, 0.707, 3.0))
+            .unwrap();
+        // 12 kHz is Nyquist at 24 kHz
+        assert_eq!(
+            eq.set_sample_rate(24_000),
+            Err(FilterError::InvalidFrequency)
+        );
+        assert_eq!(eq.sample_rate(), FS);
+        eq.set_sample_rate(44_100).unwrap();
+        assert_eq!(eq.sample_rate(), 44_100);
+    }
+
+    #[test]
+    fn dc_gain() {
+        // +6 dB low shelf, then -6 dB of volume
+        let mut eq = ParametricEq::new(FS);
+        eq.set_band(0, EqBand::new(FilterType::LowShelf, 200.0, 0.707, 6.0))
+            .unwrap();
+        eq.volume_mut().set_db(-6.0);
+        let mut buf = [8000; 2 * 4800];
+        eq.process(&mut buf);
+        let settled = &buf[buf.len() - 2..];
+        assert!(settled.iter().all(|s| (i32::from(*s) - 8000).abs() < 40));
+    }
+}
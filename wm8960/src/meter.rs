@@ -0,0 +1,176 @@
+use crate::audio::{AudioProcessor, NUM_CHANNELS};
+
+/// Floor of the dBFS readings, returned for silence
+pub const MIN_DBFS: f32 = -120.0;
+
+/// Full scale sample magnitude
+const FULL_SCALE: f32 = 32768.0;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct MeterConfig {
+    /// Fall rate of the peak reading after the peak hold time
+    pub peak_decay_db_per_sec: f32,
+    /// How long a peak is held before it decays
+    pub peak_hold_ms: u16,
+    /// Time constant of the RMS average
+    pub rms_window_ms: u16,
+    /// Samples at or above this magnitude count as clipped
+    pub clip_level: i16,
+}
+
+impl Default for MeterConfig {
+    fn default() -> Self {
+        MeterConfig {
+            peak_decay_db_per_sec: 20.0,
+            peak_hold_ms: 500,
+            rms_window_ms: 300,
+            clip_level: core::i16::MAX,
+        }
+    }
+}
+
+/// Readings of one channel
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct ChannelLevel {
+    /// Decaying peak magnitude, full scale is 1.0
+    pub peak: f32,
+    /// Mean square, full scale square wave is 1.0
+    pub mean_square: f32,
+    /// Clipped samples since the last `take_clips`
+    pub clips: u32,
+    /// Frames left before the peak starts to decay
+    hold_frames: u32,
+}
+
+impl ChannelLevel {
+    pub fn peak_dbfs(&self) -> f32 {
+        amplitude_to_dbfs(self.peak)
+    }
+
+    pub fn rms(&self) -> f32 {
+        libm::sqrtf(self.mean_square)
+    }
+
+    pub fn rms_dbfs(&self) -> f32 {
+        amplitude_to_dbfs(self.rms())
+    }
+}
+
+/// Peak, RMS and clip metering of interleaved stereo samples.
+///
+/// Usable as a pass-through `AudioProcessor` on the playback path or fed
+/// captured audio with `measure`.
+pub struct LevelMeter {
+    sample_rate: u32,
+    config: MeterConfig,
+    /// Per frame peak decay factor
+    peak_decay: f32,
+    /// Per frame RMS smoothing factor
+    rms_alpha: f32,
+    hold_frames: u32,
+    channels: [ChannelLevel; NUM_CHANNELS],
+}
+
+impl LevelMeter {
+    pub fn new(sample_rate: u32) -> Self {
+        LevelMeter::with_config(sample_rate, MeterConfig::default())
+    }
+
+    pub fn with_config(sample_rate: u32, config: MeterConfig) -> Self {
+        let mut meter = LevelMeter {
+            sample_rate,
+            config,
+            peak_decay: 1.0,
+            rms_alpha: 1.0,
+            hold_frames: 0,
+            channels: [ChannelLevel::default(); NUM_CHANNELS],
+        };
+        meter.set_config(config);
+        meter
+    }
+
+    pub fn config(&self) -> &MeterConfig {
+        &self.config
+    }
+
+    pub fn set_config(&mut self, config: MeterConfig) {
+        let fs = self.sample_rate as f32;
+        self.config = config;
+        self.peak_decay = libm::powf(10.0, -config.peak_decay_db_per_sec / (20.0 * fs));
+        let tau = f32::from(config.rms_window_ms.max(1)) / 1000.0;
+        self.rms_alpha = 1.0 - libm::expf(-1.0 / (tau * fs));
+        self.hold_frames = u32::from(config.peak_hold_ms) * self.sample_rate / 1000;
+    }
+
+    pub fn channel(&self, channel: usize) -> &ChannelLevel {
+        &self.channels[channel]
+    }
+
+    pub fn channels(&self) -> &[ChannelLevel; NUM_CHANNELS] {
+        &self.channels
+    }
+
+    /// Total clipped samples of all channels since the last call, resets
+    /// the counts
+    pub fn take_clips(&mut self) -> u32 {
+        self.channels
+            .iter_mut()
+            .map(|c| core::mem::replace(&mut c.clips, 0))
+            .sum()
+    }
+
+    pub fn reset(&mut self) {
+        self.channels = [ChannelLevel::default(); NUM_CHANNELS];
+    }
+
+    /// Update the readings from interleaved samples
+    pub fn measure(&mut self, buf: &[i16]) {
+        let clip_level = i32::from(self.config.clip_level);
+        for frame in buf.chunks_exact(NUM_CHANNELS) {
+            for (level, s) in self.channels.iter_mut().zip(frame.iter()) {
+                let magnitude = i32::from(*s).abs();
+                if magnitude >= clip_level {
+                    level.clips = level.clips.saturating_add(1);
+                }
+
+                let x = magnitude as f32 / FULL_SCALE;
+                if x >= level.peak {
+                    level.peak = x;
+                    level.hold_frames = self.hold_frames;
+                } else if level.hold_frames > 0 {
+                    level.hold_frames -= 1;
+                } else {
+                    level.peak *= self.peak_decay;
+                }
+
+                level.mean_square += (x * x - level.mean_square) * self.rms_alpha;
+            }
+        }
+    }
+}
+
+impl AudioProcessor for LevelMeter {
+    fn process(&mut self, buf: &mut [i16]) {
+        self.measure(buf);
+    }
+}
+
+pub fn amplitude_to_dbfs(amplitude: f32) -> f32 {
+    if amplitude > 0.0 {
+        (20.0 * libm::log10f(amplitude)).max(MIN_DBFS)
+    } else {
+        MIN_DBFS
+    }
+}
+
+/// Map a dBFS reading onto 0.0 ..= 1.0 for a bar display spanning
+/// `floor_dbfs` to 0 dBFS
+pub fn meter_scale(dbfs: f32, floor_dbfs: f32) -> f32 {
+    if dbfs <= floor_dbfs {
+        0.0
+    } else if dbfs >= 0.0 {
+        1.0
+    } else {
+        1.0 - dbfs / floor_dbfs
+    }
+}
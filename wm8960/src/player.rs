@@ -0,0 +1,146 @@
+// Playback of 16-bit PCM with "smpl" loop points
+
+use crate::audio::{AudioSource, NUM_CHANNELS};
+use crate::wave_header::{AudioFormat, ParsedWave, SampleLoop, WaveError};
+
+/// Plays signed 16-bit little endian PCM as interleaved stereo, mono is
+/// copied to both channels.
+///
+/// The loop region repeats until it has played `play_count` times, or until
+/// `release` for a count of 0, then playback runs on to the end. Only
+/// forward loops are supported, other loop types are played forward.
+#[derive(Debug, Copy, Clone)]
+pub struct Player<'a> {
+    samples: &'a [u8],
+    channels: u8,
+    sample_loop: Option<SampleLoop>,
+    /// Next frame
+    position: usize,
+    /// Times the loop has jumped back
+    loops_done: u32,
+    released: bool,
+}
+
+impl<'a> Player<'a> {
+    /// `channels` must be 1 or 2
+    pub fn new(samples: &'a [u8], channels: u8) -> Self {
+        Player {
+            samples,
+            channels: channels.max(1).min(2),
+            sample_loop: None,
+            position: 0,
+            loops_done: 0,
+            released: false,
+        }
+    }
+
+    /// Play a WAVE file, looping on the first loop of its "smpl" chunk
+    pub fn from_wave(file: &'a [u8]) -> Result<Self, WaveError> {
+        Player::from_parsed(&ParsedWave::parse(file)?)
+    }
+
+    pub fn from_parsed(wave: &ParsedWave<'a>) -> Result<Self, WaveError> {
+        let fmt = wave.fmt();
+        if fmt.audio_format != AudioFormat::PCM {
+            return Err(WaveError::UnsupportedFormat(fmt.audio_format.as_le_u16()));
+        }
+        if fmt.bits_per_sample != 16 {
+            return Err(WaveError::UnsupportedBitsPerSample(fmt.bits_per_sample));
+        }
+        if fmt.num_channels != 1 && fmt.num_channels != 2 {
+            return Err(WaveError::UnsupportedChannels(fmt.num_channels));
+        }
+
+        let mut player = Player::new(wave.data(), fmt.num_channels as u8);
+        if let Some(sampler) = wave.sampler()? {
+            player.set_loop(sampler.loops().next());
+        }
+        Ok(player)
+    }
+
+    /// Set the loop region, returns false and clears it if it doesn't fit
+    /// within the samples
+    pub fn set_loop(&mut self, sample_loop: Option<SampleLoop>) -> bool {
+        self.sample_loop =
+            sample_loop.filter(|l| l.start <= l.end && (l.end as usize) < self.len());
+        self.sample_loop.is_some() == sample_loop.is_some()
+    }
+
+    pub fn sample_loop(&self) -> Option<&SampleLoop> {
+        self.sample_loop.as_ref()
+    }
+
+    pub fn channels(&self) -> u8 {
+        self.channels
+    }
+
+    /// Number of frames
+    pub fn len(&self) -> usize {
+        self.samples.len() / (2 * usize::from(self.channels))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Next frame to play
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// Leave the loop at its end and play out the rest
+    pub fn release(&mut self) {
+        self.released = true;
+    }
+
+    pub fn rewind(&mut self) {
+        self.position = 0;
+        self.loops_done = 0;
+        self.released = false;
+    }
+
+    pub fn is_looping(&self) -> bool {
+        self.active_loop().is_some()
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.position >= self.len()
+    }
+
+    fn active_loop(&self) -> Option<&SampleLoop> {
+        let loops_done = self.loops_done;
+        self.sample_loop
+            .as_ref()
+            .filter(|l| l.play_count == 0 || loops_done.saturating_add(1) < l.play_count)
+            .filter(|_| !self.released)
+    }
+}
+
+impl<'a> AudioSource for Player<'a> {
+    fn fill(&mut self, buf: &mut [i16]) -> usize {
+        let channels = usize::from(self.channels);
+        let frame_len = 2 * channels;
+        let mut written = 0;
+        for frame in buf.chunks_exact_mut(NUM_CHANNELS) {
+            if let Some(l) = self.active_loop() {
+                if self.position == l.end as usize + 1 {
+                    self.position = l.start as usize;
+                    self.loops_done = self.loops_done.saturating_add(1);
+                }
+            }
+            if self.is_done() {
+                break;
+            }
+
+            let start = self.position * frame_len;
+            let bytes = &self.samples[start..start + frame_len];
+            for (ch, s) in frame.iter_mut().enumerate() {
+                let i = 2 * (ch % channels);
+                *s = i16::from_le_bytes([bytes[i], bytes[i + 1]]);
+            }
+            self.position += 1;
+            written += NUM_CHANNELS;
+        }
+        written
+    }
+}
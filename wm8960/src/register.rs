@@ -140,6 +140,17 @@ bitfield! {
     pub alrswap, set_alrswap : 8;
 }
 
+bitfield! {
+    #[derive(Debug, Copy, Clone, PartialEq)]
+    pub struct AudioIface2(u16);
+    u16;
+    pub loopback, set_loopback : 0;
+    pub adccomp, set_adccomp : 2, 1;
+    pub daccomp, set_daccomp : 4, 3;
+    pub wl8, set_wl8 : 5;
+    pub alrcgpio, set_alrcgpio : 6;
+}
+
 bitfield! {
     #[derive(Debug, Copy, Clone, PartialEq)]
     pub struct LdacVol(u16);
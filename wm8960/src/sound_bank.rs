@@ -0,0 +1,320 @@
+// Packed sound bank, a blob of indexed clips.
+//
+// ```text
+// Header (16 bytes)
+//   magic        "SBNK"
+//   version      u16
+//   num_entries  u16
+//   total_len    u32, length of the whole blob
+//   reserved     u32
+// Index (16 bytes per entry)
+//   id           u16
+//   kind         u8, 0 = WAV file, 1 = raw signed 16-bit PCM
+//   channels     u8
+//   offset       u32, from the start of the blob, 4 byte aligned
+//   length       u32
+//   sample_rate  u32
+// Payloads
+// ```
+//
+// All fields are little endian. The `sound-bank-gen` tool builds banks on
+// the host.
+
+use crate::player::Player;
+use crate::wave_header::{AudioFormat, Info, ParsedWave, SampleLoop, WaveError, WaveHeader};
+use core::convert::TryFrom;
+
+pub const MAGIC: [u8; 4] = *b"SBNK";
+pub const VERSION: u16 = 1;
+pub const HEADER_LEN: usize = 16;
+pub const ENTRY_LEN: usize = 16;
+/// Payload alignment
+pub const ALIGN: usize = 4;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SoundBankError {
+    BadMagic,
+    UnsupportedVersion(u16),
+    /// The blob is shorter than the header or index claims
+    Truncated,
+    /// An entry's payload is out of bounds or misaligned
+    InvalidEntry(u16),
+    UnknownKind(u8),
+    NotFound(u16),
+    /// The WAV payload of the clip failed to parse
+    InvalidWave(u16, WaveError),
+    /// Only 16-bit PCM with 1 or 2 channels can be played
+    UnsupportedFormat(u16),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum EntryKind {
+    Wave = 0,
+    Raw = 1,
+}
+
+impl TryFrom<u8> for EntryKind {
+    type Error = SoundBankError;
+
+    fn try_from(kind: u8) -> Result<Self, Self::Error> {
+        match kind {
+            0 => Ok(EntryKind::Wave),
+            1 => Ok(EntryKind::Raw),
+            _ => Err(SoundBankError::UnknownKind(kind)),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Header {
+    pub version: u16,
+    pub num_entries: u16,
+    pub total_len: u32,
+}
+
+impl Header {
+    pub fn parse(input: &[u8]) -> Result<Self, SoundBankError> {
+        if input.len() < HEADER_LEN {
+            return Err(SoundBankError::Truncated);
+        }
+        if input[0..4] != MAGIC {
+            return Err(SoundBankError::BadMagic);
+        }
+        let version = le_u16(&input[4..]);
+        if version != VERSION {
+            return Err(SoundBankError::UnsupportedVersion(version));
+        }
+        Ok(Header {
+            version,
+            num_entries: le_u16(&input[6..]),
+            total_len: le_u32(&input[8..]),
+        })
+    }
+
+    /// Serialize into the first `HEADER_LEN` bytes of `buf`
+    pub fn write(self, buf: &mut [u8]) {
+        buf[0..4].copy_from_slice(&MAGIC);
+        buf[4..6].copy_from_slice(&self.version.to_le_bytes());
+        buf[6..8].copy_from_slice(&self.num_entries.to_le_bytes());
+        buf[8..12].copy_from_slice(&self.total_len.to_le_bytes());
+        buf[12..16].copy_from_slice(&0_u32.to_le_bytes());
+    }
+
+    /// Offset of the first payload
+    pub fn payload_offset(num_entries: u16) -> usize {
+        align(HEADER_LEN + usize::from(num_entries) * ENTRY_LEN)
+    }
+}
+
+/// Index table entry
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Entry {
+    pub id: u16,
+    pub kind: EntryKind,
+    pub channels: u8,
+    pub offset: u32,
+    pub length: u32,
+    pub sample_rate: u32,
+}
+
+impl Entry {
+    pub fn parse(input: &[u8]) -> Result<Self, SoundBankError> {
+        if input.len() < ENTRY_LEN {
+            return Err(SoundBankError::Truncated);
+        }
+        Ok(Entry {
+            id: le_u16(&input[0..]),
+            kind: EntryKind::try_from(input[2])?,
+            channels: input[3],
+            offset: le_u32(&input[4..]),
+            length: le_u32(&input[8..]),
+            sample_rate: le_u32(&input[12..]),
+        })
+    }
+
+    /// Serialize into the first `ENTRY_LEN` bytes of `buf`
+    pub fn write(&self, buf: &mut [u8]) {
+        buf[0..2].copy_from_slice(&self.id.to_le_bytes());
+        buf[2] = self.kind as u8;
+        buf[3] = self.channels;
+        buf[4..8].copy_from_slice(&self.offset.to_le_bytes());
+        buf[8..12].copy_from_slice(&self.length.to_le_bytes());
+        buf[12..16].copy_from_slice(&self.sample_rate.to_le_bytes());
+    }
+}
+
+/// Read-only view of a sound bank, typically in flash
+#[derive(Debug, Copy, Clone)]
+pub struct SoundBank<'a> {
+    data: &'a [u8],
+    header: Header,
+}
+
+impl<'a> SoundBank<'a> {
+    /// Check the header and the bounds of every index entry.
+    ///
+    /// `data` may extend past the end of the bank, it is trimmed to the
+    /// header's `total_len`.
+    pub fn new(data: &'a [u8]) -> Result<Self, SoundBankError> {
+        let header = Header::parse(data)?;
+        let total_len = header.total_len as usize;
+        if total_len > data.len() || total_len < Header::payload_offset(header.num_entries) {
+            return Err(SoundBankError::Truncated);
+        }
+
+        let bank = SoundBank {
+            data: &data[..total_len],
+            header,
+        };
+
+        for index in 0..bank.len() {
+            let entry = bank.entry(index)?;
+            let start = entry.offset as usize;
+            let end = start
+                .checked_add(entry.length as usize)
+                .ok_or(SoundBankError::InvalidEntry(entry.id))?;
+            if start % ALIGN != 0 || start < Header::payload_offset(header.num_entries) {
+                return Err(SoundBankError::InvalidEntry(entry.id));
+            }
+            if end > total_len {
+                return Err(SoundBankError::InvalidEntry(entry.id));
+            }
+        }
+
+        Ok(bank)
+    }
+
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    /// The bank bytes, trimmed to `total_len`
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.data
+    }
+
+    pub fn len(&self) -> usize {
+        usize::from(self.header.num_entries)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.header.num_entries == 0
+    }
+
+    pub fn entry(&self, index: usize) -> Result<Entry, SoundBankError> {
+        index
+            .checked_mul(ENTRY_LEN)
+            .and_then(|offset| offset.checked_add(HEADER_LEN))
+            .and_then(|start| self.data.get(start..start + ENTRY_LEN))
+            .ok_or(SoundBankError::Truncated)
+            .and_then(Entry::parse)
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = Entry> + 'a {
+        let bank = *self;
+        (0..self.len()).filter_map(move |i| bank.entry(i).ok())
+    }
+
+    pub fn find(&self, id: u16) -> Option<Entry> {
+        self.entries().find(|e| e.id == id)
+    }
+
+    /// Look up a clip by ID, WAV payloads are validated with `ParsedWave`
+    /// and their loop points read from the "smpl" chunk
+    pub fn clip(&self, id: u16) -> Result<Clip<'a>, SoundBankError> {
+        let entry = self.find(id).ok_or(SoundBankError::NotFound(id))?;
+        let start = entry.offset as usize;
+        let payload = &self.data[start..start + entry.length as usize];
+
+        let wave = match entry.kind {
+            EntryKind::Wave => {
+                Some(ParsedWave::parse(payload).map_err(|e| SoundBankError::InvalidWave(id, e))?)
+            }
+            EntryKind::Raw => None,
+        };
+
+        let (channels, sample_rate) = match wave {
+            Some(w) => {
+                let fmt = w.fmt();
+                if fmt.audio_format != AudioFormat::PCM || fmt.bits_per_sample != 16 {
+                    return Err(SoundBankError::UnsupportedFormat(id));
+                }
+                (fmt.num_channels, fmt.sample_rate)
+            }
+            None => (u16::from(entry.channels), entry.sample_rate),
+        };
+        if channels != 1 && channels != 2 {
+            return Err(SoundBankError::UnsupportedFormat(id));
+        }
+
+        let sample_loop = match wave {
+            Some(w) => w
+                .sampler()
+                .map_err(|e| SoundBankError::InvalidWave(id, e))?
+                .and_then(|s| s.loops().next()),
+            None => None,
+        };
+
+        let mut clip = Clip {
+            id,
+            header: wave.map(|w| *w.header()),
+            channels: channels as u8,
+            sample_rate,
+            samples: wave.map_or(payload, |w| w.data()),
+            sample_loop: None,
+            // Metadata is only for display, a malformed "LIST" doesn't stop
+            // playback
+            info: wave.and_then(|w| w.info().ok().and_then(|i| i)),
+        };
+        // Loops outside the samples are dropped, see `Player::set_loop`
+        clip.sample_loop =
+            sample_loop.filter(|l| l.start <= l.end && (l.end as usize) < clip.len());
+        Ok(clip)
+    }
+}
+
+/// A playable clip, signed 16-bit little endian PCM
+#[derive(Debug, Copy, Clone)]
+pub struct Clip<'a> {
+    pub id: u16,
+    /// Present for WAV payloads
+    pub header: Option<WaveHeader>,
+    pub channels: u8,
+    pub sample_rate: u32,
+    pub samples: &'a [u8],
+    /// First loop of the "smpl" chunk of WAV payloads
+    pub sample_loop: Option<SampleLoop>,
+    /// "INFO" metadata of WAV payloads
+    pub info: Option<Info<'a>>,
+}
+
+impl<'a> Clip<'a> {
+    /// Number of frames
+    pub fn len(&self) -> usize {
+        self.samples.len() / (2 * usize::from(self.channels.max(1)))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// A player honouring the clip's loop, if any
+    pub fn source(&self) -> Player<'a> {
+        let mut player = Player::new(self.samples, self.channels);
+        player.set_loop(self.sample_loop);
+        player
+    }
+}
+
+/// Round up to the payload alignment
+pub fn align(offset: usize) -> usize {
+    (offset + ALIGN - 1) / ALIGN * ALIGN
+}
+
+fn le_u16(b: &[u8]) -> u16 {
+    u16::from_le_bytes([b[0], b[1]])
+}
+
+fn le_u32(b: &[u8]) -> u32 {
+    u32::from_le_bytes([b[0], b[1], b[2], b[3]])
+}
@@ -0,0 +1,516 @@
+// Call progress tones per ITU-T E.180 (Supplement 2)
+
+use crate::audio::{clamp_i16, AudioSource, NUM_CHANNELS};
+use crate::dtmf::DtmfDigit;
+
+/// Maximum number of digits queued by `ToneGenerator::play_digits`
+pub const MAX_DIGITS: usize = 32;
+
+/// Default level of a single tone, roughly -12 dBFS
+pub const DEFAULT_LEVEL: i16 = 0x2000;
+
+const CONTINUOUS: &[u16] = &[];
+
+/// One period of a sine wave, plus a guard entry for interpolation
+const SINE_TABLE: [i16; 257] = [
+    0, 804, 1608, 2410, 3212, 4011, 4808, 5602, 6393, 7179, 7962, 8739, 9512, 10278, 11039, 11793,
+    12539, 13279, 14010, 14732, 15446, 16151, 16846, 17530, 18204, 18868, 19519, 20159, 20787,
+    21403, 22005, 22594, 23170, 23731, 24279, 24811, 25329, 25832, 26319, 26790, 27245, 27683,
+    28105, 28510, 28898, 29268, 29621, 29956, 30273, 30571, 30852, 31113, 31356, 31580, 31785,
+    31971, 32137, 32285, 32412, 32521, 32609, 32678, 32728, 32757, 32767, 32757, 32728, 32678,
+    32609, 32521, 32412, 32285, 32137, 31971, 31785, 31580, 31356, 31113, 30852, 30571, 30273,
+    29956, 29621, 29268, 28898, 28510, 28105, 27683, 27245, 26790, 26319, 25832, 25329, 24811,
+    24279, 23731, 23170, 22594, 22005, 21403, 20787, 20159, 19519, 18868, 18204, 17530, 16846,
+    16151, 15446, 14732, 14010, 13279, 12539, 11793, 11039, 10278, 9512, 8739, 7962, 7179, 6393,
+    5602, 4808, 4011, 3212, 2410, 1608, 804, 0, -804, -1608, -2410, -3212, -4011, -4808, -5602,
+    -6393, -7179, -7962, -8739, -9512, -10278, -11039, -11793, -12539, -13279, -14010, -14732,
+    -15446, -16151, -16846, -17530, -18204, -18868, -19519, -20159, -20787, -21403, -22005, -22594,
+    -23170, -23731, -24279, -24811, -25329, -25832, -26319, -26790, -27245, -27683, -28105, -28510,
+    -28898, -29268, -29621, -29956, -30273, -30571, -30852, -31113, -31356, -31580, -31785, -31971,
+    -32137, -32285, -32412, -32521, -32609, -32678, -32728, -32757, -32767, -32757, -32728, -32678,
+    -32609, -32521, -32412, -32285, -32137, -31971, -31785, -31580, -31356, -31113, -30852, -30571,
+    -30273, -29956, -29621, -29268, -28898, -28510, -28105, -27683, -27245, -26790, -26319, -25832,
+    -25329, -24811, -24279, -23731, -23170, -22594, -22005, -21403, -20787, -20159, -19519, -18868,
+    -18204, -17530, -16846, -16151, -15446, -14732, -14010, -13279, -12539, -11793, -11039, -10278,
+    -9512, -8739, -7962, -7179, -6393, -5602, -4808, -4011, -3212, -2410, -1608, -804, 0,
+];
+
+/// Phase accumulator sine oscillator
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Oscillator {
+    phase: u32,
+    step: u32,
+}
+
+impl Oscillator {
+    /// `freq_hz` has to be below half the sample rate, the phase step
+    /// would alias or wrap otherwise
+    pub fn new(freq_hz: u16, sample_rate: u32) -> Result<Self, ToneError> {
+        if u64::from(freq_hz) * 2 >= u64::from(sample_rate) {
+            return Err(ToneError::InvalidFrequency);
+        }
+        let step = ((u64::from(freq_hz) << 32) / u64::from(sample_rate)) as u32;
+        Ok(Oscillator { phase: 0, step })
+    }
+
+    /// A silent oscillator
+    pub fn off() -> Self {
+        Oscillator { phase: 0, step: 0 }
+    }
+
+    pub fn is_off(self) -> bool {
+        self.step == 0
+    }
+
+    pub fn reset(&mut self) {
+        self.phase = 0;
+    }
+
+    /// Next sample, full scale
+    pub fn next_sample(&mut self) -> i16 {
+        if self.step == 0 {
+            return 0;
+        }
+
+        let index = (self.phase >> 24) as usize;
+        let frac = ((self.phase >> 8) & 0xFFFF) as i32;
+        let a = i32::from(SINE_TABLE[index]);
+        let b = i32::from(SINE_TABLE[index + 1]);
+        self.phase = self.phase.wrapping_add(self.step);
+
+        (a + (((b - a) * frac) >> 16)) as i16
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ToneError {
+    /// A cadence needs an off for every on and no 0 ms steps
+    InvalidCadence,
+    /// At or above half the sample rate
+    InvalidFrequency,
+}
+
+/// A (dual) tone with an optional on/off cadence
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ToneSpec {
+    /// Tone frequencies in Hz, 0 means unused
+    pub frequencies: [u16; 2],
+    /// Level of each tone, linear full scale
+    pub level: i16,
+    /// Alternating on/off durations in milliseconds, starting with on.
+    /// An empty cadence is a continuous tone.
+    pub cadence: &'static [u16],
+}
+
+impl ToneSpec {
+    pub const fn continuous(f1: u16, f2: u16) -> Self {
+        ToneSpec {
+            frequencies: [f1, f2],
+            level: DEFAULT_LEVEL,
+            cadence: CONTINUOUS,
+        }
+    }
+
+    pub const fn cadenced(f1: u16, f2: u16, cadence: &'static [u16]) -> Self {
+        ToneSpec {
+            frequencies: [f1, f2],
+            level: DEFAULT_LEVEL,
+            cadence,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum CallProgress {
+    DialTone,
+    Ringback,
+    Busy,
+    /// Also known as reorder or fast busy
+    Congestion,
+}
+
+/// Per-country call progress tone plan
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct CountryTones {
+    pub dial: ToneSpec,
+    pub ringback: ToneSpec,
+    pub busy: ToneSpec,
+    pub congestion: ToneSpec,
+}
+
+impl CountryTones {
+    pub const NORTH_AMERICA: CountryTones = CountryTones {
+        dial: ToneSpec::continuous(350, 440),
+        ringback: ToneSpec::cadenced(440, 480, &[2000, 4000]),
+        busy: ToneSpec::cadenced(480, 620, &[500, 500]),
+        congestion: ToneSpec::cadenced(480, 620, &[250, 250]),
+    };
+
+    pub const UNITED_KINGDOM: CountryTones = CountryTones {
+        dial: ToneSpec::continuous(350, 450),
+        ringback: ToneSpec::cadenced(400, 450, &[400, 200, 400, 2000]),
+        busy: ToneSpec::cadenced(400, 0, &[375, 375]),
+        congestion: ToneSpec::cadenced(400, 0, &[400, 350, 225, 525]),
+    };
+
+    /// CEPT recommendation, used by most of continental Europe
+    pub const EUROPE: CountryTones = CountryTones {
+        dial: ToneSpec::continuous(425, 0),
+        ringback: ToneSpec::cadenced(425, 0, &[1000, 4000]),
+        busy: ToneSpec::cadenced(425, 0, &[500, 500]),
+        congestion: ToneSpec::cadenced(425, 0, &[250, 250]),
+    };
+
+    pub fn tone(&self, signal: CallProgress) -> ToneSpec {
+        match signal {
+            CallProgress::DialTone => self.dial,
+            CallProgress::Ringback => self.ringback,
+            CallProgress::Busy => self.busy,
+            CallProgress::Congestion => self.congestion,
+        }
+    }
+}
+
+impl Default for CountryTones {
+    fn default() -> Self {
+        CountryTones::NORTH_AMERICA
+    }
+}
+
+/// DTMF digit on/off durations in milliseconds
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct DtmfTiming {
+    pub on_ms: u16,
+    pub off_ms: u16,
+}
+
+impl Default for DtmfTiming {
+    fn default() -> Self {
+        DtmfTiming {
+            on_ms: 100,
+            off_ms: 100,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Mode {
+    Idle,
+    Tone,
+    Digits,
+}
+
+/// Synthesized tone source for the playback path.
+///
+/// Produces continuous or cadenced tones, call progress signals and DTMF
+/// digit sequences as interleaved stereo samples, both channels carry the
+/// same signal.
+pub struct ToneGenerator {
+    sample_rate: u32,
+    country: CountryTones,
+    dtmf_timing: DtmfTiming,
+    dtmf_level: i16,
+    mode: Mode,
+    osc: [Oscillator; 2],
+    level: i16,
+    cadence: &'static [u16],
+    cadence_index: usize,
+    /// Frames left in the current cadence step or digit
+    frames_left: u32,
+    tone_on: bool,
+    digits: [DtmfDigit; MAX_DIGITS],
+    num_digits: usize,
+    digit_index: usize,
+}
+
+impl ToneGenerator {
+    pub fn new(sample_rate: u32) -> Self {
+        ToneGenerator {
+            sample_rate,
+            country: CountryTones::default(),
+            dtmf_timing: DtmfTiming::default(),
+            dtmf_level: DEFAULT_LEVEL,
+            mode: Mode::Idle,
+            osc: [Oscillator::off(); 2],
+            level: 0,
+            cadence: CONTINUOUS,
+            cadence_index: 0,
+            frames_left: 0,
+            tone_on: false,
+            digits: [DtmfDigit::D0; MAX_DIGITS],
+            num_digits: 0,
+            digit_index: 0,
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn set_country(&mut self, country: CountryTones) {
+        self.country = country;
+    }
+
+    pub fn country(&self) -> &CountryTones {
+        &self.country
+    }
+
+    pub fn set_dtmf_timing(&mut self, timing: DtmfTiming) {
+        self.dtmf_timing = timing;
+    }
+
+    pub fn set_dtmf_level(&mut self, level: i16) {
+        self.dtmf_level = level;
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.mode == Mode::Idle
+    }
+
+    /// Stop any tone, `fill` returns 0 until something else is played
+    pub fn stop(&mut self) {
+        self.mode = Mode::Idle;
+        self.osc = [Oscillator::off(); 2];
+    }
+
+    /// Play a tone, repeating its cadence until stopped
+    pub fn play_tone(&mut self, spec: ToneSpec) -> Result<(), ToneError> {
+        if spec.cadence.len() % 2 != 0 || spec.cadence.contains(&0) {
+            return Err(ToneError::InvalidCadence);
+        }
+        self.set_oscillators(spec.frequencies[0], spec.frequencies[1])?;
+        self.level = spec.level;
+        self.cadence = spec.cadence;
+        self.cadence_index = 0;
+        self.tone_on = true;
+        self.frames_left = spec.cadence.first().map_or(0, |ms| self.step_frames(*ms));
+        self.mode = Mode::Tone;
+        Ok(())
+    }
+
+    /// Play a call progress signal using the configured country tone plan
+    pub fn play_call_progress(&mut self, signal: CallProgress) -> Result<(), ToneError> {
+        let spec = self.country.tone(signal);
+        self.play_tone(spec)
+    }
+
+    /// Queue a sequence of DTMF digits, invalid characters are ignored.
+    ///
+    /// Returns the number of digits queued, at most `MAX_DIGITS`. Nothing
+    /// is played if a digit's tones don't fit below half the sample rate.
+    pub fn play_digits(&mut self, digits: &str) -> Result<usize, ToneError> {
+        self.stop();
+        self.num_digits = 0;
+        for d in digits.chars().filter_map(DtmfDigit::from_char) {
+            if self.num_digits == MAX_DIGITS {
+                break;
+            }
+            let (_, high) = d.frequencies();
+            Oscillator::new(high, self.sample_rate)?;
+            self.digits[self.num_digits] = d;
+            self.num_digits += 1;
+        }
+
+        self.digit_index = 0;
+        if self.num_digits != 0 {
+            self.start_digit()?;
+            self.mode = Mode::Digits;
+        }
+
+        Ok(self.num_digits)
+    }
+
+    /// Play a single DTMF digit
+    pub fn play_digit(&mut self, digit: DtmfDigit) -> Result<(), ToneError> {
+        self.stop();
+        self.digits[0] = digit;
+        self.num_digits = 1;
+        self.digit_index = 0;
+        self.start_digit()?;
+        self.mode = Mode::Digits;
+        Ok(())
+    }
+
+    fn ms_to_frames(&self, ms: u16) -> u32 {
+        ((u64::from(ms) * u64::from(self.sample_rate)) / 1000) as u32
+    }
+
+    /// A cadence step lasts at least a frame, even at low sample rates
+    fn step_frames(&self, ms: u16) -> u32 {
+        self.ms_to_frames(ms).max(1)
+    }
+
+    fn set_oscillators(&mut self, f1: u16, f2: u16) -> Result<(), ToneError> {
+        let sample_rate = self.sample_rate;
+        let oscillator = |f| match f {
+            0 => Ok(Oscillator::off()),
+            f => Oscillator::new(f, sample_rate),
+        };
+        self.osc = [oscillator(f1)?, oscillator(f2)?];
+        Ok(())
+    }
+
+    fn start_digit(&mut self) -> Result<(), ToneError> {
+        let (low, high) = self.digits[self.digit_index].frequencies();
+        self.set_oscillators(low, high)?;
+        self.level = self.dtmf_level;
+        self.tone_on = true;
+        self.frames_left = self.ms_to_frames(self.dtmf_timing.on_ms);
+        Ok(())
+    }
+
+    /// Advance the cadence or digit sequence, returns false when done
+    fn advance(&mut self) -> bool {
+        match self.mode {
+            Mode::Idle => false,
+            Mode::Tone => {
+                // Continuous tones never expire
+                if self.cadence.is_empty() {
+                    self.frames_left = core::u32::MAX;
+                    return true;
+                }
+                self.cadence_index = (self.cadence_index + 1) % self.cadence.len();
+                self.tone_on = self.cadence_index % 2 == 0;
+                if self.tone_on {
+                    self.osc.iter_mut().for_each(Oscillator::reset);
+                }
+                self.frames_left = self.step_frames(self.cadence[self.cadence_index]);
+                true
+            }
+            Mode::Digits => {
+                if self.tone_on {
+                    self.tone_on = false;
+                    self.frames_left = self.ms_to_frames(self.dtmf_timing.off_ms);
+                    true
+                } else {
+                    self.digit_index += 1;
+                    // Checked when the digits were queued
+                    if self.digit_index < self.num_digits && self.start_digit().is_ok() {
+                        true
+                    } else {
+                        self.stop();
+                        false
+                    }
+                }
+            }
+        }
+    }
+
+    fn next_sample(&mut self) -> i16 {
+        let a = i32::from(self.osc[0].next_sample());
+        let b = i32::from(self.osc[1].next_sample());
+        let level = i32::from(self.level);
+        clamp_i16(((a + b) * level) >> 15)
+    }
+}
+
+impl AudioSource for ToneGenerator {
+    fn fill(&mut self, buf: &mut [i16]) -> usize {
+        let mut written = 0;
+        for frame in buf.chunks_exact_mut(NUM_CHANNELS) {
+            while self.frames_left == 0 {
+                if !self.advance() {
+                    return written;
+                }
+            }
+            if self.mode == Mode::Idle {
+                return written;
+            }
+
+            let sample = if self.tone_on { self.next_sample() } else { 0 };
+            for s in frame.iter_mut() {
+                *s = sample;
+            }
+
+            self.frames_left -= 1;
+            written += NUM_CHANNELS;
+        }
+        written
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::dtmf::DtmfDigit;
+    use std::vec::Vec;
+
+    #[test]
+    fn oscillator_below_nyquist() {
+        assert!(Oscillator::new(3999, 8000).is_ok());
+        assert_eq!(
+            Oscillator::new(4000, 8000),
+            Err(ToneError::InvalidFrequency)
+        );
+        assert_eq!(
+            Oscillator::new(8000, 8000),
+            Err(ToneError::InvalidFrequency)
+        );
+        assert_eq!(
+            Oscillator::new(12_000, 8000),
+            Err(ToneError::InvalidFrequency)
+        );
+    }
+
+    #[test]
+    fn oscillator_period() {
+        // A quarter of the sample rate peaks every 4 samples
+        let mut osc = Oscillator::new(2000, 8000).unwrap();
+        let samples = [
+            osc.next_sample(),
+            osc.next_sample(),
+            osc.next_sample(),
+            osc.next_sample(),
+            osc.next_sample(),
+        ];
+        assert_eq!(samples[0], 0);
+        assert!(samples[1] > 32_000);
+        assert!(samples[2].abs() < 100);
+        assert!(samples[3] < -32_000);
+        assert_eq!(samples[4], samples[0]);
+    }
+
+    #[test]
+    fn tone_frequencies() {
+        let mut tones = ToneGenerator::new(8000);
+        assert_eq!(
+            tones.play_tone(ToneSpec::continuous(440, 4000)),
+            Err(ToneError::InvalidFrequency)
+        );
+        assert!(tones.is_idle());
+        assert_eq!(tones.play_tone(ToneSpec::continuous(440, 0)), Ok(()));
+
+        // 1633 Hz, the A to D column, needs more than 3266 Hz
+        let mut tones = ToneGenerator::new(3000);
+        assert_eq!(tones.play_digits("1A"), Err(ToneError::InvalidFrequency));
+        assert!(tones.is_idle());
+        assert_eq!(tones.play_digit(DtmfDigit::D1), Ok(()));
+        assert_eq!(tones.play_digits("123"), Ok(3));
+    }
+
+    #[test]
+    fn cadences() {
+        let mut tones = ToneGenerator::new(8000);
+        assert_eq!(
+            tones.play_tone(ToneSpec::cadenced(440, 0, &[100])),
+            Err(ToneError::InvalidCadence)
+        );
+        assert_eq!(
+            tones.play_tone(ToneSpec::cadenced(440, 0, &[0, 0])),
+            Err(ToneError::InvalidCadence)
+        );
+
+        // 1 ms is 8 frames on, 8 off
+        tones
+            .play_tone(ToneSpec::cadenced(440, 0, &[1, 1]))
+            .unwrap();
+        let mut buf = [0; 32 * NUM_CHANNELS];
+        assert_eq!(tones.fill(&mut buf), buf.len());
+        let on: Vec<bool> = buf.chunks(NUM_CHANNELS).map(|f| f[0] != 0).collect();
+        assert!(on[1..8].iter().all(|s| *s));
+        assert!(on[8..16].iter().all(|s| !*s));
+        assert!(on[17..24].iter().all(|s| *s));
+    }
+}
@@ -1,12 +1,41 @@
 // http://soundfile.sapp.org/doc/WaveFormat/
 
+use crate::audio::{NUM_CHANNELS, SAMPLE_RATE};
 use core::convert::TryFrom;
-use nom::{
-    bytes::complete::tag,
-    combinator::opt,
-    number::complete::{le_u16, le_u32},
-    IResult,
-};
+use core::fmt;
+
+/// Offsets are from the start of the file
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum WaveError {
+    /// Missing the "RIFF" chunk ID
+    NotRiff,
+    /// The RIFF form type isn't "WAVE"
+    NotWave,
+    /// No "fmt " chunk ahead of the "data" chunk
+    MissingFmt,
+    MissingData,
+    /// Unknown `audio_format` tag
+    UnsupportedFormat(u16),
+    /// The sample size isn't supported by the consumer
+    UnsupportedBitsPerSample(u16),
+    /// The channel count isn't supported by the consumer
+    UnsupportedChannels(u16),
+    /// WAVE_FORMAT_EXTENSIBLE with a sub-format GUID that isn't PCM or float
+    UnsupportedSubFormat([u8; 16]),
+    /// `needed` bytes were expected at `offset`
+    Truncated {
+        offset: usize,
+        needed: usize,
+    },
+    /// Not the kind of chunk asked for, `offset` is the chunk header
+    UnexpectedChunk {
+        offset: usize,
+    },
+    /// A chunk size disagrees with its contents, `offset` is the chunk header
+    InconsistentSizes {
+        offset: usize,
+    },
+}
 
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub enum ChunkId {
@@ -16,13 +45,41 @@ pub enum ChunkId {
     FMT = 0x2074_6D66,
     /// "fact" = 0x7463_6166
     FACT = 0x7463_6166,
-    /// "data" = 0x5453_494C
-    DATA = 0x5453_494C,
+    /// "data" = 0x6174_6164
+    DATA = 0x6174_6164,
+    /// "LIST" = 0x5453_494C
+    LIST = 0x5453_494C,
+    /// "INFO" = 0x4F46_4E49, list type of a "LIST" chunk
+    INFO = 0x4F46_4E49,
+    /// "cue " = 0x2065_7563
+    CUE = 0x2065_7563,
+    /// "smpl" = 0x6C70_6D73
+    SMPL = 0x6C70_6D73,
 }
 
 impl ChunkId {
-    pub fn as_le_u32(&self) -> u32 {
-        *self as _
+    const ALL: [ChunkId; 8] = [
+        ChunkId::RIFF,
+        ChunkId::FMT,
+        ChunkId::FACT,
+        ChunkId::DATA,
+        ChunkId::LIST,
+        ChunkId::INFO,
+        ChunkId::CUE,
+        ChunkId::SMPL,
+    ];
+
+    pub fn as_le_u32(self) -> u32 {
+        self as _
+    }
+
+    pub fn fourcc(self) -> [u8; 4] {
+        self.as_le_u32().to_le_bytes()
+    }
+
+    pub fn from_fourcc(fourcc: [u8; 4]) -> Option<Self> {
+        let id = u32::from_le_bytes(fourcc);
+        ChunkId::ALL.iter().find(|c| c.as_le_u32() == id).copied()
     }
 }
 
@@ -33,8 +90,8 @@ pub enum Format {
 }
 
 impl Format {
-    pub fn as_le_u32(&self) -> u32 {
-        *self as _
+    pub fn as_le_u32(self) -> u32 {
+        self as _
     }
 }
 
@@ -49,29 +106,42 @@ pub struct ChunkRiff {
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub enum AudioFormat {
     PCM = 0x01,
+    /// WAVE_FORMAT_IEEE_FLOAT
+    FLOAT = 0x03,
 }
 
+/// Format tag of a "fmt " chunk carrying a `FmtExtensible`
+pub const WAVE_FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+/// Sub-format GUIDs are the format tag followed by these bytes,
+/// `xxxxxxxx-0000-0010-8000-00aa00389b71`
+const SUB_FORMAT_GUID_TAIL: [u8; 14] = [
+    0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xAA, 0x00, 0x38, 0x9B, 0x71,
+];
+
 impl AudioFormat {
-    pub fn as_le_u16(&self) -> u16 {
-        *self as _
+    pub fn as_le_u16(self) -> u16 {
+        self as _
     }
 }
 
 impl TryFrom<u16> for AudioFormat {
-    type Error = nom::Err<usize>;
+    type Error = WaveError;
 
     fn try_from(af: u16) -> Result<Self, Self::Error> {
         if af == AudioFormat::PCM.as_le_u16() {
             Ok(AudioFormat::PCM)
+        } else if af == AudioFormat::FLOAT.as_le_u16() {
+            Ok(AudioFormat::FLOAT)
         } else {
-            Err(nom::Err::Failure(0))
+            Err(WaveError::UnsupportedFormat(af))
         }
     }
 }
 
 impl Format {
-    pub fn as_le_u16(&self) -> u16 {
-        *self as _
+    pub fn as_le_u16(self) -> u16 {
+        self as _
     }
 }
 
@@ -85,7 +155,28 @@ pub struct ChunkFmt {
     pub sample_rate: u32,
     pub byte_rate: u32,
     pub block_align: u16,
+    /// Container size, see `FmtExtensible::valid_bits_per_sample`
     pub bits_per_sample: u16,
+    /// Present for WAVE_FORMAT_EXTENSIBLE, `audio_format` is then taken from
+    /// the sub-format
+    pub extensible: Option<FmtExtensible>,
+}
+
+/// WAVE_FORMAT_EXTENSIBLE fields of the "fmt " chunk
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct FmtExtensible {
+    pub valid_bits_per_sample: u16,
+    /// Speaker positions, bit 0 is front left
+    pub channel_mask: u32,
+    pub sub_format: [u8; 16],
+}
+
+impl ChunkFmt {
+    /// Bits of each sample that carry audio
+    pub fn valid_bits_per_sample(&self) -> u16 {
+        self.extensible
+            .map_or(self.bits_per_sample, |e| e.valid_bits_per_sample)
+    }
 }
 
 /// "fact" chunk
@@ -101,6 +192,8 @@ pub struct ChunkFact {
 pub struct ChunkData {
     pub chunk_id: ChunkId,
     pub chunk_size: u32,
+    /// Offset of the samples from the start of the file
+    pub offset: usize,
 }
 
 #[derive(Debug, Copy, Clone, PartialEq)]
@@ -111,90 +204,1681 @@ pub struct WaveHeader {
     pub data: ChunkData,
 }
 
+/// Chunk size written by encoders that don't know the final length, along
+/// with 0
+pub const UNKNOWN_SIZE: u32 = 0xFFFF_FFFF;
+
+/// Maximum number of violations a `Validation` holds, one per check
+pub const MAX_VIOLATIONS: usize = 9;
+
+/// A header field that disagrees with the file or the other fields
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Violation {
+    /// `riff.chunk_size` doesn't match the file length
+    RiffSize {
+        header: u32,
+        expected: u32,
+    },
+    /// The "data" chunk extends past the end of the file
+    DataSize {
+        header: u32,
+        available: u32,
+    },
+    /// The data size isn't a whole number of frames
+    PartialFrame {
+        data_size: u32,
+        block_align: u16,
+    },
+    NoChannels,
+    NoSampleRate,
+    NoBitsPerSample,
+    /// `block_align != num_channels * bytes per sample`
+    BlockAlign {
+        header: u16,
+        expected: u16,
+    },
+    /// `byte_rate != sample_rate * block_align`
+    ByteRate {
+        header: u32,
+        expected: u32,
+    },
+    /// More valid bits than the container holds
+    ValidBits {
+        valid: u16,
+        container: u16,
+    },
+}
+
+/// The violations found by `WaveHeader::validate`
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Validation {
+    violations: [Option<Violation>; MAX_VIOLATIONS],
+}
+
+impl Validation {
+    fn push(&mut self, violation: Violation) {
+        if let Some(slot) = self.violations.iter_mut().find(|v| v.is_none()) {
+            *slot = Some(violation);
+        }
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.violations[0].is_none()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Violation> + '_ {
+        self.violations.iter().filter_map(|v| *v)
+    }
+
+    /// True if only the RIFF and data sizes are wrong, which `repair` fixes
+    pub fn is_repairable(&self) -> bool {
+        self.iter().all(|v| match v {
+            Violation::RiffSize { .. } | Violation::DataSize { .. } => true,
+            _ => false,
+        })
+    }
+}
+
 impl WaveHeader {
+    /// Offset of the samples from the start of the file, any chunks ahead of
+    /// "data" are accounted for
     pub fn data_offset(&self) -> usize {
-        // RIFF chunk
-        12
-        // "fmt " chunk
-        + self.fmt.chunk_size as usize + 8
-        // Optional "fact" chunk
-        + self.fact.map_or(0, |f| f.chunk_size as usize + 8)
-        // "data" chunk
-        + 8
-    }
-}
-
-pub fn parse_header(input: &[u8]) -> IResult<&[u8], WaveHeader> {
-    // "RIFF" chunk
-    let (input, _) = tag("RIFF")(input)?;
-    let (input, riff_chunk_size) = le_u32(input)?;
-    let (input, _) = tag("WAVE")(input)?;
+        self.data.offset
+    }
+
+    /// Check the header against itself and a file of `total_len` bytes.
+    ///
+    /// Chunks may follow "data", so a data chunk ending before the file does
+    /// is fine.
+    pub fn validate(&self, total_len: usize) -> Validation {
+        let mut v = Validation {
+            violations: [None; MAX_VIOLATIONS],
+        };
+        let fmt = &self.fmt;
+
+        let expected = clamp_u32(total_len.saturating_sub(8));
+        if self.riff.chunk_size != expected {
+            v.push(Violation::RiffSize {
+                header: self.riff.chunk_size,
+                expected,
+            });
+        }
+
+        let available = clamp_u32(total_len.saturating_sub(self.data.offset));
+        if self.data.chunk_size > available {
+            v.push(Violation::DataSize {
+                header: self.data.chunk_size,
+                available,
+            });
+        }
+
+        if fmt.num_channels == 0 {
+            v.push(Violation::NoChannels);
+        }
+        if fmt.sample_rate == 0 {
+            v.push(Violation::NoSampleRate);
+        }
+        if fmt.bits_per_sample == 0 {
+            v.push(Violation::NoBitsPerSample);
+        }
+
+        // Samples are padded to whole bytes, e.g. 12-bit in 2 bytes
+        let bytes_per_sample = (u32::from(fmt.bits_per_sample) + 7) / 8;
+        let expected = u32::from(fmt.num_channels) * bytes_per_sample;
+        if u32::from(fmt.block_align) != expected {
+            v.push(Violation::BlockAlign {
+                header: fmt.block_align,
+                expected: clamp_u16(expected),
+            });
+        }
+
+        let expected = fmt.sample_rate.saturating_mul(u32::from(fmt.block_align));
+        if fmt.byte_rate != expected {
+            v.push(Violation::ByteRate {
+                header: fmt.byte_rate,
+                expected,
+            });
+        }
+
+        let valid = fmt.valid_bits_per_sample();
+        if valid > fmt.bits_per_sample {
+            v.push(Violation::ValidBits {
+                valid,
+                container: fmt.bits_per_sample,
+            });
+        }
+
+        // Already reported if the data size runs past the end
+        let partial =
+            fmt.block_align != 0 && self.data.chunk_size % u32::from(fmt.block_align) != 0;
+        if partial && self.data.chunk_size <= available {
+            v.push(Violation::PartialFrame {
+                data_size: self.data.chunk_size,
+                block_align: fmt.block_align,
+            });
+        }
+
+        v
+    }
+
+    /// Fix up the sizes of a truncated or streamed file of `total_len` bytes.
+    ///
+    /// A data size of 0, `UNKNOWN_SIZE` or one that runs past the end of the
+    /// file is cut to the whole frames available, a RIFF size of 0,
+    /// `UNKNOWN_SIZE` or past the end is set to the file length. Returns true
+    /// if anything changed.
+    pub fn repair(&mut self, total_len: usize) -> bool {
+        let old = (self.riff.chunk_size, self.data.chunk_size);
+        let available = clamp_u32(total_len.saturating_sub(self.data.offset));
+
+        let size = self.data.chunk_size;
+        if size == 0 || size == UNKNOWN_SIZE || size > available {
+            let block_align = u32::from(self.fmt.block_align.max(1));
+            self.data.chunk_size = available - available % block_align;
+        }
+
+        let riff_size = self.riff.chunk_size;
+        if riff_size == 0
+            || riff_size == UNKNOWN_SIZE
+            || (riff_size as usize).saturating_add(8) > total_len
+        {
+            self.riff.chunk_size = clamp_u32(total_len.saturating_sub(8));
+        }
+
+        (self.riff.chunk_size, self.data.chunk_size) != old
+    }
+}
+
+fn clamp_u32(x: usize) -> u32 {
+    x.min(UNKNOWN_SIZE as usize) as u32
+}
+
+fn clamp_u16(x: u32) -> u16 {
+    x.min(u32::from(core::u16::MAX)) as u16
+}
+
+/// A RIFF sub-chunk
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Chunk<'a> {
+    pub fourcc: [u8; 4],
+    /// Size from the chunk header, excluding the pad byte
+    pub size: u32,
+    /// Offset of the body from the start of the file
+    pub offset: usize,
+    /// Shorter than `size` if the input is truncated
+    pub body: &'a [u8],
+}
+
+impl<'a> Chunk<'a> {
+    /// `None` for chunks without a `ChunkId`, compare `fourcc` for those
+    pub fn id(&self) -> Option<ChunkId> {
+        ChunkId::from_fourcc(self.fourcc)
+    }
+
+    pub fn is(&self, id: ChunkId) -> bool {
+        self.fourcc == id.fourcc()
+    }
+
+    /// True if the whole body is present in the input
+    pub fn is_complete(&self) -> bool {
+        self.body.len() == self.size as usize
+    }
+}
+
+/// Iterator over a sequence of RIFF chunks.
+///
+/// Odd sized chunks are followed by a pad byte. Iteration stops at the first
+/// truncated chunk, after yielding it, so a header-only prefix of a file
+/// still yields the "data" chunk.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct Chunks<'a> {
+    input: &'a [u8],
+    /// File offset of `input`
+    offset: usize,
+}
+
+impl<'a> Chunks<'a> {
+    /// Walk the chunks in `input`, which starts at `offset` in the file
+    pub fn new(input: &'a [u8], offset: usize) -> Self {
+        Chunks { input, offset }
+    }
+}
+
+impl<'a> Iterator for Chunks<'a> {
+    type Item = Chunk<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.input.len() < 8 {
+            self.input = &[];
+            return None;
+        }
+
+        let mut fourcc = [0; 4];
+        fourcc.copy_from_slice(&self.input[..4]);
+        let size = u32::from_le_bytes([self.input[4], self.input[5], self.input[6], self.input[7]]);
+        let rest = &self.input[8..];
+        let body_len = rest.len().min(size as usize);
+        let chunk = Chunk {
+            fourcc,
+            size,
+            offset: self.offset.saturating_add(8),
+            body: &rest[..body_len],
+        };
+
+        let padded = (size as usize).saturating_add(size as usize & 1);
+        if padded >= rest.len() {
+            self.input = &[];
+        } else {
+            self.input = &rest[padded..];
+            self.offset = self.offset.saturating_add(8 + padded);
+        }
+
+        Some(chunk)
+    }
+}
+
+/// Parse the "RIFF" header and return a walker over the chunks that follow.
+///
+/// The walk is limited to the RIFF chunk size, trailing bytes are ignored.
+pub fn parse_riff(input: &[u8]) -> Result<(ChunkRiff, Chunks<'_>), WaveError> {
+    if bytes(input, 0, 0, 4)? != ChunkId::RIFF.fourcc() {
+        return Err(WaveError::NotRiff);
+    }
+    let riff_chunk_size = le_u32(input, 0, 4)?;
+    if bytes(input, 0, 8, 4)? != Format::WAVE.as_le_u32().to_le_bytes() {
+        return Err(WaveError::NotWave);
+    }
     let riff = ChunkRiff {
         chunk_id: ChunkId::RIFF,
         chunk_size: riff_chunk_size,
         format: Format::WAVE,
     };
 
-    // "fmt " chunk
-    let (input, _) = tag("fmt ")(input)?;
-    let (input, fmt_chunk_size) = le_u32(input)?;
-    let (input, audio_format) = le_u16(input)?;
-    let audio_format = AudioFormat::try_from(audio_format)
-        .map_err(|_| nom::Err::Failure((input, nom::error::ErrorKind::ParseTo)))?;
-    let (input, num_channels) = le_u16(input)?;
-    let (input, sample_rate) = le_u32(input)?;
-    let (input, byte_rate) = le_u32(input)?;
-    let (input, block_align) = le_u16(input)?;
-    let (input, bits_per_sample) = le_u16(input)?;
-
-    let input = if fmt_chunk_size == 18 {
-        let (input, _) = le_u16(input)?;
-        input
+    // The chunk size covers the "WAVE" form type, streamed files may not
+    // know it
+    let end = if riff_chunk_size == 0 || riff_chunk_size == UNKNOWN_SIZE {
+        input.len()
     } else {
-        input
+        (riff_chunk_size as usize)
+            .saturating_add(8)
+            .min(input.len())
+    };
+    let chunks = Chunks::new(&input[12..end.max(12)], 12);
+
+    Ok((riff, chunks))
+}
+
+fn parse_fmt(chunk: &Chunk) -> Result<ChunkFmt, WaveError> {
+    let inconsistent = WaveError::InconsistentSizes {
+        offset: chunk.offset.saturating_sub(8),
     };
+    if chunk.size < 16 {
+        return Err(inconsistent);
+    }
 
-    let fmt = ChunkFmt {
+    let (body, base) = (chunk.body, chunk.offset);
+    let format_tag = le_u16(body, base, 0)?;
+    let extensible = if format_tag == WAVE_FORMAT_EXTENSIBLE {
+        // 22 bytes follow the cbSize field
+        if chunk.size < 40 || le_u16(body, base, 16)? < 22 {
+            return Err(inconsistent);
+        }
+        let mut sub_format = [0; 16];
+        sub_format.copy_from_slice(bytes(body, base, 24, 16)?);
+        Some(FmtExtensible {
+            valid_bits_per_sample: le_u16(body, base, 18)?,
+            channel_mask: le_u32(body, base, 20)?,
+            sub_format,
+        })
+    } else {
+        None
+    };
+
+    let audio_format = match extensible {
+        Some(ext) => {
+            if ext.sub_format[2..] != SUB_FORMAT_GUID_TAIL {
+                return Err(WaveError::UnsupportedSubFormat(ext.sub_format));
+            }
+            AudioFormat::try_from(u16::from_le_bytes([ext.sub_format[0], ext.sub_format[1]]))
+                .map_err(|_| WaveError::UnsupportedSubFormat(ext.sub_format))?
+        }
+        None => AudioFormat::try_from(format_tag)?,
+    };
+
+    Ok(ChunkFmt {
         chunk_id: ChunkId::FMT,
-        chunk_size: fmt_chunk_size,
+        chunk_size: chunk.size,
         audio_format,
-        num_channels,
-        sample_rate,
-        byte_rate,
-        block_align,
-        bits_per_sample,
-    };
+        num_channels: le_u16(body, base, 2)?,
+        sample_rate: le_u32(body, base, 4)?,
+        byte_rate: le_u32(body, base, 8)?,
+        block_align: le_u16(body, base, 12)?,
+        bits_per_sample: le_u16(body, base, 14)?,
+        extensible,
+    })
+}
 
-    // "fact" chunk
-    let (input, maybe_fact) = opt(tag("fact"))(input)?;
-    let (input, fact) = if maybe_fact.is_some() {
-        let (input, fact_chunk_size) = le_u32(input)?;
-        let (input, fact_size) = le_u32(input)?;
-        let fact = Some(ChunkFact {
-            chunk_id: ChunkId::FACT,
-            chunk_size: fact_chunk_size,
-            fact_size,
+fn parse_fact(chunk: &Chunk) -> Result<ChunkFact, WaveError> {
+    if chunk.size < 4 {
+        return Err(WaveError::InconsistentSizes {
+            offset: chunk.offset.saturating_sub(8),
         });
-        (input, fact)
+    }
+
+    Ok(ChunkFact {
+        chunk_id: ChunkId::FACT,
+        chunk_size: chunk.size,
+        fact_size: le_u32(chunk.body, chunk.offset, 0)?,
+    })
+}
+
+/// Parse the header of a WAVE file.
+///
+/// Chunks other than "fmt ", "fact" and "data" are skipped, use `parse_riff`
+/// to get at them. The input only needs to extend to the start of the
+/// samples.
+pub fn parse_header(input: &[u8]) -> Result<WaveHeader, WaveError> {
+    let (riff, chunks) = parse_riff(input)?;
+
+    let mut fmt = None;
+    let mut fact = None;
+    let mut end = 12;
+    for chunk in chunks {
+        end = chunk
+            .offset
+            .checked_add(chunk.body.len())
+            .and_then(|end| end.checked_add(chunk.size as usize & 1))
+            .ok_or(WaveError::InconsistentSizes {
+                offset: chunk.offset.saturating_sub(8),
+            })?;
+        if chunk.is(ChunkId::FMT) {
+            fmt = Some(parse_fmt(&chunk)?);
+        } else if chunk.is(ChunkId::FACT) {
+            fact = Some(parse_fact(&chunk)?);
+        } else if chunk.is(ChunkId::DATA) {
+            return Ok(WaveHeader {
+                riff,
+                fmt: fmt.ok_or(WaveError::MissingFmt)?,
+                fact,
+                data: ChunkData {
+                    chunk_id: ChunkId::DATA,
+                    chunk_size: chunk.size,
+                    offset: chunk.offset,
+                },
+            });
+        }
+    }
+
+    // Ran out of input, or the RIFF chunk ended, without finding "data"
+    if end.saturating_add(8) > input.len() {
+        Err(WaveError::Truncated {
+            offset: end,
+            needed: 8,
+        })
     } else {
-        (input, None)
-    };
+        Err(WaveError::MissingData)
+    }
+}
+
+/// A parsed WAVE file with its sample data bounds checked against the input
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ParsedWave<'a> {
+    header: WaveHeader,
+    file: &'a [u8],
+    data: &'a [u8],
+}
+
+impl<'a> ParsedWave<'a> {
+    /// Parse `input`, which must hold the whole "data" chunk
+    pub fn parse(input: &'a [u8]) -> Result<Self, WaveError> {
+        let header = parse_header(input)?;
+        let start = header.data_offset();
+        let end = start.checked_add(header.data.chunk_size as usize).ok_or(
+            WaveError::InconsistentSizes {
+                offset: start.saturating_sub(8),
+            },
+        )?;
+        let data = input.get(start..end).ok_or(WaveError::Truncated {
+            offset: input.len(),
+            needed: end.saturating_sub(input.len()),
+        })?;
+
+        Ok(ParsedWave {
+            header,
+            file: input,
+            data,
+        })
+    }
+
+    pub fn header(&self) -> &WaveHeader {
+        &self.header
+    }
+
+    pub fn fmt(&self) -> &ChunkFmt {
+        &self.header.fmt
+    }
+
+    /// The whole input
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.file
+    }
+
+    /// The body of the "data" chunk
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+
+    /// Number of whole frames in the data
+    pub fn num_frames(&self) -> usize {
+        match self.header.fmt.block_align {
+            0 => 0,
+            block_align => self.data.len() / usize::from(block_align),
+        }
+    }
+
+    pub fn find_chunk(&self, id: ChunkId) -> Option<Chunk<'a>> {
+        // The RIFF header was already checked
+        find_chunk(self.file, id).ok().and_then(|c| c)
+    }
+
+    pub fn info(&self) -> Result<Option<Info<'a>>, WaveError> {
+        find_info(self.file)
+    }
+
+    pub fn sampler(&self) -> Result<Option<Sampler<'a>>, WaveError> {
+        self.find_chunk(ChunkId::SMPL)
+            .map(|c| parse_sampler(&c))
+            .transpose()
+    }
+
+    pub fn cue_points(&self) -> Result<Option<CuePoints<'a>>, WaveError> {
+        self.find_chunk(ChunkId::CUE)
+            .map(|c| parse_cue(&c))
+            .transpose()
+    }
+
+    /// Interleaved 16-bit PCM samples
+    pub fn samples_i16(&self) -> Result<Samples<'a, i16>, WaveError> {
+        self.samples(AudioFormat::PCM, 16, |b| i16::from_le_bytes([b[0], b[1]]))
+    }
+
+    /// Interleaved 24-bit PCM samples, sign extended
+    pub fn samples_i24(&self) -> Result<Samples<'a, i32>, WaveError> {
+        self.samples(AudioFormat::PCM, 24, |b| {
+            i32::from_le_bytes([0, b[0], b[1], b[2]]) >> 8
+        })
+    }
+
+    /// Interleaved 32-bit PCM samples
+    pub fn samples_i32(&self) -> Result<Samples<'a, i32>, WaveError> {
+        self.samples(AudioFormat::PCM, 32, |b| {
+            i32::from_le_bytes([b[0], b[1], b[2], b[3]])
+        })
+    }
+
+    /// Interleaved 32-bit float samples
+    pub fn samples_f32(&self) -> Result<Samples<'a, f32>, WaveError> {
+        self.samples(AudioFormat::FLOAT, 32, |b| {
+            f32::from_bits(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        })
+    }
+
+    fn samples<T>(
+        &self,
+        audio_format: AudioFormat,
+        bits_per_sample: u16,
+        decode: fn(&[u8]) -> T,
+    ) -> Result<Samples<'a, T>, WaveError> {
+        let fmt = &self.header.fmt;
+        if fmt.audio_format != audio_format {
+            return Err(WaveError::UnsupportedFormat(fmt.audio_format.as_le_u16()));
+        }
+        if fmt.bits_per_sample != bits_per_sample {
+            return Err(WaveError::UnsupportedBitsPerSample(fmt.bits_per_sample));
+        }
+
+        // Drop a trailing partial frame
+        let frames_len = self.num_frames() * usize::from(fmt.block_align);
+        Ok(Samples {
+            chunks: self.data[..frames_len].chunks_exact(usize::from(bits_per_sample / 8)),
+            decode,
+        })
+    }
+}
+
+/// Iterator over the samples of a `ParsedWave`
+#[derive(Clone)]
+pub struct Samples<'a, T> {
+    chunks: core::slice::ChunksExact<'a, u8>,
+    decode: fn(&[u8]) -> T,
+}
+
+impl<'a, T> Iterator for Samples<'a, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.chunks.next().map(self.decode)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.chunks.size_hint()
+    }
+}
+
+impl<'a, T> ExactSizeIterator for Samples<'a, T> {}
+
+/// A WAVE file embedded with `wm8960_macros::include_wav!`, the header is
+/// parsed and validated when the firmware is built
+#[derive(Copy, Clone, PartialEq)]
+pub struct EmbeddedWave {
+    pub header: WaveHeader,
+    /// The whole file, 4 byte aligned
+    pub bytes: &'static [u8],
+}
+
+impl EmbeddedWave {
+    /// The body of the "data" chunk
+    pub fn data(&self) -> &'static [u8] {
+        self.parsed().data
+    }
+
+    pub fn parsed(&self) -> ParsedWave<'static> {
+        let start = self.header.data.offset;
+        let end = start.saturating_add(self.header.data.chunk_size as usize);
+        ParsedWave {
+            header: self.header,
+            file: self.bytes,
+            // Checked by the macro, but don't trust a hand written one
+            data: self.bytes.get(start..end).unwrap_or(&[]),
+        }
+    }
+}
+
+impl fmt::Debug for EmbeddedWave {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("EmbeddedWave")
+            .field("header", &self.header)
+            .field("len", &self.bytes.len())
+            .finish()
+    }
+}
+
+/// Find the first chunk with `id` in a WAVE file
+pub fn find_chunk(input: &[u8], id: ChunkId) -> Result<Option<Chunk<'_>>, WaveError> {
+    let (_, mut chunks) = parse_riff(input)?;
+    Ok(chunks.find(|c| c.is(id)))
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum LoopType {
+    Forward,
+    /// Ping-pong
+    Alternating,
+    Backward,
+    Unknown,
+}
+
+impl From<u32> for LoopType {
+    fn from(t: u32) -> Self {
+        match t {
+            0 => LoopType::Forward,
+            1 => LoopType::Alternating,
+            2 => LoopType::Backward,
+            _ => LoopType::Unknown,
+        }
+    }
+}
 
-    // "data" chunk
-    let (input, _) = tag("data")(input)?;
-    let (input, data_chunk_size) = le_u32(input)?;
-    let data = ChunkData {
-        chunk_id: ChunkId::DATA,
-        chunk_size: data_chunk_size,
+/// Loop record of a "smpl" chunk, positions are in frames
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SampleLoop {
+    pub cue_point_id: u32,
+    pub loop_type: LoopType,
+    pub start: u32,
+    /// Last frame of the loop, inclusive
+    pub end: u32,
+    pub fraction: u32,
+    /// 0 loops until released
+    pub play_count: u32,
+}
+
+/// "smpl" chunk, the loop records are read from the chunk body
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Sampler<'a> {
+    pub manufacturer: u32,
+    pub product: u32,
+    /// Nanoseconds per frame
+    pub sample_period: u32,
+    pub midi_unity_note: u32,
+    pub midi_pitch_fraction: u32,
+    pub smpte_format: u32,
+    pub smpte_offset: u32,
+    pub num_sample_loops: u32,
+    pub sampler_data: u32,
+    loops: &'a [u8],
+}
+
+impl<'a> Sampler<'a> {
+    const LEN: usize = 36;
+    const LOOP_LEN: usize = 24;
+
+    pub fn loops(&self) -> impl Iterator<Item = SampleLoop> + 'a {
+        self.loops.chunks_exact(Self::LOOP_LEN).map(|b| SampleLoop {
+            cue_point_id: u32_at(b, 0),
+            loop_type: LoopType::from(u32_at(b, 4)),
+            start: u32_at(b, 8),
+            end: u32_at(b, 12),
+            fraction: u32_at(b, 16),
+            play_count: u32_at(b, 20),
+        })
+    }
+}
+
+/// Parse a "smpl" chunk, the loop count is checked against the chunk size
+pub fn parse_sampler<'a>(chunk: &Chunk<'a>) -> Result<Sampler<'a>, WaveError> {
+    let (body, base) = (chunk.body, chunk.offset);
+    let num_sample_loops = le_u32(body, base, 28)?;
+    let sampler_data = le_u32(body, base, 32)?;
+    let loops_len = (num_sample_loops as usize)
+        .checked_mul(Sampler::LOOP_LEN)
+        .and_then(|l| l.checked_add(Sampler::LEN))
+        .filter(|&l| l <= chunk.size as usize)
+        .ok_or(WaveError::InconsistentSizes {
+            offset: chunk.offset.saturating_sub(8),
+        })?;
+
+    Ok(Sampler {
+        manufacturer: le_u32(body, base, 0)?,
+        product: le_u32(body, base, 4)?,
+        sample_period: le_u32(body, base, 8)?,
+        midi_unity_note: le_u32(body, base, 12)?,
+        midi_pitch_fraction: le_u32(body, base, 16)?,
+        smpte_format: le_u32(body, base, 20)?,
+        smpte_offset: le_u32(body, base, 24)?,
+        num_sample_loops,
+        sampler_data,
+        loops: bytes(body, base, Sampler::LEN, loops_len - Sampler::LEN)?,
+    })
+}
+
+/// Cue point of a "cue " chunk
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct CuePoint {
+    pub id: u32,
+    /// Play order position
+    pub position: u32,
+    /// "data" for uncompressed files
+    pub data_chunk_id: [u8; 4],
+    pub chunk_start: u32,
+    pub block_start: u32,
+    /// Frame within the data chunk
+    pub sample_offset: u32,
+}
+
+/// "cue " chunk
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct CuePoints<'a> {
+    pub num_cue_points: u32,
+    points: &'a [u8],
+}
+
+impl<'a> CuePoints<'a> {
+    const POINT_LEN: usize = 24;
+
+    pub fn iter(&self) -> impl Iterator<Item = CuePoint> + 'a {
+        self.points.chunks_exact(Self::POINT_LEN).map(|b| CuePoint {
+            id: u32_at(b, 0),
+            position: u32_at(b, 4),
+            data_chunk_id: u32_at(b, 8).to_le_bytes(),
+            chunk_start: u32_at(b, 12),
+            block_start: u32_at(b, 16),
+            sample_offset: u32_at(b, 20),
+        })
+    }
+
+    pub fn find(&self, id: u32) -> Option<CuePoint> {
+        self.iter().find(|c| c.id == id)
+    }
+}
+
+/// Parse a "cue " chunk, the point count is checked against the chunk size
+pub fn parse_cue<'a>(chunk: &Chunk<'a>) -> Result<CuePoints<'a>, WaveError> {
+    let (body, base) = (chunk.body, chunk.offset);
+    let num_cue_points = le_u32(body, base, 0)?;
+    let len = (num_cue_points as usize)
+        .checked_mul(CuePoints::POINT_LEN)
+        .filter(|&l| l <= (chunk.size as usize).saturating_sub(4))
+        .ok_or(WaveError::InconsistentSizes {
+            offset: chunk.offset.saturating_sub(8),
+        })?;
+
+    Ok(CuePoints {
+        num_cue_points,
+        points: bytes(body, base, 4, len)?,
+    })
+}
+
+/// Text from a "LIST" chunk of type "INFO", fields are `None` when absent or
+/// not valid UTF-8
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct Info<'a> {
+    /// INAM
+    pub name: Option<&'a str>,
+    /// IART
+    pub artist: Option<&'a str>,
+    /// ICMT
+    pub comment: Option<&'a str>,
+    /// ICOP
+    pub copyright: Option<&'a str>,
+    /// ICRD
+    pub creation_date: Option<&'a str>,
+    /// IGNR
+    pub genre: Option<&'a str>,
+    /// IPRD, the album or product
+    pub product: Option<&'a str>,
+    /// ISFT
+    pub software: Option<&'a str>,
+    /// IENG
+    pub engineer: Option<&'a str>,
+    /// ISBJ
+    pub subject: Option<&'a str>,
+    list: Chunks<'a>,
+}
+
+impl<'a> Info<'a> {
+    /// Every subchunk, including those without a field above
+    pub fn entries(&self) -> impl Iterator<Item = ([u8; 4], &'a str)> + 'a {
+        self.list
+            .filter_map(|c| info_str(c.body).map(|s| (c.fourcc, s)))
+    }
+
+    pub fn get(&self, fourcc: [u8; 4]) -> Option<&'a str> {
+        self.entries().find(|(id, _)| *id == fourcc).map(|(_, s)| s)
+    }
+}
+
+/// Parse a "LIST" chunk of type "INFO"
+pub fn parse_info<'a>(chunk: &Chunk<'a>) -> Result<Info<'a>, WaveError> {
+    let list_type = bytes(chunk.body, chunk.offset, 0, 4)?;
+    if !chunk.is(ChunkId::LIST) || list_type != ChunkId::INFO.fourcc() {
+        return Err(WaveError::UnexpectedChunk {
+            offset: chunk.offset.saturating_sub(8),
+        });
+    }
+
+    let mut info = Info {
+        list: Chunks::new(&chunk.body[4..], chunk.offset + 4),
+        ..Info::default()
     };
+    for c in info.list {
+        let field = match &c.fourcc {
+            b"INAM" => &mut info.name,
+            b"IART" => &mut info.artist,
+            b"ICMT" => &mut info.comment,
+            b"ICOP" => &mut info.copyright,
+            b"ICRD" => &mut info.creation_date,
+            b"IGNR" => &mut info.genre,
+            b"IPRD" => &mut info.product,
+            b"ISFT" => &mut info.software,
+            b"IENG" => &mut info.engineer,
+            b"ISBJ" => &mut info.subject,
+            _ => continue,
+        };
+        *field = info_str(c.body);
+    }
+    Ok(info)
+}
+
+/// Find and parse the "INFO" list of a WAVE file, other "LIST" types such as
+/// "adtl" are skipped
+pub fn find_info(input: &[u8]) -> Result<Option<Info<'_>>, WaveError> {
+    let (_, chunks) = parse_riff(input)?;
+    for chunk in chunks.filter(|c| c.is(ChunkId::LIST)) {
+        if chunk.body.get(..4) == Some(&ChunkId::INFO.fourcc()[..]) {
+            return parse_info(&chunk).map(Some);
+        }
+    }
+    Ok(None)
+}
+
+/// INFO strings are NUL terminated, often with extra padding
+fn info_str(body: &[u8]) -> Option<&str> {
+    let len = body
+        .iter()
+        .position(|&b| b == 0)
+        .unwrap_or_else(|| body.len());
+    core::str::from_utf8(&body[..len]).ok()
+}
+
+/// Builds the header of a new file, defaults to 16-bit stereo PCM at the
+/// codec sample rate
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct WaveHeaderBuilder {
+    audio_format: AudioFormat,
+    num_channels: u16,
+    sample_rate: u32,
+    bits_per_sample: u16,
+    extensible: Option<(u16, u32)>,
+    num_frames: u32,
+}
+
+impl Default for WaveHeaderBuilder {
+    fn default() -> Self {
+        WaveHeaderBuilder::new()
+    }
+}
+
+impl WaveHeaderBuilder {
+    pub fn new() -> Self {
+        WaveHeaderBuilder {
+            audio_format: AudioFormat::PCM,
+            num_channels: NUM_CHANNELS as u16,
+            sample_rate: SAMPLE_RATE,
+            bits_per_sample: 16,
+            extensible: None,
+            num_frames: 0,
+        }
+    }
+
+    pub fn audio_format(mut self, audio_format: AudioFormat) -> Self {
+        self.audio_format = audio_format;
+        self
+    }
+
+    pub fn num_channels(mut self, num_channels: u16) -> Self {
+        self.num_channels = num_channels;
+        self
+    }
+
+    pub fn sample_rate(mut self, sample_rate: u32) -> Self {
+        self.sample_rate = sample_rate;
+        self
+    }
+
+    pub fn bits_per_sample(mut self, bits_per_sample: u16) -> Self {
+        self.bits_per_sample = bits_per_sample;
+        self
+    }
+
+    /// Use WAVE_FORMAT_EXTENSIBLE, `bits_per_sample` is then the container
+    /// size
+    pub fn extensible(mut self, valid_bits_per_sample: u16, channel_mask: u32) -> Self {
+        self.extensible = Some((valid_bits_per_sample, channel_mask));
+        self
+    }
+
+    /// Size the data chunk, `WaveWriter` sets it on finalize
+    pub fn num_frames(mut self, num_frames: u32) -> Self {
+        self.num_frames = num_frames;
+        self
+    }
+
+    pub fn build(self) -> WaveHeader {
+        let block_align =
+            clamp_u16(u32::from(self.num_channels) * ((u32::from(self.bits_per_sample) + 7) / 8));
+        let extensible = self
+            .extensible
+            .map(|(valid_bits_per_sample, channel_mask)| {
+                let mut sub_format = [0; 16];
+                sub_format[..2].copy_from_slice(&self.audio_format.as_le_u16().to_le_bytes());
+                sub_format[2..].copy_from_slice(&SUB_FORMAT_GUID_TAIL);
+                FmtExtensible {
+                    valid_bits_per_sample,
+                    channel_mask,
+                    sub_format,
+                }
+            });
+
+        let fmt = ChunkFmt {
+            chunk_id: ChunkId::FMT,
+            chunk_size: if extensible.is_some() {
+                40
+            } else if self.audio_format != AudioFormat::PCM {
+                18
+            } else {
+                16
+            },
+            audio_format: self.audio_format,
+            num_channels: self.num_channels,
+            sample_rate: self.sample_rate,
+            byte_rate: self.sample_rate.saturating_mul(u32::from(block_align)),
+            block_align,
+            bits_per_sample: self.bits_per_sample,
+            extensible,
+        };
 
-    Ok((
-        input,
-        WaveHeader {
-            riff,
+        // Required for anything but PCM
+        let fact = if self.audio_format != AudioFormat::PCM {
+            Some(ChunkFact {
+                chunk_id: ChunkId::FACT,
+                chunk_size: 4,
+                fact_size: self.num_frames,
+            })
+        } else {
+            None
+        };
+
+        let mut header = WaveHeader {
+            riff: ChunkRiff {
+                chunk_id: ChunkId::RIFF,
+                chunk_size: 0,
+                format: Format::WAVE,
+            },
             fmt,
             fact,
-            data,
-        },
-    ))
+            data: ChunkData {
+                chunk_id: ChunkId::DATA,
+                chunk_size: self.num_frames.saturating_mul(u32::from(block_align)),
+                offset: 0,
+            },
+        };
+        header.data.offset = header_len(&header);
+        header.set_data_size(header.data.chunk_size);
+        header
+    }
+}
+
+/// Longest header `write_header` produces, an extensible "fmt " and a "fact"
+pub const MAX_HEADER_LEN: usize = 12 + (8 + 40) + (8 + 4) + 8;
+
+/// Length of the header `write_header` produces
+pub fn header_len(header: &WaveHeader) -> usize {
+    12 + 8 + fmt_len(&header.fmt) as usize + header.fact.map_or(0, |_| 8 + 4) + 8
+}
+
+/// The "fmt " body is 16 bytes, plus cbSize and the extension if any
+fn fmt_len(fmt: &ChunkFmt) -> u32 {
+    if fmt.extensible.is_some() {
+        40
+    } else if fmt.chunk_size >= 18 {
+        18
+    } else {
+        16
+    }
+}
+
+/// Serialize `header` into `buf`, returning the number of bytes written.
+///
+/// Only "fmt ", "fact" and "data" are written, in that order, so the
+/// samples start at `header_len(header)` which may differ from
+/// `header.data.offset` for a parsed file.
+pub fn write_header(header: &WaveHeader, buf: &mut [u8]) -> Result<usize, WaveError> {
+    let len = header_len(header);
+    if buf.len() < len {
+        return Err(WaveError::Truncated {
+            offset: buf.len(),
+            needed: len - buf.len(),
+        });
+    }
+
+    let fmt = &header.fmt;
+    let fmt_size = fmt_len(fmt);
+    let mut w = ByteWriter { buf, pos: 0 };
+    w.bytes(&ChunkId::RIFF.fourcc());
+    w.u32(header.riff.chunk_size);
+    w.bytes(&Format::WAVE.as_le_u32().to_le_bytes());
+
+    w.bytes(&ChunkId::FMT.fourcc());
+    w.u32(fmt_size);
+    match fmt.extensible {
+        Some(_) => w.u16(WAVE_FORMAT_EXTENSIBLE),
+        None => w.u16(fmt.audio_format.as_le_u16()),
+    }
+    w.u16(fmt.num_channels);
+    w.u32(fmt.sample_rate);
+    w.u32(fmt.byte_rate);
+    w.u16(fmt.block_align);
+    w.u16(fmt.bits_per_sample);
+    if let Some(ext) = fmt.extensible {
+        w.u16(22);
+        w.u16(ext.valid_bits_per_sample);
+        w.u32(ext.channel_mask);
+        w.bytes(&ext.sub_format);
+    } else if fmt_size == 18 {
+        w.u16(0);
+    }
+
+    if let Some(fact) = header.fact {
+        w.bytes(&ChunkId::FACT.fourcc());
+        w.u32(4);
+        w.u32(fact.fact_size);
+    }
+
+    w.bytes(&ChunkId::DATA.fourcc());
+    w.u32(header.data.chunk_size);
+
+    Ok(w.pos)
+}
+
+impl WaveHeader {
+    /// Set the data size and the RIFF size to match, assuming the layout of
+    /// `write_header` and nothing after the data
+    pub fn set_data_size(&mut self, data_size: u32) {
+        self.data.chunk_size = data_size;
+        let riff_size = (header_len(self) - 8) as u32;
+        self.riff.chunk_size = riff_size
+            .saturating_add(data_size)
+            .saturating_add(data_size & 1);
+        if let Some(fact) = self.fact.as_mut() {
+            if self.fmt.block_align != 0 {
+                fact.fact_size = data_size / u32::from(self.fmt.block_align);
+            }
+        }
+    }
+}
+
+/// Storage a `WaveWriter` streams into, e.g. flash or a RAM buffer
+pub trait WaveSink {
+    type Error;
+
+    /// Write `data` at `offset` from the start of the file
+    fn write_at(&mut self, offset: usize, data: &[u8]) -> Result<(), Self::Error>;
+}
+
+impl WaveSink for &mut [u8] {
+    type Error = WaveError;
+
+    fn write_at(&mut self, offset: usize, data: &[u8]) -> Result<(), Self::Error> {
+        let end = offset.saturating_add(data.len());
+        if end > self.len() {
+            return Err(WaveError::Truncated {
+                offset: self.len(),
+                needed: end - self.len(),
+            });
+        }
+        self[offset..end].copy_from_slice(data);
+        Ok(())
+    }
+}
+
+/// Streams samples after a header, the sizes are patched on `finalize`.
+///
+/// Until then the header carries `UNKNOWN_SIZE`, so a file cut short can
+/// still be parsed and fixed up with `WaveHeader::repair`.
+#[derive(Debug)]
+pub struct WaveWriter<S> {
+    sink: S,
+    header: WaveHeader,
+    /// Bytes of sample data written
+    data_len: usize,
+}
+
+impl<S: WaveSink> WaveWriter<S> {
+    /// Write the header to the start of `sink`, see `WaveHeaderBuilder`
+    pub fn new(mut sink: S, mut header: WaveHeader) -> Result<Self, S::Error> {
+        header.riff.chunk_size = UNKNOWN_SIZE;
+        header.data.chunk_size = UNKNOWN_SIZE;
+        header.data.offset = header_len(&header);
+        write_to(&mut sink, &header)?;
+        Ok(WaveWriter {
+            sink,
+            header,
+            data_len: 0,
+        })
+    }
+
+    pub fn header(&self) -> &WaveHeader {
+        &self.header
+    }
+
+    /// Bytes of sample data written so far
+    pub fn data_len(&self) -> usize {
+        self.data_len
+    }
+
+    /// Append raw sample data, already in the header's format
+    pub fn write_bytes(&mut self, data: &[u8]) -> Result<(), S::Error> {
+        let offset = self.header.data.offset + self.data_len;
+        self.sink.write_at(offset, data)?;
+        self.data_len += data.len();
+        Ok(())
+    }
+
+    /// Append 16-bit samples, interleaved as in the header, which has to be
+    /// 16-bit PCM
+    pub fn write_samples(&mut self, samples: &[i16]) -> Result<(), S::Error>
+    where
+        S::Error: From<WaveError>,
+    {
+        let fmt = &self.header.fmt;
+        if fmt.audio_format != AudioFormat::PCM {
+            return Err(WaveError::UnsupportedFormat(fmt.audio_format.as_le_u16()).into());
+        }
+        if fmt.bits_per_sample != 16 {
+            return Err(WaveError::UnsupportedBitsPerSample(fmt.bits_per_sample).into());
+        }
+
+        let mut buf = [0; 128];
+        for chunk in samples.chunks(buf.len() / 2) {
+            for (s, b) in chunk.iter().zip(buf.chunks_exact_mut(2)) {
+                b.copy_from_slice(&s.to_le_bytes());
+            }
+            self.write_bytes(&buf[..chunk.len() * 2])?;
+        }
+        Ok(())
+    }
+
+    /// Pad the data chunk to an even length and patch the sizes, returning
+    /// the sink and the final header
+    pub fn finalize(mut self) -> Result<(S, WaveHeader), S::Error> {
+        if self.data_len % 2 != 0 {
+            self.sink
+                .write_at(self.header.data.offset + self.data_len, &[0])?;
+        }
+        self.header.set_data_size(clamp_u32(self.data_len));
+        write_to(&mut self.sink, &self.header)?;
+        Ok((self.sink, self.header))
+    }
+}
+
+fn write_to<S: WaveSink>(sink: &mut S, header: &WaveHeader) -> Result<(), S::Error> {
+    let mut buf = [0; MAX_HEADER_LEN];
+    let len = header_len(header);
+    // Can't fail, the buffer holds the longest header
+    let _ = write_header(header, &mut buf);
+    sink.write_at(0, &buf[..len])
+}
+
+struct ByteWriter<'a> {
+    buf: &'a mut [u8],
+    pos: usize,
+}
+
+impl<'a> ByteWriter<'a> {
+    fn bytes(&mut self, data: &[u8]) {
+        self.buf[self.pos..self.pos + data.len()].copy_from_slice(data);
+        self.pos += data.len();
+    }
+
+    fn u16(&mut self, x: u16) {
+        self.bytes(&x.to_le_bytes());
+    }
+
+    fn u32(&mut self, x: u32) {
+        self.bytes(&x.to_le_bytes());
+    }
+}
+
+/// `len` bytes at `pos` in `buf`, which starts at `base` in the file
+fn bytes(buf: &[u8], base: usize, pos: usize, len: usize) -> Result<&[u8], WaveError> {
+    pos.checked_add(len)
+        .and_then(|end| buf.get(pos..end))
+        .ok_or(WaveError::Truncated {
+            offset: base.saturating_add(pos),
+            needed: len,
+        })
+}
+
+fn le_u16(buf: &[u8], base: usize, pos: usize) -> Result<u16, WaveError> {
+    let b = bytes(buf, base, pos, 2)?;
+    Ok(u16::from_le_bytes([b[0], b[1]]))
+}
+
+fn le_u32(buf: &[u8], base: usize, pos: usize) -> Result<u32, WaveError> {
+    let b = bytes(buf, base, pos, 4)?;
+    Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+/// Caller checks the bounds
+fn u32_at(buf: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes([buf[pos], buf[pos + 1], buf[pos + 2], buf[pos + 3]])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 16-bit stereo PCM at the codec rate
+    fn pcm() -> WaveHeader {
+        WaveHeaderBuilder::new().num_frames(3).build()
+    }
+
+    #[test]
+    fn build_write_parse() {
+        let header = pcm();
+        let mut buf = [0; MAX_HEADER_LEN];
+        let len = write_header(&header, &mut buf).unwrap();
+        assert_eq!(len, header_len(&header));
+        assert_eq!(len, header.data.offset);
+        assert_eq!(parse_header(&buf[..len]), Ok(header));
+        assert_eq!(header.data.chunk_size, 3 * 4);
+        assert_eq!(header.riff.chunk_size as usize, len - 8 + 3 * 4);
+    }
+
+    #[test]
+    fn write_header_short_buffer() {
+        let header = pcm();
+        let mut buf = [0; MAX_HEADER_LEN];
+        let len = header_len(&header);
+        assert_eq!(
+            write_header(&header, &mut buf[..len - 1]),
+            Err(WaveError::Truncated {
+                offset: len - 1,
+                needed: 1
+            })
+        );
+    }
+
+    #[test]
+    fn writer_patches_sizes() {
+        let mut file = [0xAA; 128];
+        let header = WaveHeaderBuilder::new().build();
+        let mut writer = WaveWriter::new(&mut file[..], header).unwrap();
+        let offset = writer.header().data.offset;
+        writer.write_samples(&[1, -1, 2, -2, 3, -3]).unwrap();
+        assert_eq!(writer.data_len(), 12);
+        let (sink, header) = writer.finalize().unwrap();
+
+        let len = offset + 12;
+        assert_eq!(header.data.chunk_size, 12);
+        assert_eq!(header.riff.chunk_size as usize, len - 8);
+        assert_eq!(parse_header(sink), Ok(header));
+        assert_eq!(sink[offset..offset + 4], [1, 0, 0xFF, 0xFF]);
+        assert_eq!(sink[len], 0xAA);
+    }
+
+    #[test]
+    fn writer_unknown_sizes_until_finalize() {
+        let mut file = [0; 128];
+        let header = WaveHeaderBuilder::new().build();
+        let mut writer = WaveWriter::new(&mut file[..], header).unwrap();
+        writer.write_samples(&[0; 4]).unwrap();
+        let header = parse_header(&file).unwrap();
+        assert_eq!(header.riff.chunk_size, UNKNOWN_SIZE);
+        assert_eq!(header.data.chunk_size, UNKNOWN_SIZE);
+    }
+
+    #[test]
+    fn writer_pads_odd_data() {
+        let mut file = [0xAA; 128];
+        let header = WaveHeaderBuilder::new()
+            .num_channels(1)
+            .bits_per_sample(8)
+            .build();
+        let mut writer = WaveWriter::new(&mut file[..], header).unwrap();
+        let offset = writer.header().data.offset;
+        writer.write_bytes(&[0x80, 0x81, 0x82]).unwrap();
+        let (sink, header) = writer.finalize().unwrap();
+
+        assert_eq!(header.data.chunk_size, 3);
+        assert_eq!(sink[offset..offset + 4], [0x80, 0x81, 0x82, 0]);
+        assert_eq!(header.riff.chunk_size as usize, offset + 4 - 8);
+        assert_eq!(parse_header(sink), Ok(header));
+    }
+
+    #[test]
+    fn writer_samples_need_16_bit_pcm() {
+        let mut file = [0; 128];
+        let header = WaveHeaderBuilder::new().bits_per_sample(24).build();
+        let mut writer = WaveWriter::new(&mut file[..], header).unwrap();
+        assert_eq!(
+            writer.write_samples(&[0; 2]),
+            Err(WaveError::UnsupportedBitsPerSample(24))
+        );
+
+        let header = WaveHeaderBuilder::new()
+            .audio_format(AudioFormat::FLOAT)
+            .bits_per_sample(32)
+            .build();
+        let mut writer = WaveWriter::new(&mut file[..], header).unwrap();
+        assert_eq!(
+            writer.write_samples(&[0; 2]),
+            Err(WaveError::UnsupportedFormat(AudioFormat::FLOAT.as_le_u16()))
+        );
+        assert_eq!(writer.data_len(), 0);
+    }
+
+    const RING: &[u8] = include_bytes!("../../assets/ring.wav");
+
+    fn round_trip(header: WaveHeader) {
+        let mut buf = [0; MAX_HEADER_LEN];
+        let len = write_header(&header, &mut buf).unwrap();
+        assert_eq!(parse_header(&buf[..len]), Ok(header), "{:?}", header);
+    }
+
+    #[test]
+    fn chunk_id_fourcc() {
+        let ids = [
+            (ChunkId::RIFF, b"RIFF"),
+            (ChunkId::FMT, b"fmt "),
+            (ChunkId::FACT, b"fact"),
+            (ChunkId::DATA, b"data"),
+            (ChunkId::LIST, b"LIST"),
+            (ChunkId::INFO, b"INFO"),
+            (ChunkId::CUE, b"cue "),
+            (ChunkId::SMPL, b"smpl"),
+        ];
+        assert_eq!(ids.len(), ChunkId::ALL.len());
+        for (id, tag) in ids.iter() {
+            assert_eq!(id.fourcc(), **tag, "{:?}", id);
+            assert_eq!(ChunkId::from_fourcc(**tag), Some(*id));
+        }
+        assert_eq!(Format::WAVE.as_le_u32().to_le_bytes(), *b"WAVE");
+        assert_eq!(ChunkId::from_fourcc(*b"junk"), None);
+    }
+
+    #[test]
+    fn asset_fixture() {
+        let header = parse_header(RING).unwrap();
+        assert_eq!(header.fmt.audio_format, AudioFormat::PCM);
+        assert_eq!(header.fmt.num_channels, 2);
+        assert_eq!(header.fmt.sample_rate, 48_000);
+        assert_eq!(header.fmt.bits_per_sample, 16);
+        assert!(header.fact.is_some());
+        assert_eq!(header.data.offset, header_len(&header));
+        assert_eq!(
+            header.data.offset + header.data.chunk_size as usize,
+            RING.len()
+        );
+        assert!(header.validate(RING.len()).is_ok());
+
+        let mut buf = [0; MAX_HEADER_LEN];
+        let len = write_header(&header, &mut buf).unwrap();
+        assert_eq!(buf[..len], RING[..len]);
+        round_trip(header);
+    }
+
+    #[test]
+    fn pcm_round_trip() {
+        for num_channels in 1..=8 {
+            for sample_rate in [8000, 11_025, 44_100, 48_000, 192_000].iter() {
+                for bits_per_sample in [8, 16, 24, 32].iter() {
+                    for num_frames in [0, 1, 4801].iter() {
+                        let header = WaveHeaderBuilder::new()
+                            .num_channels(num_channels)
+                            .sample_rate(*sample_rate)
+                            .bits_per_sample(*bits_per_sample)
+                            .num_frames(*num_frames)
+                            .build();
+                        assert_eq!(header.fact, None);
+                        round_trip(header);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn float_round_trip() {
+        for num_channels in 1..=2 {
+            for bits_per_sample in [32, 64].iter() {
+                let header = WaveHeaderBuilder::new()
+                    .audio_format(AudioFormat::FLOAT)
+                    .num_channels(num_channels)
+                    .bits_per_sample(*bits_per_sample)
+                    .num_frames(100)
+                    .build();
+                assert_eq!(header.fmt.chunk_size, 18);
+                assert_eq!(header.fact.map(|f| f.fact_size), Some(100));
+                round_trip(header);
+            }
+        }
+    }
+
+    #[test]
+    fn extensible_round_trip() {
+        let formats = [
+            (AudioFormat::PCM, 24, 20),
+            (AudioFormat::PCM, 32, 24),
+            (AudioFormat::FLOAT, 32, 32),
+        ];
+        for (audio_format, bits_per_sample, valid_bits_per_sample) in formats.iter() {
+            for channel_mask in [0x4_u32, 0x3, 0x3F].iter() {
+                let header = WaveHeaderBuilder::new()
+                    .audio_format(*audio_format)
+                    .num_channels(channel_mask.count_ones() as u16)
+                    .bits_per_sample(*bits_per_sample)
+                    .extensible(*valid_bits_per_sample, *channel_mask)
+                    .num_frames(10)
+                    .build();
+                assert_eq!(header.fmt.chunk_size, 40);
+                assert_eq!(header.fmt.valid_bits_per_sample(), *valid_bits_per_sample);
+                round_trip(header);
+            }
+        }
+    }
+
+    #[test]
+    fn fact_round_trip() {
+        // PCM with an optional "fact"
+        let mut header = pcm();
+        header.fact = Some(ChunkFact {
+            chunk_id: ChunkId::FACT,
+            chunk_size: 4,
+            fact_size: 0,
+        });
+        header.data.offset = header_len(&header);
+        header.set_data_size(3 * 4);
+        assert_eq!(header.fact.map(|f| f.fact_size), Some(3));
+        round_trip(header);
+    }
+
+    /// JUNK, an odd sized bext, fmt, LIST/INFO, data and a trailing odd
+    /// sized "id3 " chunk, 8 kHz mono
+    const DAW: &[u8] = include_bytes!("../fixtures/daw.wav");
+
+    #[test]
+    fn chunks_pad_odd_sizes() {
+        let (riff, chunks) = parse_riff(DAW).unwrap();
+        assert_eq!(riff.chunk_size as usize, DAW.len() - 8);
+
+        let expected: [(&[u8; 4], u32, usize); 6] = [
+            (b"JUNK", 28, 20),
+            (b"bext", 7, 56),
+            (b"fmt ", 16, 72),
+            (b"LIST", 18, 96),
+            (b"data", 8, 122),
+            (b"id3 ", 3, 138),
+        ];
+        let mut n = 0;
+        for (chunk, (fourcc, size, offset)) in chunks.zip(expected.iter()) {
+            assert_eq!(chunk.fourcc, **fourcc);
+            assert_eq!(chunk.size, *size);
+            assert_eq!(chunk.offset, *offset);
+            assert_eq!(chunk.body, &DAW[*offset..*offset + *size as usize]);
+            assert!(chunk.is_complete());
+            n += 1;
+        }
+        assert_eq!(n, expected.len());
+        assert_eq!(chunks.count(), expected.len());
+
+        // The pad byte of the last chunk is optional
+        let last = Chunks::new(&DAW[130..DAW.len() - 1], 130).last().unwrap();
+        assert_eq!((last.body, last.is_complete()), (&b"ID3"[..], true));
+    }
+
+    #[test]
+    fn chunks_stop_after_truncated() {
+        let chunks = Chunks::new(&DAW[12..60], 12);
+        let mut ids = chunks.map(|c| (c.fourcc, c.body.len(), c.is_complete()));
+        assert_eq!(ids.next(), Some((*b"JUNK", 28, true)));
+        assert_eq!(ids.next(), Some((*b"bext", 4, false)));
+        assert_eq!(ids.next(), None);
+
+        // Less than a chunk header
+        assert_eq!(Chunks::new(&DAW[12..19], 12).next(), None);
+    }
+
+    #[test]
+    fn parse_header_skips_unknown_chunks() {
+        let header = parse_header(DAW).unwrap();
+        assert_eq!(header.fmt.num_channels, 1);
+        assert_eq!(header.fmt.sample_rate, 8000);
+        assert_eq!(header.fact, None);
+        assert_eq!(header.data.offset, 122);
+        assert_eq!(header.data.chunk_size, 8);
+        assert!(header.validate(DAW.len()).is_ok());
+
+        // Only needs the input up to the samples
+        assert_eq!(parse_header(&DAW[..122]), Ok(header));
+        assert_eq!(
+            parse_header(&DAW[..100]),
+            Err(WaveError::Truncated {
+                offset: 100,
+                needed: 8
+            })
+        );
+
+        let wave = ParsedWave::parse(DAW).unwrap();
+        assert_eq!(wave.num_frames(), 4);
+        assert!(wave
+            .samples_i16()
+            .unwrap()
+            .eq([1, -1, 2, -2].iter().cloned()));
+        assert_eq!(wave.info().unwrap().and_then(|i| i.name), Some("Ring"));
+        assert_eq!(wave.find_chunk(ChunkId::LIST).map(|c| c.offset), Some(96));
+    }
+
+    fn violations(file: &[u8]) -> (WaveHeader, [Option<Violation>; MAX_VIOLATIONS]) {
+        let header = parse_header(file).unwrap();
+        let mut found = [None; MAX_VIOLATIONS];
+        for (slot, v) in found.iter_mut().zip(header.validate(file.len()).iter()) {
+            *slot = Some(v);
+        }
+        (header, found)
+    }
+
+    #[test]
+    fn validate_format() {
+        // Stereo 16-bit with a block_align of 2, a byte_rate for 4 and 5
+        // bytes of data, RIFF size 100 for 50 bytes
+        let file = include_bytes!("../fixtures/inconsistent.wav");
+        let (header, found) = violations(file);
+        assert_eq!(
+            found[..5],
+            [
+                Some(Violation::RiffSize {
+                    header: 100,
+                    expected: 42
+                }),
+                Some(Violation::BlockAlign {
+                    header: 2,
+                    expected: 4
+                }),
+                Some(Violation::ByteRate {
+                    header: 32_000,
+                    expected: 16_000
+                }),
+                Some(Violation::PartialFrame {
+                    data_size: 5,
+                    block_align: 2
+                }),
+                None,
+            ]
+        );
+        assert!(!header.validate(file.len()).is_repairable());
+
+        let file = include_bytes!("../fixtures/no_format.wav");
+        let (_, found) = violations(file);
+        assert_eq!(
+            found[..4],
+            [
+                Some(Violation::NoChannels),
+                Some(Violation::NoSampleRate),
+                Some(Violation::NoBitsPerSample),
+                None,
+            ]
+        );
+
+        // Extensible, 24 valid bits in 16-bit samples
+        let file = include_bytes!("../fixtures/valid_bits.wav");
+        let (header, found) = violations(file);
+        assert!(header.fmt.extensible.is_some());
+        assert_eq!(
+            found[..2],
+            [
+                Some(Violation::ValidBits {
+                    valid: 24,
+                    container: 16
+                }),
+                None,
+            ]
+        );
+    }
+
+    #[test]
+    fn validate_truncated() {
+        // Header for 100 bytes of data, 10 present
+        let file = include_bytes!("../fixtures/truncated.wav");
+        let (mut header, found) = violations(file);
+        assert_eq!(
+            found[..3],
+            [
+                Some(Violation::RiffSize {
+                    header: 136,
+                    expected: 46
+                }),
+                Some(Violation::DataSize {
+                    header: 100,
+                    available: 10
+                }),
+                None,
+            ]
+        );
+        assert!(header.validate(file.len()).is_repairable());
+        assert_eq!(
+            ParsedWave::parse(file),
+            Err(WaveError::Truncated {
+                offset: 54,
+                needed: 90
+            })
+        );
+
+        assert!(header.repair(file.len()));
+        assert_eq!(header.riff.chunk_size, 46);
+        assert_eq!(header.data.chunk_size, 10);
+        assert!(header.validate(file.len()).is_ok());
+    }
+
+    #[test]
+    fn repair_streamed() {
+        // Sizes of 0, 3.5 stereo frames follow
+        let file = include_bytes!("../fixtures/streamed.wav");
+        let (mut header, found) = violations(file);
+        assert_eq!(
+            found[..2],
+            [
+                Some(Violation::RiffSize {
+                    header: 0,
+                    expected: 50
+                }),
+                None,
+            ]
+        );
+        assert!(header.repair(file.len()));
+        assert_eq!(header.riff.chunk_size, 50);
+        assert_eq!(header.data.chunk_size, 12);
+        assert!(header.validate(file.len()).is_ok());
+        assert!(!header.repair(file.len()));
+
+        // Sizes of UNKNOWN_SIZE, 2 stereo frames follow
+        let file = include_bytes!("../fixtures/unknown_size.wav");
+        let (mut header, found) = violations(file);
+        assert_eq!(
+            found[..3],
+            [
+                Some(Violation::RiffSize {
+                    header: UNKNOWN_SIZE,
+                    expected: 44
+                }),
+                Some(Violation::DataSize {
+                    header: UNKNOWN_SIZE,
+                    available: 8
+                }),
+                None,
+            ]
+        );
+        assert!(header.repair(file.len()));
+        assert_eq!(header.riff.chunk_size, 44);
+        assert_eq!(header.data.chunk_size, 8);
+        assert!(header.validate(file.len()).is_ok());
+
+        // Only the sizes are repaired
+        let file = include_bytes!("../fixtures/inconsistent.wav");
+        let mut header = parse_header(file).unwrap();
+        assert!(header.repair(file.len()));
+        assert_eq!(header.riff.chunk_size, 42);
+        assert_eq!(header.data.chunk_size, 5);
+        assert!(!header.validate(file.len()).is_ok());
+    }
 }